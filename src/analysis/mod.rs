@@ -0,0 +1,342 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use crate::node::{NodeId, NodeRef};
+use crate::result::{ChildStats, CONFIDENCE_Z};
+use crate::state::State;
+use crate::tree::Tree;
+
+// How a node's value is derived from its visited children
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backup {
+    // Visit-weighted mean of the children values
+    Mean,
+    Max,
+    // Max where the root player moves, min where another player does
+    Minimax,
+}
+
+// Stored mean of a node next to the mean of fresh rollouts from its state,
+// `z` is the Welch statistic of their difference
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ValueAudit {
+    pub node: NodeId,
+    pub visits: u32,
+    pub mean: f32,
+    pub resimulated: f32,
+    pub z: f32,
+}
+
+impl ValueAudit {
+    pub fn is_divergent(&self) -> bool {
+        self.z.abs() > CONFIDENCE_Z
+    }
+}
+
+impl<T, S> Tree<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    // Re-simulates `sample` nodes with at least two visits, drawn with the
+    // config seed, `rollouts` times each past the rollout cache, most
+    // divergent first. Backup bugs, stale reused values and nondeterministic
+    // states show up as divergences, as do the nodes where the tree plays
+    // much better than the rollout policy, so the leaves are the most telling
+    pub fn audit_values(&self, sample: usize, rollouts: u32) -> Vec<ValueAudit> {
+        let mut nodes = vec![self.root()];
+        nodes.extend(Self::descendants(&self.root));
+        nodes.retain(|x| x.borrow().visits > 1);
+        let mut rng = StdRng::seed_from_u64(self.config.seed);
+        let nodes: Vec<&NodeRef<T, S>> = nodes.choose_multiple(&mut rng, sample).collect();
+
+        let mut audits: Vec<ValueAudit> = nodes
+            .into_iter()
+            .filter(|_| rollouts > 0)
+            .map(|node| {
+                let values: Vec<f32> = (0..rollouts)
+                    .map(|_| self.rollout_with(node, 0., false))
+                    .collect();
+                let n = rollouts as f32;
+                let resimulated = values.iter().sum::<f32>() / n;
+                let variance = match rollouts {
+                    1 => 0.,
+                    _ => {
+                        values
+                            .iter()
+                            .map(|x| (x - resimulated).powi(2))
+                            .sum::<f32>()
+                            / (n - 1.)
+                    }
+                };
+
+                let node = node.borrow();
+                let visits = node.visits as f32;
+                let stored = node.value_variance() * visits / (visits - 1.);
+                let error = (stored / visits + variance / n).sqrt();
+                let difference = node.mean_value() - resimulated;
+                let z = if error > 0. {
+                    difference / error
+                } else if difference.abs() > f32::EPSILON {
+                    difference.signum() * f32::INFINITY
+                } else {
+                    0.
+                };
+                ValueAudit {
+                    node: node.id(),
+                    visits: node.visits,
+                    mean: node.mean_value(),
+                    resimulated,
+                    z,
+                }
+            })
+            .collect();
+        audits.sort_by(|a, b| b.z.abs().total_cmp(&a.z.abs()));
+        audits
+    }
+
+    // Node values re-derived bottom-up from the leaf means with another backup
+    // rule, the tree itself is left untouched
+    pub fn recompute_values(&self, backup: Backup) -> HashMap<NodeId, f32> {
+        let root_player = self.root.borrow().state.current_player();
+        let mut values = HashMap::new();
+
+        // Pre-order reversed, children come before their parent
+        let mut order = vec![self.root()];
+        order.extend(Self::descendants(&self.root));
+        for node in order.iter().rev() {
+            let node = node.borrow();
+            let children: Vec<(u32, f32)> = node
+                .children
+                .iter()
+                .filter(|x| x.borrow().visits > 0 && !x.borrow().illegal)
+                .filter_map(|x| {
+                    let x = x.borrow();
+                    values.get(&x.id()).map(|y| (x.visits, *y))
+                })
+                .collect();
+
+            let maximizing = match backup {
+                Backup::Minimax => node.state.current_player() == root_player,
+                _ => true,
+            };
+            let value = if children.is_empty() {
+                node.mean_value()
+            } else if backup == Backup::Mean {
+                let visits: u32 = children.iter().map(|x| x.0).sum();
+                children.iter().map(|x| x.0 as f32 * x.1).sum::<f32>() / visits as f32
+            } else if maximizing {
+                children
+                    .iter()
+                    .map(|x| x.1)
+                    .fold(f32::NEG_INFINITY, f32::max)
+            } else {
+                children.iter().map(|x| x.1).fold(f32::INFINITY, f32::min)
+            };
+            values.insert(node.id(), value);
+        }
+
+        values
+    }
+}
+
+impl<T, S> Tree<T, S>
+where
+    S: State<T>,
+    T: Clone + PartialEq,
+{
+    // Gives every requested root action the same budget instead of letting UCB
+    // pick, actions that are not legal at the root are left out
+    pub fn analyze(&mut self, actions: &[T], iterations: u32) -> Vec<ChildStats<T>> {
+        let mut root = self.root();
+        self.expand_fully(&mut root);
+
+        let mut stats = vec![];
+        for action in actions {
+            let child = match self.root_child(action) {
+                Some(x) => x,
+                None => continue,
+            };
+
+            self.search_from(&child, iterations, true);
+            stats.push(ChildStats::from_node(&child.borrow()));
+        }
+
+        stats
+    }
+
+    // Best move other than the excluded ones, as in "what if not X" analysis
+    pub fn search_excluding(&mut self, iterations: u32, excluded: &[T]) -> Option<NodeRef<T, S>> {
+        let mut root = self.root();
+        self.expand_fully(&mut root);
+
+        // Excluded children are detached meanwhile so selection cannot reach them
+        let children = root.borrow().children.clone();
+        root.borrow_mut()
+            .children
+            .retain(|x| !excluded.contains(&x.borrow().action));
+        root.borrow().invalidate_subtree_stats();
+        let best = self.search(iterations);
+
+        root.borrow().invalidate_subtree_stats();
+        let mut root = root.borrow_mut();
+        let searched = std::mem::take(&mut root.children);
+        root.children = children
+            .iter()
+            .filter(|x| {
+                excluded.contains(&x.borrow().action) || searched.iter().any(|y| Rc::ptr_eq(x, y))
+            })
+            .chain(
+                searched
+                    .iter()
+                    .filter(|x| !children.iter().any(|y| Rc::ptr_eq(x, y))),
+            )
+            .map(Rc::clone)
+            .collect();
+
+        best
+    }
+
+    fn root_child(&self, action: &T) -> Option<NodeRef<T, S>> {
+        self.root
+            .borrow()
+            .children
+            .iter()
+            .find(|x| x.borrow().action == *action)
+            .map(Rc::clone)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SearchConfig;
+    use crate::state::Outcome;
+    use crate::testing::{MockState, PathGame};
+
+    #[test]
+    fn audit_values() {
+        let game = PathGame::new(3, |x| match x[0] {
+            3 => Outcome::Win,
+            _ => Outcome::Loss,
+        });
+        let mut tree = Tree::with_config(SearchConfig::game(), 0u8, game);
+        tree.search(60);
+
+        // The children of the root agree with their rollouts
+        let children: Vec<NodeId> = tree
+            .root()
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().id())
+            .collect();
+        let audits = tree.audit_values(100, 10);
+        assert!(audits.len() > 3);
+        assert!(audits
+            .iter()
+            .filter(|x| children.contains(&x.node))
+            .all(|x| !x.is_divergent()));
+
+        // A corrupted backup does not
+        let winner = tree.root().borrow().child_at(0).unwrap();
+        for _ in 0..20 {
+            winner.borrow_mut().push_value(0.);
+        }
+        let audits = tree.audit_values(100, 10);
+        let audit = audits
+            .iter()
+            .find(|x| x.node == winner.borrow().id())
+            .unwrap();
+        assert!(audit.is_divergent());
+        assert_eq!(audit.resimulated, 1.);
+        assert_eq!(tree.audit_values(2, 10).len(), 2);
+    }
+
+    #[test]
+    fn analyze() {
+        let mut config = SearchConfig::new(1.0);
+        config.expand_batch_size = 1;
+        let mut tree = Tree::with_config(config, 0u8, MockState::countdown(4, 0.5));
+
+        let stats = tree.analyze(&[1, 3, 9], 20);
+        assert_eq!(
+            stats.iter().map(|x| x.action).collect::<Vec<u8>>(),
+            vec![1, 3]
+        );
+        assert!(stats.iter().all(|x| x.visits == 20));
+        assert_eq!(tree.root().borrow().visits, 40);
+    }
+
+    #[test]
+    fn search_excluding() {
+        let mut tree = Tree::new(1.0, 0u8, MockState::countdown(4, 0.5));
+        let best = tree.search_excluding(30, &[4, 2]).unwrap();
+        assert!(![4, 2].contains(&best.borrow().action));
+
+        let root = tree.root();
+        let actions: Vec<u8> = root
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().action)
+            .collect();
+        assert_eq!(actions, vec![4, 3, 2, 1]);
+        assert_eq!(root.borrow().child_at(0).unwrap().borrow().visits, 0);
+        assert_eq!(root.borrow().child_at(2).unwrap().borrow().visits, 0);
+    }
+
+    #[test]
+    fn recompute_values() {
+        let mut tree = Tree::new(1.0, 0u8, MockState::countdown(2, 0.5));
+        let mut root = tree.root();
+        tree.expand_fully(&mut root);
+        let first = root.borrow().child_at(0).unwrap();
+        let second = root.borrow().child_at(1).unwrap();
+        for (node, visits, mean) in [(&first, 3, 0.2), (&second, 1, 1.)] {
+            node.borrow_mut().visits = visits;
+            node.borrow_mut().mean = mean;
+        }
+
+        let id = root.borrow().id();
+        let mean = tree.recompute_values(Backup::Mean)[&id];
+        assert!((mean - 0.4).abs() < 1e-6);
+        assert_eq!(tree.recompute_values(Backup::Max)[&id], 1.);
+
+        // The opponent moves below the root
+        let mut tree = Tree::new(1.0, 0u8, PathGame::new(3, |_| Outcome::Draw));
+        let mut root = tree.root();
+        tree.expand_fully(&mut root);
+        let mut child = root.borrow().child_at(0).unwrap();
+        tree.expand_fully(&mut child);
+        child.borrow_mut().visits = 2;
+        for (i, mean) in [0.2, 0.8].iter().enumerate() {
+            let answer = child.borrow().child_at(i).unwrap();
+            answer.borrow_mut().visits = 1;
+            answer.borrow_mut().mean = *mean;
+        }
+
+        let id = child.borrow().id();
+        assert_eq!(tree.recompute_values(Backup::Max)[&id], 0.8);
+        assert_eq!(tree.recompute_values(Backup::Minimax)[&id], 0.2);
+        assert_eq!(
+            tree.recompute_values(Backup::Minimax)[&root.borrow().id()],
+            0.2
+        );
+    }
+}
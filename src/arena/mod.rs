@@ -0,0 +1,544 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::Debug;
+use std::rc::Rc;
+
+use crate::config::{RewardMode, SearchConfig};
+use crate::node::{stat_to_f32, Node, NodeId, NodeRef, Stat};
+use crate::state::State;
+
+// Value statistics of an arena node, picked at compile time by the value
+// type of the tree. The node counts the visits
+pub trait ArenaValue: Clone + Debug + Default {
+    fn mean(&self) -> Stat;
+
+    // `visits` already counts the new value
+    fn push(&mut self, value: f32, visits: u32);
+
+    // Sum of squared deviations, 0 for layouts that do not keep it
+    fn m2(&self) -> Stat {
+        0.
+    }
+
+    // Whether the layout can hold the values of the mode
+    fn supports(_mode: RewardMode) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FullValue {
+    pub mean: Stat,
+    pub m2: Stat,
+}
+
+impl ArenaValue for FullValue {
+    fn mean(&self) -> Stat {
+        self.mean
+    }
+
+    fn push(&mut self, value: f32, visits: u32) {
+        let value = Stat::from(value);
+        let delta = value - self.mean;
+        self.mean += delta / visits as Stat;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    fn m2(&self) -> Stat {
+        self.m2
+    }
+}
+
+const COMPACT_SCALE: f32 = u16::MAX as f32;
+
+// Two bytes for trees of hundreds of millions of nodes: the mean as 16 bit
+// fixed point over [0, 1], values outside are clamped. Planning rewards are
+// unbounded, so the layout only takes Game mode. Rounding is dithered so that
+// updates below the resolution still move the mean on average
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CompactValue(u16);
+
+impl ArenaValue for CompactValue {
+    fn mean(&self) -> Stat {
+        Stat::from(f32::from(self.0) / COMPACT_SCALE)
+    }
+
+    fn push(&mut self, value: f32, visits: u32) {
+        let mean = f32::from(self.0) / COMPACT_SCALE;
+        let mean = mean + (value.clamp(0., 1.) - mean) / visits as f32;
+        let dither = (visits.wrapping_mul(0x9e37_79b9) >> 16) as f32 / 65536.;
+        self.0 = (mean * COMPACT_SCALE + dither).floor().min(COMPACT_SCALE) as u16;
+    }
+
+    fn supports(mode: RewardMode) -> bool {
+        mode == RewardMode::Game
+    }
+}
+
+// No node, for the links of ArenaNode
+const NONE: u32 = u32::MAX;
+
+// Node of an ArenaTree. Links are 32 bit indices into the arena and the
+// children a list through next_sibling in expansion order, so a node holds
+// no allocation of its own
+#[derive(Clone, Debug)]
+pub struct ArenaNode<T, S, V = FullValue> {
+    pub action: T,
+    pub state: S,
+    pub visits: u32,
+    pub value: V,
+    parent: u32,
+    first_child: u32,
+    last_child: u32,
+    next_sibling: u32,
+    pub expanded: bool,
+}
+
+impl<T, S, V> ArenaNode<T, S, V>
+where
+    V: ArenaValue,
+{
+    fn new(action: T, state: S, parent: u32) -> Self {
+        Self {
+            action,
+            state,
+            visits: 0,
+            value: V::default(),
+            parent,
+            first_child: NONE,
+            last_child: NONE,
+            next_sibling: NONE,
+            expanded: false,
+        }
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        link(self.parent)
+    }
+
+    pub fn mean_value(&self) -> f32 {
+        stat_to_f32(self.value.mean())
+    }
+
+    pub fn total_reward(&self) -> Stat {
+        self.value.mean() * self.visits as Stat
+    }
+
+    fn push_value(&mut self, value: f32) {
+        self.visits += 1;
+        self.value.push(value, self.visits);
+    }
+}
+
+fn link(index: u32) -> Option<NodeId> {
+    (index != NONE).then(|| NodeId(u64::from(index)))
+}
+
+// Standalone plain UCT over nodes stored in a Vec, no reference counting or
+// runtime borrows, and Send whenever the actions and states are. This is not
+// a storage backend for Tree, which still runs on NodeRef: its strategies,
+// observers, solver, spill and checkpoints don't work here. to_node_ref
+// converts the result for the NodeRef based helpers
+#[derive(Clone, Debug)]
+pub struct ArenaTree<T, S, V = FullValue> {
+    config: SearchConfig,
+    nodes: Vec<ArenaNode<T, S, V>>,
+    // Enumeration cursors of the partially expanded nodes
+    expansion_states: HashMap<NodeId, S>,
+    reward_bounds: Option<(f32, f32)>,
+    pub iterations: u32,
+}
+
+impl<T, S> ArenaTree<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    pub fn new(learning_rate: f32, action: T, state: S) -> Self {
+        Self::with_config(SearchConfig::new(learning_rate), action, state)
+    }
+
+    pub fn with_config(config: SearchConfig, action: T, state: S) -> Self {
+        Self::with_layout(config, action, state)
+    }
+}
+
+impl<T, S, V> ArenaTree<T, S, V>
+where
+    S: State<T>,
+    T: Clone,
+    V: ArenaValue,
+{
+    // Same search with the node values kept as V, e.g.
+    // ArenaTree::<_, _, CompactValue>::with_layout. Panics when V can't hold
+    // the values of the config mode
+    pub fn with_layout(config: SearchConfig, action: T, state: S) -> Self {
+        assert!(
+            V::supports(config.mode),
+            "value layout does not support {:?} mode",
+            config.mode
+        );
+        Self {
+            config,
+            nodes: vec![ArenaNode::new(action, state, NONE)],
+            expansion_states: HashMap::new(),
+            reward_bounds: None,
+            iterations: 0,
+        }
+    }
+
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<&ArenaNode<T, S, V>> {
+        self.nodes.get(id.0 as usize)
+    }
+
+    pub fn size(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn children(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        let first = self.node(id).and_then(|x| link(x.first_child));
+        std::iter::successors(first, move |x| link(self.get(*x).next_sibling))
+    }
+
+    fn get(&self, id: NodeId) -> &ArenaNode<T, S, V> {
+        &self.nodes[id.0 as usize]
+    }
+
+    fn get_mut(&mut self, id: NodeId) -> &mut ArenaNode<T, S, V> {
+        &mut self.nodes[id.0 as usize]
+    }
+
+    pub fn search(&mut self, iterations: u32) -> Option<NodeId> {
+        for _i in 0..iterations {
+            let mut leaf = self.select();
+            if self.get(leaf).visits > 0 {
+                leaf = self.expand(leaf).unwrap_or(leaf);
+            }
+            let value = self.simulate(leaf);
+            self.backpropagate(leaf, value);
+            self.iterations += 1;
+        }
+
+        self.best_child()
+    }
+
+    pub fn best_child(&self) -> Option<NodeId> {
+        let children = self.children(self.root());
+        match self.config.mode {
+            RewardMode::Game => children.max_by_key(|x| self.get(*x).visits),
+            RewardMode::Planning => children.max_by(|x, y| {
+                self.get(*x)
+                    .total_reward()
+                    .partial_cmp(&self.get(*y).total_reward())
+                    .unwrap_or(std::cmp::Ordering::Less)
+            }),
+        }
+    }
+
+    fn score(&self, id: NodeId) -> f32 {
+        let node = self.get(id);
+        let parent_visits = match node.parent() {
+            Some(x) => self.get(x).visits as f32,
+            None => return 0.,
+        };
+        let exploration =
+            self.config.learning_rate * ((2. * parent_visits.ln()) / node.visits as f32).sqrt();
+
+        match (self.config.mode, self.reward_bounds) {
+            (RewardMode::Planning, Some((low, high))) if high > low => {
+                (node.mean_value() - low) / (high - low) + exploration
+            }
+            _ => node.mean_value() + exploration,
+        }
+    }
+
+    fn select(&self) -> NodeId {
+        let mut id = self.root();
+
+        loop {
+            if self.get(id).first_child == NONE || self.expansion_states.contains_key(&id) {
+                return id;
+            }
+
+            // Unvisited children first, same tie-breaking as Tree
+            id = self
+                .children(id)
+                .map(|x| (x, self.score(x)))
+                .max_by(|a, b| {
+                    if self.get(a.0).visits == 0 {
+                        return std::cmp::Ordering::Greater;
+                    }
+                    a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Less)
+                })
+                .unwrap()
+                .0;
+        }
+    }
+
+    fn expand(&mut self, id: NodeId) -> Option<NodeId> {
+        let mut curr_state = match self.expansion_states.remove(&id) {
+            Some(x) => x,
+            None => self.get(id).state.clone(),
+        };
+
+        let (mut first_new, mut added) = (None, 0);
+        let batch_size = self.config.expand_batch_size;
+
+        while batch_size == 0 || added < batch_size {
+            let action = match curr_state.next_action() {
+                Some(x) => x,
+                None => {
+                    self.get_mut(id).expanded = true;
+                    break;
+                }
+            };
+
+            if curr_state.try_do_action(&action).is_err() {
+                self.get_mut(id).expanded = true;
+                break;
+            }
+
+            let mut state = self.get(id).state.clone();
+            if state.try_do_action(&action).is_ok() {
+                let child = self.push_child(id, action, state);
+                first_new = first_new.or(Some(child));
+                added += 1;
+            }
+        }
+
+        // Keep the enumeration cursor around for the next batch
+        if !self.get(id).expanded {
+            self.expansion_states.insert(id, curr_state);
+        }
+
+        first_new
+    }
+
+    fn push_child(&mut self, id: NodeId, action: T, state: S) -> NodeId {
+        let index = u32::try_from(self.nodes.len())
+            .ok()
+            .filter(|x| *x != NONE)
+            .expect("arena is limited to u32::MAX - 1 nodes");
+        self.nodes.push(ArenaNode::new(action, state, id.0 as u32));
+
+        let parent = self.get_mut(id);
+        let last = parent.last_child;
+        parent.last_child = index;
+        if last == NONE {
+            parent.first_child = index;
+        } else {
+            self.nodes[last as usize].next_sibling = index;
+        }
+        NodeId(u64::from(index))
+    }
+
+    fn simulate(&self, id: NodeId) -> f32 {
+        let mut total_reward = 0.;
+        let mut state = self.get(id).state.clone();
+        let root_player = self.get(self.root()).state.current_player();
+
+        let mut truncated = false;
+        while let Some(action) = state.next_action() {
+            match state.try_do_action(&action) {
+                Ok(x) => total_reward += x,
+                Err(_) => {
+                    truncated = true;
+                    break;
+                }
+            }
+        }
+
+        // Only terminal scores count the handicap
+        match state.outcome() {
+            Some(x) => self
+                .config
+                .outcome_value(x.for_player(root_player, state.num_players())),
+            None if truncated => total_reward,
+            None => total_reward + self.config.reward_offset(root_player),
+        }
+    }
+
+    fn backpropagate(&mut self, id: NodeId, value: f32) {
+        self.reward_bounds = match self.reward_bounds {
+            Some((low, high)) => Some((low.min(value), high.max(value))),
+            None => Some((value, value)),
+        };
+
+        let mut node = Some(id);
+        while let Some(x) = node {
+            self.get_mut(x).push_value(value);
+            node = self.get(x).parent();
+        }
+    }
+
+    // Same tree as a NodeRef graph, e.g. for the checkpoint or analysis helpers
+    pub fn to_node_ref(&self) -> NodeRef<T, S> {
+        let refs: Vec<NodeRef<T, S>> = self
+            .nodes
+            .iter()
+            .map(|x| {
+                let node = Node::new(x.action.clone(), x.state.clone());
+                {
+                    let mut node = node.borrow_mut();
+                    node.visits = x.visits;
+                    node.mean = x.value.mean();
+                    node.m2 = x.value.m2();
+                    node.expanded = x.expanded;
+                }
+                node
+            })
+            .collect();
+        for (id, state) in self.expansion_states.iter() {
+            refs[id.0 as usize].borrow_mut().expansion_state = Some(state.clone());
+        }
+
+        // Children always come after their parent in the arena
+        for (i, node) in refs.iter().enumerate() {
+            for child in self.children(NodeId(i as u64)) {
+                let child = Rc::clone(&refs[child.0 as usize]);
+                child.borrow_mut().set_parent(node);
+                node.borrow_mut().add_child(child);
+            }
+        }
+
+        Rc::clone(&refs[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::ActionError;
+    use crate::testing::tree_shape;
+    use crate::tree::Tree;
+
+    #[derive(Debug, Clone)]
+    struct DummyState {
+        actions: u8,
+    }
+
+    impl State<u8> for DummyState {
+        fn next_action(&self) -> Option<u8> {
+            if self.actions == 0 {
+                return None;
+            }
+            Some(self.actions)
+        }
+
+        fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+            self.actions -= 1;
+            Ok(*action as f32 / 10.)
+        }
+    }
+
+    #[test]
+    fn same_search_as_tree() {
+        let mut arena = ArenaTree::new(1.0, 0, DummyState { actions: 4 });
+        let mut tree = Tree::new(1.0, 0, DummyState { actions: 4 });
+        let best = arena.search(60).unwrap();
+        let tree_best = tree.search(60).unwrap();
+
+        assert_eq!(arena.node(best).unwrap().action, tree_best.borrow().action);
+        let root = arena.to_node_ref();
+        assert_eq!(tree_shape(&root), tree_shape(&tree.root()));
+        for (x, y) in root
+            .borrow()
+            .children
+            .iter()
+            .zip(tree.root().borrow().children.iter())
+        {
+            assert_eq!(x.borrow().visits, y.borrow().visits);
+            assert_eq!(x.borrow().mean, y.borrow().mean);
+            assert_eq!(x.borrow().m2, y.borrow().m2);
+        }
+        assert_eq!(arena.size() as u32, tree.size);
+    }
+
+    #[test]
+    fn compact_value() {
+        assert_eq!(size_of::<CompactValue>(), 2);
+        // Links, visits and flag around the value, nothing on the heap
+        assert_eq!(size_of::<ArenaNode<u8, u8, CompactValue>>(), 28);
+
+        let mut arena = ArenaTree::<_, _, CompactValue>::with_layout(
+            SearchConfig::game(),
+            0,
+            DummyState { actions: 4 },
+        );
+        let mut full = ArenaTree::with_config(SearchConfig::game(), 0, DummyState { actions: 4 });
+        let best = arena.search(60).unwrap();
+        let full_best = full.search(60).unwrap();
+        assert_eq!(
+            arena.node(best).unwrap().action,
+            full.node(full_best).unwrap().action
+        );
+        let root = arena.node(arena.root()).unwrap();
+        assert!((root.mean_value() - full.node(full.root()).unwrap().mean_value()).abs() < 1e-3);
+
+        // Far more values than the 16 bit resolution could follow undithered
+        let mut value = CompactValue::default();
+        for i in 1..=100_000 {
+            value.push(if i % 4 == 0 { 1. } else { 0.2 }, i);
+        }
+        assert!((stat_to_f32(value.mean()) - 0.4).abs() < 1e-3);
+    }
+
+    #[test]
+    #[should_panic(expected = "Planning")]
+    fn compact_value_rejects_planning() {
+        ArenaTree::<_, _, CompactValue>::with_layout(
+            SearchConfig::new(1.0),
+            0,
+            DummyState { actions: 4 },
+        );
+    }
+
+    #[test]
+    fn children() {
+        let config = SearchConfig {
+            expand_batch_size: 2,
+            ..SearchConfig::new(1.0)
+        };
+        let mut arena = ArenaTree::with_config(config, 0, DummyState { actions: 5 });
+        arena.search(40);
+        // Batches of other nodes come in between, the order stays the enumeration order
+        let actions: Vec<u8> = arena
+            .children(arena.root())
+            .map(|x| arena.get(x).action)
+            .collect();
+        assert_eq!(actions, vec![5, 4, 3, 2, 1]);
+        for x in arena.children(arena.root()) {
+            assert_eq!(arena.get(x).parent(), Some(arena.root()));
+        }
+    }
+
+    #[test]
+    fn send() {
+        fn assert_send<X: Send>(_x: &X) {}
+
+        let mut arena = ArenaTree::new(1.0, 0, DummyState { actions: 3 });
+        assert_send(&arena);
+        let handle = std::thread::spawn(move || {
+            arena.search(20);
+            arena
+        });
+        let arena = handle.join().unwrap();
+        assert_eq!(arena.get(arena.root()).visits, 20);
+        assert!(arena.node(NodeId(arena.size() as u64)).is_none());
+    }
+}
@@ -0,0 +1,103 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::rc::Rc;
+
+use crate::node::NodeRef;
+use crate::state::State;
+use crate::strategies::{BackpropagationStrategy, ExpansionStrategy, SimulationStrategy};
+use crate::tree::Tree;
+
+impl<T, S> Tree<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    // Keeps the `width` most promising leaves and expands the best of them at
+    // every step instead of descending from the root, for single-agent
+    // deterministic problems
+    pub fn search_beam(&mut self, width: usize, steps: u32) -> Option<NodeRef<T, S>> {
+        let mut frontier = vec![self.root()];
+
+        for _i in 0..steps {
+            let index = match (0..frontier.len()).max_by(|&x, &y| {
+                self.frontier_value(&frontier[x])
+                    .partial_cmp(&self.frontier_value(&frontier[y]))
+                    .unwrap_or(std::cmp::Ordering::Less)
+            }) {
+                Some(x) => x,
+                None => break,
+            };
+            let mut node = frontier.swap_remove(index);
+
+            if node.borrow().visits == 0 {
+                self.evaluate_leaf(&node);
+            }
+
+            let first_new = node.borrow().children.len();
+            self.expand(&mut node);
+            let children: Vec<NodeRef<T, S>> = node.borrow().children[first_new..].to_vec();
+            for child in children {
+                self.evaluate_leaf(&child);
+                frontier.push(child);
+            }
+            if node.borrow().is_partially_expanded() {
+                frontier.push(node);
+            }
+
+            frontier.sort_by(|x, y| {
+                self.frontier_value(y)
+                    .partial_cmp(&self.frontier_value(x))
+                    .unwrap_or(std::cmp::Ordering::Less)
+            });
+            frontier.truncate(width.max(1));
+            self.enforce_node_cap();
+        }
+
+        self.best_child()
+    }
+
+    fn evaluate_leaf(&mut self, node: &NodeRef<T, S>) {
+        let reward = self.simulate(node);
+        self.backpropagate(&mut Rc::clone(node), reward);
+        self.record_value(reward);
+        self.iterations += 1;
+    }
+
+    // Mean plus an optimism bound shrinking with the visits
+    fn frontier_value(&self, node: &NodeRef<T, S>) -> f32 {
+        let node = node.borrow();
+        if node.visits == 0 {
+            return f32::INFINITY;
+        }
+        node.mean_value() + self.config().learning_rate / (node.visits as f32).sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockState;
+
+    #[test]
+    fn search_beam() {
+        let mut tree = Tree::new(1.0, 0u8, MockState::countdown(4, 0.5));
+        assert!(tree.search_beam(2, 5).is_some());
+        assert_eq!(tree.root().borrow().children.len(), 4);
+        assert_eq!(tree.iterations, tree.size);
+        assert_eq!(tree.root().borrow().visits, tree.size);
+
+        // Terminal leaves leave the frontier, the search then stops early
+        let mut tree = Tree::new(1.0, 0u8, MockState::countdown(1, 0.5));
+        tree.search_beam(2, 50);
+        assert_eq!(tree.size, 2);
+    }
+}
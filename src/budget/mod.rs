@@ -0,0 +1,205 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::fmt;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::result::SearchResult;
+use crate::state::State;
+use crate::strategies::SimulationStrategy;
+use crate::tree::Tree;
+
+type Stop<T, S> = Rc<dyn Fn(&Tree<T, S>) -> bool>;
+
+// Limits of a single search, it stops at the first one reached. The config
+// limits on steps and cost still apply
+#[derive(Clone)]
+pub struct SearchBudget<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    pub iterations: Option<u32>,
+    pub time: Option<Duration>,
+    pub max_nodes: Option<u32>,
+    pub max_depth: Option<u32>,
+    stop: Option<Stop<T, S>>,
+}
+
+impl<T, S> SearchBudget<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn new() -> Self {
+        Self {
+            iterations: None,
+            time: None,
+            max_nodes: None,
+            max_depth: None,
+            stop: None,
+        }
+    }
+
+    pub fn iterations(iterations: u32) -> Self {
+        Self::new().with_iterations(iterations)
+    }
+
+    pub fn time(time: Duration) -> Self {
+        Self::new().with_time(time)
+    }
+
+    // Runs until `stop` returns true, checked before every iteration
+    pub fn until<F>(stop: F) -> Self
+    where
+        F: Fn(&Tree<T, S>) -> bool + 'static,
+    {
+        Self::new().with_stop(stop)
+    }
+
+    pub fn with_iterations(mut self, iterations: u32) -> Self {
+        self.iterations = Some(iterations);
+        self
+    }
+
+    pub fn with_time(mut self, time: Duration) -> Self {
+        self.time = Some(time);
+        self
+    }
+
+    pub fn with_max_nodes(mut self, nodes: u32) -> Self {
+        self.max_nodes = Some(nodes);
+        self
+    }
+
+    // Stops once a leaf this deep below the root has been reached
+    pub fn with_max_depth(mut self, depth: u32) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    pub fn with_stop<F>(mut self, stop: F) -> Self
+    where
+        F: Fn(&Tree<T, S>) -> bool + 'static,
+    {
+        self.stop = Some(Rc::new(stop));
+        self
+    }
+
+    // Fraction spent, for the schedules, by iterations if limited or else by time
+    fn progress(&self, iterations: u32, elapsed: Duration) -> f32 {
+        match (self.iterations, self.time) {
+            (Some(x), _) => iterations as f32 / x as f32,
+            (None, Some(x)) => elapsed.as_secs_f32() / x.as_secs_f32(),
+            (None, None) => 0.,
+        }
+    }
+}
+
+impl<T, S> fmt::Debug for SearchBudget<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SearchBudget")
+            .field("iterations", &self.iterations)
+            .field("time", &self.time)
+            .field("max_nodes", &self.max_nodes)
+            .field("max_depth", &self.max_depth)
+            .field("stop", &self.stop.is_some())
+            .finish()
+    }
+}
+
+impl<T, S> Tree<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    pub fn search_with(&mut self, budget: SearchBudget<T, S>) -> SearchResult<T> {
+        let start = Instant::now();
+        self.deadline = budget.time.map(|x| start + x);
+        self.warm_up();
+        let root = self.root();
+        let mut iterations = 0;
+        let mut depth = 0;
+
+        loop {
+            let done = budget.iterations.is_some_and(|x| iterations >= x)
+                || budget.max_nodes.is_some_and(|x| self.size >= x)
+                || budget.max_depth.is_some_and(|x| depth >= x)
+                || budget.stop.as_ref().is_some_and(|x| x(self))
+                || self.past_deadline()
+                || self.out_of_budget();
+            if done {
+                break;
+            }
+            self.progress = budget.progress(iterations, start.elapsed());
+
+            let leaf = match self.next_leaf(&root) {
+                Some(x) => x,
+                None => break,
+            };
+            let reward = self.simulate(&leaf);
+            self.finish_iteration(&root, &leaf, reward, true);
+            iterations += 1;
+            if budget.max_depth.is_some() {
+                depth = depth.max(Self::depth(&leaf));
+            }
+        }
+
+        self.deadline = None;
+        self.progress = 1.;
+        self.elapsed += start.elapsed();
+        self.result()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockState;
+
+    #[test]
+    fn search_with() {
+        let mut tree = Tree::new(1.0, 0, MockState::countdown(5, 0.5));
+        let result = tree.search_with(SearchBudget::iterations(30));
+        assert_eq!(result.iterations, 30);
+        assert_eq!(result.nodes, tree.size);
+        assert!(result.max_depth > 1);
+        assert!(result.best_action.is_some());
+        assert!(!result.children.is_empty());
+
+        let result = tree.search_with(SearchBudget::iterations(1000).with_max_nodes(60));
+        assert!(result.nodes >= 60 && result.iterations < 1030);
+    }
+
+    #[test]
+    fn max_depth() {
+        let mut tree = Tree::new(1.0, 0, MockState::countdown(5, 0.5));
+        let result = tree.search_with(SearchBudget::iterations(1000).with_max_depth(2));
+        assert_eq!(result.max_depth, 2);
+        assert!(result.iterations < 1000);
+    }
+
+    #[test]
+    fn until() {
+        let mut tree = Tree::new(1.0, 0, MockState::countdown(5, 0.5));
+        let result = tree.search_with(SearchBudget::until(|x: &Tree<u8, _>| x.iterations >= 12));
+        assert_eq!(result.iterations, 12);
+
+        let result = tree.search_with(SearchBudget::time(Duration::from_millis(5)));
+        assert!(result.iterations > 12);
+        assert!(result.elapsed >= Duration::from_millis(5));
+    }
+}
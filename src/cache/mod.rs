@@ -0,0 +1,490 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::rc::Rc;
+
+use crate::result::SearchResult;
+use crate::sketch::DistinctCounter;
+use crate::state::{key_hash, StateKey};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub len: usize,
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f32 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            return 0.;
+        }
+        self.hits as f32 / lookups as f32
+    }
+}
+
+// Least recently used cache keyed by state hashes
+#[derive(Clone, Debug)]
+pub struct LruCache<V> {
+    capacity: usize,
+    entries: HashMap<u64, (V, u64)>,
+    order: BTreeMap<u64, u64>,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl<V> LruCache<V>
+where
+    V: Clone,
+{
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn get(&mut self, key: u64) -> Option<V> {
+        self.tick += 1;
+        let tick = self.tick;
+
+        match self.entries.get_mut(&key) {
+            Some(x) => {
+                self.order.remove(&x.1);
+                self.order.insert(tick, key);
+                x.1 = tick;
+                self.hits += 1;
+                Some(x.0.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, key: u64, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+
+        if let Some((_, tick)) = self.entries.insert(key, (value, self.tick)) {
+            self.order.remove(&tick);
+        }
+        self.order.insert(self.tick, key);
+
+        while self.entries.len() > self.capacity {
+            let (&tick, &oldest) = self.order.iter().next().unwrap();
+            self.order.remove(&tick);
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+impl<V> LruCache<V> {
+    // Lookup that leaves the order and the statistics alone
+    pub(crate) fn peek(&self, key: u64) -> Option<&V> {
+        self.entries.get(&key).map(|x| &x.0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            len: self.entries.len(),
+            capacity: self.capacity,
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+}
+
+// StateKey of a state type the tables cannot name, so they need no bound on
+// it: the hash of the key picks a bucket, the stored keys are compared with
+// the state's one
+pub(crate) struct Keyer<S> {
+    pub(crate) hash: fn(&S) -> u64,
+    boxed: fn(&S) -> Rc<dyn Any>,
+    matches: fn(&dyn Any, &S) -> bool,
+}
+
+impl<S> Keyer<S>
+where
+    S: StateKey,
+{
+    pub(crate) fn new() -> Self {
+        Self {
+            hash: key_hash::<S>,
+            boxed: |state| Rc::new(state.key()),
+            matches: |key, state| key.downcast_ref::<S::Key>() == Some(&state.key()),
+        }
+    }
+}
+
+impl<S> Clone for Keyer<S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S> Copy for Keyer<S> {}
+
+// Entry of a KeyMap, valid until the map is cleared
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeySlot {
+    hash: u64,
+    index: usize,
+}
+
+// Key and value of a KeyMap entry, no key for the ones loaded by hash
+type KeyedEntry<V> = (Option<Rc<dyn Any>>, V);
+
+// Values by state key. Entries loaded by hash alone, see KnowledgeStore::load,
+// match any state with that hash and take its key on the first update
+#[derive(Clone)]
+pub(crate) struct KeyMap<S, V> {
+    keyer: Keyer<S>,
+    buckets: HashMap<u64, Vec<KeyedEntry<V>>>,
+    len: usize,
+}
+
+impl<S, V> KeyMap<S, V> {
+    pub(crate) fn new(keyer: Keyer<S>) -> Self {
+        Self {
+            keyer,
+            buckets: HashMap::new(),
+            len: 0,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.buckets.clear();
+        self.len = 0;
+    }
+
+    fn find(&self, state: &S) -> Option<KeySlot> {
+        let hash = (self.keyer.hash)(state);
+        let bucket = self.buckets.get(&hash)?;
+        let index = bucket
+            .iter()
+            .position(|x| {
+                x.0.as_ref()
+                    .is_some_and(|x| (self.keyer.matches)(&**x, state))
+            })
+            .or_else(|| bucket.iter().position(|x| x.0.is_none()))?;
+        Some(KeySlot { hash, index })
+    }
+
+    pub(crate) fn get(&self, state: &S) -> Option<&V> {
+        let slot = self.find(state)?;
+        Some(&self.buckets[&slot.hash][slot.index].1)
+    }
+
+    // The entry of the state, added with `default` when missing
+    pub(crate) fn entry<F>(&mut self, state: &S, default: F) -> (KeySlot, &mut V)
+    where
+        F: FnOnce() -> V,
+    {
+        let slot = match self.find(state) {
+            Some(x) => x,
+            None => {
+                let hash = (self.keyer.hash)(state);
+                let bucket = self.buckets.entry(hash).or_default();
+                bucket.push((None, default()));
+                self.len += 1;
+                KeySlot {
+                    hash,
+                    index: bucket.len() - 1,
+                }
+            }
+        };
+        let entry = &mut self.buckets.get_mut(&slot.hash).unwrap()[slot.index];
+        if entry.0.is_none() {
+            entry.0 = Some((self.keyer.boxed)(state));
+        }
+        (slot, &mut entry.1)
+    }
+
+    pub(crate) fn slot_mut(&mut self, slot: KeySlot) -> Option<&mut V> {
+        self.buckets
+            .get_mut(&slot.hash)?
+            .get_mut(slot.index)
+            .map(|x| &mut x.1)
+    }
+
+    pub(crate) fn insert_hashed(&mut self, hash: u64, value: V) {
+        self.buckets.entry(hash).or_default().push((None, value));
+        self.len += 1;
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (u64, &V)> {
+        self.buckets
+            .iter()
+            .flat_map(|(hash, x)| x.iter().map(move |x| (*hash, &x.1)))
+    }
+}
+
+// Cache looked up by state, usable from `&self` during a search. Entries
+// of states with the same key hash replace each other
+#[derive(Clone)]
+pub struct StateCache<S, V> {
+    keyer: Keyer<S>,
+    cache: RefCell<LruCache<(Rc<dyn Any>, V)>>,
+}
+
+impl<S, V> StateCache<S, V>
+where
+    S: StateKey,
+    V: Clone,
+{
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            keyer: Keyer::new(),
+            cache: RefCell::new(LruCache::new(capacity)),
+        }
+    }
+}
+
+impl<S, V> StateCache<S, V>
+where
+    V: Clone,
+{
+    pub fn get(&self, state: &S) -> Option<V> {
+        let mut cache = self.cache.borrow_mut();
+        let (key, value) = cache.get((self.keyer.hash)(state))?;
+        if (self.keyer.matches)(&*key, state) {
+            return Some(value);
+        }
+        cache.hits -= 1;
+        cache.misses += 1;
+        None
+    }
+
+    pub(crate) fn contains(&self, state: &S) -> bool {
+        match self.cache.borrow().peek((self.keyer.hash)(state)) {
+            Some((key, _)) => (self.keyer.matches)(&**key, state),
+            None => false,
+        }
+    }
+
+    pub fn insert(&self, state: &S, value: V) {
+        let key = (self.keyer.boxed)(state);
+        self.cache
+            .borrow_mut()
+            .insert((self.keyer.hash)(state), (key, value));
+    }
+
+    pub fn clear(&self) {
+        self.cache.borrow_mut().clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.cache.borrow().stats()
+    }
+}
+
+impl<S, V> fmt::Debug for StateCache<S, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StateCache")
+            .field("len", &self.cache.borrow().len())
+            .finish()
+    }
+}
+
+// Recent results by root state, see Tree::search_cached. One cache per
+// config, the results do not tell searches under other configs apart
+pub type ResultCache<S, T> = StateCache<S, SearchResult<T>>;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CoverageStats {
+    pub distinct: u64,
+    // False once the count is an estimate
+    pub exact: bool,
+}
+
+// Distinct states met by a search, in the tree and in the rollouts, counted
+// by key hash
+#[derive(Clone)]
+pub struct StateCoverage<S> {
+    keyer: Keyer<S>,
+    counter: RefCell<DistinctCounter>,
+}
+
+impl<S> StateCoverage<S>
+where
+    S: StateKey,
+{
+    pub fn new(cap: usize) -> Self {
+        Self {
+            keyer: Keyer::new(),
+            counter: RefCell::new(DistinctCounter::new(cap)),
+        }
+    }
+}
+
+impl<S> StateCoverage<S> {
+    pub fn record(&self, state: &S) {
+        self.counter.borrow_mut().add((self.keyer.hash)(state));
+    }
+
+    pub fn stats(&self) -> CoverageStats {
+        let counter = self.counter.borrow();
+        CoverageStats {
+            distinct: counter.count(),
+            exact: counter.is_exact(),
+        }
+    }
+}
+
+impl<S> fmt::Debug for StateCoverage<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StateCoverage")
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::hash::{Hash, Hasher};
+
+    #[test]
+    fn lru() {
+        let mut cache = LruCache::new(2);
+        cache.insert(1, 'a');
+        cache.insert(2, 'b');
+        assert_eq!(cache.get(1), Some('a'));
+
+        // 2 is now the least recently used entry
+        cache.insert(3, 'c');
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(2), None);
+        assert_eq!(cache.get(3), Some('c'));
+        assert_eq!(cache.get(1), Some('a'));
+    }
+
+    #[test]
+    fn stats() {
+        let mut cache = LruCache::new(4);
+        assert_eq!(cache.stats().hit_rate(), 0.);
+
+        cache.insert(1, 1.);
+        cache.get(1);
+        cache.get(2);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        assert_eq!(stats.hit_rate(), 0.5);
+        assert_eq!(stats.capacity, 4);
+    }
+
+    #[test]
+    fn state_key() {
+        struct Position {
+            board: u8,
+            history: Vec<u8>,
+        }
+
+        impl StateKey for Position {
+            type Key = u8;
+
+            fn key(&self) -> u8 {
+                self.board
+            }
+        }
+
+        // The history does not tell the positions apart
+        let cache = StateCache::new(4);
+        let position = Position {
+            board: 3,
+            history: vec![1, 2],
+        };
+        cache.insert(&position, 0.5);
+        let other = Position {
+            board: 3,
+            history: vec![2, 1],
+        };
+        assert_ne!(position.history, other.history);
+        assert_eq!(cache.get(&other), Some(0.5));
+        assert_eq!(cache.get(&Position { board: 4, ..other }), None);
+    }
+
+    // Every key hashes the same
+    #[derive(PartialEq, Eq)]
+    struct CollidingKey(u8);
+
+    impl Hash for CollidingKey {
+        fn hash<H: Hasher>(&self, _state: &mut H) {}
+    }
+
+    struct Colliding(u8);
+
+    impl StateKey for Colliding {
+        type Key = CollidingKey;
+
+        fn key(&self) -> CollidingKey {
+            CollidingKey(self.0)
+        }
+    }
+
+    #[test]
+    fn colliding_keys() {
+        let cache = StateCache::new(4);
+        cache.insert(&Colliding(1), 'a');
+        assert_eq!(cache.get(&Colliding(1)), Some('a'));
+        assert_eq!(cache.get(&Colliding(2)), None);
+        assert_eq!((cache.stats().hits, cache.stats().misses), (1, 1));
+
+        let mut map = KeyMap::new(Keyer::new());
+        *map.entry(&Colliding(1), || 0).1 += 1;
+        *map.entry(&Colliding(2), || 0).1 += 5;
+        *map.entry(&Colliding(1), || 0).1 += 1;
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&Colliding(1)), Some(&2));
+        assert_eq!(map.get(&Colliding(2)), Some(&5));
+        assert_eq!(map.get(&Colliding(3)), None);
+
+        // Loaded entries match by hash until a state claims them
+        let mut map = KeyMap::new(Keyer::new());
+        map.insert_hashed(key_hash(&Colliding(1)), 7);
+        assert_eq!(map.get(&Colliding(9)), Some(&7));
+        *map.entry(&Colliding(1), || 0).1 += 1;
+        assert_eq!(map.get(&Colliding(1)), Some(&8));
+        assert_eq!(map.get(&Colliding(9)), None);
+    }
+}
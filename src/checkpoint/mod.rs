@@ -0,0 +1,517 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::config::SearchConfig;
+use crate::node::{stat_from_f64, stat_to_f64, Node, NodeId, NodeRef, Stat};
+use crate::state::State;
+use crate::tree::Tree;
+
+const MAGIC: &[u8; 4] = b"RMCT";
+const FORMAT_VERSION: u8 = 3;
+
+// Compact binary encoding of actions, states are rebuilt by replaying them
+pub trait ActionBytes: Sized {
+    fn write_bytes(&self, out: &mut Vec<u8>);
+    fn read_bytes(bytes: &[u8]) -> Option<Self>;
+}
+
+macro_rules! impl_action_bytes {
+    ($($t:ty),*) => {
+        $(
+            impl ActionBytes for $t {
+                fn write_bytes(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn read_bytes(bytes: &[u8]) -> Option<Self> {
+                    Some(<$t>::from_le_bytes(bytes.try_into().ok()?))
+                }
+            }
+        )*
+    };
+}
+
+impl_action_bytes!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+pub(crate) fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+pub(crate) struct Writer {
+    pub(crate) bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub(crate) fn u8(&mut self, x: u8) {
+        self.bytes.push(x);
+    }
+
+    pub(crate) fn u16(&mut self, x: u16) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, x: u32) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, x: u64) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    pub(crate) fn f32(&mut self, x: f32) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    pub(crate) fn f64(&mut self, x: f64) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    pub(crate) fn action<T>(&mut self, action: &T) -> io::Result<()>
+    where
+        T: ActionBytes,
+    {
+        let mut bytes = vec![];
+        action.write_bytes(&mut bytes);
+        let len: u16 = bytes
+            .len()
+            .try_into()
+            .map_err(|_| invalid_data("encoded action is too long"))?;
+        self.u16(len);
+        self.bytes.extend_from_slice(&bytes);
+        Ok(())
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() < n {
+            return Err(invalid_data("unexpected end of checkpoint"));
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn f64(&mut self) -> io::Result<f64> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn action<T>(&mut self) -> io::Result<T>
+    where
+        T: ActionBytes,
+    {
+        let len = self.u16()? as usize;
+        T::read_bytes(self.take(len)?).ok_or_else(|| invalid_data("invalid action encoding"))
+    }
+}
+
+// Quantizes means into [low, high] with 16 bits
+#[derive(Clone, Copy)]
+pub(crate) struct Quantizer {
+    low: f32,
+    high: f32,
+}
+
+impl Quantizer {
+    fn encode(&self, mean: f32) -> u16 {
+        if self.high <= self.low {
+            return 0;
+        }
+        (((mean - self.low) / (self.high - self.low)).clamp(0., 1.) * u16::MAX as f32).round()
+            as u16
+    }
+
+    fn decode(&self, x: u16) -> f32 {
+        self.low + (self.high - self.low) * x as f32 / u16::MAX as f32
+    }
+}
+
+impl<T, S> Tree<T, S>
+where
+    S: State<T>,
+    T: Clone + ActionBytes,
+{
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.encode(0, None)?)
+    }
+
+    // Drops subtrees with fewer than `min_visits` visits and stores quantized means,
+    // the dropped actions are kept as pruned actions so they can be regenerated
+    pub fn save_compressed<P: AsRef<Path>>(&self, path: P, min_visits: u32) -> io::Result<()> {
+        let (low, high) = self.reward_bounds().unwrap_or((0., 0.));
+        fs::write(
+            path,
+            self.encode(min_visits, Some(Quantizer { low, high }))?,
+        )
+    }
+
+    fn encode(&self, min_visits: u32, quantizer: Option<Quantizer>) -> io::Result<Vec<u8>> {
+        encode_subtree(&self.root(), min_visits, quantizer)
+    }
+
+    pub(crate) fn decode(bytes: &[u8], config: SearchConfig, state: S) -> io::Result<Self> {
+        let mut input = Reader { bytes };
+        if input.take(4)? != MAGIC {
+            return Err(invalid_data("not a checkpoint"));
+        }
+        if input.u8()? != FORMAT_VERSION {
+            return Err(invalid_data("unsupported checkpoint version"));
+        }
+
+        let quantizer = match input.u8()? {
+            0 => None,
+            _ => Some(Quantizer {
+                low: input.f32()?,
+                high: input.f32()?,
+            }),
+        };
+
+        let mut tree: Option<Self> = None;
+        let mut parents: Vec<(NodeRef<T, S>, u32)> = vec![];
+
+        loop {
+            let action: T = input.action()?;
+            let node_state = match parents.last() {
+                Some((parent, _)) => {
+                    let mut node_state = parent.borrow().state.clone();
+                    node_state
+                        .try_do_action(&action)
+                        .map_err(|e| invalid_data(&e.to_string()))?;
+                    node_state
+                }
+                None => state.clone(),
+            };
+            let node = Node::new(action, node_state);
+
+            {
+                let mut node = node.borrow_mut();
+                node.visits = input.u32()?;
+                match quantizer {
+                    Some(x) => {
+                        node.mean = Stat::from(x.decode(input.u16()?));
+                        node.m2 = 0.;
+                    }
+                    None => {
+                        node.mean = stat_from_f64(input.f64()?);
+                        node.m2 = stat_from_f64(input.f64()?);
+                    }
+                }
+                node.expanded = input.u8()? != 0;
+
+                for _i in 0..input.u32()? {
+                    let action = input.action()?;
+                    node.pruned_actions.push(action);
+                }
+                node.pruned_at = node.visits;
+            }
+            let children = input.u32()?;
+
+            let node = match (&mut tree, parents.last_mut()) {
+                (Some(tree), Some((parent, remaining))) => {
+                    *remaining -= 1;
+                    tree.add_node(node, parent)
+                }
+                _ => {
+                    let (action, state) = {
+                        let node = node.borrow();
+                        (node.action.clone(), node.state.clone())
+                    };
+                    let mut root = Self::with_config(config, action, state);
+                    root.root = node;
+                    root.register_as(&root.root(), NodeId(0));
+                    root.reward_bounds = quantizer.map(|x| (x.low, x.high));
+                    let node = root.root();
+                    tree = Some(root);
+                    node
+                }
+            };
+
+            if children > 0 {
+                parents.push((node, children));
+            }
+            while let Some((_, 0)) = parents.last() {
+                parents.pop();
+            }
+            if parents.is_empty() {
+                break;
+            }
+        }
+
+        Ok(tree.unwrap())
+    }
+}
+
+impl<T, S> Tree<T, S>
+where
+    S: State<T>,
+    T: Clone + PartialEq + ActionBytes,
+{
+    pub fn load<P: AsRef<Path>>(path: P, config: SearchConfig, state: S) -> io::Result<Self> {
+        let tree = Self::decode(&fs::read(path)?, config, state)?;
+        let mut nodes = Self::descendants(&tree.root());
+        nodes.push(tree.root());
+        for node in nodes.iter() {
+            tree.resume_expansion(node);
+        }
+        Ok(tree)
+    }
+
+    // Expansion cursors and untried actions aren't saved. A partially
+    // expanded node gets them back from its state, less the actions it
+    // already has a child for or pruned
+    fn resume_expansion(&self, node: &NodeRef<T, S>) {
+        let mut node = node.borrow_mut();
+        if node.expanded || node.children.is_empty() {
+            return;
+        }
+        let mut left: Vec<T> = node
+            .children
+            .iter()
+            .map(|x| x.borrow().action.clone())
+            .collect();
+        left.extend(node.pruned_actions.iter().cloned());
+
+        if self.config.random_expansion {
+            let reduce = node.parent().is_some();
+            let mut actions = node.state.legal_actions();
+            actions.retain(|x| {
+                let commutes = reduce && node.state.commutes(&node.action, x);
+                !left.contains(x) && !commutes && self.expansion_rule.expand(&node.state, x)
+            });
+            node.expanded = actions.is_empty();
+            node.untried_actions = Some(actions);
+            return;
+        }
+
+        // The children were enumerated in order, the cursor goes on after the
+        // last of them
+        let mut cursor = node.state.clone();
+        while !left.is_empty() {
+            let action = match cursor.next_action() {
+                Some(x) => x,
+                None => break,
+            };
+            if cursor.try_do_action(&action).is_err() {
+                break;
+            }
+            left.retain(|x| *x != action);
+        }
+        if left.is_empty() && cursor.next_action().is_some() {
+            node.expansion_state = Some(cursor);
+        } else {
+            node.expanded = true;
+        }
+    }
+}
+
+pub(crate) fn encode_subtree<T, S>(
+    node: &NodeRef<T, S>,
+    min_visits: u32,
+    quantizer: Option<Quantizer>,
+) -> io::Result<Vec<u8>>
+where
+    S: State<T>,
+    T: Clone + ActionBytes,
+{
+    let mut out = Writer { bytes: vec![] };
+    out.bytes.extend_from_slice(MAGIC);
+    out.u8(FORMAT_VERSION);
+
+    match quantizer {
+        Some(x) => {
+            out.u8(1);
+            out.f32(x.low);
+            out.f32(x.high);
+        }
+        None => out.u8(0),
+    }
+
+    let mut stack = vec![Rc::clone(node)];
+    while let Some(node) = stack.pop() {
+        let node = node.borrow();
+        let (kept, dropped): (Vec<_>, Vec<_>) = node
+            .children
+            .iter()
+            .partition(|x| x.borrow().visits >= min_visits);
+
+        out.action(&node.action)?;
+        out.u32(node.visits);
+        match quantizer {
+            Some(x) if node.visits > 0 => out.u16(x.encode(node.mean_value())),
+            Some(_) => out.u16(0),
+            None => {
+                // Stored at full precision whatever the width of Stat
+                out.f64(stat_to_f64(node.mean));
+                out.f64(stat_to_f64(node.m2));
+            }
+        }
+        out.u8(node.expanded as u8);
+
+        out.u32((node.pruned_actions.len() + dropped.len()) as u32);
+        for action in node.pruned_actions.iter() {
+            out.action(action)?;
+        }
+        for child in dropped.iter() {
+            out.action(&child.borrow().action)?;
+        }
+
+        out.u32(kept.len() as u32);
+        stack.extend(kept.iter().rev().map(|x| Rc::clone(x)));
+    }
+
+    Ok(out.bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{tree_shape, MockState};
+
+    fn path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("rmcts-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn action_bytes() {
+        let mut bytes = vec![];
+        (-3i32).write_bytes(&mut bytes);
+        assert_eq!(bytes.len(), 4);
+        assert_eq!(i32::read_bytes(&bytes), Some(-3));
+        assert_eq!(u64::read_bytes(&bytes), None);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut tree = Tree::new(1.0, 0u8, MockState::countdown(4, 0.5));
+        tree.search(30);
+
+        let path = path("full");
+        tree.save(&path).unwrap();
+        let loaded = Tree::load(&path, *tree.config(), MockState::countdown(4, 0.5)).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(tree_shape(&loaded.root()), tree_shape(&tree.root()));
+        assert_eq!(loaded.size, tree.size);
+        assert_eq!(loaded.result().children, tree.result().children);
+
+        // States are rebuilt by replaying the actions
+        let leaf = loaded.principal_variation().pop().unwrap();
+        assert_eq!(
+            leaf.borrow().state,
+            tree.principal_variation().pop().unwrap().borrow().state
+        );
+    }
+
+    #[test]
+    fn save_compressed() {
+        let mut tree = Tree::new(1.0, 0u8, MockState::countdown(5, 0.5));
+        tree.search(60);
+
+        let full = path("uncompressed");
+        let compressed = path("compressed");
+        tree.save(&full).unwrap();
+        tree.save_compressed(&compressed, 5).unwrap();
+        assert!(fs::metadata(&compressed).unwrap().len() < fs::metadata(&full).unwrap().len());
+
+        let loaded = Tree::load(&compressed, *tree.config(), MockState::countdown(5, 0.5)).unwrap();
+        fs::remove_file(&full).unwrap();
+        fs::remove_file(&compressed).unwrap();
+
+        assert!(loaded.size < tree.size);
+        let root = loaded.root();
+        let kept = root.borrow().children.len();
+        assert_eq!(kept + root.borrow().pruned_actions.len(), 5);
+
+        // Quantized means stay close to the originals
+        let (low, high) = tree.reward_bounds().unwrap();
+        for (x, y) in loaded
+            .result()
+            .children
+            .iter()
+            .zip(tree.result().children.iter().filter(|x| x.visits >= 5))
+        {
+            assert_eq!(x.action, y.action);
+            assert!((x.mean - y.mean).abs() <= (high - low) / u16::MAX as f32);
+        }
+
+        // The search can carry on from the loaded tree
+        let mut loaded = loaded;
+        assert!(loaded.search(10).is_some());
+    }
+
+    #[test]
+    fn load_partial_expansion() {
+        let mut config = SearchConfig::new(1.0);
+        config.expand_batch_size = 1;
+        let mut tree = Tree::with_config(config, 0u8, MockState::countdown(5, 0.5));
+        tree.search(3);
+        let saved = tree.root().borrow().children.len();
+        assert!(saved > 0 && saved < 5);
+
+        let path = path("partial");
+        tree.save(&path).unwrap();
+        let mut loaded = Tree::load(&path, config, MockState::countdown(5, 0.5)).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(!loaded.root().borrow().expanded);
+
+        // The loaded root carries on with the actions it hadn't tried
+        loaded.search(40);
+        let mut actions: Vec<u8> = loaded
+            .root()
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().action)
+            .collect();
+        actions.sort_unstable();
+        assert_eq!(actions, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn load_invalid() {
+        let path = path("invalid");
+        fs::write(&path, b"nope").unwrap();
+        let loaded =
+            Tree::<u8, _>::load(&path, SearchConfig::default(), MockState::countdown(2, 0.));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,505 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use std::error::Error;
+#[cfg(feature = "serde")]
+use std::fmt;
+
+use crate::state::Outcome;
+
+// Game rewards are win rates in [0, 1], planning rewards are arbitrary returns
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum RewardMode {
+    Game,
+    Planning,
+}
+
+// Greedy always descends into the child with the best mean, for evaluation
+// and analysis passes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SelectionPolicy {
+    Uct,
+    Greedy,
+}
+
+// Node simulated after an expansion: the first new child, a random one, the
+// one with the best State::heuristic for the player choosing, or the
+// expanded leaf itself
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SimulationStart {
+    FirstChild,
+    RandomChild,
+    BestChild,
+    Leaf,
+}
+
+// Coherent starting points, the fields can still be adjusted afterwards
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Preset {
+    // UCB1 on win rates, the most visited move is played
+    ClassicUct,
+    // Arbitrary returns rescaled to the observed bounds, exploits more
+    SinglePlayerPuzzle,
+    // Win rates with the solver proving won and lost lines
+    TwoPlayerGame,
+    // Leaf values and move priors from an evaluator under PUCT selection, see
+    // Tree::with_evaluator and Tree::with_policy_priors
+    NeuralPuct,
+}
+
+impl Preset {
+    pub fn config(self) -> SearchConfig {
+        match self {
+            Preset::ClassicUct => SearchConfig {
+                mode: RewardMode::Game,
+                ..SearchConfig::new(1.)
+            },
+            Preset::SinglePlayerPuzzle => SearchConfig {
+                mode: RewardMode::Planning,
+                ..SearchConfig::new(0.5)
+            },
+            Preset::TwoPlayerGame => SearchConfig {
+                mode: RewardMode::Game,
+                solver: true,
+                ..SearchConfig::new(1.)
+            },
+            Preset::NeuralPuct => SearchConfig {
+                mode: RewardMode::Game,
+                prior_weight: 0.,
+                ..SearchConfig::new(1.)
+            },
+        }
+    }
+}
+
+// Exploration constant learning_rate * decay^depth, not going below min, for
+// children at the given depth below the root
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DepthExploration {
+    pub decay: f32,
+    pub min: f32,
+}
+
+impl DepthExploration {
+    pub fn new(decay: f32, min: f32) -> Self {
+        Self { decay, min }
+    }
+}
+
+// Exploration constant, in place of learning_rate, of the nodes where one
+// player moves. fpu, first play urgency, scores their unvisited children
+// instead of trying them first
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PlayerExploration {
+    pub exploration: f32,
+    pub fpu: Option<f32>,
+}
+
+impl PlayerExploration {
+    pub fn new(exploration: f32, fpu: Option<f32>) -> Self {
+        Self { exploration, fpu }
+    }
+}
+
+// Rollouts per simulation, 1 + scale * the standard deviation of the values
+// at the nearest ancestor with two visits or more, up to max
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AdaptiveRollouts {
+    pub scale: f32,
+    pub max: u32,
+}
+
+impl AdaptiveRollouts {
+    pub fn new(scale: f32, max: u32) -> Self {
+        Self { scale, max }
+    }
+}
+
+// Leaves at most `within` below the root are valued by an alpha-beta search
+// `depth` plies deep, scored with State::heuristic at its horizon
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ShallowSearch {
+    pub depth: u32,
+    pub within: u32,
+}
+
+impl ShallowSearch {
+    pub fn new(depth: u32, within: u32) -> Self {
+        Self { depth, within }
+    }
+}
+
+// At nodes with `min_visits` visits, selection only scores the `top`
+// children by mean and every `interval` visits one of the others, taken in
+// turn. 0 never reconsiders them
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LateMoveReduction {
+    pub min_visits: u32,
+    pub top: usize,
+    pub interval: u32,
+}
+
+impl LateMoveReduction {
+    pub fn new(min_visits: u32, top: usize, interval: u32) -> Self {
+        Self {
+            min_visits,
+            top,
+            interval,
+        }
+    }
+}
+
+// Constant added to the terminal rewards of one player, e.g. komi, in
+// Planning mode. Game values are outcomes in [0, 1], a State counts the
+// handicap in its outcomes itself
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Handicap {
+    pub player: usize,
+    pub offset: f32,
+}
+
+impl Handicap {
+    pub fn new(player: usize, offset: f32) -> Self {
+        Self { player, offset }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+// Fields left out of a serialized config keep their default
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct SearchConfig {
+    pub learning_rate: f32,
+    pub mode: RewardMode,
+    pub draw_value: f32,
+    pub handicap: Option<Handicap>,
+    pub value_window: usize,
+    pub expand_batch_size: usize,
+    pub unprune_visits: u32,
+    pub opponent_selection: bool,
+    pub root_min_visits: u32,
+    pub max_nodes: u32,
+    // At max_nodes the search stops expanding and simulates the leaves it
+    // has instead of pruning, see SearchResult::degraded
+    pub degrade_at_node_cap: bool,
+    pub prune_min_visits: u32,
+    pub seed: u64,
+    pub telemetry_interval: u32,
+    pub quantile_sketch_size: usize,
+    pub selection: SelectionPolicy,
+    pub root_value_interval: u32,
+    pub min_decision_visits: u32,
+    pub prior_weight: f32,
+    pub solver: bool,
+    // State transitions, tree and rollouts together, 0 for no limit
+    pub max_steps: u64,
+    // Total State::step_cost, 0 for no limit
+    pub max_cost: f64,
+    // The root quota is shared by simulation cost rather than by visits
+    pub root_quota_by_cost: bool,
+    // Opponents pick by a softmax over values with this inverse temperature,
+    // 0 assumes a perfect opponent
+    pub opponent_rationality: f32,
+    // Rollouts still running at the deadline of a timed search stop there and
+    // are bootstrapped with State::heuristic
+    pub truncate_at_deadline: bool,
+    // Root children whose upper confidence bound stays below the best lower
+    // bound for this many consecutive iterations are pruned, 0 to keep them
+    pub lcb_prune_checks: u32,
+    // None uses learning_rate at every depth
+    pub depth_exploration: Option<DepthExploration>,
+    // Expansion adds one random untried child per visit, drawn from the
+    // tree's seeded generator, instead of following next_action
+    pub random_expansion: bool,
+    // When advance_root promotes a child with fewer visits than
+    // sibling_transfer_below, its new children start with this fraction of
+    // the visits of the same actions below its siblings, 0 to start cold
+    pub sibling_transfer: f32,
+    pub sibling_transfer_below: u32,
+    // Factor on the visits of the subtree kept by advance_root, below 1 lets
+    // fresh search overturn stale conclusions sooner
+    pub reuse_decay: f32,
+    // The first search of a tree expands it breadth-first this many plies
+    // deep before any rollout, for cheap expansions and costly rollouts
+    pub warm_up_depth: u32,
+    // None runs a single rollout per simulation
+    pub adaptive_rollouts: Option<AdaptiveRollouts>,
+    // None rolls out every leaf
+    pub shallow_search: Option<ShallowSearch>,
+    // Rollouts stop after this many steps and are bootstrapped with
+    // State::heuristic, 0 for no limit
+    pub rollout_depth: u32,
+    // Steps a cutoff may be put off while the state is not quiet
+    pub quiescence_steps: u32,
+    // Times the phases of every iteration, see Tree::profile
+    pub profile_iterations: bool,
+    // The first search of a tree with an evaluator starts by evaluating the
+    // root and its children in one batch, see Tree::pre_evaluate_root
+    pub pre_evaluate_root: bool,
+    // Backups of the last iterations kept for debugging, see
+    // Tree::backup_paths
+    pub capture_paths: usize,
+    pub simulation_start: SimulationStart,
+    // None scores every child at every node
+    pub late_move_reduction: Option<LateMoveReduction>,
+}
+
+impl SearchConfig {
+    pub fn new(learning_rate: f32) -> Self {
+        Self {
+            learning_rate,
+            mode: RewardMode::Planning,
+            draw_value: 0.5,
+            handicap: None,
+            value_window: 100,
+            expand_batch_size: 0,
+            unprune_visits: 0,
+            opponent_selection: false,
+            root_min_visits: 0,
+            max_nodes: 0,
+            degrade_at_node_cap: false,
+            prune_min_visits: 0,
+            seed: 0,
+            telemetry_interval: 1,
+            quantile_sketch_size: 0,
+            selection: SelectionPolicy::Uct,
+            root_value_interval: 0,
+            min_decision_visits: 0,
+            prior_weight: 1.,
+            solver: false,
+            max_steps: 0,
+            max_cost: 0.,
+            root_quota_by_cost: false,
+            opponent_rationality: 0.,
+            truncate_at_deadline: false,
+            lcb_prune_checks: 0,
+            depth_exploration: None,
+            shallow_search: None,
+            rollout_depth: 0,
+            quiescence_steps: 0,
+            random_expansion: false,
+            sibling_transfer: 0.,
+            sibling_transfer_below: 0,
+            reuse_decay: 1.,
+            warm_up_depth: 0,
+            adaptive_rollouts: None,
+            profile_iterations: false,
+            pre_evaluate_root: false,
+            capture_paths: 0,
+            simulation_start: SimulationStart::FirstChild,
+            late_move_reduction: None,
+        }
+    }
+
+    pub fn outcome_value(&self, outcome: Outcome) -> f32 {
+        match outcome {
+            Outcome::Win => 1.,
+            Outcome::Draw => self.draw_value,
+            Outcome::Loss => 0.,
+        }
+    }
+
+    // Terminal rewards are from the point of view of `player`, so the
+    // opponent's handicap counts against it. None in Game mode
+    pub fn reward_offset(&self, player: usize) -> f32 {
+        match self.handicap {
+            _ if self.mode == RewardMode::Game => 0.,
+            Some(x) if x.player == player => x.offset,
+            Some(x) => -x.offset,
+            None => 0.,
+        }
+    }
+
+    // Exploration constant of the nodes at `depth`, the root children are at 1
+    pub fn exploration_at(&self, depth: u32) -> f32 {
+        match self.depth_exploration {
+            Some(x) => (self.learning_rate * x.decay.powi(depth as i32 - 1)).max(x.min),
+            None => self.learning_rate,
+        }
+    }
+
+    // Whether observers see this iteration, 0 disables telemetry
+    pub fn is_sampled(&self, iteration: u32) -> bool {
+        self.telemetry_interval > 0 && iteration.is_multiple_of(self.telemetry_interval)
+    }
+
+    pub fn game() -> Self {
+        Self {
+            mode: RewardMode::Game,
+            ..Self::default()
+        }
+    }
+
+    pub fn planning() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+pub enum ConfigError {
+    Toml(String),
+    Json(serde_json::Error),
+}
+
+#[cfg(feature = "serde")]
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Toml(e) => write!(f, "invalid TOML config: {}", e),
+            ConfigError::Json(e) => write!(f, "invalid JSON config: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Error for ConfigError {}
+
+#[cfg(feature = "serde")]
+impl SearchConfig {
+    pub fn from_toml(s: &str) -> Result<Self, ConfigError> {
+        toml::from_str(s).map_err(|e| ConfigError::Toml(e.to_string()))
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, ConfigError> {
+        serde_json::from_str(s).map_err(ConfigError::Json)
+    }
+
+    pub fn to_toml(&self) -> Result<String, ConfigError> {
+        toml::to_string(self).map_err(|e| ConfigError::Toml(e.to_string()))
+    }
+
+    pub fn to_json(&self) -> Result<String, ConfigError> {
+        serde_json::to_string(self).map_err(ConfigError::Json)
+    }
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self::new(2f32.sqrt())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outcome_value() {
+        let mut config = SearchConfig::game();
+        assert_eq!(config.outcome_value(Outcome::Win), 1.);
+        assert_eq!(config.outcome_value(Outcome::Draw), 0.5);
+        assert_eq!(config.outcome_value(Outcome::Loss), 0.);
+
+        // Contempt for draws against weaker opponents
+        config.draw_value = 0.45;
+        assert_eq!(config.outcome_value(Outcome::Draw), 0.45);
+    }
+
+    #[test]
+    fn reward_offset() {
+        let mut config = SearchConfig::default();
+        assert_eq!(config.reward_offset(0), 0.);
+
+        config.handicap = Some(Handicap::new(1, 6.5));
+        assert_eq!(config.reward_offset(1), 6.5);
+        assert_eq!(config.reward_offset(0), -6.5);
+
+        // Outcomes stay in [0, 1]
+        config.mode = RewardMode::Game;
+        assert_eq!(config.reward_offset(1), 0.);
+    }
+
+    #[test]
+    fn is_sampled() {
+        let mut config = SearchConfig::default();
+        assert!(config.is_sampled(0) && config.is_sampled(1));
+
+        config.telemetry_interval = 1000;
+        assert!(config.is_sampled(2000));
+        assert!(!config.is_sampled(2001));
+
+        config.telemetry_interval = 0;
+        assert!(!config.is_sampled(0));
+    }
+
+    #[test]
+    fn presets() {
+        assert_eq!(Preset::ClassicUct.config().learning_rate, 1.);
+        assert_eq!(
+            Preset::SinglePlayerPuzzle.config().mode,
+            RewardMode::Planning
+        );
+        assert!(Preset::TwoPlayerGame.config().solver);
+        assert!(!Preset::NeuralPuct.config().solver);
+        assert_eq!(Preset::NeuralPuct.config().prior_weight, 0.);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn from_toml() {
+        let config = SearchConfig::from_toml(
+            "learning_rate = 0.5\nmode = \"Game\"\nhandicap = { player = 1, offset = 6.5 }\n",
+        )
+        .unwrap();
+        assert_eq!(config.learning_rate, 0.5);
+        assert_eq!(config.mode, RewardMode::Game);
+        assert_eq!(config.handicap, Some(Handicap::new(1, 6.5)));
+        // The rest is the default config
+        assert_eq!(config.seed, SearchConfig::default().seed);
+
+        let config = Preset::TwoPlayerGame.config();
+        assert_eq!(
+            SearchConfig::from_toml(&config.to_toml().unwrap()).unwrap(),
+            config
+        );
+        assert!(SearchConfig::from_toml("learning_rate = \"fast\"").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn from_json() {
+        let config = SearchConfig::from_json(r#"{"solver": true, "max_steps": 1000}"#).unwrap();
+        assert!(config.solver);
+        assert_eq!(config.max_steps, 1000);
+
+        let config = Preset::NeuralPuct.config();
+        assert_eq!(
+            SearchConfig::from_json(&config.to_json().unwrap()).unwrap(),
+            config
+        );
+        assert!(SearchConfig::from_json("{").is_err());
+    }
+
+    #[test]
+    fn exploration_at() {
+        let mut config = SearchConfig::new(2.);
+        assert_eq!(config.exploration_at(5), 2.);
+
+        config.depth_exploration = Some(DepthExploration::new(0.5, 0.3));
+        assert_eq!(config.exploration_at(1), 2.);
+        assert_eq!(config.exploration_at(3), 0.5);
+        assert_eq!(config.exploration_at(10), 0.3);
+    }
+}
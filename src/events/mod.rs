@@ -0,0 +1,454 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io;
+use std::mem;
+use std::rc::Rc;
+
+use crate::checkpoint::{invalid_data, ActionBytes, Reader, Writer};
+use crate::node::{Node, NodeId, NodeRef};
+use crate::state::State;
+use crate::tree::Tree;
+
+const CREATED: u8 = 1;
+const UPDATED: u8 = 2;
+const ROOT_ADVANCED: u8 = 3;
+const REMOVED: u8 = 4;
+
+// Changes of the tree in the order they happen. A root advance drops the
+// nodes outside the new root's subtree, a removal the node's subtree
+#[derive(Clone, Debug, PartialEq)]
+pub enum TreeEvent<T> {
+    Created {
+        id: NodeId,
+        parent: Option<NodeId>,
+        action: T,
+    },
+    Updated {
+        id: NodeId,
+        visits: u32,
+        mean: f32,
+    },
+    RootAdvanced {
+        root: NodeId,
+    },
+    Removed {
+        id: NodeId,
+    },
+}
+
+impl<T> TreeEvent<T>
+where
+    T: ActionBytes,
+{
+    pub fn encode(&self, out: &mut Vec<u8>) -> io::Result<()> {
+        let mut writer = Writer {
+            bytes: mem::take(out),
+        };
+        let result = match self {
+            TreeEvent::Created { id, parent, action } => {
+                writer.u8(CREATED);
+                writer.u64(id.0);
+                writer.u64(parent.map_or(u64::MAX, |x| x.0));
+                writer.action(action)
+            }
+            TreeEvent::Updated { id, visits, mean } => {
+                writer.u8(UPDATED);
+                writer.u64(id.0);
+                writer.u32(*visits);
+                writer.f32(*mean);
+                Ok(())
+            }
+            TreeEvent::RootAdvanced { root } => {
+                writer.u8(ROOT_ADVANCED);
+                writer.u64(root.0);
+                Ok(())
+            }
+            TreeEvent::Removed { id } => {
+                writer.u8(REMOVED);
+                writer.u64(id.0);
+                Ok(())
+            }
+        };
+        *out = writer.bytes;
+        result
+    }
+
+    // Every event in `bytes`, which has to end on an event boundary
+    pub fn decode_all(bytes: &[u8]) -> io::Result<Vec<Self>> {
+        let mut input = Reader { bytes };
+        let mut events = vec![];
+        while !input.bytes.is_empty() {
+            let event = match input.u8()? {
+                CREATED => {
+                    let id = NodeId(input.u64()?);
+                    let parent = match input.u64()? {
+                        u64::MAX => None,
+                        x => Some(NodeId(x)),
+                    };
+                    TreeEvent::Created {
+                        id,
+                        parent,
+                        action: input.action()?,
+                    }
+                }
+                UPDATED => TreeEvent::Updated {
+                    id: NodeId(input.u64()?),
+                    visits: input.u32()?,
+                    mean: input.f32()?,
+                },
+                ROOT_ADVANCED => TreeEvent::RootAdvanced {
+                    root: NodeId(input.u64()?),
+                },
+                REMOVED => TreeEvent::Removed {
+                    id: NodeId(input.u64()?),
+                },
+                _ => return Err(invalid_data("unknown tree event")),
+            };
+            events.push(event);
+        }
+        Ok(events)
+    }
+}
+
+// Spilled subtrees are not reported, they come back under the same ids
+pub trait EventSink<T>: Debug {
+    fn record(&self, event: &TreeEvent<T>);
+}
+
+impl<T, E> EventSink<T> for Rc<E>
+where
+    E: EventSink<T> + ?Sized,
+{
+    fn record(&self, event: &TreeEvent<T>) {
+        (**self).record(event)
+    }
+}
+
+// Append-only binary log, drained by whoever ships it to the mirror
+#[derive(Debug, Default)]
+pub struct EventLog {
+    bytes: RefCell<Vec<u8>>,
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The bytes logged since the last call
+    pub fn take(&self) -> Vec<u8> {
+        mem::take(&mut self.bytes.borrow_mut())
+    }
+}
+
+impl<T> EventSink<T> for EventLog
+where
+    T: ActionBytes,
+{
+    fn record(&self, event: &TreeEvent<T>) {
+        // Only actions longer than u16::MAX bytes fail, their event is skipped
+        let mut bytes = self.bytes.borrow_mut();
+        let len = bytes.len();
+        if event.encode(&mut bytes).is_err() {
+            bytes.truncate(len);
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MirrorNode<T> {
+    pub action: T,
+    pub parent: Option<NodeId>,
+    pub children: Vec<NodeId>,
+    pub visits: u32,
+    pub mean: f32,
+}
+
+// Copy of a tree rebuilt from its events, e.g. in another process
+#[derive(Clone, Debug, PartialEq)]
+pub struct TreeMirror<T> {
+    pub root: Option<NodeId>,
+    pub nodes: HashMap<NodeId, MirrorNode<T>>,
+}
+
+impl<T> Default for TreeMirror<T> {
+    fn default() -> Self {
+        Self {
+            root: None,
+            nodes: HashMap::new(),
+        }
+    }
+}
+
+impl<T> TreeMirror<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn apply(&mut self, event: TreeEvent<T>) {
+        match event {
+            TreeEvent::Created { id, parent, action } => {
+                if let Some(x) = parent.and_then(|x| self.nodes.get_mut(&x)) {
+                    x.children.push(id);
+                }
+                if parent.is_none() && self.root.is_none() {
+                    self.root = Some(id);
+                }
+                self.nodes.insert(
+                    id,
+                    MirrorNode {
+                        action,
+                        parent,
+                        children: vec![],
+                        visits: 0,
+                        mean: 0.,
+                    },
+                );
+            }
+            TreeEvent::Updated { id, visits, mean } => {
+                if let Some(x) = self.nodes.get_mut(&id) {
+                    x.visits = visits;
+                    x.mean = mean;
+                }
+            }
+            TreeEvent::RootAdvanced { root } => {
+                let mut kept = HashMap::new();
+                let mut stack = vec![root];
+                while let Some(id) = stack.pop() {
+                    if let Some(x) = self.nodes.remove(&id) {
+                        stack.extend(x.children.iter().copied());
+                        kept.insert(id, x);
+                    }
+                }
+                if let Some(x) = kept.get_mut(&root) {
+                    x.parent = None;
+                }
+                self.nodes = kept;
+                self.root = Some(root);
+            }
+            TreeEvent::Removed { id } => {
+                let parent = self.nodes.get(&id).and_then(|x| x.parent);
+                if let Some(x) = parent.and_then(|x| self.nodes.get_mut(&x)) {
+                    x.children.retain(|x| *x != id);
+                }
+                let mut stack = vec![id];
+                while let Some(id) = stack.pop() {
+                    if let Some(x) = self.nodes.remove(&id) {
+                        stack.extend(x.children);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<T, S> Tree<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    // Sends every change of the tree to `sink`, starting with the nodes it
+    // already has
+    pub fn with_event_sink<E>(mut self, sink: E) -> Self
+    where
+        E: EventSink<T> + 'static,
+    {
+        let root = self.root();
+        let mut nodes = vec![Rc::clone(&root)];
+        nodes.extend(Self::descendants(&root));
+        for node in nodes.iter() {
+            sink.record(&created(node));
+            if node.borrow().visits > 0 {
+                sink.record(&updated(&node.borrow()));
+            }
+        }
+        self.event_sink = Some(Rc::new(sink));
+        self
+    }
+}
+
+pub(crate) fn updated<T, S>(node: &Node<T, S>) -> TreeEvent<T>
+where
+    S: State<T>,
+    T: Clone,
+{
+    TreeEvent::Updated {
+        id: node.id(),
+        visits: node.visits,
+        mean: node.mean_value(),
+    }
+}
+
+pub(crate) fn created<T, S>(node: &NodeRef<T, S>) -> TreeEvent<T>
+where
+    S: State<T>,
+    T: Clone,
+{
+    let node = node.borrow();
+    TreeEvent::Created {
+        id: node.id(),
+        parent: node.parent().map(|x| x.borrow().id()),
+        action: node.action.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SearchConfig;
+    use crate::knowledge::KnowledgeStore;
+    use crate::state::Outcome;
+    use crate::testing::{MockState, PathGame};
+
+    fn mirror(log: &EventLog, mirror: &mut TreeMirror<u8>) {
+        for event in TreeEvent::decode_all(&log.take()).unwrap() {
+            mirror.apply(event);
+        }
+    }
+
+    fn assert_mirrors<S>(tree: &Tree<u8, S>, mirror: &TreeMirror<u8>)
+    where
+        S: State<u8>,
+    {
+        let root = tree.root();
+        let mut nodes = vec![Rc::clone(&root)];
+        nodes.extend(Tree::descendants(&root));
+        assert_eq!(mirror.root, Some(root.borrow().id()));
+        assert_eq!(mirror.nodes.len(), nodes.len());
+        for node in nodes.iter() {
+            let node = node.borrow();
+            let x = &mirror.nodes[&node.id()];
+            assert_eq!((x.action, x.visits), (node.action, node.visits));
+            assert_eq!(x.mean, node.mean_value());
+            assert_eq!(x.children.len(), node.children.len());
+        }
+    }
+
+    #[test]
+    fn encode() {
+        let events = vec![
+            TreeEvent::Created {
+                id: NodeId(0),
+                parent: None,
+                action: 7u8,
+            },
+            TreeEvent::Created {
+                id: NodeId(1),
+                parent: Some(NodeId(0)),
+                action: 1,
+            },
+            TreeEvent::Updated {
+                id: NodeId(1),
+                visits: 3,
+                mean: 0.25,
+            },
+            TreeEvent::RootAdvanced { root: NodeId(1) },
+            TreeEvent::Removed { id: NodeId(1) },
+        ];
+        let mut bytes = vec![];
+        for x in events.iter() {
+            x.encode(&mut bytes).unwrap();
+        }
+        assert_eq!(TreeEvent::decode_all(&bytes).unwrap(), events);
+        assert!(TreeEvent::<u8>::decode_all(&bytes[..bytes.len() - 1]).is_err());
+        assert!(TreeEvent::<u8>::decode_all(&[9]).is_err());
+    }
+
+    #[test]
+    fn mirror_search() {
+        let log = Rc::new(EventLog::new());
+        let mut config = SearchConfig::new(1.0);
+        config.max_nodes = 6;
+        config.prune_min_visits = 1;
+        let mut tree = Tree::with_config(config, 0, MockState::countdown(4, 0.5))
+            .with_event_sink(Rc::clone(&log));
+        let mut copy = TreeMirror::new();
+        tree.search(40);
+        assert!(!log.is_empty());
+        mirror(&log, &mut copy);
+        assert_mirrors(&tree, &copy);
+
+        let action = tree.best_child().unwrap().borrow().action;
+        tree.advance_root(&action).unwrap();
+        tree.search(20);
+        mirror(&log, &mut copy);
+        assert_mirrors(&tree, &copy);
+    }
+
+    #[test]
+    fn existing_nodes() {
+        let mut tree = Tree::new(1.0, 0, MockState::countdown(3, 0.5));
+        tree.search(10);
+        let log = Rc::new(EventLog::new());
+        let tree = tree.with_event_sink(Rc::clone(&log));
+        let mut copy = TreeMirror::new();
+        mirror(&log, &mut copy);
+        assert_mirrors(&tree, &copy);
+    }
+
+    #[test]
+    fn mirror_stat_changes() {
+        let log = Rc::new(EventLog::new());
+        let mut tree =
+            Tree::new(1.0, 0, MockState::countdown(4, 0.5)).with_event_sink(Rc::clone(&log));
+        let mut copy = TreeMirror::new();
+        tree.search(30);
+        let child = tree.root().borrow().child_at(1).unwrap();
+        let grandchild = child.borrow().child_at(0).unwrap();
+        assert!(tree.invalidate(&grandchild));
+        mirror(&log, &mut copy);
+        assert_mirrors(&tree, &copy);
+
+        let game = PathGame::new(3, |x| match x[0] {
+            3 => Outcome::Win,
+            _ => Outcome::Loss,
+        });
+        let mut tree = Tree::new(1.0, 0, game.clone()).with_event_sink(Rc::clone(&log));
+        let mut copy = TreeMirror::new();
+        tree.search_parallel(40, 2);
+        mirror(&log, &mut copy);
+        assert_mirrors(&tree, &copy);
+
+        // Priors seed the new nodes
+        let mut tree =
+            Tree::new(1.0, 0, MockState::countdown(3, 0.5)).with_knowledge(KnowledgeStore::new(5));
+        tree.search(10);
+        tree.record_knowledge();
+        let store = tree.take_knowledge().unwrap();
+        let mut tree = Tree::new(1.0, 0, MockState::countdown(3, 0.5))
+            .with_knowledge(store)
+            .with_event_sink(Rc::clone(&log));
+        let mut copy = TreeMirror::new();
+        tree.search(3);
+        assert!(tree
+            .root()
+            .borrow()
+            .child_at(0)
+            .unwrap()
+            .borrow()
+            .seeded
+            .is_some());
+        mirror(&log, &mut copy);
+        assert_mirrors(&tree, &copy);
+    }
+}
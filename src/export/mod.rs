@@ -0,0 +1,186 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::fmt::{Debug, Write};
+use std::rc::Rc;
+
+use crate::node::NodeRef;
+use crate::state::State;
+use crate::tree::Tree;
+
+// Label of an action in exports, e.g. "Nf3" rather than its Debug output.
+// Closures taking the action work as formatters
+pub trait ActionFormatter<T> {
+    fn format(&self, action: &T) -> String;
+}
+
+impl<T, F> ActionFormatter<T> for F
+where
+    F: Fn(&T) -> String,
+{
+    fn format(&self, action: &T) -> String {
+        self(action)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DebugFormatter;
+
+impl<T> ActionFormatter<T> for DebugFormatter
+where
+    T: Debug,
+{
+    fn format(&self, action: &T) -> String {
+        format!("{:?}", action)
+    }
+}
+
+pub(crate) fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+impl<T, S> Tree<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    // Graphviz graph of the nodes up to `max_depth` below the root
+    pub fn to_dot<F>(&self, formatter: &F, max_depth: u32) -> String
+    where
+        F: ActionFormatter<T>,
+    {
+        let mut out = String::from("digraph {\n");
+        let mut stack: Vec<(NodeRef<T, S>, u32)> = vec![(self.root(), 0)];
+
+        while let Some((x, depth)) = stack.pop() {
+            let node = x.borrow();
+            let id = node.id().0;
+            let _ = writeln!(
+                out,
+                "  n{} [label=\"{}\\n{} visits, {:.3}\"];",
+                id,
+                escape(&formatter.format(&node.action)),
+                node.visits,
+                node.mean_value()
+            );
+            if depth == max_depth {
+                continue;
+            }
+            for child in node.children.iter() {
+                let _ = writeln!(out, "  n{} -> n{};", id, child.borrow().id().0);
+                stack.push((Rc::clone(child), depth + 1));
+            }
+        }
+
+        out.push_str("}\n");
+        out
+    }
+
+    // Nested {"action", "visits", "mean", "children"} objects up to `max_depth`
+    pub fn to_json<F>(&self, formatter: &F, max_depth: u32) -> String
+    where
+        F: ActionFormatter<T>,
+    {
+        let mut out = String::new();
+        Self::write_json(&mut out, &self.root(), formatter, max_depth);
+        out
+    }
+
+    fn write_json<F>(out: &mut String, node: &NodeRef<T, S>, formatter: &F, depth: u32)
+    where
+        F: ActionFormatter<T>,
+    {
+        let node = node.borrow();
+        let _ = write!(
+            out,
+            "{{\"action\":\"{}\",\"visits\":{},\"mean\":{},\"children\":[",
+            escape(&formatter.format(&node.action)),
+            node.visits,
+            node.mean_value()
+        );
+        if depth > 0 {
+            for (i, child) in node.children.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                Self::write_json(out, child, formatter, depth - 1);
+            }
+        }
+        out.push_str("]}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockState;
+
+    #[test]
+    fn to_dot() {
+        let mut tree = Tree::new(1.0, 0u8, MockState::countdown(3, 0.5));
+        tree.search(10);
+
+        let dot = tree.to_dot(&|x: &u8| format!("move \"{}\"", x), 1);
+        assert!(dot.starts_with("digraph {\n"));
+        assert!(dot.contains("label=\"move \\\"3\\\"\\n"));
+        assert_eq!(dot.matches(" -> ").count(), 3);
+
+        let dot = tree.to_dot(&DebugFormatter, 0);
+        assert_eq!(dot.matches("label=").count(), 1);
+    }
+
+    #[test]
+    fn to_json() {
+        let mut tree = Tree::new(1.0, 0u8, MockState::countdown(2, 0.5));
+        tree.search(1);
+
+        assert_eq!(
+            tree.to_json(&DebugFormatter, 1),
+            "{\"action\":\"0\",\"visits\":1,\"mean\":1,\"children\":[]}"
+        );
+
+        tree.search(3);
+        let json = tree.to_json(&|x: &u8| format!("m{}", x), 1);
+        assert!(json.contains("\"action\":\"m2\""));
+        assert_eq!(escape("a\"b\\\n"), "a\\\"b\\\\\\n");
+    }
+
+    #[test]
+    fn stable_exports() {
+        let export = |seed| {
+            let mut config = crate::config::SearchConfig::new(1.0);
+            config.random_expansion = true;
+            config.seed = seed;
+            let mut tree = Tree::with_config(config, 0u8, MockState::countdown(5, 0.5));
+            tree.search(200);
+            (
+                tree.to_json(&DebugFormatter, 3),
+                tree.to_dot(&DebugFormatter, 3),
+            )
+        };
+
+        // Golden files of exported trees can rely on it
+        assert_eq!(export(3), export(3));
+        assert_ne!(export(3).0, export(4).0);
+    }
+}
@@ -0,0 +1,91 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use crate::node::NodeRef;
+use crate::state::State;
+use crate::tree::Tree;
+
+// Broad then deep: the `broad` fraction of the budget explores every root
+// child with full expansion, the rest exploits the `keep` most visited ones.
+// The scales multiply the configured learning rate in each phase
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Funnel {
+    pub broad: f32,
+    pub keep: usize,
+    pub broad_scale: f32,
+    pub deep_scale: f32,
+}
+
+impl Funnel {
+    pub fn new(broad: f32, keep: usize) -> Self {
+        Self {
+            broad,
+            keep,
+            broad_scale: 2.,
+            deep_scale: 0.5,
+        }
+    }
+}
+
+impl<T, S> Tree<T, S>
+where
+    S: State<T>,
+    T: Clone + PartialEq,
+{
+    pub fn search_funnel(&mut self, iterations: u32, funnel: Funnel) -> Option<NodeRef<T, S>> {
+        let config = self.config;
+        let broad = (iterations as f32 * funnel.broad.clamp(0., 1.)).round() as u32;
+
+        self.config.learning_rate = config.learning_rate * funnel.broad_scale;
+        self.config.expand_batch_size = 0;
+        self.search(broad);
+
+        let mut children = self.root().borrow().children.clone();
+        children.sort_by_key(|x| std::cmp::Reverse(x.borrow().visits));
+        let excluded: Vec<T> = children
+            .iter()
+            .skip(funnel.keep.max(1))
+            .map(|x| x.borrow().action.clone())
+            .collect();
+
+        self.config = config;
+        self.config.learning_rate = config.learning_rate * funnel.deep_scale;
+        let best = self.search_excluding(iterations - broad, &excluded);
+        self.config = config;
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockState;
+
+    #[test]
+    fn search_funnel() {
+        let mut tree = Tree::new(1.0, 0u8, MockState::countdown(5, 0.5));
+        let best = tree.search_funnel(100, Funnel::new(0.3, 2)).unwrap();
+        assert_eq!(tree.config().learning_rate, 1.0);
+        assert_eq!(tree.root().borrow().children.len(), 5);
+
+        // Only the broad phase reached the three children that were cut
+        let mut visits: Vec<u32> = tree
+            .root()
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().visits)
+            .collect();
+        visits.sort_unstable_by(|x, y| y.cmp(x));
+        assert!(visits[2..].iter().sum::<u32>() <= 30);
+        assert!(best.borrow().visits >= visits[1]);
+    }
+}
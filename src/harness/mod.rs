@@ -0,0 +1,253 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use crate::config::SearchConfig;
+use crate::export::escape;
+use crate::state::{Outcome, State};
+use crate::tree::Tree;
+
+// Search settings under test, `id` names them in the records
+#[derive(Clone, Debug, PartialEq)]
+pub struct Contestant {
+    pub id: String,
+    pub config: SearchConfig,
+    pub iterations: u32,
+}
+
+impl Contestant {
+    pub fn new(id: &str, config: SearchConfig, iterations: u32) -> Self {
+        Self {
+            id: id.to_string(),
+            config,
+            iterations,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameRecord {
+    pub seed: u64,
+    // Ids of the contestants playing player 0 and the others
+    pub first: String,
+    pub second: String,
+    // Final State::outcome, for player 0
+    pub result: Option<Outcome>,
+    pub reward: f32,
+    pub moves: u32,
+    pub time: Duration,
+}
+
+// One game from `state`, each move searched by a fresh tree seeded from
+// `seed` and the move number. Stops after `max_moves`, 0 for no limit
+pub fn play_game<T, S>(
+    first: &Contestant,
+    second: &Contestant,
+    action: T,
+    mut state: S,
+    seed: u64,
+    max_moves: u32,
+) -> GameRecord
+where
+    S: State<T>,
+    T: Clone,
+{
+    let start = Instant::now();
+    let mut reward = 0.;
+    let mut moves = 0;
+
+    while state.outcome().is_none() && (max_moves == 0 || moves < max_moves) {
+        let contestant = match state.current_player() {
+            0 => first,
+            _ => second,
+        };
+        let config = SearchConfig {
+            seed: seed.wrapping_add(u64::from(moves)),
+            ..contestant.config
+        };
+        let mut tree = Tree::with_config(config, action.clone(), state.clone());
+        // Too few iterations to expand the root play the first action
+        let best = match tree.search(contestant.iterations) {
+            Some(x) => x.borrow().action.clone(),
+            None => match state.next_action() {
+                Some(x) => x,
+                None => break,
+            },
+        };
+        match state.try_do_action(&best) {
+            Ok(x) => reward += x,
+            Err(_) => break,
+        }
+        moves += 1;
+    }
+
+    GameRecord {
+        seed,
+        first: first.id.clone(),
+        second: second.id.clone(),
+        result: state.outcome(),
+        reward,
+        moves,
+        time: start.elapsed(),
+    }
+}
+
+// Two games per seed, the contestants swapping sides for the second
+pub fn play_games<T, S>(
+    a: &Contestant,
+    b: &Contestant,
+    action: T,
+    state: S,
+    seeds: &[u64],
+    max_moves: u32,
+) -> Vec<GameRecord>
+where
+    S: State<T>,
+    T: Clone,
+{
+    let mut records = vec![];
+    for seed in seeds.iter().copied() {
+        for (first, second) in [(a, b), (b, a)] {
+            records.push(play_game(
+                first,
+                second,
+                action.clone(),
+                state.clone(),
+                seed,
+                max_moves,
+            ));
+        }
+    }
+    records
+}
+
+fn result_name(result: Option<Outcome>) -> &'static str {
+    match result {
+        Some(Outcome::Win) => "win",
+        Some(Outcome::Draw) => "draw",
+        Some(Outcome::Loss) => "loss",
+        None => "",
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+// One row per game under a header, the time in seconds
+pub fn to_csv(records: &[GameRecord]) -> String {
+    let mut out = String::from("seed,first,second,result,reward,moves,time\n");
+    for x in records {
+        let _ = writeln!(
+            out,
+            "{},{},{},{},{},{},{}",
+            x.seed,
+            csv_field(&x.first),
+            csv_field(&x.second),
+            result_name(x.result),
+            x.reward,
+            x.moves,
+            x.time.as_secs_f64()
+        );
+    }
+    out
+}
+
+// Array of objects with the CSV columns as keys, no result is null
+pub fn to_json(records: &[GameRecord]) -> String {
+    let mut out = String::from("[");
+    for (i, x) in records.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let result = match x.result {
+            Some(_) => format!("\"{}\"", result_name(x.result)),
+            None => String::from("null"),
+        };
+        let _ = write!(
+            out,
+            "{{\"seed\":{},\"first\":\"{}\",\"second\":\"{}\",\"result\":{},\"reward\":{},\"moves\":{},\"time\":{}}}",
+            x.seed,
+            escape(&x.first),
+            escape(&x.second),
+            result,
+            x.reward,
+            x.moves,
+            x.time.as_secs_f64()
+        );
+    }
+    out.push(']');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{MockState, PathGame};
+
+    #[test]
+    fn matches() {
+        let game = PathGame::new(3, |x| match x[0] {
+            3 => Outcome::Win,
+            _ => Outcome::Loss,
+        });
+        let strong = Contestant::new("strong", SearchConfig::game(), 100);
+        let weak = Contestant::new("weak", SearchConfig::game(), 1);
+        let records = play_games(&strong, &weak, 0, game, &[1, 2], 0);
+
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0].first, "strong");
+        assert_eq!(records[1].first, "weak");
+        assert_eq!(records[3].seed, 2);
+        assert!(records.iter().all(|x| x.moves == 3));
+        assert_eq!(records[0].result, Some(Outcome::Win));
+
+        let record = play_game(&strong, &weak, 0, MockState::countdown(4, 0.5), 0, 2);
+        assert_eq!((record.moves, record.result, record.reward), (2, None, 1.));
+    }
+
+    #[test]
+    fn formats() {
+        let record = GameRecord {
+            seed: 7,
+            first: String::from("a,b"),
+            second: String::from("c\"d"),
+            result: Some(Outcome::Draw),
+            reward: 0.5,
+            moves: 12,
+            time: Duration::from_millis(1500),
+        };
+        let csv = to_csv(std::slice::from_ref(&record));
+        assert_eq!(
+            csv,
+            "seed,first,second,result,reward,moves,time\n7,\"a,b\",\"c\"\"d\",draw,0.5,12,1.5\n"
+        );
+
+        let json = to_json(&[
+            record.clone(),
+            GameRecord {
+                result: None,
+                ..record
+            },
+        ]);
+        assert!(json.starts_with("[{\"seed\":7,\"first\":\"a,b\",\"second\":\"c\\\"d\","));
+        assert!(json.contains("\"result\":\"draw\""));
+        assert!(json.contains("\"result\":null"));
+        assert!(json.ends_with("\"time\":1.5}]"));
+        assert_eq!(to_json(&[]), "[]");
+    }
+}
@@ -0,0 +1,114 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use crate::node::NodeId;
+use crate::result::ChildStats;
+use crate::state::State;
+use crate::tree::Tree;
+
+// Read-only access by node id for debuggers and monitors, independent of how
+// the nodes are stored. Ids that left the tree give None
+pub trait TreeInspector<T> {
+    fn root_id(&self) -> NodeId;
+
+    fn parent_of(&self, id: NodeId) -> Option<NodeId>;
+
+    fn children_of(&self, id: NodeId) -> Option<Vec<NodeId>>;
+
+    fn stats_of(&self, id: NodeId) -> Option<ChildStats<T>>;
+
+    // Best root child, then the most visited child at every level
+    fn principal_line(&self) -> Vec<NodeId>;
+
+    fn iterations(&self) -> u32;
+
+    fn size(&self) -> u32;
+}
+
+impl<T, S> TreeInspector<T> for Tree<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn root_id(&self) -> NodeId {
+        self.root().borrow().id()
+    }
+
+    fn parent_of(&self, id: NodeId) -> Option<NodeId> {
+        self.with_node(id, |x| x.parent())?.map(|x| x.borrow().id())
+    }
+
+    fn children_of(&self, id: NodeId) -> Option<Vec<NodeId>> {
+        self.child_ids(id)
+    }
+
+    fn stats_of(&self, id: NodeId) -> Option<ChildStats<T>> {
+        self.node_stats(id)
+    }
+
+    fn principal_line(&self) -> Vec<NodeId> {
+        self.principal_variation()
+            .iter()
+            .map(|x| x.borrow().id())
+            .collect()
+    }
+
+    fn iterations(&self) -> u32 {
+        self.iterations
+    }
+
+    fn size(&self) -> u32 {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockState;
+
+    // What a debugger needs, written against the trait only
+    fn walk<T, I>(inspector: &I) -> u32
+    where
+        I: TreeInspector<T>,
+    {
+        let mut stack = vec![inspector.root_id()];
+        let mut nodes = 0;
+        while let Some(id) = stack.pop() {
+            nodes += 1;
+            for child in inspector.children_of(id).unwrap() {
+                assert_eq!(inspector.parent_of(child), Some(id));
+                assert!(inspector.stats_of(child).is_some());
+                stack.push(child);
+            }
+        }
+        nodes
+    }
+
+    #[test]
+    fn inspect_tree() {
+        let mut tree = Tree::new(1.0, 0u8, MockState::countdown(4, 0.5));
+        tree.search(30);
+
+        assert_eq!(walk(&tree), TreeInspector::size(&tree));
+        assert_eq!(TreeInspector::iterations(&tree), 30);
+        assert_eq!(tree.parent_of(tree.root_id()), None);
+
+        let line = tree.principal_line();
+        assert_eq!(line.len(), tree.principal_variation().len());
+        let best = tree.stats_of(line[0]).unwrap();
+        assert_eq!(
+            Some(best.action),
+            tree.best_child().map(|x| x.borrow().action)
+        );
+        assert!(tree.children_of(NodeId(1000)).is_none());
+    }
+}
@@ -0,0 +1,333 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::convert::TryInto;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::cache::{KeyMap, Keyer};
+use crate::node::{stat_from_f64, stat_to_f64, NodeRef};
+use crate::state::{State, StateKey};
+use crate::tree::Tree;
+
+const MAGIC: &[u8; 4] = b"RMKS";
+// 2 keys the records on the StableHasher hash of the state key, 1 used the
+// release dependent DefaultHasher and is not read
+const FORMAT_VERSION: u8 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Experience {
+    pub visits: u32,
+    pub total_reward: f64,
+}
+
+impl Experience {
+    pub fn mean(&self) -> f64 {
+        if self.visits == 0 {
+            return 0.;
+        }
+        self.total_reward / self.visits as f64
+    }
+}
+
+// Statistics aggregated over earlier searches, used as priors for new nodes
+#[derive(Clone)]
+pub struct KnowledgeStore<S> {
+    // Priors count as at most this many visits
+    pub max_prior_visits: u32,
+    entries: KeyMap<S, Experience>,
+}
+
+impl<S> KnowledgeStore<S>
+where
+    S: StateKey,
+{
+    pub fn new(max_prior_visits: u32) -> Self {
+        Self {
+            max_prior_visits,
+            entries: KeyMap::new(Keyer::new()),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, max_prior_visits: u32) -> io::Result<Self> {
+        let mut store = Self::new(max_prior_visits);
+        let bytes = fs::read(path)?;
+        if bytes.len() < 5 || &bytes[..4] != MAGIC || bytes[4] != FORMAT_VERSION {
+            return Err(invalid_data("not a knowledge store"));
+        }
+
+        let records = bytes[5..].chunks(20);
+        for record in records {
+            if record.len() != 20 {
+                return Err(invalid_data("unexpected end of knowledge store"));
+            }
+            let key = u64::from_le_bytes(record[..8].try_into().unwrap());
+            store.entries.insert_hashed(
+                key,
+                Experience {
+                    visits: u32::from_le_bytes(record[8..12].try_into().unwrap()),
+                    total_reward: f64::from_le_bytes(record[12..].try_into().unwrap()),
+                },
+            );
+        }
+
+        Ok(store)
+    }
+}
+
+impl<S> KnowledgeStore<S> {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.len() == 0
+    }
+
+    pub fn get(&self, state: &S) -> Option<Experience> {
+        self.entries.get(state).copied()
+    }
+
+    pub fn record(&mut self, state: &S, visits: u32, total_reward: f64) {
+        let (_, entry) = self.entries.entry(state, || Experience {
+            visits: 0,
+            total_reward: 0.,
+        });
+        entry.visits = entry.visits.saturating_add(visits);
+        entry.total_reward += total_reward;
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(FORMAT_VERSION);
+        // By key, equal stores give equal files
+        let mut entries: Vec<(u64, &Experience)> = self.entries.iter().collect();
+        entries.sort_by_key(|x| x.0);
+        for (key, x) in entries {
+            bytes.extend_from_slice(&key.to_le_bytes());
+            bytes.extend_from_slice(&x.visits.to_le_bytes());
+            bytes.extend_from_slice(&x.total_reward.to_le_bytes());
+        }
+        fs::write(path, bytes)
+    }
+}
+
+impl<S> fmt::Debug for KnowledgeStore<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KnowledgeStore")
+            .field("max_prior_visits", &self.max_prior_visits)
+            .field("entries", &self.entries.len())
+            .finish()
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl<T, S> Tree<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    pub fn with_knowledge(mut self, store: KnowledgeStore<S>) -> Self {
+        self.knowledge = Some(store);
+        self
+    }
+
+    pub fn knowledge(&self) -> Option<&KnowledgeStore<S>> {
+        self.knowledge.as_ref()
+    }
+
+    pub fn take_knowledge(&mut self) -> Option<KnowledgeStore<S>> {
+        self.knowledge.take()
+    }
+
+    // Folds the statistics of every visited node into the store
+    pub fn record_knowledge(&mut self) {
+        let store = match &mut self.knowledge {
+            Some(x) => x,
+            None => return,
+        };
+
+        let mut stack = vec![Rc::clone(&self.root)];
+        while let Some(x) = stack.pop() {
+            let node = x.borrow();
+            let (visits, total) = match node.seeded {
+                Some((visits, mean)) => {
+                    let seeded = visits.min(node.visits);
+                    (
+                        node.visits - seeded,
+                        stat_to_f64(node.total_reward()) - seeded as f64 * stat_to_f64(mean),
+                    )
+                }
+                None => (node.visits, stat_to_f64(node.total_reward())),
+            };
+            if visits > 0 {
+                store.record(&node.state, visits, total);
+            }
+            stack.extend(node.children.iter().map(Rc::clone));
+        }
+    }
+
+    pub(crate) fn apply_prior(&self, node: &NodeRef<T, S>) {
+        let store = match &self.knowledge {
+            Some(x) => x,
+            None => return,
+        };
+        let experience = match store.get(&node.borrow().state) {
+            Some(x) if x.visits > 0 => x,
+            _ => return,
+        };
+
+        let mut node = node.borrow_mut();
+        let visits = experience.visits.min(store.max_prior_visits);
+        let mean = experience.mean();
+        node.visits = visits;
+        node.mean = stat_from_f64(mean);
+        node.m2 = 0.;
+        node.seeded = Some((visits, node.mean));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::key_hash;
+    use crate::state::ActionError;
+    use std::collections::HashMap;
+
+    #[derive(Debug, Clone)]
+    struct CountdownState {
+        moves: u8,
+    }
+
+    impl StateKey for CountdownState {
+        type Key = u8;
+
+        fn key(&self) -> u8 {
+            self.moves
+        }
+    }
+
+    impl State<u8> for CountdownState {
+        fn next_action(&self) -> Option<u8> {
+            if self.moves == 0 {
+                return None;
+            }
+            Some(self.moves)
+        }
+
+        fn try_do_action(&mut self, _action: &u8) -> Result<f32, ActionError> {
+            self.moves -= 1;
+            Ok(0.5)
+        }
+    }
+
+    #[test]
+    fn record() {
+        let mut store = KnowledgeStore::new(10);
+        assert!(store.get(&1u8).is_none());
+
+        store.record(&1u8, 2, 1.);
+        store.record(&1u8, 2, 2.);
+        let experience = store.get(&1u8).unwrap();
+        assert_eq!(experience.visits, 4);
+        assert_eq!(experience.mean(), 0.75);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("rmcts-{}-knowledge", std::process::id()));
+        let mut store = KnowledgeStore::new(10);
+        store.record(&1u8, 3, 1.5);
+        store.record(&2u8, 1, 0.25);
+        store.save(&path).unwrap();
+
+        let loaded = KnowledgeStore::<u8>::load(&path, 10).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.get(&1u8), store.get(&1u8));
+
+        let mut other = KnowledgeStore::new(10);
+        for x in (0..50u8).rev() {
+            other.record(&x, 1, 0.);
+        }
+        other.save(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let mut store = KnowledgeStore::new(10);
+        for x in 0..50u8 {
+            store.record(&x, 1, 0.);
+        }
+        store.save(&path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn prior() {
+        let state = CountdownState { moves: 3 };
+        let mut tree = Tree::new(1.0, 0, state.clone()).with_knowledge(KnowledgeStore::new(5));
+        tree.search(10);
+        tree.record_knowledge();
+        let store = tree.take_knowledge().unwrap();
+        assert!(store.get(&state).unwrap().visits >= 10);
+
+        // A fresh tree starts its expanded children from the stored values
+        let mut tree = Tree::new(1.0, 0, state).with_knowledge(store);
+        tree.search(2);
+        let child = tree.root().borrow().child_at(0).unwrap();
+        assert!(child.borrow().visits > 5);
+        assert!(child.borrow().visits <= 6);
+    }
+
+    #[test]
+    fn record_without_priors() {
+        let state = CountdownState { moves: 3 };
+        let mut tree = Tree::new(1.0, 0, state.clone()).with_knowledge(KnowledgeStore::new(5));
+        tree.search(10);
+        tree.record_knowledge();
+        let first = tree.take_knowledge().unwrap();
+
+        // The second game only adds the visits it ran itself
+        let mut tree = Tree::new(1.0, 0, state.clone()).with_knowledge(first.clone());
+        tree.search(10);
+        tree.record_knowledge();
+        let mut expected: HashMap<u8, u32> = HashMap::new();
+        let root = tree.root();
+        let mut nodes = vec![Rc::clone(&root)];
+        nodes.extend(Tree::descendants(&root));
+        for node in nodes.iter() {
+            let node = node.borrow();
+            let seeded = node.seeded.map_or(0, |x| x.0);
+            *expected.entry(node.state.moves).or_insert(0) += node.visits - seeded;
+        }
+        assert!(root.borrow().child_at(0).unwrap().borrow().seeded.is_some());
+
+        let second = tree.take_knowledge().unwrap();
+        assert_eq!(second.get(&state).unwrap().visits, 20);
+        for moves in 0..=3 {
+            let state = CountdownState { moves };
+            let before = first.get(&state).map_or(0, |x| x.visits);
+            let after = second.get(&state).map_or(0, |x| x.visits);
+            assert_eq!(after - before, expected.get(&moves).copied().unwrap_or(0));
+        }
+    }
+
+    #[test]
+    fn stable_keys() {
+        // Fixed by the file format, whatever the platform or release
+        assert_eq!(key_hash(&1u8), 0xaf63_bc4c_8601_b62c);
+        assert_eq!(key_hash(&1u32), 0xad2a_ca77_4798_5764);
+    }
+}
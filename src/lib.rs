@@ -9,7 +9,42 @@
     unused_qualifications
 )]
 
+pub mod analysis;
+pub mod arena;
+pub mod beam;
+pub mod budget;
+pub mod cache;
+pub mod checkpoint;
+pub mod config;
+pub mod events;
+pub mod export;
+pub mod funnel;
+pub mod harness;
+pub mod inspect;
+pub mod knowledge;
 pub mod node;
+pub mod novelty;
+pub mod observer;
+pub mod parallel;
+#[cfg(feature = "pipeline")]
+pub mod pipeline;
+pub mod profile;
+#[cfg(feature = "protocol")]
+pub mod protocol;
+pub mod result;
+pub mod roots;
+pub mod schedule;
+pub mod selfplay;
+pub mod session;
+pub mod sketch;
+pub mod snapshot;
+pub mod spill;
 pub mod state;
 pub mod strategies;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
 pub mod tree;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(feature = "validate")]
+pub mod validate;
@@ -9,13 +9,75 @@
     unused_qualifications
 )]
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 use std::rc::Weak;
 
-use crate::state::State;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::sketch::QuantileSketch;
+use crate::state::{Outcome, OutcomeCounts, State};
 
 pub type NodeRef<T, S> = Rc<RefCell<Node<T, S>>>;
+pub type WeakNodeRef<T, S> = Weak<RefCell<Node<T, S>>>;
+
+// Accumulated reward statistics, f64 avoids flattening values on long searches
+#[cfg(not(feature = "f64-stats"))]
+pub type Stat = f32;
+#[cfg(feature = "f64-stats")]
+pub type Stat = f64;
+
+#[allow(trivial_numeric_casts, clippy::unnecessary_cast)]
+pub fn stat_to_f32(x: Stat) -> f32 {
+    x as f32
+}
+
+#[allow(clippy::useless_conversion)]
+pub fn stat_to_f64(x: Stat) -> f64 {
+    f64::from(x)
+}
+
+#[allow(trivial_numeric_casts, clippy::unnecessary_cast)]
+pub fn stat_from_f64(x: f64) -> Stat {
+    x as Stat
+}
+
+// Visits, mean and sum of squared deviations of two sets of values together
+pub(crate) fn combine_stats(a: (u32, Stat, Stat), b: (u32, Stat, Stat)) -> (u32, Stat, Stat) {
+    let total = a.0 + b.0;
+    if total == 0 {
+        return a;
+    }
+
+    let delta = b.1 - a.1;
+    let weight = a.0 as Stat * b.0 as Stat / total as Stat;
+    (
+        total,
+        a.1 + delta * b.0 as Stat / total as Stat,
+        a.2 + b.2 + delta * delta * weight,
+    )
+}
+
+// Handle to a node that does not keep it alive, stable across searches
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NodeId(pub(crate) u64);
+
+impl NodeId {
+    // Nodes built outside the tree, the ids of the tree count up from 0
+    pub(crate) const SCRATCH: NodeId = NodeId(u64::MAX);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SubtreeStats {
+    // Summed over every node of the subtree
+    pub visits: u32,
+    pub nodes: u32,
+    pub depth: u32,
+    // Best mean of a visited terminal node
+    pub best_terminal: Option<f32>,
+}
 
 #[derive(Clone, Debug)]
 pub struct Node<T, S>
@@ -26,10 +88,42 @@ where
     pub action: T,
     pub state: S,
     pub visits: u32,
-    pub total_reward: f32,
+    // Part of the visits that are virtual losses of pipelined leaves in flight
+    pub virtual_visits: u32,
+    // Online mean of the backed-up values and sum of their squared deviations
+    pub mean: Stat,
+    pub m2: Stat,
+    // Probability of the action under an external policy
+    pub prior: Option<f32>,
+    // Visits and mean seeded from a KnowledgeStore, left out when the node is
+    // recorded back into it
+    pub seeded: Option<(u32, Stat)>,
+    // Solved outcome for the root player
+    pub proven: Option<Outcome>,
+    pub outcomes: OutcomeCounts,
+    // Simulation cost of the iterations that went through the node
+    pub cost: f64,
+    // Mean payoff of every player, kept for games of more than two players
+    pub payoffs: Vec<Stat>,
+    pub(crate) payoff_visits: u32,
+    subtree_stats: Cell<Option<SubtreeStats>>,
     pub expanded: bool,
+    pub expansion_state: Option<S>,
+    // In the order State::next_action enumerates the actions, or drawn from
+    // the tree seed with random expansion. Children brought back after
+    // pruning go last. Same state and seed, same order
     pub children: Vec<NodeRef<T, S>>,
-    parent: Option<Weak<RefCell<Node<T, S>>>>,
+    pub pruned_actions: Vec<T>,
+    // Actions left for random expansion, which adds them one at a time
+    pub untried_actions: Option<Vec<T>>,
+    // Evaluator policy of the state, the children take their priors from it
+    pub policy: Option<Vec<(T, f32)>>,
+    pub pruned_at: u32,
+    pub sketch: Option<QuantileSketch>,
+    // No longer legal, skipped by selection and final move choice
+    pub illegal: bool,
+    id: NodeId,
+    parent: Option<WeakNodeRef<T, S>>,
 }
 
 impl<T, S> Node<T, S>
@@ -42,13 +136,39 @@ where
             action,
             state,
             visits: 0,
-            total_reward: 0.,
+            virtual_visits: 0,
+            mean: 0.,
+            m2: 0.,
+            prior: None,
+            seeded: None,
+            proven: None,
+            outcomes: OutcomeCounts::default(),
+            cost: 0.,
+            payoffs: vec![],
+            payoff_visits: 0,
+            subtree_stats: Cell::new(None),
             expanded: false,
+            expansion_state: None,
             children: vec![],
+            pruned_actions: vec![],
+            untried_actions: None,
+            policy: None,
+            pruned_at: 0,
+            sketch: None,
+            illegal: false,
+            id: NodeId(0),
             parent: None,
         }))
     }
 
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    pub(crate) fn set_id(&mut self, id: NodeId) {
+        self.id = id;
+    }
+
     pub fn parent(&self) -> Option<NodeRef<T, S>> {
         if let Some(parent) = self.parent.clone() {
             parent.upgrade()
@@ -61,6 +181,10 @@ where
         self.parent = Some(Rc::downgrade(node));
     }
 
+    pub(crate) fn clear_parent(&mut self) {
+        self.parent = None;
+    }
+
     pub fn child_at(&self, index: usize) -> Option<NodeRef<T, S>> {
         if self.children.len() > index {
             Some(Rc::clone(&self.children[index]))
@@ -69,27 +193,259 @@ where
         }
     }
 
+    // Some actions are still waiting to be expanded
+    pub fn is_partially_expanded(&self) -> bool {
+        self.expansion_state.is_some()
+            || self.untried_actions.as_ref().is_some_and(|x| !x.is_empty())
+    }
+
     pub fn best_child(&self) -> Option<NodeRef<T, S>> {
-        match self.children.iter().max_by(|x, y| {
-            x.borrow()
-                .total_reward
-                .partial_cmp(&y.borrow().total_reward)
-                .unwrap_or(std::cmp::Ordering::Less)
-        }) {
+        match self
+            .children
+            .iter()
+            .filter(|x| !x.borrow().illegal)
+            .max_by(|x, y| {
+                x.borrow()
+                    .total_reward()
+                    .partial_cmp(&y.borrow().total_reward())
+                    .unwrap_or(std::cmp::Ordering::Less)
+            }) {
             Some(x) => Some(Rc::clone(x)),
             None => None,
         }
     }
 
+    pub fn most_visited_child(&self) -> Option<NodeRef<T, S>> {
+        self.children
+            .iter()
+            .filter(|x| !x.borrow().illegal)
+            .max_by_key(|x| x.borrow().visits)
+            .map(Rc::clone)
+    }
+
     pub fn add_child(&mut self, node: NodeRef<T, S>) -> NodeRef<T, S> {
+        self.invalidate_subtree_stats();
         self.children.push(node);
         Rc::clone(&self.children[self.children.len() - 1])
     }
 
+    pub fn value_variance(&self) -> f32 {
+        if self.visits == 0 {
+            return 0.;
+        }
+        stat_to_f32(self.m2 / self.visits as Stat)
+    }
+
+    pub fn mean_value(&self) -> f32 {
+        stat_to_f32(self.mean)
+    }
+
+    pub fn total_reward(&self) -> Stat {
+        self.mean * self.visits as Stat
+    }
+
+    // Welford's update, one visit with the given value
+    pub fn push_value(&mut self, value: f32) {
+        self.invalidate_subtree_stats();
+        self.visits += 1;
+        let value = Stat::from(value);
+        let delta = value - self.mean;
+        self.mean += delta / self.visits as Stat;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn push_payoffs(&mut self, payoffs: &[f32]) {
+        if self.payoffs.len() != payoffs.len() {
+            self.payoffs = vec![0.; payoffs.len()];
+            self.payoff_visits = 0;
+        }
+        self.payoff_visits += 1;
+        for (mean, x) in self.payoffs.iter_mut().zip(payoffs) {
+            *mean += (Stat::from(*x) - *mean) / self.payoff_visits as Stat;
+        }
+    }
+
+    pub fn payoff(&self, player: usize) -> Option<f32> {
+        self.payoffs.get(player).map(|x| stat_to_f32(*x))
+    }
+
+    // Folds in the statistics of another set of visits
+    pub fn merge_stats(&mut self, visits: u32, mean: Stat, m2: Stat) {
+        self.invalidate_subtree_stats();
+        let (visits, mean, m2) =
+            combine_stats((self.visits, self.mean, self.m2), (visits, mean, m2));
+        self.visits = visits;
+        self.mean = mean;
+        self.m2 = m2;
+    }
+
+    // Inverse of merge_stats
+    pub fn remove_stats(&mut self, visits: u32, mean: Stat, m2: Stat) {
+        self.invalidate_subtree_stats();
+        let rest = self.visits.saturating_sub(visits);
+        if rest == 0 {
+            self.visits = 0;
+            self.mean = 0.;
+            self.m2 = 0.;
+            return;
+        }
+
+        let rest_mean = (self.total_reward() - mean * visits as Stat) / rest as Stat;
+        let delta = mean - rest_mean;
+        let weight = rest as Stat * visits as Stat / self.visits as Stat;
+        self.m2 = (self.m2 - m2 - delta * delta * weight).max(0.);
+        self.mean = rest_mean;
+        self.visits = rest;
+    }
+
+    // Scales the visits down keeping the mean, visited nodes keep at least one
+    pub fn decay_stats(&mut self, factor: f32) {
+        if self.visits == 0 {
+            return;
+        }
+        self.invalidate_subtree_stats();
+        let visits = ((self.visits as f32 * factor).round() as u32).clamp(1, self.visits);
+        self.m2 *= visits as Stat / self.visits as Stat;
+        self.visits = visits;
+        self.payoff_visits = self.payoff_visits.min(visits);
+    }
+
+    // Values for the other player of a two-player game, each mean x becomes
+    // pivot - x and wins swap with losses
+    pub fn reflect_stats(&mut self, pivot: Stat) {
+        if self.visits == 0 {
+            return;
+        }
+        self.invalidate_subtree_stats();
+        self.mean = pivot - self.mean;
+        if let Some(x) = &mut self.seeded {
+            x.1 = pivot - x.1;
+        }
+        self.proven = self.proven.map(|x| x.for_player(1, 2));
+        let outcomes = &mut self.outcomes;
+        std::mem::swap(&mut outcomes.wins, &mut outcomes.losses);
+        if let Some(x) = &mut self.sketch {
+            x.reflect(stat_to_f32(pivot));
+        }
+    }
+
+    // Cached until the next update of a node in the subtree, every change of
+    // the statistics or children of a node invalidates its ancestors too
+    pub fn subtree_stats(&self) -> SubtreeStats {
+        if let Some(x) = self.subtree_stats.get() {
+            return x;
+        }
+
+        // Descendants without cached statistics are filled in post-order
+        let mut stack: Vec<(NodeRef<T, S>, bool)> = self
+            .children
+            .iter()
+            .map(|x| (Rc::clone(x), false))
+            .collect();
+        while let Some((x, done)) = stack.pop() {
+            let node = x.borrow();
+            if done {
+                node.subtree_stats.set(Some(node.combine_subtree_stats()));
+                continue;
+            }
+            if node.subtree_stats.get().is_some() {
+                continue;
+            }
+            stack.push((Rc::clone(&x), true));
+            stack.extend(node.children.iter().map(|y| (Rc::clone(y), false)));
+        }
+
+        let stats = self.combine_subtree_stats();
+        self.subtree_stats.set(Some(stats));
+        stats
+    }
+
+    // Drops the cached statistics of the node and its ancestors. Computing
+    // them fills in the whole subtree, so above a node without any the
+    // ancestors have none either
+    pub fn invalidate_subtree_stats(&self) {
+        self.subtree_stats.set(None);
+        let mut node = self.parent();
+        while let Some(x) = node {
+            let x = x.borrow();
+            if x.subtree_stats.get().is_none() {
+                break;
+            }
+            x.subtree_stats.set(None);
+            node = x.parent();
+        }
+    }
+
+    fn combine_subtree_stats(&self) -> SubtreeStats {
+        let terminal = self.visits > 0 && self.state.next_action().is_none();
+        let mut stats = SubtreeStats {
+            visits: self.visits,
+            nodes: 1,
+            depth: 0,
+            best_terminal: if terminal {
+                Some(self.mean_value())
+            } else {
+                None
+            },
+        };
+
+        for child in self.children.iter() {
+            let child = match child.borrow().subtree_stats.get() {
+                Some(x) => x,
+                None => continue,
+            };
+            stats.visits += child.visits;
+            stats.nodes += child.nodes;
+            stats.depth = stats.depth.max(child.depth + 1);
+            stats.best_terminal = match (stats.best_terminal, child.best_terminal) {
+                (Some(x), Some(y)) => Some(x.max(y)),
+                (x, y) => x.or(y),
+            };
+        }
+        stats
+    }
+
+    // Only available when the search keeps quantile sketches
+    pub fn quantile(&self, q: f32) -> Option<f32> {
+        self.sketch.as_ref()?.quantile(q)
+    }
+
     pub fn score(&self, c: f32) -> f32 {
+        match self.parent() {
+            Some(x) => self.score_with(self.mean_value(), c, x.borrow().visits),
+            None => 0.,
+        }
+    }
+
+    // Same as score with `value` in place of the mean
+    pub fn score_for(&self, value: f32, c: f32) -> f32 {
+        match self.parent() {
+            Some(x) => self.score_with(value, c, x.borrow().visits),
+            None => 0.,
+        }
+    }
+
+    // Same as score, None instead of a panic while the parent is borrowed mutably
+    pub fn try_score(&self, c: f32) -> Option<f32> {
+        match self.parent() {
+            Some(x) => Some(self.score_with(self.mean_value(), c, x.try_borrow().ok()?.visits)),
+            None => Some(0.),
+        }
+    }
+
+    fn score_with(&self, value: f32, c: f32, parent_visits: u32) -> f32 {
+        value + c * ((2. * (parent_visits as f32).ln()) / self.visits as f32).sqrt()
+    }
+
+    // Same as score, with the mean reward rescaled from [low, high] into [0, 1]
+    pub fn normalized_score(&self, c: f32, low: f32, high: f32) -> f32 {
+        if high <= low {
+            return self.score(c);
+        }
+
         match self.parent() {
             Some(x) => {
-                self.total_reward / self.visits as f32
+                (self.mean_value() - low) / (high - low)
                     + c * ((2. * (x.borrow().visits as f32).ln()) / self.visits as f32).sqrt()
             }
             None => 0.,
@@ -97,9 +453,27 @@ where
     }
 }
 
+// Tears the subtree down with an explicit stack, recursive drops overflow the
+// stack on very deep lines
+impl<T, S> Drop for Node<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn drop(&mut self) {
+        let mut stack = std::mem::take(&mut self.children);
+        while let Some(x) = stack.pop() {
+            if let Ok(node) = Rc::try_unwrap(x) {
+                stack.append(&mut node.into_inner().children);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::state::ActionError;
 
     #[derive(Debug, Clone)]
     struct DummyState {}
@@ -109,8 +483,8 @@ mod tests {
             Some(1)
         }
 
-        fn do_action(&mut self, _action: &u8) -> f32 {
-            1.0
+        fn try_do_action(&mut self, _action: &u8) -> Result<f32, ActionError> {
+            Ok(1.0)
         }
     }
 
@@ -166,13 +540,88 @@ mod tests {
         assert!(leaf.borrow().best_child().is_none());
 
         // Increase reward manually and check the node is selected
-        leaf.borrow_mut().total_reward = 0.5;
+        leaf.borrow_mut().visits = 1;
+        leaf.borrow_mut().mean = 0.5;
         assert_eq!(
-            node.borrow().best_child().unwrap().borrow().total_reward,
+            node.borrow().best_child().unwrap().borrow().mean_value(),
             0.5
         );
     }
 
+    #[test]
+    fn most_visited_child() {
+        let node = build_1depth_tree(5);
+        node.borrow().child_at(3).unwrap().borrow_mut().visits = 2;
+        node.borrow().child_at(1).unwrap().borrow_mut().mean = 5.;
+
+        let child = node.borrow().most_visited_child().unwrap();
+        assert_eq!(child.borrow().visits, 2);
+    }
+
+    #[test]
+    fn normalized_score() {
+        let node = build_1depth_tree(5);
+        node.borrow_mut().visits = 1;
+
+        let leaf = node.borrow().child_at(2).unwrap();
+        leaf.borrow_mut().visits = 1;
+        leaf.borrow_mut().mean = 30.;
+
+        // Without exploration the mean is mapped into [0, 1]
+        assert_eq!(leaf.borrow().normalized_score(0., 10., 50.), 0.5);
+        assert_eq!(leaf.borrow().normalized_score(0., 0., 0.), 30.);
+    }
+
+    #[test]
+    fn value_variance() {
+        let node = build_1depth_tree(1);
+        assert_eq!(node.borrow().value_variance(), 0.);
+
+        node.borrow_mut().push_value(1.);
+        node.borrow_mut().push_value(3.);
+        assert_eq!(node.borrow().mean_value(), 2.);
+        assert_eq!(node.borrow().value_variance(), 1.);
+    }
+
+    #[test]
+    fn push_value() {
+        let node = build_1depth_tree(1);
+
+        // Raw sums of squares of large offsets lose the variance in f32
+        for x in [1e6, 1e6 + 1., 1e6 + 2.] {
+            node.borrow_mut().push_value(x);
+        }
+        assert_eq!(node.borrow().visits, 3);
+        assert_eq!(node.borrow().mean_value(), 1e6 + 1.);
+        assert!((node.borrow().value_variance() - 2. / 3.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn merge_stats() {
+        let node = build_1depth_tree(1);
+        let other = build_1depth_tree(1);
+        for x in [1., 2., 3., 4.] {
+            node.borrow_mut().push_value(x);
+        }
+        for x in [5., 6.] {
+            other.borrow_mut().push_value(x);
+        }
+
+        let (visits, mean, m2) = {
+            let other = other.borrow();
+            (other.visits, other.mean, other.m2)
+        };
+        node.borrow_mut().merge_stats(visits, mean, m2);
+        assert_eq!(node.borrow().visits, 6);
+        assert_eq!(node.borrow().mean_value(), 3.5);
+        assert!((node.borrow().value_variance() - 35. / 12.).abs() < 1e-6);
+
+        node.borrow_mut().remove_stats(visits, mean, m2);
+        assert_eq!(node.borrow().visits, 4);
+        assert_eq!(node.borrow().mean_value(), 2.5);
+        assert!((node.borrow().value_variance() - 1.25).abs() < 1e-6);
+    }
+
     #[test]
     fn score() {
         let node = build_1depth_tree(5);
@@ -181,11 +630,69 @@ mod tests {
         // If the parent was not visited
         let leaf = node.borrow().child_at(2).unwrap();
         leaf.borrow_mut().visits = 1;
-        leaf.borrow_mut().total_reward = 0.5;
+        leaf.borrow_mut().mean = 0.5;
         assert!(leaf.borrow().score(1.).is_nan());
 
         // If the parent has been visited
         node.borrow_mut().visits = 1;
         assert!(!leaf.borrow().score(1.).is_nan());
     }
+
+    #[test]
+    fn try_score() {
+        let node = build_1depth_tree(2);
+        let leaf = node.borrow().child_at(0).unwrap();
+        leaf.borrow_mut().visits = 1;
+        node.borrow_mut().visits = 1;
+        assert_eq!(leaf.borrow().try_score(1.), Some(leaf.borrow().score(1.)));
+
+        let _parent = node.borrow_mut();
+        assert_eq!(leaf.borrow().try_score(1.), None);
+    }
+
+    #[cfg(feature = "f64-stats")]
+    #[test]
+    fn f64_stats() {
+        let node = build_1depth_tree(1);
+
+        // The update is below half an f32 ulp of the mean
+        node.borrow_mut().visits = 1 << 24;
+        node.borrow_mut().mean = 0.5;
+        node.borrow_mut().push_value(1.);
+        assert!(stat_to_f64(node.borrow().mean) > 0.5);
+    }
+
+    #[test]
+    fn drop_deep_chain() {
+        let root = Node::new(1, DummyState::new());
+        let mut leaf = Rc::clone(&root);
+        for _i in 0..1_000_000 {
+            let child = Node::new(1, DummyState::new());
+            child.borrow_mut().set_parent(&leaf);
+            let next = leaf.borrow_mut().add_child(child);
+            leaf = next;
+        }
+
+        drop(leaf);
+        drop(root);
+    }
+
+    #[test]
+    fn subtree_stats() {
+        let node = build_1depth_tree(3);
+        let leaf = node.borrow().child_at(1).unwrap();
+        let grandchild = Node::new(1, DummyState::new());
+        grandchild.borrow_mut().set_parent(&leaf);
+        leaf.borrow_mut().add_child(grandchild);
+
+        let stats = node.borrow().subtree_stats();
+        assert_eq!((stats.visits, stats.nodes, stats.depth), (0, 5, 2));
+        assert_eq!(stats.best_terminal, None);
+
+        // Backpropagation invalidates the cached statistics
+        leaf.borrow_mut().push_value(1.);
+        node.borrow_mut().push_value(1.);
+        assert_eq!(node.borrow().subtree_stats().visits, 2);
+        assert_eq!(leaf.borrow().subtree_stats().nodes, 2);
+    }
 }
@@ -0,0 +1,94 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::fmt;
+
+use crate::cache::{KeyMap, Keyer};
+use crate::state::StateKey;
+
+// Exploration bonus for rarely visited states: scale * decay^t / sqrt(n + 1)
+#[derive(Clone)]
+pub struct Novelty<S> {
+    pub scale: f32,
+    pub decay: f32,
+    counts: KeyMap<S, u32>,
+}
+
+impl<S> Novelty<S>
+where
+    S: StateKey,
+{
+    pub fn new(scale: f32, decay: f32) -> Self {
+        Self {
+            scale,
+            decay,
+            counts: KeyMap::new(Keyer::new()),
+        }
+    }
+}
+
+impl<S> Novelty<S> {
+    pub fn count(&self, state: &S) -> u32 {
+        self.counts.get(state).copied().unwrap_or(0)
+    }
+
+    pub fn distinct_states(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn visit(&mut self, state: &S) {
+        *self.counts.entry(state, || 0).1 += 1;
+    }
+
+    pub fn bonus(&self, state: &S, iterations: u32) -> f32 {
+        self.scale * self.decay.powi(iterations as i32) / ((self.count(state) + 1) as f32).sqrt()
+    }
+}
+
+impl<S> fmt::Debug for Novelty<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Novelty")
+            .field("scale", &self.scale)
+            .field("decay", &self.decay)
+            .field("distinct_states", &self.counts.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count() {
+        let mut novelty = Novelty::new(1., 1.);
+        assert_eq!(novelty.count(&3u8), 0);
+
+        novelty.visit(&3u8);
+        novelty.visit(&3u8);
+        novelty.visit(&4u8);
+        assert_eq!(novelty.count(&3u8), 2);
+        assert_eq!(novelty.distinct_states(), 2);
+    }
+
+    #[test]
+    fn bonus() {
+        let mut novelty = Novelty::new(2., 0.5);
+        assert_eq!(novelty.bonus(&1u8, 0), 2.);
+
+        // Visited states and later iterations get a smaller bonus
+        for _i in 0..3 {
+            novelty.visit(&1u8);
+        }
+        assert_eq!(novelty.bonus(&1u8, 0), 1.);
+        assert_eq!(novelty.bonus(&1u8, 1), 0.5);
+    }
+}
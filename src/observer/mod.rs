@@ -0,0 +1,73 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::fmt::Debug;
+use std::rc::Rc;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IterationSample {
+    pub iteration: u32,
+    pub depth: usize,
+    pub value: f32,
+    pub size: u32,
+}
+
+// How often an interval callback fires
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interval {
+    Iterations(u32),
+    Time(Duration),
+}
+
+// Receives a sample every `SearchConfig::telemetry_interval` iterations
+pub trait Observer: Debug {
+    fn on_iteration(&self, sample: &IterationSample);
+}
+
+impl<O> Observer for Rc<O>
+where
+    O: Observer + ?Sized,
+{
+    fn on_iteration(&self, sample: &IterationSample) {
+        (**self).on_iteration(sample)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[derive(Debug, Default)]
+    struct Recorder {
+        samples: RefCell<Vec<IterationSample>>,
+    }
+
+    impl Observer for Recorder {
+        fn on_iteration(&self, sample: &IterationSample) {
+            self.samples.borrow_mut().push(*sample);
+        }
+    }
+
+    #[test]
+    fn on_iteration() {
+        let recorder = Recorder::default();
+        let sample = IterationSample {
+            iteration: 3,
+            depth: 2,
+            value: 0.5,
+            size: 7,
+        };
+        recorder.on_iteration(&sample);
+        assert_eq!(recorder.samples.borrow().as_slice(), &[sample]);
+    }
+}
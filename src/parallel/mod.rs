@@ -0,0 +1,710 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::thread;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::config::SearchConfig;
+use crate::events::updated;
+use crate::node::{stat_to_f32, Node, NodeRef, Stat};
+use crate::state::State;
+use crate::strategies::RolloutPolicy;
+use crate::tree::Tree;
+
+// Builds a rollout policy on the thread of the member using it
+pub type RolloutFactory<T, S> = Arc<dyn Fn() -> Box<dyn RolloutPolicy<T, S>> + Send + Sync>;
+
+pub struct EnsembleMember<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    pub config: SearchConfig,
+    pub iterations: u32,
+    // Default rollout policy of the member tree, the tree's own without one
+    pub rollout_policy: Option<RolloutFactory<T, S>>,
+}
+
+impl<T, S> EnsembleMember<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    pub fn new(config: SearchConfig, iterations: u32) -> Self {
+        Self {
+            config,
+            iterations,
+            rollout_policy: None,
+        }
+    }
+
+    pub fn with_rollout_policy<F, P>(mut self, factory: F) -> Self
+    where
+        F: Fn() -> P + Send + Sync + 'static,
+        P: RolloutPolicy<T, S> + 'static,
+    {
+        self.rollout_policy = Some(Arc::new(move || -> Box<dyn RolloutPolicy<T, S>> {
+            Box::new(factory())
+        }));
+        self
+    }
+}
+
+impl<T, S> Clone for EnsembleMember<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            config: self.config,
+            iterations: self.iterations,
+            rollout_policy: self.rollout_policy.clone(),
+        }
+    }
+}
+
+impl<T, S> fmt::Debug for EnsembleMember<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnsembleMember")
+            .field("config", &self.config)
+            .field("iterations", &self.iterations)
+            .field("rollout_policy", &self.rollout_policy.is_some())
+            .finish()
+    }
+}
+
+// Rollout policies are equal when they come from the same factory
+impl<T, S> PartialEq for EnsembleMember<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn eq(&self, other: &Self) -> bool {
+        let policies = match (&self.rollout_policy, &other.rollout_policy) {
+            (Some(x), Some(y)) => Arc::ptr_eq(x, y),
+            (x, y) => x.is_none() && y.is_none(),
+        };
+        self.config == other.config && self.iterations == other.iterations && policies
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ActionStats<T> {
+    pub action: T,
+    pub visits: u32,
+    pub total_reward: f32,
+    pub weight: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemberResult<T> {
+    pub children: Vec<ActionStats<T>>,
+    pub confidence: f32,
+}
+
+impl<T> MemberResult<T>
+where
+    T: Clone,
+{
+    fn from_tree<S>(tree: &Tree<T, S>) -> Self
+    where
+        S: State<T>,
+    {
+        let root = tree.root();
+        let total_visits: u32 = root
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().visits)
+            .sum();
+
+        let children: Vec<ActionStats<T>> = root
+            .borrow()
+            .children
+            .iter()
+            .map(|x| {
+                let node = x.borrow();
+                ActionStats {
+                    action: node.action.clone(),
+                    visits: node.visits,
+                    total_reward: stat_to_f32(node.total_reward()),
+                    weight: if total_visits > 0 {
+                        node.visits as f32 / total_visits as f32
+                    } else {
+                        0.
+                    },
+                }
+            })
+            .collect();
+
+        // A member that concentrated its visits on one action is more sure of it
+        let confidence = children.iter().map(|x| x.weight).fold(0., f32::max);
+
+        Self {
+            children,
+            confidence,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnsembleResult<T> {
+    pub members: Vec<MemberResult<T>>,
+    pub children: Vec<ActionStats<T>>,
+}
+
+impl<T> EnsembleResult<T>
+where
+    T: Clone + PartialEq,
+{
+    fn merge(members: Vec<MemberResult<T>>) -> Self {
+        let total_confidence: f32 = members.iter().map(|x| x.confidence).sum();
+        let mut children: Vec<ActionStats<T>> = vec![];
+
+        for member in members.iter() {
+            let weight = if total_confidence > 0. {
+                member.confidence / total_confidence
+            } else {
+                1. / members.len() as f32
+            };
+
+            for stats in member.children.iter() {
+                match children.iter_mut().find(|x| x.action == stats.action) {
+                    Some(x) => {
+                        x.visits += stats.visits;
+                        x.total_reward += stats.total_reward;
+                        x.weight += weight * stats.weight;
+                    }
+                    None => children.push(ActionStats {
+                        weight: weight * stats.weight,
+                        ..stats.clone()
+                    }),
+                }
+            }
+        }
+
+        Self { members, children }
+    }
+
+    pub fn best(&self) -> Option<&ActionStats<T>> {
+        self.children.iter().max_by(|x, y| {
+            x.weight
+                .partial_cmp(&y.weight)
+                .unwrap_or(std::cmp::Ordering::Less)
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ensemble<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    members: Vec<EnsembleMember<T, S>>,
+    jitter: f32,
+    seed: u64,
+    member_seed: Option<u64>,
+}
+
+impl<T, S> Ensemble<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    pub fn new(members: Vec<EnsembleMember<T, S>>) -> Self {
+        Self {
+            members,
+            jitter: 0.,
+            seed: 0,
+            member_seed: None,
+        }
+    }
+
+    pub fn with_jitter(mut self, jitter: f32, seed: u64) -> Self {
+        self.jitter = jitter;
+        self.seed = seed;
+        self
+    }
+
+    // Reproducible runs: every member gets its own search seed drawn from
+    // `seed` in member order, so identical members diverge the same way on
+    // every run
+    pub fn deterministic(mut self, seed: u64) -> Self {
+        self.member_seed = Some(seed);
+        self
+    }
+
+    pub fn uniform(config: SearchConfig, iterations: u32, workers: usize) -> Self {
+        Self::new(vec![EnsembleMember::new(config, iterations); workers])
+    }
+
+    pub fn members(&self) -> &[EnsembleMember<T, S>] {
+        &self.members
+    }
+
+    // Each worker gets its own perturbed exploration constant so that identical
+    // members don't walk the same selection paths
+    pub fn jittered_members(&self) -> Vec<EnsembleMember<T, S>> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut seeds = self.member_seed.map(StdRng::seed_from_u64);
+
+        self.members
+            .iter()
+            .map(|member| {
+                let mut member = member.clone();
+                if self.jitter > 0. {
+                    member.config.learning_rate *= 1. + rng.gen_range(-self.jitter..=self.jitter);
+                }
+                if let Some(seeds) = &mut seeds {
+                    member.config.seed = seeds.gen();
+                }
+                member
+            })
+            .collect()
+    }
+
+    pub fn search(&self, action: T, state: S) -> EnsembleResult<T>
+    where
+        S: Send,
+        T: PartialEq + Send,
+    {
+        let members = self.jittered_members();
+        let results = thread::scope(|scope| {
+            let handles: Vec<_> = members
+                .iter()
+                .map(|member| {
+                    let action = action.clone();
+                    let state = state.clone();
+                    scope.spawn(move || {
+                        let mut tree = Tree::with_config(member.config, action, state);
+                        if let Some(factory) = &member.rollout_policy {
+                            tree = tree.with_boxed_rollout_policy(factory());
+                        }
+                        tree.search(member.iterations);
+                        MemberResult::from_tree(&tree)
+                    })
+                })
+                .collect();
+
+            // Joined in member order, the merge does not depend on which thread
+            // finishes first
+            handles
+                .into_iter()
+                .map(|x| x.join().expect("ensemble member panicked"))
+                .collect()
+        });
+
+        EnsembleResult::merge(results)
+    }
+}
+
+// Root parallelization: independent trees from the root state, one per
+// thread, whose root children statistics are merged into this tree. Leaf
+// parallelization with virtual loss is Tree::search_pipelined
+impl<T, S> Tree<T, S>
+where
+    S: State<T> + Send,
+    T: Clone + PartialEq + Send,
+{
+    // Worker trees only get the config. A tree with its own selection,
+    // expansion, rollout or backup strategies, evaluator or opponent model
+    // searches nothing and gets None, those go through search_parallel_with
+    pub fn search_parallel(&mut self, iterations: u32, threads: usize) -> Option<NodeRef<T, S>> {
+        if self.custom_strategies {
+            return None;
+        }
+        self.search_parallel_with(iterations, threads, |x| x)
+    }
+
+    // Every worker tree goes through `build` on its own thread before searching,
+    // so the strategies are created there and don't need to be Send. Workers
+    // start from the statistics of the root and its children, deeper nodes
+    // stay here, and only their own iterations are merged back. Nothing else
+    // of this tree reaches them: temperature schedule, novelty, intrinsic
+    // reward, per-player rollout policies and exploration, return weights,
+    // policy priors, rollout bandit, rollout and evaluation caches, coverage,
+    // knowledge store, observers, callbacks and event sink
+    pub fn search_parallel_with<F>(
+        &mut self,
+        iterations: u32,
+        threads: usize,
+        build: F,
+    ) -> Option<NodeRef<T, S>>
+    where
+        F: Fn(Tree<T, S>) -> Tree<T, S> + Sync,
+    {
+        let threads = threads.max(1);
+        let mut seeds = StdRng::seed_from_u64(self.config.seed);
+        let configs: Vec<(SearchConfig, u32)> = (0..threads)
+            .map(|i| {
+                let mut config = self.config;
+                config.seed = seeds.gen();
+                let share = iterations / threads as u32
+                    + u32::from((i as u32) < iterations % threads as u32);
+                (config, share)
+            })
+            .collect();
+
+        let (action, state) = {
+            let root = self.root.borrow();
+            (root.action.clone(), root.state.clone())
+        };
+        let seed = root_stats(&self.root);
+        let results: Vec<RootStats<T>> = thread::scope(|scope| {
+            let handles: Vec<_> = configs
+                .iter()
+                .map(|(config, share)| {
+                    let action = action.clone();
+                    let state = state.clone();
+                    let seed = seed.clone();
+                    let build = &build;
+                    scope.spawn(move || {
+                        let mut tree = build(Tree::with_config(*config, action, state));
+                        let seeded = seed.0 .0 > 0;
+                        if seeded {
+                            tree.apply_root_stats(&seed, Node::merge_stats);
+                        }
+                        tree.search(*share);
+                        if seeded {
+                            tree.apply_root_stats(&seed, Node::remove_stats);
+                        }
+                        root_stats(&tree.root)
+                    })
+                })
+                .collect();
+
+            // Joined in thread order so the merge is reproducible
+            handles
+                .into_iter()
+                .map(|x| x.join().expect("search thread panicked"))
+                .collect()
+        });
+
+        let mut root = self.root();
+        self.expand_fully(&mut root);
+        for ((visits, mean, m2), children) in results {
+            root.borrow_mut().merge_stats(visits, mean, m2);
+            self.emit(|| updated(&root.borrow()));
+            for (action, (visits, mean, m2)) in children {
+                let child = root
+                    .borrow()
+                    .children
+                    .iter()
+                    .find(|x| x.borrow().action == action)
+                    .map(Rc::clone);
+                if let Some(child) = child {
+                    child.borrow_mut().merge_stats(visits, mean, m2);
+                    self.emit(|| updated(&child.borrow()));
+                }
+            }
+        }
+        self.iterations += iterations;
+
+        self.best_child()
+    }
+
+    fn apply_root_stats(
+        &mut self,
+        stats: &RootStats<T>,
+        apply: fn(&mut Node<T, S>, u32, Stat, Stat),
+    ) {
+        let mut root = self.root();
+        self.expand_fully(&mut root);
+        let ((visits, mean, m2), children) = stats;
+        apply(&mut root.borrow_mut(), *visits, *mean, *m2);
+        for (action, (visits, mean, m2)) in children.iter() {
+            let child = root
+                .borrow()
+                .children
+                .iter()
+                .find(|x| x.borrow().action == *action)
+                .map(Rc::clone);
+            if let Some(child) = child {
+                apply(&mut child.borrow_mut(), *visits, *mean, *m2);
+            }
+        }
+    }
+}
+
+type Stats = (u32, Stat, Stat);
+// Of the root, then of its children by action
+type RootStats<T> = (Stats, Vec<(T, Stats)>);
+
+fn root_stats<T, S>(root: &NodeRef<T, S>) -> RootStats<T>
+where
+    S: State<T>,
+    T: Clone,
+{
+    let root = root.borrow();
+    let children = root
+        .children
+        .iter()
+        .map(|x| {
+            let x = x.borrow();
+            (x.action.clone(), (x.visits, x.mean, x.m2))
+        })
+        .collect();
+    ((root.visits, root.mean, root.m2), children)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::DebugFormatter;
+    use crate::state::ActionError;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug, Clone)]
+    struct DummyState {
+        actions: u8,
+    }
+
+    impl DummyState {
+        fn new() -> Self {
+            Self { actions: 5 }
+        }
+    }
+
+    impl State<u8> for DummyState {
+        fn next_action(&self) -> Option<u8> {
+            if self.actions == 0 {
+                return None;
+            }
+            Some(self.actions)
+        }
+
+        fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+            self.actions -= 1;
+            Ok(*action as f32 / 10.)
+        }
+    }
+
+    fn stats(action: u8, visits: u32, weight: f32) -> ActionStats<u8> {
+        ActionStats {
+            action,
+            visits,
+            total_reward: 0.,
+            weight,
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let sure = MemberResult {
+            children: vec![stats(1, 9, 0.9), stats(2, 1, 0.1)],
+            confidence: 0.9,
+        };
+        let unsure = MemberResult {
+            children: vec![stats(1, 4, 0.4), stats(2, 6, 0.6)],
+            confidence: 0.6,
+        };
+
+        // The confident member outweighs the unsure one
+        let result = EnsembleResult::merge(vec![sure, unsure]);
+        assert_eq!(result.children.len(), 2);
+        assert_eq!(result.children[0].visits, 13);
+        assert_eq!(result.best().unwrap().action, 1);
+    }
+
+    #[test]
+    fn search() {
+        let ensemble = Ensemble::new(vec![
+            EnsembleMember::new(SearchConfig::new(0.5), 20),
+            EnsembleMember::new(SearchConfig::new(1.0), 20),
+            EnsembleMember::new(SearchConfig::new(2.0), 20),
+        ]);
+
+        let state = DummyState::new();
+        let action = state.next_action().unwrap();
+        let result = ensemble.search(action, state);
+
+        assert_eq!(result.members.len(), 3);
+        assert!(result.best().is_some());
+
+        let total_weight: f32 = result.children.iter().map(|x| x.weight).sum();
+        assert!((total_weight - 1.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn jitter() {
+        let ensemble = Ensemble::<u8, DummyState>::uniform(SearchConfig::new(1.0), 10, 4);
+        assert!(ensemble
+            .jittered_members()
+            .iter()
+            .all(|x| x.config.learning_rate == 1.0));
+
+        let ensemble = ensemble.with_jitter(0.1, 42);
+        let members = ensemble.jittered_members();
+        assert!(members
+            .iter()
+            .all(|x| (x.config.learning_rate - 1.0).abs() <= 0.1));
+        assert!(members
+            .iter()
+            .any(|x| x.config.learning_rate != members[0].config.learning_rate));
+
+        // Same seed, same perturbation
+        assert_eq!(members, ensemble.jittered_members());
+    }
+
+    #[test]
+    fn deterministic() {
+        let ensemble = Ensemble::<u8, DummyState>::uniform(SearchConfig::new(1.0), 30, 3)
+            .with_jitter(0.2, 7)
+            .deterministic(11);
+        let members = ensemble.jittered_members();
+        assert_ne!(members[0].config.seed, members[1].config.seed);
+        assert_eq!(members, ensemble.jittered_members());
+
+        let state = DummyState::new();
+        let action = state.next_action().unwrap();
+        let first = ensemble.search(action, state.clone());
+        for _i in 0..3 {
+            assert_eq!(ensemble.search(action, state.clone()), first);
+        }
+    }
+
+    #[test]
+    fn search_parallel_reproducible() {
+        let export = || {
+            let mut config = SearchConfig::new(1.0);
+            config.random_expansion = true;
+            let mut tree = Tree::with_config(config, 0, DummyState::new());
+            tree.search_parallel(60, 3);
+            tree.to_json(&DebugFormatter, 2)
+        };
+        assert_eq!(export(), export());
+    }
+
+    #[test]
+    fn search_parallel() {
+        let mut tree = Tree::new(1.0, 0, DummyState::new());
+        assert!(tree.search_parallel(41, 4).is_some());
+
+        let root = tree.root();
+        assert_eq!(root.borrow().visits, 41);
+        assert_eq!(root.borrow().children.len(), 5);
+        // Each thread spends its first iteration on the root itself
+        let visits: u32 = root
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().visits)
+            .sum();
+        assert_eq!(visits, 41 - 4);
+        assert_eq!(tree.iterations, 41);
+
+        // Thread seeds come from the config seed
+        let mut other = Tree::new(1.0, 0, DummyState::new());
+        other.search_parallel(41, 4);
+        assert_eq!(
+            tree.best_child().unwrap().borrow().visits,
+            other.best_child().unwrap().borrow().visits
+        );
+
+        // Later sequential iterations build on the merged statistics
+        tree.search(10);
+        assert_eq!(tree.root().borrow().visits, 51);
+    }
+
+    #[derive(Debug)]
+    struct CountingRollout(Arc<AtomicUsize>);
+
+    impl RolloutPolicy<u8, DummyState> for CountingRollout {
+        fn choose(&self, state: &DummyState) -> Option<u8> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            state.next_action()
+        }
+    }
+
+    #[test]
+    fn member_rollout_policies() {
+        let first = Arc::new(AtomicUsize::new(0));
+        let second = Arc::new(AtomicUsize::new(0));
+        let counting = |x: &Arc<AtomicUsize>| {
+            let x = Arc::clone(x);
+            move || CountingRollout(Arc::clone(&x))
+        };
+        let ensemble = Ensemble::new(vec![
+            EnsembleMember::new(SearchConfig::new(1.0), 10).with_rollout_policy(counting(&first)),
+            EnsembleMember::new(SearchConfig::new(1.0), 20).with_rollout_policy(counting(&second)),
+            EnsembleMember::new(SearchConfig::new(1.0), 20),
+        ]);
+        let state = DummyState::new();
+        let action = state.next_action().unwrap();
+        assert_eq!(ensemble.search(action, state).members.len(), 3);
+
+        // Each member ran its own policy, the last one the tree default
+        let (first, second) = (
+            first.load(Ordering::Relaxed),
+            second.load(Ordering::Relaxed),
+        );
+        assert!(first > 0);
+        assert!(second > first);
+    }
+
+    #[test]
+    fn search_parallel_with() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut tree = Tree::new(1.0, 0, DummyState::new());
+        tree.search_parallel_with(20, 2, |x| {
+            x.with_default_rollout_policy(CountingRollout(Arc::clone(&calls)))
+        });
+        assert_eq!(tree.root().borrow().visits, 20);
+        assert!(calls.load(Ordering::Relaxed) > 0);
+    }
+
+    #[test]
+    fn search_parallel_rejects_strategies() {
+        let mut tree = Tree::new(1.0, 0, DummyState::new())
+            .with_default_rollout_policy(CountingRollout(Arc::new(AtomicUsize::new(0))));
+        assert!(tree.search_parallel(20, 2).is_none());
+        assert_eq!(tree.root().borrow().visits, 0);
+    }
+
+    #[test]
+    fn search_parallel_seeded() {
+        let mut tree = Tree::new(1.0, 0, DummyState::new());
+        tree.search(100);
+        let before: Vec<u32> = tree
+            .root()
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().visits)
+            .collect();
+        tree.search_parallel(4, 2);
+
+        // Seeded workers go on from the root statistics instead of trying the
+        // root and every child again, and only send back their own visits
+        let root = tree.root();
+        assert_eq!(root.borrow().visits, 104);
+        let added: Vec<u32> = root
+            .borrow()
+            .children
+            .iter()
+            .zip(before)
+            .map(|(x, y)| x.borrow().visits - y)
+            .collect();
+        assert_eq!(added.iter().sum::<u32>(), 4);
+        assert!(added.iter().filter(|x| **x > 0).count() < 4);
+    }
+}
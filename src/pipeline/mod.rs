@@ -0,0 +1,438 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::config::RewardMode;
+use crate::events::updated;
+use crate::node::{NodeRef, Stat};
+use crate::state::State;
+use crate::tree::Tree;
+
+type Batch<S> = Vec<(u64, S)>;
+
+// Staged search: the thread owning the tree selects leaves and applies the
+// results, evaluator threads evaluate batches of leaf states meanwhile. The
+// backups stay with selection, nodes can't leave the thread owning the tree.
+// Every leaf in flight holds a virtual loss on its path so the following
+// selections spread out, and pruning leaves the path alone. Steps run by the
+// evaluation are not counted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pipeline {
+    pub evaluators: usize,
+    pub batch_size: usize,
+    // Batches sent out before waiting for results
+    pub depth: usize,
+    pub virtual_loss: u32,
+    // Relative perturbation of the exploration constant. There are as many
+    // constants as evaluators, the batches take them in turn while their
+    // leaves are selected, whichever evaluator then takes the batch
+    pub jitter: f32,
+    pub jitter_seed: u64,
+}
+
+impl Pipeline {
+    pub fn new(evaluators: usize, batch_size: usize) -> Self {
+        Self {
+            evaluators,
+            batch_size,
+            depth: 2 * evaluators,
+            virtual_loss: 1,
+            jitter: 0.,
+            jitter_seed: 0,
+        }
+    }
+
+    pub fn with_jitter(mut self, jitter: f32, seed: u64) -> Self {
+        self.jitter = jitter;
+        self.jitter_seed = seed;
+        self
+    }
+
+    // Exploration constants the batches take in turn, drawn from the jitter
+    // seed
+    pub(crate) fn exploration(&self, learning_rate: f32) -> Vec<f32> {
+        let mut rng = StdRng::seed_from_u64(self.jitter_seed);
+        (0..self.evaluators.max(1))
+            .map(|_| {
+                if self.jitter > 0. {
+                    learning_rate * (1. + rng.gen_range(-self.jitter..=self.jitter))
+                } else {
+                    learning_rate
+                }
+            })
+            .collect()
+    }
+}
+
+struct InFlight<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    leaf: NodeRef<T, S>,
+    // From the leaf up to the root
+    losses: Vec<f32>,
+}
+
+impl<T, S> Tree<T, S>
+where
+    S: State<T> + Send,
+    T: Clone,
+{
+    pub fn search_pipelined<F>(
+        &mut self,
+        iterations: u32,
+        pipeline: Pipeline,
+        evaluate: F,
+    ) -> Option<NodeRef<T, S>>
+    where
+        F: Fn(&S) -> f32 + Sync,
+    {
+        let (jobs, job_queue) = mpsc::channel::<Batch<S>>();
+        let (results, result_queue) = mpsc::channel::<Vec<(u64, f32)>>();
+        let job_queue = Mutex::new(job_queue);
+        let evaluate = &evaluate;
+        let start = Instant::now();
+        self.warm_up();
+
+        thread::scope(|scope| {
+            for _i in 0..pipeline.evaluators.max(1) {
+                let job_queue = &job_queue;
+                let results = results.clone();
+                scope.spawn(move || loop {
+                    let batch = match job_queue.lock().expect("job queue poisoned").recv() {
+                        Ok(x) => x,
+                        Err(_) => break,
+                    };
+                    let values = batch.into_iter().map(|(x, y)| (x, evaluate(&y))).collect();
+                    if results.send(values).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(results);
+
+            let root = self.root();
+            let mut in_flight: HashMap<u64, InFlight<T, S>> = HashMap::new();
+            let (mut issued, mut completed, mut batches) = (0, 0, 0);
+            let mut next_ticket = 0;
+            let learning_rate = self.config.learning_rate;
+            let exploration = pipeline.exploration(learning_rate);
+            let mut sent = 0;
+
+            while completed < iterations {
+                while issued < iterations && batches < pipeline.depth.max(1) {
+                    let mut batch = vec![];
+                    while issued < iterations
+                        && batch.len() < pipeline.batch_size.max(1)
+                        && !self.out_of_budget()
+                    {
+                        self.config.learning_rate = exploration[sent % exploration.len()];
+                        let leaf = self.next_leaf(&root);
+                        self.config.learning_rate = learning_rate;
+                        let leaf = match leaf {
+                            Some(x) => x,
+                            None => break,
+                        };
+                        // Still waiting for its first value, so is the next leaf
+                        let waiting = {
+                            let x = leaf.borrow();
+                            x.visits > 0 && x.visits == x.virtual_visits
+                        };
+                        if waiting {
+                            break;
+                        }
+                        let losses = self.add_virtual_loss(&leaf, pipeline.virtual_loss);
+                        batch.push((next_ticket, leaf.borrow().state.clone()));
+                        in_flight.insert(next_ticket, InFlight { leaf, losses });
+                        next_ticket += 1;
+                        issued += 1;
+                    }
+                    if batch.is_empty() {
+                        break;
+                    }
+                    batches += 1;
+                    sent += 1;
+                    if jobs.send(batch).is_err() {
+                        break;
+                    }
+                }
+                if batches == 0 {
+                    break;
+                }
+
+                let values = match result_queue.recv() {
+                    Ok(x) => x,
+                    Err(_) => break,
+                };
+                batches -= 1;
+                for (ticket, value) in values {
+                    let x = match in_flight.remove(&ticket) {
+                        Some(x) => x,
+                        None => continue,
+                    };
+                    self.remove_virtual_loss(&x.leaf, pipeline.virtual_loss, &x.losses);
+                    self.progress = completed as f32 / iterations as f32;
+                    self.finish_iteration(&root, &x.leaf, value, true);
+                    completed += 1;
+                }
+            }
+
+            drop(jobs);
+        });
+
+        self.progress = 1.;
+        self.elapsed += start.elapsed();
+        self.best_child()
+    }
+
+    // Counts as `visits` lost playouts down to the leaf, each one lost by the
+    // player moving into the node. Values are kept for the root player, so at
+    // the nodes an opponent moves into the loss is the best value. Returns the
+    // values used from the leaf up
+    fn add_virtual_loss(&self, leaf: &NodeRef<T, S>, visits: u32) -> Vec<f32> {
+        let (low, high) = match self.config.mode {
+            RewardMode::Game => (0., 1.),
+            RewardMode::Planning => self.reward_bounds.unwrap_or((0., 0.)),
+        };
+        let (players, root_player) = {
+            let root = self.root.borrow();
+            (root.state.num_players(), root.state.current_player())
+        };
+
+        let mut losses = vec![];
+        let mut node = Some(Rc::clone(leaf));
+        while let Some(x) = node {
+            let parent = x.borrow().parent();
+            let mover = parent
+                .as_ref()
+                .map_or(root_player, |x| x.borrow().state.current_player());
+            let loss = if players > 1 && mover != root_player {
+                high
+            } else {
+                low
+            };
+            {
+                let mut x = x.borrow_mut();
+                x.merge_stats(visits, Stat::from(loss), 0.);
+                x.virtual_visits += visits;
+            }
+            self.emit(|| updated(&x.borrow()));
+            losses.push(loss);
+            node = parent;
+        }
+        losses
+    }
+
+    fn remove_virtual_loss(&self, leaf: &NodeRef<T, S>, visits: u32, losses: &[f32]) {
+        let mut node = Some(Rc::clone(leaf));
+        for loss in losses.iter() {
+            let x = match node {
+                Some(x) => x,
+                None => break,
+            };
+            {
+                let mut x = x.borrow_mut();
+                x.remove_stats(visits, Stat::from(*loss), 0.);
+                x.virtual_visits -= visits;
+            }
+            self.emit(|| updated(&x.borrow()));
+            node = x.borrow().parent();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SearchConfig;
+    use crate::node::Node;
+    use crate::state::ActionError;
+    use crate::state::Outcome;
+
+    #[derive(Debug, Clone)]
+    struct DummyState {
+        actions: u8,
+    }
+
+    impl State<u8> for DummyState {
+        fn next_action(&self) -> Option<u8> {
+            if self.actions == 0 {
+                return None;
+            }
+            Some(self.actions)
+        }
+
+        fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+            self.actions -= 1;
+            Ok(*action as f32 / 10.)
+        }
+    }
+
+    // Players take turns, player 0 moves at the root. Always a draw
+    #[derive(Debug, Clone)]
+    struct TwoPlayers {
+        moves: u8,
+    }
+
+    impl State<u8> for TwoPlayers {
+        fn next_action(&self) -> Option<u8> {
+            if self.moves == 4 {
+                return None;
+            }
+            Some(self.moves)
+        }
+
+        fn try_do_action(&mut self, _action: &u8) -> Result<f32, ActionError> {
+            self.moves += 1;
+            Ok(0.)
+        }
+
+        fn outcome(&self) -> Option<Outcome> {
+            (self.moves == 4).then_some(Outcome::Draw)
+        }
+
+        fn current_player(&self) -> usize {
+            usize::from(self.moves % 2)
+        }
+
+        fn num_players(&self) -> usize {
+            2
+        }
+    }
+
+    fn rollout(state: &DummyState) -> f32 {
+        let mut state = state.clone();
+        let mut total = 0.;
+        while let Some(action) = state.next_action() {
+            total += state.do_action(&action);
+        }
+        total
+    }
+
+    #[test]
+    fn search_pipelined() {
+        let mut tree = Tree::new(1.0, 0u8, DummyState { actions: 5 });
+        let best = tree.search_pipelined(60, Pipeline::new(3, 4), rollout);
+
+        assert!(best.is_some());
+        assert_eq!(tree.iterations, 60);
+        assert!(tree.result().elapsed > std::time::Duration::ZERO);
+
+        // Virtual losses are all taken back out
+        let root = tree.root();
+        assert_eq!(root.borrow().visits, 60);
+        let visits: u32 = root
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().visits)
+            .sum();
+        assert_eq!(visits, 59);
+    }
+
+    #[test]
+    fn virtual_loss_per_player() {
+        let mut tree = Tree::with_config(SearchConfig::game(), 0u8, TwoPlayers { moves: 0 });
+        tree.search(20);
+        let child = tree.root().borrow().child_at(0).unwrap();
+        let grandchild = child.borrow().child_at(0).unwrap();
+        let before = |x: &NodeRef<u8, TwoPlayers>| (x.borrow().visits, x.borrow().mean);
+        let (child_before, grandchild_before) = (before(&child), before(&grandchild));
+
+        // A loss for player 0 into the child, for player 1 into the grandchild
+        let losses = tree.add_virtual_loss(&grandchild, 2);
+        assert_eq!(losses, vec![1., 0., 0.]);
+        assert!(grandchild.borrow().mean > grandchild_before.1);
+        assert!(child.borrow().mean < child_before.1);
+        assert_eq!(grandchild.borrow().virtual_visits, 2);
+
+        // Only virtual visits, the leaf is selected again without expanding it
+        let leaf = Node::new(1u8, TwoPlayers { moves: 3 });
+        tree.add_virtual_loss(&leaf, 1);
+        assert!(tree.next_leaf(&leaf).is_some());
+        assert!(leaf.borrow().children.is_empty());
+
+        tree.remove_virtual_loss(&grandchild, 2, &losses);
+        for (node, (visits, mean)) in [(&child, child_before), (&grandchild, grandchild_before)] {
+            assert_eq!(node.borrow().visits, visits);
+            assert!((node.borrow().mean - mean).abs() < 1e-5);
+        }
+        assert_eq!(grandchild.borrow().virtual_visits, 0);
+    }
+
+    #[test]
+    fn jitter() {
+        let pipeline = Pipeline::new(4, 2).with_jitter(0.2, 3);
+        let exploration = pipeline.exploration(1.);
+        assert_eq!(exploration.len(), 4);
+        assert!(exploration.iter().all(|x| (0.8..=1.2).contains(x)));
+        assert_ne!(exploration[0], exploration[1]);
+        assert_eq!(exploration, pipeline.exploration(1.));
+        assert_eq!(Pipeline::new(2, 2).exploration(1.5), vec![1.5, 1.5]);
+
+        // The tree keeps its own constant
+        let mut tree = Tree::new(1.0, 0u8, DummyState { actions: 5 });
+        assert!(tree.search_pipelined(40, pipeline, rollout).is_some());
+        assert_eq!(tree.config.learning_rate, 1.0);
+        assert_eq!(tree.root().borrow().visits, 40);
+    }
+
+    #[test]
+    fn prune_in_flight() {
+        let mut config = SearchConfig::new(1.0);
+        config.max_nodes = 8;
+        let mut tree = Tree::with_config(config, 0u8, DummyState { actions: 5 });
+        tree.search_pipelined(100, Pipeline::new(2, 4), rollout);
+
+        // Nothing holding a leaf in flight was pruned, every loss came back out
+        let root = tree.root();
+        assert_eq!(root.borrow().visits, 100);
+        assert_eq!(root.borrow().virtual_visits, 0);
+        for x in Tree::descendants(&root) {
+            assert_eq!(x.borrow().virtual_visits, 0);
+        }
+    }
+
+    #[test]
+    fn sequential() {
+        // A single evaluator with batches of one is the sequential search
+        let mut pipelined = Tree::new(1.0, 0u8, DummyState { actions: 4 });
+        let pipeline = Pipeline {
+            depth: 1,
+            ..Pipeline::new(1, 1)
+        };
+        pipelined.search_pipelined(30, pipeline, rollout);
+        let mut sequential = Tree::new(1.0, 0u8, DummyState { actions: 4 });
+        sequential.search(30);
+
+        let visits = |tree: &Tree<u8, DummyState>| -> Vec<u32> {
+            let root = tree.root();
+            let visits = root
+                .borrow()
+                .children
+                .iter()
+                .map(|x| x.borrow().visits)
+                .collect();
+            visits
+        };
+        assert_eq!(visits(&pipelined), visits(&sequential));
+    }
+}
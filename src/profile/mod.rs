@@ -0,0 +1,208 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::time::Duration;
+
+// Buckets of powers of two microseconds, the last one open ended
+const BUCKETS: usize = 16;
+// Slowest iterations kept for diagnostics
+const SLOWEST: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Phase {
+    Selection,
+    Expansion,
+    Simulation,
+    Backpropagation,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 4] = [
+        Phase::Selection,
+        Phase::Expansion,
+        Phase::Simulation,
+        Phase::Backpropagation,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SlowIteration {
+    pub iteration: u32,
+    pub time: Duration,
+    // Phase that took the most of it
+    pub phase: Phase,
+}
+
+// Wall clock of the iterations by phase, see SearchConfig::profile_iterations.
+// Selection dominated profiles point at the tree, expansion at State::clone
+// and next_action, simulation at the rollouts
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IterationProfile {
+    pub iterations: u32,
+    totals: [Duration; 4],
+    dominated: [u32; 4],
+    buckets: [u32; BUCKETS],
+    slowest: Vec<SlowIteration>,
+}
+
+impl IterationProfile {
+    pub fn record(&mut self, iteration: u32, phases: [Duration; 4]) {
+        let time: Duration = phases.iter().sum();
+        let phase = Phase::ALL
+            .iter()
+            .copied()
+            .max_by_key(|x| phases[x.index()])
+            .unwrap_or(Phase::Selection);
+
+        self.iterations += 1;
+        for (total, x) in self.totals.iter_mut().zip(phases) {
+            *total += x;
+        }
+        self.dominated[phase.index()] += 1;
+        self.buckets[bucket(time)] += 1;
+
+        let index = self.slowest.partition_point(|x| x.time >= time);
+        if index < SLOWEST {
+            self.slowest.insert(
+                index,
+                SlowIteration {
+                    iteration,
+                    time,
+                    phase,
+                },
+            );
+            self.slowest.truncate(SLOWEST);
+        }
+    }
+
+    pub fn total(&self, phase: Phase) -> Duration {
+        self.totals[phase.index()]
+    }
+
+    // Fraction of the profiled time spent in `phase`
+    pub fn share(&self, phase: Phase) -> f32 {
+        let total: Duration = self.totals.iter().sum();
+        if total.is_zero() {
+            return 0.;
+        }
+        self.total(phase).as_secs_f32() / total.as_secs_f32()
+    }
+
+    // Iterations that spent the most of their time in `phase`
+    pub fn dominated_by(&self, phase: Phase) -> u32 {
+        self.dominated[phase.index()]
+    }
+
+    pub fn dominant(&self) -> Option<Phase> {
+        if self.iterations == 0 {
+            return None;
+        }
+        Phase::ALL.iter().copied().max_by_key(|x| self.total(*x))
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.iterations {
+            0 => Duration::ZERO,
+            n => self.totals.iter().sum::<Duration>() / n,
+        }
+    }
+
+    // Iteration counts as (lower edge, count), up to the last bucket used
+    pub fn histogram(&self) -> Vec<(Duration, u32)> {
+        let used = self
+            .buckets
+            .iter()
+            .rposition(|x| *x > 0)
+            .map_or(0, |x| x + 1);
+        (0..used)
+            .map(|i| (lower_edge(i), self.buckets[i]))
+            .collect()
+    }
+
+    // Slowest first
+    pub fn slowest(&self) -> &[SlowIteration] {
+        &self.slowest
+    }
+}
+
+fn lower_edge(bucket: usize) -> Duration {
+    match bucket {
+        0 => Duration::ZERO,
+        i => Duration::from_micros(1 << (i - 1)),
+    }
+}
+
+fn bucket(time: Duration) -> usize {
+    let micros = time.as_micros();
+    let bits = (u128::BITS - micros.leading_zeros()) as usize;
+    bits.min(BUCKETS - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn micros(x: [u64; 4]) -> [Duration; 4] {
+        x.map(Duration::from_micros)
+    }
+
+    #[test]
+    fn record() {
+        let mut profile = IterationProfile::default();
+        assert_eq!(profile.dominant(), None);
+        profile.record(0, micros([1, 0, 8, 1]));
+        profile.record(1, micros([1, 0, 6, 1]));
+        profile.record(2, micros([30, 2, 0, 0]));
+
+        assert_eq!(profile.iterations, 3);
+        assert_eq!(profile.total(Phase::Simulation), Duration::from_micros(14));
+        assert_eq!(profile.dominated_by(Phase::Simulation), 2);
+        assert_eq!(profile.dominated_by(Phase::Selection), 1);
+        assert_eq!(profile.dominant(), Some(Phase::Selection));
+        assert_eq!(profile.mean(), Duration::from_micros(50) / 3);
+        assert!((profile.share(Phase::Simulation) - 0.28).abs() < 1e-6);
+
+        let slowest = profile.slowest();
+        assert_eq!(
+            (slowest[0].iteration, slowest[0].phase),
+            (2, Phase::Selection)
+        );
+        assert_eq!(slowest[0].time, Duration::from_micros(32));
+        assert_eq!(slowest.len(), 3);
+    }
+
+    #[test]
+    fn histogram() {
+        let mut profile = IterationProfile::default();
+        assert_eq!(profile.histogram(), vec![]);
+        for x in [0, 1, 3, 3, 40] {
+            profile.record(0, micros([x, 0, 0, 0]));
+        }
+        profile.record(0, micros([u64::MAX / 2, 0, 0, 0]));
+
+        let histogram = profile.histogram();
+        assert_eq!(histogram.len(), BUCKETS);
+        assert_eq!(
+            &histogram[..3],
+            &[
+                (Duration::ZERO, 1),
+                (Duration::from_micros(1), 1),
+                (Duration::from_micros(2), 2),
+            ]
+        );
+        assert_eq!(histogram[6], (Duration::from_micros(32), 1));
+        assert_eq!(histogram[BUCKETS - 1].1, 1);
+    }
+}
@@ -0,0 +1,360 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::budget::SearchBudget;
+use crate::config::SearchConfig;
+use crate::export::ActionFormatter;
+use crate::result::ChildStats;
+use crate::state::State;
+use crate::tree::Tree;
+
+// Reads the positions and moves sent by the front end, e.g. from FEN or SGF
+pub trait PositionParser<T, S> {
+    fn parse_position(&self, position: &str) -> Result<S, String>;
+
+    fn parse_action(&self, state: &S, action: &str) -> Result<T, String>;
+}
+
+// Commands waiting while a search runs, shared with its stop check
+#[derive(Debug)]
+struct Input {
+    lines: Receiver<String>,
+    pending: VecDeque<String>,
+    closed: bool,
+}
+
+impl Input {
+    fn next(&mut self) -> Option<String> {
+        if let Some(x) = self.pending.pop_front() {
+            return Some(x);
+        }
+        self.lines.recv().ok()
+    }
+
+    // Whether a stop came in, other commands wait for the search
+    fn stop_requested(&mut self) -> bool {
+        loop {
+            match self.lines.try_recv() {
+                Ok(x) if x.trim() == "stop" => return true,
+                Ok(x) => self.pending.push_back(x),
+                Err(TryRecvError::Empty) => return false,
+                Err(TryRecvError::Disconnected) => {
+                    self.closed = true;
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+// Line based engine loop in the style of UCI and GTP:
+//
+//   position <text> [moves <action>...]
+//   go [iterations <n>] [movetime <ms>]
+//   stop, isready, quit
+//
+// go answers with info lines every info_interval and a final bestmove, a
+// go without limits runs until stop. Applications write the parser and the
+// action formatter, the rest is wiring
+pub struct Engine<T, S, P, F>
+where
+    S: State<T>,
+    T: Clone,
+{
+    config: SearchConfig,
+    action: T,
+    parser: P,
+    formatter: Rc<F>,
+    state: Option<S>,
+    pub info_interval: Duration,
+}
+
+impl<T, S, P, F> fmt::Debug for Engine<T, S, P, F>
+where
+    S: State<T> + fmt::Debug,
+    T: Clone,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Engine")
+            .field("config", &self.config)
+            .field("state", &self.state)
+            .field("info_interval", &self.info_interval)
+            .finish()
+    }
+}
+
+impl<T, S, P, F> Engine<T, S, P, F>
+where
+    S: State<T> + 'static,
+    T: Clone + 'static,
+    P: PositionParser<T, S>,
+    F: ActionFormatter<T> + 'static,
+{
+    // `action` labels the root nodes, as for Tree::new
+    pub fn new(config: SearchConfig, action: T, parser: P, formatter: F) -> Self {
+        Self {
+            config,
+            action,
+            parser,
+            formatter: Rc::new(formatter),
+            state: None,
+            info_interval: Duration::from_secs(1),
+        }
+    }
+
+    pub fn state(&self) -> Option<&S> {
+        self.state.as_ref()
+    }
+
+    // Serves the commands of `input` until quit or its end, the output is
+    // handed back afterwards
+    pub fn run<R, W>(&mut self, input: R, output: W) -> io::Result<W>
+    where
+        R: BufRead + Send + 'static,
+        W: Write + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in input.lines() {
+                if line.is_err() || sender.send(line.unwrap_or_default()).is_err() {
+                    break;
+                }
+            }
+        });
+        let input = Rc::new(RefCell::new(Input {
+            lines: receiver,
+            pending: VecDeque::new(),
+            closed: false,
+        }));
+        let output = Rc::new(RefCell::new(output));
+
+        loop {
+            let line = match input.borrow_mut().next() {
+                Some(x) => x,
+                None => break,
+            };
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("position") => {
+                    let rest = line.trim()["position".len()..].trim();
+                    if let Err(e) = self.set_position(rest) {
+                        writeln!(output.borrow_mut(), "info string {}", e)?;
+                    }
+                }
+                Some("go") => {
+                    let words: Vec<&str> = words.collect();
+                    self.go(&words, &input, &output)?;
+                }
+                Some("isready") => writeln!(output.borrow_mut(), "readyok")?,
+                Some("quit") => break,
+                Some("stop") | None => {}
+                Some(x) => writeln!(output.borrow_mut(), "info string unknown command {}", x)?,
+            }
+            output.borrow_mut().flush()?;
+        }
+
+        drop(input);
+        match Rc::try_unwrap(output) {
+            Ok(x) => Ok(x.into_inner()),
+            Err(_) => Err(io::Error::other("output still borrowed")),
+        }
+    }
+
+    // `<position> [moves <action>...]`
+    pub fn set_position(&mut self, command: &str) -> Result<(), String> {
+        let (position, moves) = match command.split_once("moves") {
+            Some((x, y)) => (x.trim(), y),
+            None => (command.trim(), ""),
+        };
+        let mut state = self.parser.parse_position(position)?;
+        for word in moves.split_whitespace() {
+            let action = self.parser.parse_action(&state, word)?;
+            state.try_do_action(&action).map_err(|e| e.to_string())?;
+        }
+        self.state = Some(state);
+        Ok(())
+    }
+
+    fn go<W>(
+        &mut self,
+        words: &[&str],
+        input: &Rc<RefCell<Input>>,
+        output: &Rc<RefCell<W>>,
+    ) -> io::Result<()>
+    where
+        W: Write + 'static,
+    {
+        let state = match &self.state {
+            Some(x) => x.clone(),
+            None => {
+                writeln!(output.borrow_mut(), "info string no position")?;
+                return Ok(());
+            }
+        };
+
+        let input_ = Rc::clone(input);
+        let output_ = Rc::clone(output);
+        let formatter = Rc::clone(&self.formatter);
+        let interval = self.info_interval;
+        let unlimited = !words.contains(&"iterations") && !words.contains(&"movetime");
+        let start = Instant::now();
+        let last = Cell::new(start);
+        let mut budget = SearchBudget::until(move |tree: &Tree<T, S>| {
+            if last.get().elapsed() >= interval {
+                last.set(Instant::now());
+                let line = info(tree, &*formatter, start.elapsed());
+                let _ = writeln!(output_.borrow_mut(), "{}", line);
+            }
+            // Searches without limits also end with the input
+            let mut input = input_.borrow_mut();
+            input.stop_requested() || (unlimited && input.closed)
+        });
+        for pair in words.chunks(2) {
+            match pair {
+                ["iterations", x] => {
+                    if let Ok(x) = x.parse() {
+                        budget = budget.with_iterations(x);
+                    }
+                }
+                ["movetime", x] => {
+                    if let Ok(x) = x.parse() {
+                        budget = budget.with_time(Duration::from_millis(x));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut tree = Tree::with_config(self.config, self.action.clone(), state);
+        let result = tree.search_with(budget);
+        let mut output = output.borrow_mut();
+        writeln!(output, "{}", info(&tree, &*self.formatter, result.elapsed))?;
+        match result.best_action {
+            Some(x) => writeln!(output, "bestmove {}", self.formatter.format(&x)),
+            None => writeln!(output, "bestmove none"),
+        }
+    }
+}
+
+// `info iterations <n> nodes <n> time <ms> score <mean> pv <action>...`
+fn info<T, S, F>(tree: &Tree<T, S>, formatter: &F, elapsed: Duration) -> String
+where
+    S: State<T>,
+    T: Clone,
+    F: ActionFormatter<T> + ?Sized,
+{
+    let mut line = format!(
+        "info iterations {} nodes {} time {}",
+        tree.iterations,
+        tree.size,
+        elapsed.as_millis()
+    );
+    if let Some(best) = tree.best_child() {
+        let stats = ChildStats::from_node(&best.borrow());
+        let pv: Vec<String> = std::iter::once(&stats.action)
+            .chain(stats.line.iter())
+            .map(|x| formatter.format(x))
+            .collect();
+        line.push_str(&format!(" score {:.3} pv {}", stats.mean, pv.join(" ")));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockState;
+    use std::io::Cursor;
+
+    #[derive(Debug)]
+    struct Countdown;
+
+    impl PositionParser<u8, MockState<u8>> for Countdown {
+        fn parse_position(&self, position: &str) -> Result<MockState<u8>, String> {
+            match position.strip_prefix("countdown ") {
+                Some(x) => Ok(MockState::countdown(
+                    x.parse().map_err(|_| "bad count")?,
+                    0.5,
+                )),
+                None => Err(format!("unknown position {}", position)),
+            }
+        }
+
+        fn parse_action(&self, _state: &MockState<u8>, action: &str) -> Result<u8, String> {
+            action.parse().map_err(|_| format!("bad action {}", action))
+        }
+    }
+
+    fn run(commands: &str) -> String {
+        let mut engine = Engine::new(SearchConfig::new(1.0), 0, Countdown, |x: &u8| {
+            format!("m{}", x)
+        });
+        let output = engine
+            .run(Cursor::new(commands.to_string()), vec![])
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn go() {
+        let output = run("isready\nposition countdown 4 moves 4\ngo iterations 30\nquit\n");
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0], "readyok");
+        assert!(lines[1].starts_with("info iterations 30 nodes "));
+        assert!(lines[1].contains(" pv m"));
+        assert!(lines[2].starts_with("bestmove m"));
+        assert_eq!(lines.len(), 3);
+
+        let output = run("position countdown 3\ngo\nstop\nisready\n");
+        assert!(output.contains("\nbestmove "));
+        assert!(output.ends_with("readyok\n"));
+    }
+
+    #[test]
+    fn errors() {
+        let output = run("go\nposition chess\nposition countdown 3 moves x\nfoo\n");
+        assert_eq!(
+            output,
+            "info string no position\ninfo string unknown position chess\n\
+             info string bad action x\ninfo string unknown command foo\n"
+        );
+    }
+
+    #[test]
+    fn stop() {
+        let (sender, receiver) = mpsc::channel::<String>();
+        let mut input = Input {
+            lines: receiver,
+            pending: VecDeque::new(),
+            closed: false,
+        };
+        sender.send(String::from("isready")).unwrap();
+        assert!(!input.stop_requested());
+        sender.send(String::from("stop")).unwrap();
+        assert!(input.stop_requested());
+
+        // Commands sent during the search are served after it
+        assert_eq!(input.next().as_deref(), Some("isready"));
+        drop(sender);
+        assert!(!input.stop_requested());
+        assert!(input.closed);
+        assert_eq!(input.next(), None);
+    }
+}
@@ -0,0 +1,473 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::cache::CoverageStats;
+use crate::config::SearchConfig;
+use crate::node::{Node, NodeId};
+use crate::profile::IterationProfile;
+use crate::state::{Outcome, OutcomeCounts, State};
+
+// Two-sided 95% normal quantile
+pub(crate) const CONFIDENCE_Z: f32 = 1.96;
+// Moves of the principal variation kept below every root child
+const LINE_LENGTH: usize = 8;
+const HISTOGRAM_BUCKETS: usize = 8;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChildStats<T> {
+    pub action: T,
+    pub visits: u32,
+    pub mean: f32,
+    pub stderr: f32,
+    pub line: Vec<T>,
+    // Terminal children and the ones the solver proved
+    pub proven: Option<Outcome>,
+    pub outcomes: OutcomeCounts,
+    // Value buckets as (lower edge, count), with SearchConfig::quantile_sketch_size
+    pub histogram: Vec<(f32, u32)>,
+}
+
+impl<T> ChildStats<T>
+where
+    T: Clone,
+{
+    pub fn from_node<S>(node: &Node<T, S>) -> Self
+    where
+        S: State<T>,
+    {
+        let visits = node.visits as f32;
+        let (mean, stderr) = if node.visits > 1 {
+            let variance = node.value_variance() * visits / (visits - 1.);
+            (node.mean_value(), (variance / visits).sqrt())
+        } else if node.visits == 1 {
+            (node.mean_value(), f32::INFINITY)
+        } else {
+            (0., f32::INFINITY)
+        };
+
+        let mut line = vec![];
+        let mut next = node.most_visited_child();
+        while let Some(x) = next {
+            if line.len() == LINE_LENGTH || x.borrow().visits == 0 {
+                break;
+            }
+            line.push(x.borrow().action.clone());
+            next = x.borrow().most_visited_child();
+        }
+
+        // Proofs are for the root player, so is the outcome of a terminal node
+        let proven = match node.state.next_action() {
+            Some(_) => node.proven,
+            None => {
+                let mut root = node.parent();
+                while let Some(x) = root.as_ref().and_then(|x| x.borrow().parent()) {
+                    root = Some(x);
+                }
+                let player = match &root {
+                    Some(x) => x.borrow().state.current_player(),
+                    None => node.state.current_player(),
+                };
+                let players = node.state.num_players();
+                node.state.outcome().map(|x| x.for_player(player, players))
+            }
+        };
+
+        Self {
+            action: node.action.clone(),
+            visits: node.visits,
+            mean,
+            stderr,
+            line,
+            proven,
+            outcomes: node.outcomes,
+            histogram: node
+                .sketch
+                .as_ref()
+                .map_or(vec![], |x| x.histogram(HISTOGRAM_BUCKETS)),
+        }
+    }
+}
+
+impl<T> ChildStats<T> {
+    pub fn interval(&self) -> (f32, f32) {
+        let margin = CONFIDENCE_Z * self.stderr;
+        (self.mean - margin, self.mean + margin)
+    }
+
+    pub fn overlaps(&self, other: &Self) -> bool {
+        let (low, high) = self.interval();
+        let (other_low, other_high) = other.interval();
+        low <= other_high && other_low <= high
+    }
+}
+
+// How much worse the returned action can be than the best other one, see
+// Tree::suboptimality
+#[derive(Clone, Debug, PartialEq)]
+pub struct Suboptimality<T> {
+    pub action: T,
+    // Highest upper confidence bound of the other actions less the lower one
+    // of `action`, never below 0
+    pub bound: f32,
+    // Action with that upper bound, None when it is an unexplored one
+    pub rival: Option<T>,
+}
+
+impl<T> Suboptimality<T> {
+    pub fn is_within(&self, epsilon: f32) -> bool {
+        self.bound <= epsilon
+    }
+}
+
+// How long the current best action has been the best one
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stability {
+    pub lead_changes: u32,
+    pub stable_iterations: u32,
+    pub stable_time: Duration,
+}
+
+// Everything needed to re-run a search identically
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Repro {
+    pub version: String,
+    pub config: SearchConfig,
+    pub iterations: u32,
+    pub fingerprint: Option<u64>,
+}
+
+impl Repro {
+    pub fn new(config: SearchConfig, iterations: u32, fingerprint: Option<u64>) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            config,
+            iterations,
+            fingerprint,
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.config.seed
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DegradeCause {
+    NodeCap,
+    AllocationFailure,
+}
+
+// The search went on simulating the leaves it had, without expanding, from
+// this iteration
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Degradation {
+    pub cause: DegradeCause,
+    pub iteration: u32,
+}
+
+// A node as a backup left it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BackupStep {
+    pub node: NodeId,
+    pub visits: u32,
+    pub mean: f32,
+}
+
+// The nodes a value went through, from the leaf up, see
+// SearchConfig::capture_paths
+#[derive(Clone, Debug, PartialEq)]
+pub struct BackupPath {
+    pub iteration: u32,
+    pub value: f32,
+    pub steps: Vec<BackupStep>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReproError {
+    Version,
+    Fingerprint,
+}
+
+impl fmt::Display for ReproError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReproError::Version => write!(f, "capsule was recorded by another crate version"),
+            ReproError::Fingerprint => write!(f, "state does not match the recorded fingerprint"),
+        }
+    }
+}
+
+impl Error for ReproError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate<T> {
+    pub stats: ChildStats<T>,
+    pub bounds: (f32, f32),
+    pub chosen: bool,
+    // Whether its interval is clear of the next candidate's one
+    pub separated: bool,
+}
+
+// Top root moves by visits, enough for a UI to justify the choice
+#[derive(Clone, Debug, PartialEq)]
+pub struct Explanation<T> {
+    pub best_action: Option<T>,
+    pub candidates: Vec<Candidate<T>>,
+    pub iterations: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchResult<T> {
+    pub best_action: Option<T>,
+    pub iterations: u32,
+    pub steps: u64,
+    pub cost: f64,
+    pub nodes: u32,
+    // Deepest node below the root
+    pub max_depth: u32,
+    // Time spent searching over the life of the tree
+    pub elapsed: Duration,
+    pub recent_values: Vec<f32>,
+    pub children: Vec<ChildStats<T>>,
+    pub stability: Stability,
+    pub failures: u32,
+    // Distinct states met, when the tree counts them
+    pub coverage: Option<CoverageStats>,
+    pub degraded: Option<Degradation>,
+    // With SearchConfig::profile_iterations
+    pub profile: Option<IterationProfile>,
+    pub repro: Repro,
+}
+
+impl<T> SearchResult<T> {
+    pub fn repro(&self) -> Repro {
+        self.repro.clone()
+    }
+
+    pub fn root_value(&self) -> Option<f32> {
+        if self.recent_values.is_empty() {
+            return None;
+        }
+        Some(self.recent_values.iter().sum::<f32>() / self.recent_values.len() as f32)
+    }
+
+    // Confidence interval of the root value over the recent iterations
+    pub fn value_bounds(&self) -> Option<(f32, f32)> {
+        let n = self.recent_values.len();
+        if n < 2 {
+            return None;
+        }
+
+        let mean = self.root_value()?;
+        let variance = self
+            .recent_values
+            .iter()
+            .map(|x| (x - mean).powi(2))
+            .sum::<f32>()
+            / (n - 1) as f32;
+        let margin = CONFIDENCE_Z * (variance / n as f32).sqrt();
+
+        Some((mean - margin, mean + margin))
+    }
+
+    pub fn should_resign(&self, threshold: f32) -> bool {
+        match self.value_bounds() {
+            Some((_, high)) => high < threshold,
+            None => false,
+        }
+    }
+
+    pub fn is_winning(&self, threshold: f32) -> bool {
+        match self.value_bounds() {
+            Some((low, _)) => low > threshold,
+            None => false,
+        }
+    }
+
+    // Whether the two best children by mean are statistically distinguishable
+    pub fn top_moves_separated(&self) -> bool {
+        let mut children: Vec<&ChildStats<T>> = self.children.iter().collect();
+        children.sort_by(|x, y| {
+            y.mean
+                .partial_cmp(&x.mean)
+                .unwrap_or(std::cmp::Ordering::Less)
+        });
+
+        match (children.first(), children.get(1)) {
+            (Some(x), Some(y)) => !x.overlaps(y),
+            _ => false,
+        }
+    }
+}
+
+impl<T> SearchResult<T>
+where
+    T: Clone + PartialEq,
+{
+    pub fn explain(&self, top: usize) -> Explanation<T> {
+        let mut children: Vec<&ChildStats<T>> = self.children.iter().collect();
+        children.sort_by_key(|x| std::cmp::Reverse(x.visits));
+        children.truncate(top);
+
+        let candidates = children
+            .iter()
+            .enumerate()
+            .map(|(i, x)| Candidate {
+                stats: (*x).clone(),
+                bounds: x.interval(),
+                chosen: self.best_action.as_ref() == Some(&x.action),
+                separated: children.get(i + 1).is_some_and(|y| !x.overlaps(y)),
+            })
+            .collect();
+
+        Explanation {
+            best_action: self.best_action.clone(),
+            candidates,
+            iterations: self.iterations,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::ActionError;
+
+    #[derive(Debug, Clone)]
+    struct DummyState {}
+
+    impl State<u8> for DummyState {
+        fn next_action(&self) -> Option<u8> {
+            None
+        }
+
+        fn try_do_action(&mut self, _action: &u8) -> Result<f32, ActionError> {
+            Ok(0.0)
+        }
+    }
+
+    fn result(recent_values: Vec<f32>) -> SearchResult<u8> {
+        SearchResult {
+            best_action: None,
+            iterations: recent_values.len() as u32,
+            steps: 0,
+            cost: 0.,
+            nodes: 1,
+            max_depth: 0,
+            elapsed: Duration::ZERO,
+            recent_values,
+            children: vec![],
+            stability: Stability::default(),
+            failures: 0,
+            coverage: None,
+            degraded: None,
+            profile: None,
+            repro: Repro::new(SearchConfig::default(), 0, None),
+        }
+    }
+
+    fn child(action: u8, mean: f32, stderr: f32) -> ChildStats<u8> {
+        ChildStats {
+            action,
+            visits: 10,
+            mean,
+            stderr,
+            line: vec![],
+            proven: None,
+            outcomes: OutcomeCounts::default(),
+            histogram: vec![],
+        }
+    }
+
+    #[test]
+    fn value_bounds() {
+        assert!(result(vec![]).value_bounds().is_none());
+        assert!(result(vec![1.]).value_bounds().is_none());
+
+        let (low, high) = result(vec![0.4, 0.6, 0.4, 0.6]).value_bounds().unwrap();
+        assert!(low < 0.5 && high > 0.5);
+    }
+
+    #[test]
+    fn should_resign() {
+        let hopeless = result(vec![0.0, 0.05, 0.0, 0.05, 0.0, 0.05]);
+        assert!(hopeless.should_resign(0.1));
+        assert!(!hopeless.is_winning(0.1));
+
+        // Too uncertain to give up
+        let unclear = result(vec![0.0, 1.0, 0.0, 1.0]);
+        assert!(!unclear.should_resign(0.1));
+    }
+
+    #[test]
+    fn is_winning() {
+        let won = result(vec![1.0, 0.95, 1.0, 0.95, 1.0, 0.95]);
+        assert!(won.is_winning(0.9));
+        assert!(!won.should_resign(0.9));
+        assert!(!result(vec![1.0]).is_winning(0.9));
+    }
+
+    #[test]
+    fn child_stats() {
+        let node = Node::new(1, DummyState {});
+        assert_eq!(ChildStats::from_node(&node.borrow()).stderr, f32::INFINITY);
+
+        for x in [1., 2., 3.] {
+            node.borrow_mut().push_value(x);
+        }
+
+        let stats = ChildStats::from_node(&node.borrow());
+        assert_eq!(stats.mean, 2.);
+        assert!((stats.stderr - (1f32 / 3.).sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn top_moves_separated() {
+        let mut result = result(vec![]);
+        result.children = vec![child(1, 0.5, 0.01), child(2, 0.8, 0.01)];
+        assert!(result.top_moves_separated());
+
+        result.children.push(child(3, 0.75, 0.05));
+        assert!(!result.top_moves_separated());
+    }
+
+    #[test]
+    fn explain() {
+        let mut result = result(vec![]);
+        result.best_action = Some(2);
+        result.children = vec![
+            child(1, 0.5, 0.01),
+            child(2, 0.8, 0.01),
+            child(3, 0.75, 0.05),
+        ];
+        result.children[1].visits = 30;
+        result.children[2].visits = 20;
+
+        let explanation = result.explain(2);
+        let actions: Vec<u8> = explanation
+            .candidates
+            .iter()
+            .map(|x| x.stats.action)
+            .collect();
+        assert_eq!(actions, vec![2, 3]);
+        assert!(explanation.candidates[0].chosen);
+        assert!(!explanation.candidates[0].separated);
+        assert!(!explanation.candidates[1].chosen);
+    }
+}
@@ -0,0 +1,324 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::rc::Rc;
+
+use crate::cache::StateCache;
+use crate::config::SearchConfig;
+use crate::node::NodeRef;
+use crate::state::{State, StateKey};
+use crate::strategies::Evaluator;
+use crate::tree::Tree;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RootValue<T> {
+    // Position of the root in the states given to MultiRoot::new
+    pub index: usize,
+    pub value: f32,
+    pub visits: u32,
+    pub best_action: Option<T>,
+}
+
+// Alternative roots, e.g. the hands an opponent may hold, searched under one
+// budget on the calling thread. The roots share the evaluator and its cache,
+// and the leaves of all the roots are evaluated together in batches
+#[derive(Debug)]
+pub struct MultiRoot<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    trees: Vec<Tree<T, S>>,
+    evaluator: Option<Rc<dyn Evaluator<T, S>>>,
+}
+
+impl<T, S> MultiRoot<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    pub fn new(config: SearchConfig, action: T, states: Vec<S>) -> Self {
+        Self {
+            trees: states
+                .into_iter()
+                .map(|x| Tree::with_config(config, action.clone(), x))
+                .collect(),
+            evaluator: None,
+        }
+    }
+
+    pub fn with_evaluator<E>(mut self, evaluator: E) -> Self
+    where
+        E: Evaluator<T, S> + 'static,
+    {
+        let evaluator: Rc<dyn Evaluator<T, S>> = Rc::new(evaluator);
+        for tree in self.trees.iter_mut() {
+            tree.evaluator = Some(Rc::clone(&evaluator));
+        }
+        self.evaluator = Some(evaluator);
+        self
+    }
+
+    pub fn trees(&self) -> &[Tree<T, S>] {
+        &self.trees
+    }
+
+    pub fn tree_mut(&mut self, index: usize) -> Option<&mut Tree<T, S>> {
+        self.trees.get_mut(index)
+    }
+
+    // Splits the iterations evenly so the values stay comparable, the first
+    // roots take the remainder. The roots take turns, every round selects one
+    // leaf in each of them and evaluates those leaves in one
+    // Evaluator::evaluate_batch call
+    pub fn search(&mut self, iterations: u32) -> Vec<RootValue<T>> {
+        let count = self.trees.len() as u32;
+        if count == 0 {
+            return vec![];
+        }
+        let shares: Vec<u32> = (0..count)
+            .map(|i| iterations / count + u32::from(i < iterations % count))
+            .collect();
+
+        for (tree, share) in self.trees.iter_mut().zip(shares.iter()) {
+            if *share > 0 {
+                tree.warm_up();
+            }
+        }
+        for round in 0..shares[0] {
+            let mut leaves = vec![];
+            for (i, tree) in self.trees.iter_mut().enumerate() {
+                if round >= shares[i] || tree.out_of_budget() {
+                    continue;
+                }
+                let root = tree.root();
+                if let Some(x) = tree.next_leaf(&root) {
+                    leaves.push((i, x));
+                }
+            }
+            if leaves.is_empty() {
+                break;
+            }
+
+            self.prefetch(&leaves);
+            for (i, leaf) in leaves.iter() {
+                self.trees[*i].finish_leaf(leaf);
+            }
+        }
+        for tree in self.trees.iter_mut() {
+            tree.progress = 1.;
+        }
+        self.values()
+    }
+
+    // Evaluates the leaves that are not terminal or cached in one batch, each
+    // rollout then takes its evaluation
+    fn prefetch(&self, leaves: &[(usize, NodeRef<T, S>)]) {
+        let evaluator = match &self.evaluator {
+            Some(x) => x,
+            None => return,
+        };
+        let pending: Vec<&(usize, NodeRef<T, S>)> = leaves
+            .iter()
+            .filter(|(i, x)| {
+                let state = &x.borrow().state;
+                let cached = self.trees[*i]
+                    .evaluation_cache
+                    .as_ref()
+                    .is_some_and(|x| x.contains(state));
+                state.next_action().is_some() && !cached
+            })
+            .collect();
+        if pending.is_empty() {
+            return;
+        }
+
+        let borrowed: Vec<_> = pending.iter().map(|(_, x)| x.borrow()).collect();
+        let states: Vec<&S> = borrowed.iter().map(|x| &x.state).collect();
+        let evaluations = evaluator.evaluate_batch(&states);
+        for (((i, _), node), evaluation) in pending.iter().zip(borrowed.iter()).zip(evaluations) {
+            let tree = &self.trees[*i];
+            if let Some(cache) = &tree.evaluation_cache {
+                cache.insert(&node.state, evaluation.clone());
+            }
+            tree.prefetched.replace(Some((node.id(), evaluation)));
+        }
+    }
+
+    // Best root first
+    pub fn values(&self) -> Vec<RootValue<T>> {
+        let mut values: Vec<RootValue<T>> = self
+            .trees
+            .iter()
+            .enumerate()
+            .map(|(index, tree)| {
+                let root = tree.root();
+                let root = root.borrow();
+                RootValue {
+                    index,
+                    value: root.mean_value(),
+                    visits: root.visits,
+                    best_action: tree.best_child().map(|x| x.borrow().action.clone()),
+                }
+            })
+            .collect();
+        values.sort_by(|a, b| {
+            b.value
+                .partial_cmp(&a.value)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        values
+    }
+}
+
+impl<T, S> MultiRoot<T, S>
+where
+    S: State<T> + StateKey,
+    T: Clone,
+{
+    // Roots reaching the same states evaluate them once
+    pub fn with_evaluation_cache(mut self, capacity: usize) -> Self {
+        let cache = Rc::new(StateCache::new(capacity));
+        for tree in self.trees.iter_mut() {
+            tree.evaluation_cache = Some(Rc::clone(&cache));
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::ActionError;
+    use crate::strategies::Evaluation;
+    use crate::testing::MockState;
+    use std::cell::{Cell, RefCell};
+
+    #[test]
+    fn search() {
+        let states = vec![
+            MockState::countdown(3, 0.2),
+            MockState::countdown(3, 0.9),
+            MockState::countdown(3, 0.5),
+        ];
+        let mut roots = MultiRoot::new(SearchConfig::new(1.0), 0u8, states);
+        let values = roots.search(31);
+
+        assert_eq!(
+            values.iter().map(|x| x.index).collect::<Vec<_>>(),
+            vec![1, 2, 0]
+        );
+        assert_eq!(values[2].visits, 11);
+        assert_eq!(values[0].visits, 10);
+        assert!(values[0].best_action.is_some());
+        assert!(
+            MultiRoot::new(SearchConfig::new(1.0), 0u8, Vec::<MockState<u8>>::new())
+                .search(10)
+                .is_empty()
+        );
+    }
+
+    #[derive(Debug, Clone)]
+    struct CountdownState {
+        moves: u8,
+    }
+
+    impl StateKey for CountdownState {
+        type Key = u8;
+
+        fn key(&self) -> u8 {
+            self.moves
+        }
+    }
+
+    impl State<u8> for CountdownState {
+        fn next_action(&self) -> Option<u8> {
+            if self.moves == 0 {
+                return None;
+            }
+            Some(self.moves)
+        }
+
+        fn try_do_action(&mut self, _action: &u8) -> Result<f32, ActionError> {
+            self.moves -= 1;
+            Ok(0.)
+        }
+    }
+
+    #[derive(Debug)]
+    struct Counted {
+        calls: Rc<Cell<u32>>,
+    }
+
+    impl Evaluator<u8, CountdownState> for Counted {
+        fn evaluate(&self, state: &CountdownState) -> Evaluation<u8> {
+            self.calls.set(self.calls.get() + 1);
+            (vec![], state.moves as f32)
+        }
+    }
+
+    #[derive(Debug)]
+    struct Batches {
+        sizes: Rc<RefCell<Vec<usize>>>,
+    }
+
+    impl Evaluator<u8, CountdownState> for Batches {
+        fn evaluate(&self, _state: &CountdownState) -> Evaluation<u8> {
+            panic!("evaluated outside of a batch");
+        }
+
+        fn evaluate_batch(&self, states: &[&CountdownState]) -> Vec<Evaluation<u8>> {
+            self.sizes.borrow_mut().push(states.len());
+            states.iter().map(|x| (vec![], x.moves as f32)).collect()
+        }
+    }
+
+    #[test]
+    fn batched_evaluation() {
+        let sizes = Rc::new(RefCell::new(vec![]));
+        let states = vec![
+            CountdownState { moves: 6 },
+            CountdownState { moves: 7 },
+            CountdownState { moves: 8 },
+        ];
+        let mut roots = MultiRoot::new(SearchConfig::new(1.0), 0, states).with_evaluator(Batches {
+            sizes: Rc::clone(&sizes),
+        });
+        let values = roots.search(31);
+
+        // One batch per round, with a leaf of every root still searching
+        let sizes = sizes.borrow();
+        assert_eq!(sizes.len(), 11);
+        assert_eq!(sizes[0], 3);
+        assert_eq!(sizes[10], 1);
+        assert_eq!(values.iter().map(|x| x.visits).sum::<u32>(), 31);
+        assert_eq!(values[0].index, 2);
+    }
+
+    #[test]
+    fn shared_evaluation_cache() {
+        let calls = Rc::new(Cell::new(0));
+        let states = vec![CountdownState { moves: 4 }, CountdownState { moves: 5 }];
+        let mut roots = MultiRoot::new(SearchConfig::new(1.0), 0, states)
+            .with_evaluator(Counted {
+                calls: Rc::clone(&calls),
+            })
+            .with_evaluation_cache(16);
+
+        roots.tree_mut(0).unwrap().search(10);
+        let first = calls.get();
+
+        // Below its root the second tree only meets states the first evaluated
+        roots.tree_mut(1).unwrap().search(10);
+        assert_eq!(calls.get(), first + 1);
+        assert_eq!(roots.values()[0].index, 1);
+    }
+}
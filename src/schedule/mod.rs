@@ -0,0 +1,107 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::fmt::Debug;
+
+// Maps search progress in [0, 1] to a parameter value
+pub trait Schedule: Debug {
+    fn value(&self, progress: f32) -> f32;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConstantSchedule {
+    pub value: f32,
+}
+
+impl ConstantSchedule {
+    pub fn new(value: f32) -> Self {
+        Self { value }
+    }
+}
+
+impl Schedule for ConstantSchedule {
+    fn value(&self, _progress: f32) -> f32 {
+        self.value
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinearSchedule {
+    pub start: f32,
+    pub end: f32,
+}
+
+impl LinearSchedule {
+    pub fn new(start: f32, end: f32) -> Self {
+        Self { start, end }
+    }
+}
+
+impl Schedule for LinearSchedule {
+    fn value(&self, progress: f32) -> f32 {
+        let progress = progress.clamp(0., 1.);
+        self.start + (self.end - self.start) * progress
+    }
+}
+
+// Geometric cooling from start to end, as in simulated annealing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExponentialSchedule {
+    pub start: f32,
+    pub end: f32,
+}
+
+impl ExponentialSchedule {
+    pub fn new(start: f32, end: f32) -> Self {
+        Self { start, end }
+    }
+}
+
+impl Schedule for ExponentialSchedule {
+    fn value(&self, progress: f32) -> f32 {
+        let progress = progress.clamp(0., 1.);
+        if self.start <= 0. || self.end <= 0. {
+            return LinearSchedule::new(self.start, self.end).value(progress);
+        }
+        self.start * (self.end / self.start).powf(progress)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant() {
+        let schedule = ConstantSchedule::new(0.5);
+        assert_eq!(schedule.value(0.), 0.5);
+        assert_eq!(schedule.value(1.), 0.5);
+    }
+
+    #[test]
+    fn linear() {
+        let schedule = LinearSchedule::new(1., 0.);
+        assert_eq!(schedule.value(0.), 1.);
+        assert_eq!(schedule.value(0.5), 0.5);
+        assert_eq!(schedule.value(1.), 0.);
+
+        // Progress outside of the search is clamped
+        assert_eq!(schedule.value(2.), 0.);
+    }
+
+    #[test]
+    fn exponential() {
+        let schedule = ExponentialSchedule::new(1., 0.01);
+        assert_eq!(schedule.value(0.), 1.);
+        assert!((schedule.value(0.5) - 0.1).abs() < 1e-6);
+        assert!((schedule.value(1.) - 0.01).abs() < 1e-6);
+    }
+}
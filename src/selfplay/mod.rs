@@ -0,0 +1,293 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::config::SearchConfig;
+use crate::node::NodeRef;
+use crate::state::State;
+use crate::tree::Tree;
+
+// Value a sample is trained on: the observed result of the game, the value
+// of the search at the sample, or `lambda` of the first and the rest of the
+// second
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ValueTarget {
+    Outcome,
+    Search,
+    Mixed(f32),
+}
+
+impl ValueTarget {
+    pub fn value(&self, outcome: f32, search: f32) -> f32 {
+        match *self {
+            ValueTarget::Outcome => outcome,
+            ValueTarget::Search => search,
+            ValueTarget::Mixed(lambda) => lambda * outcome + (1. - lambda) * search,
+        }
+    }
+}
+
+// Temperature of the move choice, `opening` for the first `moves` moves of a
+// game and `rest` after them. The moves are drawn by visits^(1 / T), T at 0
+// plays the most visited one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MoveTemperature {
+    pub opening: f32,
+    pub moves: u32,
+    pub rest: f32,
+}
+
+impl MoveTemperature {
+    pub fn new(opening: f32, moves: u32, rest: f32) -> Self {
+        Self {
+            opening,
+            moves,
+            rest,
+        }
+    }
+
+    pub fn value(&self, move_number: u32) -> f32 {
+        if move_number < self.moves {
+            self.opening
+        } else {
+            self.rest
+        }
+    }
+}
+
+// One searched position. Both values are seen as the search sees them: the
+// outcome from State::outcome, else the rewards from this move to the end
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrainingSample<T, S> {
+    pub state: S,
+    pub player: usize,
+    // Visit shares of the root actions
+    pub policy: Vec<(T, f32)>,
+    pub search_value: f32,
+    pub outcome_value: f32,
+    // Target picked by SelfPlay::target
+    pub value: f32,
+}
+
+// Games where the same settings play every move, each searched by a fresh
+// tree seeded from the game seed and the move number
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SelfPlay {
+    pub config: SearchConfig,
+    pub iterations: u32,
+    // 0 for no limit
+    pub max_moves: u32,
+    pub target: ValueTarget,
+    // None plays the best action of every search
+    pub temperature: Option<MoveTemperature>,
+}
+
+impl SelfPlay {
+    pub fn new(config: SearchConfig, iterations: u32) -> Self {
+        Self {
+            config,
+            iterations,
+            max_moves: 0,
+            target: ValueTarget::Outcome,
+            temperature: None,
+        }
+    }
+
+    pub fn with_target(mut self, target: ValueTarget) -> Self {
+        self.target = target;
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: MoveTemperature) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn play<T, S>(&self, action: T, mut state: S, seed: u64) -> Vec<TrainingSample<T, S>>
+    where
+        S: State<T>,
+        T: Clone,
+    {
+        let mut samples = vec![];
+        let mut rewards = vec![];
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        while state.outcome().is_none()
+            && (self.max_moves == 0 || samples.len() < self.max_moves as usize)
+        {
+            let config = SearchConfig {
+                seed: seed.wrapping_add(samples.len() as u64),
+                ..self.config
+            };
+            let mut tree = Tree::with_config(config, action.clone(), state.clone());
+            let best = match tree.search(self.iterations) {
+                Some(x) => x.borrow().action.clone(),
+                None => match state.next_action() {
+                    Some(x) => x,
+                    None => break,
+                },
+            };
+
+            let root = tree.root();
+            let root = root.borrow();
+            let temperature = self
+                .temperature
+                .map_or(0., |x| x.value(samples.len() as u32));
+            let best = match self.sample_move(&root.children, temperature, &mut rng) {
+                Some(x) => x,
+                None => best,
+            };
+            let visits: u32 = root.children.iter().map(|x| x.borrow().visits).sum();
+            let policy = root
+                .children
+                .iter()
+                .filter(|x| !x.borrow().illegal)
+                .map(|x| {
+                    let x = x.borrow();
+                    let share = match visits {
+                        0 => 0.,
+                        n => x.visits as f32 / n as f32,
+                    };
+                    (x.action.clone(), share)
+                })
+                .collect();
+            samples.push(TrainingSample {
+                state: state.clone(),
+                player: state.current_player(),
+                policy,
+                search_value: root.mean_value(),
+                outcome_value: 0.,
+                value: 0.,
+            });
+
+            match state.try_do_action(&best) {
+                Ok(x) => rewards.push(x),
+                Err(_) => break,
+            }
+        }
+
+        // Search values are for the player to move, so is the outcome
+        let players = state.num_players();
+        let outcome = state.outcome();
+        let mut to_go = 0.;
+        for (i, sample) in samples.iter_mut().enumerate().rev() {
+            to_go += rewards.get(i).copied().unwrap_or(0.);
+            sample.outcome_value = match outcome {
+                Some(x) => self
+                    .config
+                    .outcome_value(x.for_player(sample.player, players)),
+                None => to_go,
+            };
+            sample.value = self.target.value(sample.outcome_value, sample.search_value);
+        }
+        samples
+    }
+
+    // Action drawn by visits^(1 / temperature), None at 0
+    fn sample_move<T, S>(
+        &self,
+        children: &[NodeRef<T, S>],
+        temperature: f32,
+        rng: &mut StdRng,
+    ) -> Option<T>
+    where
+        S: State<T>,
+        T: Clone,
+    {
+        if temperature <= 0. {
+            return None;
+        }
+        let legal: Vec<&NodeRef<T, S>> = children
+            .iter()
+            .filter(|x| !x.borrow().illegal && x.borrow().visits > 0)
+            .collect();
+        let max = legal.iter().map(|x| x.borrow().visits).max()? as f32;
+        let weights: Vec<f32> = legal
+            .iter()
+            .map(|x| (x.borrow().visits as f32 / max).powf(1. / temperature))
+            .collect();
+
+        let mut target = rng.gen::<f32>() * weights.iter().sum::<f32>();
+        for (x, weight) in legal.iter().zip(weights.iter()) {
+            if target < *weight {
+                return Some(x.borrow().action.clone());
+            }
+            target -= weight;
+        }
+        legal.last().map(|x| x.borrow().action.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::Outcome;
+    use crate::testing::{MockState, PathGame};
+
+    #[test]
+    fn play() {
+        let game = PathGame::new(3, |x| match x[0] {
+            3 => Outcome::Win,
+            _ => Outcome::Loss,
+        });
+        let samples = SelfPlay::new(SearchConfig::game(), 100).play(0, game, 1);
+
+        assert_eq!(samples.len(), 3);
+        // Player 0 wins, every target is seen by the player to move
+        let observed: Vec<f32> = samples.iter().map(|x| x.outcome_value).collect();
+        assert_eq!(observed, vec![1., 0., 1.]);
+        assert!(samples.iter().all(|x| x.value == x.outcome_value));
+        let total: f32 = samples[0].policy.iter().map(|x| x.1).sum();
+        assert!((total - 1.).abs() < 1e-6);
+        assert_eq!(samples[1].player, samples[1].state.current_player());
+    }
+
+    #[test]
+    fn targets() {
+        let mut selfplay = SelfPlay::new(SearchConfig::new(1.0), 20);
+        selfplay.max_moves = 2;
+        let samples =
+            selfplay
+                .with_target(ValueTarget::Mixed(0.25))
+                .play(0, MockState::countdown(4, 0.5), 0);
+
+        // Rewards to go without an outcome
+        let observed: Vec<f32> = samples.iter().map(|x| x.outcome_value).collect();
+        assert_eq!(observed, vec![1., 0.5]);
+        for x in samples.iter() {
+            let mixed = 0.25 * x.outcome_value + 0.75 * x.search_value;
+            assert!((x.value - mixed).abs() < 1e-6);
+        }
+        assert_eq!(ValueTarget::Search.value(1., 0.3), 0.3);
+    }
+
+    #[test]
+    fn temperature() {
+        let game = PathGame::new(3, |x| match x[0] {
+            3 => Outcome::Win,
+            _ => Outcome::Loss,
+        });
+        let selfplay = SelfPlay::new(SearchConfig::game(), 100)
+            .with_temperature(MoveTemperature::new(1., 1, 0.));
+        let explored: Vec<bool> = (0..20)
+            .map(|seed| {
+                let samples = selfplay.play(0, game.clone(), seed);
+                samples[1].state.moves()[0] != 3
+            })
+            .collect();
+        assert!(explored.iter().any(|x| *x) && !explored.iter().all(|x| *x));
+
+        let temperature = MoveTemperature::new(1., 30, 0.);
+        assert_eq!((temperature.value(29), temperature.value(30)), (1., 0.));
+    }
+}
@@ -0,0 +1,178 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::marker::PhantomData;
+use std::mem;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::config::SearchConfig;
+use crate::result::SearchResult;
+use crate::state::State;
+use crate::strategies::SimulationStrategy;
+use crate::tree::Tree;
+
+// The per-search scratch of a tree: config, random generator, iteration
+// count and deadline. The persistent statistics stay in the tree, which is
+// only borrowed while a run lasts, so analysis can read it between the runs.
+// Virtual losses never outlive a Tree::search_pipelined call
+#[derive(Debug)]
+pub struct SearchSession<T, S> {
+    config: SearchConfig,
+    rng: StdRng,
+    start: Instant,
+    deadline: Option<Instant>,
+    iterations: u32,
+    elapsed: Duration,
+    tree: PhantomData<fn(T, S)>,
+}
+
+impl<T, S> SearchSession<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    // Limits the whole session to `time` from its beginning
+    pub fn with_time(mut self, time: Duration) -> Self {
+        self.deadline = Some(self.start + time);
+        self
+    }
+
+    pub fn config(&self) -> &SearchConfig {
+        &self.config
+    }
+
+    // Iterations run by the session, whatever the tree had before
+    pub fn iterations(&self) -> u32 {
+        self.iterations
+    }
+
+    // Time spent in the runs
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.deadline.is_some_and(|x| Instant::now() >= x)
+    }
+
+    // Up to `iterations` more iterations on `tree` with the session config and
+    // generator, returns how many ran
+    pub fn run(&mut self, tree: &mut Tree<T, S>, iterations: u32) -> u32 {
+        let start = Instant::now();
+        self.swap(tree);
+        tree.deadline = self.deadline;
+        tree.warm_up();
+        let root = tree.root();
+        let mut ran = 0;
+        while ran < iterations && !self.is_over() && !tree.out_of_budget() {
+            tree.progress = ran as f32 / iterations as f32;
+            let leaf = match tree.next_leaf(&root) {
+                Some(x) => x,
+                None => break,
+            };
+            let reward = tree.simulate(&leaf);
+            tree.finish_iteration(&root, &leaf, reward, true);
+            ran += 1;
+        }
+        tree.deadline = None;
+        tree.progress = 1.;
+        tree.elapsed += start.elapsed();
+        self.swap(tree);
+
+        self.iterations += ran;
+        self.elapsed += start.elapsed();
+        ran
+    }
+
+    // Result of the tree as searched under the session config
+    pub fn finish(mut self, tree: &mut Tree<T, S>) -> SearchResult<T> {
+        self.swap(tree);
+        let result = tree.result();
+        self.swap(tree);
+        result
+    }
+
+    fn swap(&mut self, tree: &mut Tree<T, S>) {
+        mem::swap(&mut self.config, &mut tree.config);
+        mem::swap(&mut self.rng, tree.rng.get_mut());
+    }
+}
+
+impl<T, S> Tree<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    // Starts a search with its own config and generator seeded from it, the
+    // tree keeps its own for searches outside the session
+    pub fn begin_search(&self, config: SearchConfig) -> SearchSession<T, S> {
+        SearchSession {
+            config,
+            rng: StdRng::seed_from_u64(config.seed),
+            start: Instant::now(),
+            deadline: None,
+            iterations: 0,
+            elapsed: Duration::ZERO,
+            tree: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::DebugFormatter;
+    use crate::testing::MockState;
+
+    fn config() -> SearchConfig {
+        let mut config = SearchConfig::new(1.0);
+        config.random_expansion = true;
+        config.seed = 3;
+        config
+    }
+
+    #[test]
+    fn run() {
+        let mut tree = Tree::new(2.0, 0u8, MockState::countdown(5, 0.5));
+        let mut session = tree.begin_search(config());
+        assert_eq!(session.run(&mut tree, 10), 10);
+
+        // The tree can be read between the runs
+        assert_eq!(tree.root().borrow().visits, 10);
+        assert_eq!(tree.config.learning_rate, 2.0);
+        assert_eq!(session.run(&mut tree, 10), 10);
+        assert_eq!(session.iterations(), 20);
+        let result = session.finish(&mut tree);
+        assert_eq!(result.iterations, 20);
+        assert_eq!(result.repro.config.seed, 3);
+        assert_eq!(tree.config.seed, 0);
+
+        // As if the tree had been searched with the session config
+        let mut other = Tree::with_config(config(), 0u8, MockState::countdown(5, 0.5));
+        other.search(20);
+        assert_eq!(
+            tree.to_json(&DebugFormatter, 3),
+            other.to_json(&DebugFormatter, 3)
+        );
+    }
+
+    #[test]
+    fn with_time() {
+        let mut tree = Tree::new(1.0, 0u8, MockState::countdown(5, 0.5));
+        let mut session = tree.begin_search(config()).with_time(Duration::ZERO);
+        assert!(session.is_over());
+        assert_eq!(session.run(&mut tree, 10), 0);
+        assert_eq!(tree.iterations, 0);
+    }
+}
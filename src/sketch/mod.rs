@@ -0,0 +1,260 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::collections::HashSet;
+
+// Bounded summary of a value distribution as weighted centroids, merging the
+// lightest neighbours so the tails keep their resolution like in a t-digest
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuantileSketch {
+    pub(crate) capacity: usize,
+    pub(crate) centroids: Vec<(f32, u32)>,
+    pub(crate) min: f32,
+    pub(crate) max: f32,
+}
+
+impl QuantileSketch {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(2),
+            centroids: Vec::with_capacity(capacity + 1),
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.centroids.iter().map(|x| x.1).sum()
+    }
+
+    pub fn add(&mut self, value: f32) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        let index = self.centroids.partition_point(|x| x.0 < value);
+        self.centroids.insert(index, (value, 1));
+        if self.centroids.len() <= self.capacity {
+            return;
+        }
+
+        let index = (0..self.centroids.len() - 1)
+            .min_by_key(|&i| self.centroids[i].1 + self.centroids[i + 1].1)
+            .unwrap();
+        let (x, w) = self.centroids[index];
+        let (y, v) = self.centroids.remove(index + 1);
+        self.centroids[index] = ((x * w as f32 + y * v as f32) / (w + v) as f32, w + v);
+    }
+
+    // Mirrors every value around pivot / 2, as x becomes pivot - x
+    pub(crate) fn reflect(&mut self, pivot: f32) {
+        for x in self.centroids.iter_mut() {
+            x.0 = pivot - x.0;
+        }
+        self.centroids.reverse();
+        let (min, max) = (self.min, self.max);
+        self.min = pivot - max;
+        self.max = pivot - min;
+    }
+
+    // Counts in equal-width buckets between the extremes as (lower edge, count),
+    // each centroid going to the bucket of its mean
+    pub fn histogram(&self, buckets: usize) -> Vec<(f32, u32)> {
+        if self.centroids.is_empty() || buckets == 0 {
+            return vec![];
+        }
+
+        let width = (self.max - self.min) / buckets as f32;
+        let mut histogram: Vec<(f32, u32)> = (0..buckets)
+            .map(|i| (self.min + width * i as f32, 0))
+            .collect();
+        for &(mean, weight) in self.centroids.iter() {
+            let index = if width > 0. {
+                (((mean - self.min) / width) as usize).min(buckets - 1)
+            } else {
+                0
+            };
+            histogram[index].1 += weight;
+        }
+        histogram
+    }
+
+    // Interpolates between the exact extremes and the centroid means placed at
+    // their middle rank, q is clamped into [0, 1]
+    pub fn quantile(&self, q: f32) -> Option<f32> {
+        if self.centroids.is_empty() {
+            return None;
+        }
+        let count = self.count() as f32;
+        let rank = q.clamp(0., 1.) * count;
+
+        let mut seen = 0.;
+        let mut previous = (0., self.min);
+        let centers = self.centroids.iter().map(|&(mean, weight)| {
+            seen += weight as f32;
+            (seen - weight as f32 / 2., mean)
+        });
+        for (center, value) in centers.chain(std::iter::once((count, self.max))) {
+            if rank <= center {
+                if center <= previous.0 {
+                    return Some(value);
+                }
+                let t = (rank - previous.0) / (center - previous.0);
+                return Some(previous.1 + (value - previous.1) * t);
+            }
+            previous = (center, value);
+        }
+
+        Some(self.max)
+    }
+
+    pub fn median(&self) -> Option<f32> {
+        self.quantile(0.5)
+    }
+
+    pub fn interquartile_range(&self) -> Option<f32> {
+        Some(self.quantile(0.75)? - self.quantile(0.25)?)
+    }
+}
+
+const HLL_BITS: u32 = 12;
+
+// Number of distinct hashes, exact up to `cap` and a HyperLogLog estimate
+// past it, within a few percent
+#[derive(Clone, Debug, PartialEq)]
+pub struct DistinctCounter {
+    cap: usize,
+    exact: HashSet<u64>,
+    registers: Vec<u8>,
+}
+
+impl DistinctCounter {
+    pub fn new(cap: usize) -> Self {
+        Self {
+            cap,
+            exact: HashSet::new(),
+            registers: vec![],
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.registers.is_empty()
+    }
+
+    pub fn add(&mut self, hash: u64) {
+        if !self.is_exact() {
+            self.add_register(hash);
+            return;
+        }
+
+        self.exact.insert(hash);
+        if self.exact.len() > self.cap {
+            self.registers = vec![0; 1 << HLL_BITS];
+            for x in std::mem::take(&mut self.exact) {
+                self.add_register(x);
+            }
+        }
+    }
+
+    fn add_register(&mut self, hash: u64) {
+        let index = (hash >> (64 - HLL_BITS)) as usize;
+        let rank = (hash << HLL_BITS | 1 << (HLL_BITS - 1)).leading_zeros() as u8 + 1;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    pub fn count(&self) -> u64 {
+        if self.is_exact() {
+            return self.exact.len() as u64;
+        }
+
+        let m = self.registers.len() as f64;
+        let sum: f64 = self.registers.iter().map(|&x| 0.5f64.powi(x.into())).sum();
+        let estimate = 0.7213 / (1. + 1.079 / m) * m * m / sum;
+
+        // Linear counting while many registers are still empty
+        let zeros = self.registers.iter().filter(|&&x| x == 0).count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            return (m * (m / zeros as f64).ln()).round() as u64;
+        }
+        estimate.round() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    #[test]
+    fn quantile() {
+        let mut sketch = QuantileSketch::new(16);
+        assert!(sketch.median().is_none());
+
+        for i in 0..=100 {
+            sketch.add(i as f32);
+        }
+        assert_eq!(sketch.count(), 101);
+        assert!((sketch.median().unwrap() - 50.).abs() < 5.);
+        assert!((sketch.interquartile_range().unwrap() - 50.).abs() < 10.);
+        assert_eq!(sketch.quantile(0.), Some(0.));
+        assert_eq!(sketch.quantile(1.), Some(100.));
+    }
+
+    #[test]
+    fn heavy_tail() {
+        // A single huge outlier drags the mean but not the median
+        let mut sketch = QuantileSketch::new(8);
+        for _i in 0..99 {
+            sketch.add(1.);
+        }
+        sketch.add(10_000.);
+        assert_eq!(sketch.median(), Some(1.));
+    }
+
+    #[test]
+    fn distinct_counter() {
+        let hash = |x: u64| {
+            let mut hasher = DefaultHasher::new();
+            x.hash(&mut hasher);
+            hasher.finish()
+        };
+        let mut counter = DistinctCounter::new(100);
+        for i in 0..100 {
+            counter.add(hash(i));
+            counter.add(hash(i));
+        }
+        assert!(counter.is_exact());
+        assert_eq!(counter.count(), 100);
+
+        for i in 0..20_000 {
+            counter.add(hash(i));
+        }
+        assert!(!counter.is_exact());
+        let error = (counter.count() as f64 - 20_000.).abs() / 20_000.;
+        assert!(error < 0.05, "{}", counter.count());
+    }
+
+    #[test]
+    fn histogram() {
+        let mut sketch = QuantileSketch::new(8);
+        assert!(sketch.histogram(4).is_empty());
+
+        // Either all or nothing, the mean of 0.5 never happens
+        for i in 0..40 {
+            sketch.add(if i % 2 == 0 { 0. } else { 1. });
+        }
+        let histogram = sketch.histogram(4);
+        let edges: Vec<f32> = histogram.iter().map(|x| x.0).collect();
+        assert_eq!(edges, vec![0., 0.25, 0.5, 0.75]);
+        assert_eq!((histogram[1].1, histogram[2].1), (0, 0));
+        assert!(histogram[0].1 >= 15 && histogram[3].1 >= 15);
+        assert_eq!(histogram[0].1 + histogram[3].1, 40);
+    }
+}
@@ -0,0 +1,150 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::node::{NodeId, NodeRef};
+use crate::state::{Outcome, State};
+use crate::tree::Tree;
+
+// Statistics of a node when the snapshot was taken, states are left out
+#[derive(Clone, Debug, PartialEq)]
+pub struct SnapshotNode<T> {
+    pub id: NodeId,
+    pub action: T,
+    pub visits: u32,
+    pub mean: f32,
+    pub proven: Option<Outcome>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+}
+
+// Frozen copy of the tree, cheap to clone and to share with other threads
+// while the search keeps going
+#[derive(Clone, Debug)]
+pub struct Snapshot<T> {
+    nodes: Arc<[SnapshotNode<T>]>,
+    index: Arc<HashMap<NodeId, usize>>,
+    pub iterations: u32,
+}
+
+impl<T> Snapshot<T> {
+    pub fn root(&self) -> &SnapshotNode<T> {
+        &self.nodes[0]
+    }
+
+    pub fn get(&self, index: usize) -> Option<&SnapshotNode<T>> {
+        self.nodes.get(index)
+    }
+
+    // Same ids as the live tree
+    pub fn find(&self, id: NodeId) -> Option<&SnapshotNode<T>> {
+        self.index.get(&id).map(|x| &self.nodes[*x])
+    }
+
+    pub fn children<'a>(
+        &'a self,
+        node: &'a SnapshotNode<T>,
+    ) -> impl Iterator<Item = &'a SnapshotNode<T>> + 'a {
+        node.children.iter().map(move |x| &self.nodes[*x])
+    }
+
+    pub fn size(&self) -> usize {
+        self.nodes.len()
+    }
+
+    // Most visited line from the root
+    pub fn principal_variation(&self) -> Vec<&SnapshotNode<T>> {
+        let mut line = vec![];
+        let mut node = self.root();
+        while let Some(x) = self.children(node).max_by_key(|x| x.visits) {
+            line.push(x);
+            node = x;
+        }
+        line
+    }
+}
+
+impl<T, S> Tree<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    pub fn snapshot(&self) -> Snapshot<T> {
+        let mut nodes: Vec<SnapshotNode<T>> = vec![];
+        let mut stack: Vec<(NodeRef<T, S>, Option<usize>)> = vec![(self.root(), None)];
+
+        while let Some((x, parent)) = stack.pop() {
+            let index = nodes.len();
+            let node = x.borrow();
+            nodes.push(SnapshotNode {
+                id: node.id(),
+                action: node.action.clone(),
+                visits: node.visits,
+                mean: node.mean_value(),
+                proven: node.proven,
+                parent,
+                children: vec![],
+            });
+            if let Some(parent) = parent {
+                nodes[parent].children.push(index);
+            }
+            stack.extend(
+                node.children
+                    .iter()
+                    .rev()
+                    .map(|y| (Rc::clone(y), Some(index))),
+            );
+        }
+
+        let index = nodes.iter().enumerate().map(|(i, x)| (x.id, i)).collect();
+        Snapshot {
+            nodes: nodes.into(),
+            index: Arc::new(index),
+            iterations: self.iterations,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockState;
+
+    #[test]
+    fn snapshot() {
+        let mut tree = Tree::new(1.0, 0u8, MockState::countdown(4, 0.5));
+        tree.search(40);
+        let snapshot = tree.snapshot();
+        assert_eq!(snapshot.size() as u32, tree.size);
+        assert_eq!(snapshot.root().visits, 40);
+
+        let child = tree.root().borrow().child_at(1).unwrap();
+        let frozen = snapshot.find(child.borrow().id()).unwrap();
+        assert_eq!(frozen.action, child.borrow().action);
+        assert_eq!(
+            snapshot.get(frozen.parent.unwrap()).unwrap().id,
+            tree.root().borrow().id()
+        );
+        let line = snapshot.principal_variation();
+        assert_eq!(line.len(), 3);
+        assert_eq!(line[0].parent, Some(0));
+
+        // Readers on other threads see the tree as it was
+        let reader = snapshot.clone();
+        let handle = std::thread::spawn(move || reader.root().visits);
+        tree.search(10);
+        assert_eq!(handle.join().unwrap(), 40);
+        assert_eq!(snapshot.root().visits, 40);
+    }
+}
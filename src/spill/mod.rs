@@ -0,0 +1,553 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::checkpoint::{encode_subtree, invalid_data, ActionBytes, Reader, Writer};
+use crate::config::SearchConfig;
+use crate::events::TreeEvent;
+use crate::node::{stat_from_f64, stat_to_f64, Node, NodeId, NodeRef, WeakNodeRef};
+use crate::sketch::QuantileSketch;
+use crate::state::{Outcome, State};
+use crate::tree::Tree;
+
+type Encoder<T, S> = Rc<dyn Fn(&NodeRef<T, S>) -> io::Result<Vec<u8>>>;
+type Decoder<T, S> = Rc<dyn Fn(&[u8], &S) -> io::Result<Vec<NodeRef<T, S>>>>;
+
+struct Spilled<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    node: WeakNodeRef<T, S>,
+    path: PathBuf,
+    // Ids of the spilled descendants in pre-order, reassigned on restore
+    ids: Vec<NodeId>,
+}
+
+impl<T, S> Clone for Spilled<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            node: self.node.clone(),
+            path: self.path.clone(),
+            ids: self.ids.clone(),
+        }
+    }
+}
+
+// Subtrees written to disk to keep the resident part of the tree bounded
+#[derive(Clone)]
+pub struct Spill<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    dir: PathBuf,
+    max_resident: u32,
+    next_file: u64,
+    spilled: Vec<Spilled<T, S>>,
+    encode: Encoder<T, S>,
+    decode: Decoder<T, S>,
+}
+
+impl<T, S> Spill<T, S>
+where
+    S: State<T>,
+    T: Clone + ActionBytes,
+{
+    pub fn new<P: AsRef<Path>>(dir: P, max_resident: u32) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            max_resident,
+            next_file: 0,
+            spilled: vec![],
+            encode: Rc::new(|node: &NodeRef<T, S>| encode_spill(node)),
+            decode: Rc::new(|bytes: &[u8], state: &S| decode_spill(bytes, state)),
+        }
+    }
+}
+
+// The checkpoint encoding of the subtree, then the fields the checkpoint
+// leaves out for every node in the same pre-order
+fn encode_spill<T, S>(node: &NodeRef<T, S>) -> io::Result<Vec<u8>>
+where
+    S: State<T>,
+    T: Clone + ActionBytes,
+{
+    let checkpoint = encode_subtree(node, 0, None)?;
+    let mut out = Writer { bytes: vec![] };
+    out.u64(checkpoint.len() as u64);
+    out.bytes.extend_from_slice(&checkpoint);
+
+    for x in Tree::descendants(node) {
+        let x = x.borrow();
+        out.u32(x.pruned_at);
+        out.f64(x.cost);
+        out.u8(x.illegal as u8);
+        out.u8(match x.proven {
+            None => 0,
+            Some(Outcome::Win) => 1,
+            Some(Outcome::Draw) => 2,
+            Some(Outcome::Loss) => 3,
+        });
+        out.u32(x.outcomes.wins);
+        out.u32(x.outcomes.draws);
+        out.u32(x.outcomes.losses);
+        match x.prior {
+            Some(prior) => {
+                out.u8(1);
+                out.f32(prior);
+            }
+            None => out.u8(0),
+        }
+        match x.seeded {
+            Some((visits, mean)) => {
+                out.u8(1);
+                out.u32(visits);
+                out.f64(stat_to_f64(mean));
+            }
+            None => out.u8(0),
+        }
+        out.u32(x.payoff_visits);
+        out.u32(x.payoffs.len() as u32);
+        for payoff in x.payoffs.iter() {
+            out.f64(stat_to_f64(*payoff));
+        }
+        match &x.policy {
+            Some(policy) => {
+                out.u8(1);
+                out.u32(policy.len() as u32);
+                for (action, p) in policy.iter() {
+                    out.action(action)?;
+                    out.f32(*p);
+                }
+            }
+            None => out.u8(0),
+        }
+        match &x.untried_actions {
+            Some(actions) => {
+                out.u8(1);
+                out.u32(actions.len() as u32);
+                for action in actions.iter() {
+                    out.action(action)?;
+                }
+            }
+            None => out.u8(0),
+        }
+        match &x.sketch {
+            Some(sketch) => {
+                out.u8(1);
+                out.u32(sketch.capacity as u32);
+                out.f32(sketch.min);
+                out.f32(sketch.max);
+                out.u32(sketch.centroids.len() as u32);
+                for (mean, weight) in sketch.centroids.iter() {
+                    out.f32(*mean);
+                    out.u32(*weight);
+                }
+            }
+            None => out.u8(0),
+        }
+    }
+    Ok(out.bytes)
+}
+
+fn decode_spill<T, S>(bytes: &[u8], state: &S) -> io::Result<Vec<NodeRef<T, S>>>
+where
+    S: State<T>,
+    T: Clone + ActionBytes,
+{
+    let mut input = Reader { bytes };
+    let len = input.u64()? as usize;
+    let tree = Tree::decode(input.take(len)?, SearchConfig::default(), state.clone())?;
+    let root = tree.root();
+
+    for x in Tree::descendants(&root) {
+        let mut x = x.borrow_mut();
+        x.pruned_at = input.u32()?;
+        x.cost = input.f64()?;
+        x.illegal = input.u8()? != 0;
+        x.proven = match input.u8()? {
+            0 => None,
+            1 => Some(Outcome::Win),
+            2 => Some(Outcome::Draw),
+            3 => Some(Outcome::Loss),
+            _ => return Err(invalid_data("invalid proven outcome")),
+        };
+        x.outcomes.wins = input.u32()?;
+        x.outcomes.draws = input.u32()?;
+        x.outcomes.losses = input.u32()?;
+        if input.u8()? != 0 {
+            x.prior = Some(input.f32()?);
+        }
+        if input.u8()? != 0 {
+            x.seeded = Some((input.u32()?, stat_from_f64(input.f64()?)));
+        }
+        x.payoff_visits = input.u32()?;
+        for _i in 0..input.u32()? {
+            x.payoffs.push(stat_from_f64(input.f64()?));
+        }
+        if input.u8()? != 0 {
+            let mut policy = vec![];
+            for _i in 0..input.u32()? {
+                policy.push((input.action()?, input.f32()?));
+            }
+            x.policy = Some(policy);
+        }
+        if input.u8()? != 0 {
+            let mut actions = vec![];
+            for _i in 0..input.u32()? {
+                actions.push(input.action()?);
+            }
+            x.untried_actions = Some(actions);
+        }
+        if input.u8()? != 0 {
+            let mut sketch = QuantileSketch::new(input.u32()? as usize);
+            sketch.min = input.f32()?;
+            sketch.max = input.f32()?;
+            for _i in 0..input.u32()? {
+                sketch.centroids.push((input.f32()?, input.u32()?));
+            }
+            x.sketch = Some(sketch);
+        }
+    }
+
+    let children = std::mem::take(&mut root.borrow_mut().children);
+    Ok(children)
+}
+
+// Expansion cursors are states, which can't be written out, and virtual
+// losses are taken back out through the resident nodes
+fn spillable<T, S>(node: &Node<T, S>) -> bool
+where
+    S: State<T>,
+    T: Clone,
+{
+    node.expansion_state.is_none() && node.virtual_visits == 0
+}
+
+impl<T, S> Spill<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    pub fn spilled_nodes(&self) -> u32 {
+        // Entries whose node has been pruned meanwhile no longer count
+        self.spilled
+            .iter()
+            .filter(|x| x.node.strong_count() > 0)
+            .map(|x| x.ids.len() as u32)
+            .sum()
+    }
+
+    pub fn spilled_subtrees(&self) -> usize {
+        self.spilled.len()
+    }
+}
+
+impl<T, S> fmt::Debug for Spill<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Spill")
+            .field("dir", &self.dir)
+            .field("max_resident", &self.max_resident)
+            .field("spilled_subtrees", &self.spilled.len())
+            .finish()
+    }
+}
+
+impl<T, S> Tree<T, S>
+where
+    S: State<T>,
+    T: Clone + ActionBytes,
+{
+    pub fn with_spill<P: AsRef<Path>>(mut self, dir: P, max_resident: u32) -> Self {
+        self.spill = Some(Spill::new(dir, max_resident));
+        self
+    }
+}
+
+impl<T, S> Tree<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    pub fn spill(&self) -> Option<&Spill<T, S>> {
+        self.spill.as_ref()
+    }
+
+    pub fn resident_size(&self) -> u32 {
+        match &self.spill {
+            Some(x) => self.size.saturating_sub(x.spilled_nodes()),
+            None => self.size,
+        }
+    }
+
+    pub(crate) fn is_spilled(&self, node: &NodeRef<T, S>) -> bool {
+        match &self.spill {
+            Some(x) => x
+                .spilled
+                .iter()
+                .any(|y| y.node.ptr_eq(&Rc::downgrade(node))),
+            None => false,
+        }
+    }
+
+    // Forgets the spilled subtrees that are no longer below `root` and removes
+    // their files
+    pub(crate) fn drop_spilled_outside(&mut self, root: &NodeRef<T, S>) {
+        let spill = match &mut self.spill {
+            Some(x) => x,
+            None => return,
+        };
+
+        spill.spilled.retain(|x| {
+            let mut node = x.node.upgrade();
+            while let Some(y) = node {
+                if Rc::ptr_eq(&y, root) {
+                    return true;
+                }
+                node = y.borrow().parent();
+            }
+            let _ = fs::remove_file(&x.path);
+            false
+        });
+    }
+
+    // Brings the children of a spilled node back into memory
+    pub(crate) fn restore_spilled(&mut self, node: &NodeRef<T, S>) {
+        let spill = match &mut self.spill {
+            Some(x) => x,
+            None => return,
+        };
+        let index = match spill
+            .spilled
+            .iter()
+            .position(|x| x.node.ptr_eq(&Rc::downgrade(node)))
+        {
+            Some(x) => x,
+            None => return,
+        };
+
+        let spilled = spill.spilled.remove(index);
+        let decode = Rc::clone(&spill.decode);
+        let children = fs::read(&spilled.path).and_then(|x| decode(&x, &node.borrow().state));
+        let _ = fs::remove_file(&spilled.path);
+
+        match children {
+            Ok(children) => {
+                for child in children {
+                    child.borrow_mut().set_parent(node);
+                    node.borrow_mut().add_child(child);
+                }
+                node.borrow().invalidate_subtree_stats();
+                for (x, id) in Self::descendants(node).iter().zip(spilled.ids) {
+                    self.register_as(x, id);
+                }
+            }
+            Err(_) => {
+                // The subtree is lost, the node is expanded again from scratch
+                for id in spilled.ids.iter() {
+                    self.nodes.remove(id);
+                    self.emit(|| TreeEvent::Removed { id: *id });
+                }
+                self.size -= spilled.ids.len() as u32;
+                node.borrow_mut().expanded = false;
+                self.record_failure();
+            }
+        }
+    }
+
+    // Writes the coldest subtrees to disk until the resident part fits, the
+    // principal variation and the most recent path stay in memory
+    pub(crate) fn spill_cold_subtrees(&mut self, recent: &NodeRef<T, S>) {
+        let max_resident = match &self.spill {
+            Some(x) => x.max_resident,
+            None => return,
+        };
+
+        let mut protected = self.principal_variation();
+        let mut node = Some(Rc::clone(recent));
+        while let Some(x) = node {
+            node = x.borrow().parent();
+            protected.push(x);
+        }
+
+        while self.resident_size() > max_resident {
+            let mut candidate: Option<NodeRef<T, S>> = None;
+            let mut stack = self.root.borrow().children.clone();
+
+            while let Some(x) = stack.pop() {
+                stack.extend(x.borrow().children.iter().map(Rc::clone));
+
+                if x.borrow().children.is_empty() || protected.iter().any(|y| Rc::ptr_eq(y, &x)) {
+                    continue;
+                }
+
+                let better = match &candidate {
+                    Some(y) => x.borrow().visits < y.borrow().visits,
+                    None => true,
+                };
+                if better && self.can_spill(&x) {
+                    candidate = Some(x);
+                }
+            }
+
+            match candidate {
+                Some(x) => {
+                    if self.spill_subtree(&x).is_err() {
+                        self.record_failure();
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn can_spill(&self, node: &NodeRef<T, S>) -> bool {
+        Self::descendants(node)
+            .iter()
+            .all(|x| spillable(&x.borrow()))
+    }
+
+    fn spill_subtree(&mut self, node: &NodeRef<T, S>) -> io::Result<()> {
+        if !self.can_spill(node) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "subtree holds expansion cursors or virtual losses",
+            ));
+        }
+
+        // Descendants spilled earlier are folded into this subtree's file
+        let mut stack = node.borrow().children.clone();
+        while let Some(x) = stack.pop() {
+            if self.is_spilled(&x) {
+                self.restore_spilled(&x);
+            }
+            stack.extend(x.borrow().children.iter().map(Rc::clone));
+        }
+
+        let ids = Self::descendants(node)
+            .iter()
+            .map(|x| x.borrow().id())
+            .collect();
+        let spill = self.spill.as_mut().unwrap();
+
+        let bytes = (spill.encode)(node)?;
+        let path = spill.dir.join(format!(
+            "rmcts-spill-{}-{}",
+            std::process::id(),
+            spill.next_file
+        ));
+        fs::write(&path, bytes)?;
+
+        spill.next_file += 1;
+        spill.spilled.push(Spilled {
+            node: Rc::downgrade(node),
+            path,
+            ids,
+        });
+        node.borrow().invalidate_subtree_stats();
+        node.borrow_mut().children.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{tree_shape, MockState};
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rmcts-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn spill_and_restore() {
+        let dir = dir("spill");
+        let mut tree = Tree::new(1.0, 0u8, MockState::countdown(4, 0.5)).with_spill(&dir, 1000);
+        tree.search(40);
+        let shape = tree_shape(&tree.root());
+        let size = tree.size;
+
+        let node = tree.root().borrow().child_at(1).unwrap();
+        assert!(!node.borrow().children.is_empty());
+        let id = node.borrow().child_at(0).unwrap().borrow().id();
+        {
+            let child = node.borrow().child_at(0).unwrap();
+            let mut child = child.borrow_mut();
+            child.proven = Some(Outcome::Win);
+            child.prior = Some(0.25);
+            child.cost = 3.5;
+            child.outcomes.wins = 2;
+        }
+        tree.spill_subtree(&node).unwrap();
+        assert!(tree.is_spilled(&node));
+        assert!(node.borrow().children.is_empty());
+        assert_eq!(tree.size, size);
+        assert!(tree.resident_size() < size);
+
+        assert!(tree.node(id).is_none());
+        let nodes = tree.root().borrow().subtree_stats().nodes;
+        assert!(nodes < size);
+
+        tree.restore_spilled(&node);
+        assert_eq!(tree.root().borrow().subtree_stats().nodes, size);
+        assert!(!tree.is_spilled(&node));
+        assert!(Rc::ptr_eq(
+            &tree.node(id).unwrap(),
+            &node.borrow().child_at(0).unwrap()
+        ));
+        assert_eq!(tree.resident_size(), size);
+        assert_eq!(tree_shape(&tree.root()), shape);
+        assert!(fs::read_dir(&dir).unwrap().next().is_none());
+
+        // Fields the checkpoint leaves out come back too
+        let child = tree.node(id).unwrap();
+        assert_eq!(child.borrow().proven, Some(Outcome::Win));
+        assert_eq!(child.borrow().prior, Some(0.25));
+        assert_eq!(child.borrow().cost, 3.5);
+        assert_eq!(child.borrow().outcomes.wins, 2);
+
+        // An expansion cursor can't be written out, the subtree stays resident
+        child.borrow_mut().expansion_state = Some(MockState::countdown(1, 0.5));
+        assert!(tree.spill_subtree(&node).is_err());
+        assert!(!tree.is_spilled(&node));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn search_with_spill() {
+        let dir = dir("search");
+        let mut tree = Tree::new(1.0, 0u8, MockState::countdown(5, 0.5)).with_spill(&dir, 60);
+        tree.search(400);
+
+        assert!(tree.resident_size() <= 60);
+        assert!(tree.size > 60);
+        assert!(tree.spill().unwrap().spilled_subtrees() > 0);
+        assert_eq!(tree.failures(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -9,7 +9,258 @@
     unused_qualifications
 )]
 
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use rand::Rng;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Win,
+    Draw,
+    Loss,
+}
+
+impl Outcome {
+    // State::outcome is seen by player 0, this is the same result seen by
+    // `player`. Games of more than two players go by their payoffs and are
+    // left as they are
+    pub fn for_player(self, player: usize, players: usize) -> Self {
+        match self {
+            Outcome::Win if players == 2 && player != 0 => Outcome::Loss,
+            Outcome::Loss if players == 2 && player != 0 => Outcome::Win,
+            x => x,
+        }
+    }
+}
+
+// Terminal outcomes of the playouts through a node
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OutcomeCounts {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl OutcomeCounts {
+    pub fn add(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Win => self.wins += 1,
+            Outcome::Draw => self.draws += 1,
+            Outcome::Loss => self.losses += 1,
+        }
+    }
+
+    pub fn total(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ActionError(pub String);
+
+impl fmt::Display for ActionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "action failed: {}", self.0)
+    }
+}
+
+impl Error for ActionError {}
+
+// What the caches, the novelty and rollout bandit tables and the knowledge
+// store key a state on, e.g. the position without its move history or
+// cached values. States with equal keys are taken as transpositions
+pub trait StateKey {
+    type Key: Hash + Eq + 'static;
+    fn key(&self) -> Self::Key;
+}
+
+macro_rules! impl_state_key {
+    ($($t:ty),*) => {
+        $(
+            impl StateKey for $t {
+                type Key = $t;
+
+                fn key(&self) -> $t {
+                    *self
+                }
+            }
+        )*
+    };
+}
+
+impl_state_key!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+// 64-bit FNV-1a over what the key feeds to Hash, integers as little endian
+// bytes and usize as u64. Unlike DefaultHasher it is the same on every
+// platform and release, so the keys can be saved, see KnowledgeStore::save
+#[derive(Clone, Copy, Debug)]
+pub struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for x in bytes {
+            self.0 = (self.0 ^ u64::from(*x)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u16(&mut self, x: u16) {
+        self.write(&x.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, x: u32) {
+        self.write(&x.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, x: u64) {
+        self.write(&x.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, x: u128) {
+        self.write(&x.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, x: usize) {
+        self.write_u64(x as u64);
+    }
+
+    fn write_i16(&mut self, x: i16) {
+        self.write_u16(x as u16);
+    }
+
+    fn write_i32(&mut self, x: i32) {
+        self.write_u32(x as u32);
+    }
+
+    fn write_i64(&mut self, x: i64) {
+        self.write_u64(x as u64);
+    }
+
+    fn write_i128(&mut self, x: i128) {
+        self.write_u128(x as u128);
+    }
+
+    fn write_isize(&mut self, x: isize) {
+        self.write_u64(x as u64);
+    }
+}
+
+pub(crate) fn key_hash<S>(state: &S) -> u64
+where
+    S: StateKey,
+{
+    let mut hasher = StableHasher::default();
+    state.key().hash(&mut hasher);
+    hasher.finish()
+}
+
 pub trait State<T>: Clone {
     fn next_action(&self) -> Option<T>;
-    fn do_action(&mut self, action: &T) -> f32;
+
+    // Reward of the action. The search only calls this one, a failure skips
+    // the child or ends the rollout
+    fn try_do_action(&mut self, action: &T) -> Result<f32, ActionError>;
+
+    // Same as try_do_action, panics on failure
+    fn do_action(&mut self, action: &T) -> f32 {
+        match self.try_do_action(action) {
+            Ok(x) => x,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    // Simulator time taken by the action, budgets and metrics count it
+    fn step_cost(&self, _action: &T) -> f32 {
+        1.
+    }
+
+    // Terminal result for game domains, replaces the accumulated reward of a
+    // rollout. Seen by player 0 in games of two players, the search converts
+    // it for the root player with Outcome::for_player
+    fn outcome(&self) -> Option<Outcome> {
+        None
+    }
+
+    fn current_player(&self) -> usize {
+        0
+    }
+
+    // Selection takes the point of view of the player to move when more than
+    // one. Two players share the rewards of the root player, negated
+    fn num_players(&self) -> usize {
+        1
+    }
+
+    // Terminal payoff of every player, by current_player, for more than two
+    fn payoffs(&self) -> Option<Vec<f32>> {
+        None
+    }
+
+    // Estimated return of the rest of a rollout cut short from this state
+    fn heuristic(&self) -> f32 {
+        0.
+    }
+
+    // Selection score of the node of this state while unvisited, so domain
+    // knowledge orders the untried actions. None tries them first, or gives
+    // them the fpu of the player
+    fn first_play_value(&self) -> Option<f32> {
+        None
+    }
+
+    // Whether heuristic can be trusted here, false e.g. in the middle of an
+    // exchange of captures. Cutoffs play on from such states for up to
+    // SearchConfig::quiescence_steps
+    fn is_quiet(&self) -> bool {
+        true
+    }
+
+    // Whether `action` played right after `previous` reaches a state the other
+    // order reaches too, expansion then skips it. Should hold for at most one
+    // of the two orders so that one of them is kept
+    fn commutes(&self, _previous: &T, _action: &T) -> bool {
+        false
+    }
+
+    // Every action next_action enumerates, in order
+    fn legal_actions(&self) -> Vec<T> {
+        let mut actions = vec![];
+        let mut cursor = self.clone();
+        while let Some(action) = cursor.next_action() {
+            if cursor.try_do_action(&action).is_err() {
+                break;
+            }
+            actions.push(action);
+        }
+        actions
+    }
+
+    // Uniform over the legal actions unless the state samples them itself
+    fn random_action<R>(&self, rng: &mut R) -> Option<T>
+    where
+        R: Rng + ?Sized,
+    {
+        let mut actions = self.legal_actions();
+        if actions.is_empty() {
+            return None;
+        }
+        let index = rng.gen_range(0..actions.len());
+        Some(actions.swap_remove(index))
+    }
+
+    // Number of actions next_action will enumerate, used to preallocate children
+    fn action_count(&self) -> Option<usize> {
+        None
+    }
 }
@@ -9,8 +9,15 @@
     unused_qualifications
 )]
 
-use crate::node::NodeRef;
-use crate::state::State;
+use std::cell::RefCell;
+use std::fmt::{self, Debug};
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::cache::{KeyMap, KeySlot, Keyer};
+use crate::node::{Node, NodeRef};
+use crate::state::{State, StateKey};
 
 pub trait SelectionStrategy<T, S>
 where
@@ -51,3 +58,288 @@ where
 {
     fn backpropagate(&mut self, node: &mut NodeRef<T, S>, value: f32);
 }
+
+// Shaping term added to every rollout step, e.g. curiosity or novelty
+pub trait IntrinsicReward<T, S>: Debug
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn bonus(&self, state: &S, action: &T) -> f32;
+}
+
+// Picks rollout actions, None falls back to the state's next_action
+pub trait RolloutPolicy<T, S>: Debug
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn choose(&self, state: &S) -> Option<T>;
+}
+
+// Policy used for players other than the one at the root
+pub trait OpponentModel<T, S>: Debug
+where
+    S: State<T>,
+    T: Clone,
+{
+    // None falls back to the state's next_action
+    fn rollout_action(&self, state: &S) -> Option<T>;
+
+    // Index of the child to descend into at opponent nodes, None keeps UCT
+    fn select_child(&self, _state: &S, _actions: &[T]) -> Option<usize> {
+        None
+    }
+}
+
+// Action priors and a state value, e.g. from a neural network
+pub type Evaluation<T> = (Vec<(T, f32)>, f32);
+
+// Leaf evaluation that replaces the rollout when configured
+pub trait Evaluator<T, S>: Debug
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn evaluate(&self, state: &S) -> Evaluation<T>;
+
+    // Several states at once, e.g. in one network call
+    fn evaluate_batch(&self, states: &[&S]) -> Vec<Evaluation<T>> {
+        states.iter().map(|x| self.evaluate(x)).collect()
+    }
+}
+
+// The rules below are the parts of the default tree that can be swapped, the
+// tree keeps the rest (priors, novelty, opponents, solver) around them
+
+// Selection descends into the child with the highest score. `value` is the
+// mean of the node for the player choosing it, `bounds` are the reward bounds
+// observed in planning mode, seen by the same player
+pub trait SelectionRule<T, S>: Debug
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn score(&self, node: &Node<T, S>, value: f32, c: f32, bounds: Option<(f32, f32)>) -> f32;
+
+    // Unvisited children are tried before any scores are compared
+    fn unvisited_first(&self) -> bool {
+        true
+    }
+}
+
+// Whether expansion creates a child for the action
+pub trait ExpansionRule<T, S>: Debug
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn expand(&self, state: &S, action: &T) -> bool;
+}
+
+// How each node on the path takes the backed-up value
+pub trait BackupRule<T, S>: Debug
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn update(&self, node: &mut Node<T, S>, value: f32);
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Uct;
+
+impl<T, S> SelectionRule<T, S> for Uct
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn score(&self, node: &Node<T, S>, value: f32, c: f32, bounds: Option<(f32, f32)>) -> f32 {
+        match bounds {
+            Some((low, high)) if high > low => node.score_for((value - low) / (high - low), c),
+            _ => node.score_for(value, c),
+        }
+    }
+}
+
+// AlphaZero's rule, the priors spread the exploration before the values are
+// known. Children without one share it uniformly
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Puct;
+
+impl<T, S> SelectionRule<T, S> for Puct
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn score(&self, node: &Node<T, S>, value: f32, c: f32, bounds: Option<(f32, f32)>) -> f32 {
+        let parent = match node.parent() {
+            Some(x) => x,
+            None => return 0.,
+        };
+        let parent = parent.borrow();
+        let prior = node
+            .prior
+            .unwrap_or(1. / parent.children.len().max(1) as f32);
+        let value = match bounds {
+            Some((low, high)) if high > low => (value - low) / (high - low),
+            _ => value,
+        };
+        value + c * prior * (parent.visits as f32).sqrt() / (node.visits + 1) as f32
+    }
+
+    fn unvisited_first(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FullExpansion;
+
+impl<T, S> ExpansionRule<T, S> for FullExpansion
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn expand(&self, _state: &S, _action: &T) -> bool {
+        true
+    }
+}
+
+// Plays the state's own next action
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GreedyRollout;
+
+impl<T, S> RolloutPolicy<T, S> for GreedyRollout
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn choose(&self, state: &S) -> Option<T> {
+        state.next_action()
+    }
+}
+
+// Uniformly random legal actions from its own seeded generator, see
+// State::random_action
+#[derive(Clone, Debug)]
+pub struct RandomRollout {
+    rng: RefCell<StdRng>,
+}
+
+impl RandomRollout {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: RefCell::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+}
+
+impl<T, S> RolloutPolicy<T, S> for RandomRollout
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn choose(&self, state: &S) -> Option<T> {
+        state.random_action(&mut *self.rng.borrow_mut())
+    }
+}
+
+// Sums the values, the node mean is their average
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SumBackup;
+
+impl<T, S> BackupRule<T, S> for SumBackup
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn update(&self, node: &mut Node<T, S>, value: f32) {
+        node.push_value(value);
+    }
+}
+
+// Bandit statistics of the actions by state, for the first steps of the
+// rollouts. Far lighter than tree nodes, the table is cleared when full
+#[derive(Clone)]
+pub struct RolloutBandit<S> {
+    pub steps: u32,
+    pub exploration: f32,
+    capacity: usize,
+    table: RefCell<KeyMap<S, Vec<(u32, f32)>>>,
+}
+
+impl<S> RolloutBandit<S>
+where
+    S: StateKey,
+{
+    pub fn new(steps: u32, exploration: f32, capacity: usize) -> Self {
+        Self {
+            steps,
+            exploration,
+            capacity,
+            table: RefCell::new(KeyMap::new(Keyer::new())),
+        }
+    }
+}
+
+impl<S> RolloutBandit<S> {
+    // UCB1 over the `count` actions of the state, untried ones first. The
+    // values are from the root player's point of view, `maximize` is false
+    // for the other players. Returns the key to update with the index
+    pub fn choose(&self, state: &S, count: usize, maximize: bool) -> (KeySlot, usize) {
+        let mut table = self.table.borrow_mut();
+        if table.get(state).is_none() && table.len() >= self.capacity {
+            table.clear();
+        }
+        let (key, stats) = table.entry(state, || vec![(0, 0.); count]);
+        if stats.len() != count {
+            *stats = vec![(0, 0.); count];
+        }
+
+        if let Some(index) = stats.iter().position(|x| x.0 == 0) {
+            return (key, index);
+        }
+        let total: u32 = stats.iter().map(|x| x.0).sum();
+        let score = |&(visits, mean): &(u32, f32)| {
+            let mean = if maximize { mean } else { -mean };
+            mean + self.exploration * ((2. * (total as f32).ln()) / visits as f32).sqrt()
+        };
+        let index = (0..count)
+            .max_by(|&a, &b| {
+                score(&stats[a])
+                    .partial_cmp(&score(&stats[b]))
+                    .unwrap_or(std::cmp::Ordering::Less)
+            })
+            .unwrap_or(0);
+        (key, index)
+    }
+
+    pub fn update(&self, path: &[(KeySlot, usize)], value: f32) {
+        let mut table = self.table.borrow_mut();
+        for (key, index) in path {
+            if let Some(x) = table.slot_mut(*key).and_then(|x| x.get_mut(*index)) {
+                x.0 += 1;
+                x.1 += (value - x.1) / x.0 as f32;
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.table.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.table.borrow().len() == 0
+    }
+}
+
+impl<S> Debug for RolloutBandit<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RolloutBandit")
+            .field("steps", &self.steps)
+            .field("exploration", &self.exploration)
+            .field("len", &self.len())
+            .finish()
+    }
+}
@@ -0,0 +1,280 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use crate::node::NodeRef;
+use crate::state::{ActionError, Outcome, StableHasher, State, StateKey};
+
+// Plays back a table of (action, reward) rows, one row per step
+#[derive(Clone, Debug, PartialEq)]
+pub struct MockState<T> {
+    script: Rc<Vec<(T, f32)>>,
+    position: usize,
+    outcome: Option<Outcome>,
+    player: usize,
+}
+
+impl<T> MockState<T>
+where
+    T: Clone + PartialEq,
+{
+    pub fn new(script: Vec<(T, f32)>) -> Self {
+        Self {
+            script: Rc::new(script),
+            position: 0,
+            outcome: None,
+            player: 0,
+        }
+    }
+
+    pub fn with_outcome(mut self, outcome: Outcome) -> Self {
+        self.outcome = Some(outcome);
+        self
+    }
+
+    pub fn with_player(mut self, player: usize) -> Self {
+        self.player = player;
+        self
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.script.len()
+    }
+}
+
+impl MockState<u8> {
+    // Same shape as the fixtures used across the crate: actions n, n - 1, ..., 1
+    pub fn countdown(actions: u8, reward: f32) -> Self {
+        Self::new((1..=actions).rev().map(|x| (x, reward)).collect())
+    }
+}
+
+impl<T> State<T> for MockState<T>
+where
+    T: Clone + PartialEq,
+{
+    fn next_action(&self) -> Option<T> {
+        self.script.get(self.position).map(|x| x.0.clone())
+    }
+
+    fn try_do_action(&mut self, action: &T) -> Result<f32, ActionError> {
+        let reward = self.script[self.position.min(self.script.len())..]
+            .iter()
+            .find(|x| x.0 == *action)
+            .map(|x| x.1)
+            .unwrap_or(0.);
+        self.position += 1;
+        Ok(reward)
+    }
+
+    fn outcome(&self) -> Option<Outcome> {
+        if self.is_finished() {
+            return self.outcome;
+        }
+        None
+    }
+
+    fn current_player(&self) -> usize {
+        self.player
+    }
+
+    fn action_count(&self) -> Option<usize> {
+        Some(self.script.len().saturating_sub(self.position))
+    }
+}
+
+// The position in the script, states playing back different scripts are
+// never transpositions
+impl<T> StateKey for MockState<T>
+where
+    T: Hash,
+{
+    type Key = (u64, usize);
+
+    fn key(&self) -> (u64, usize) {
+        let mut hasher = StableHasher::default();
+        for (action, reward) in self.script.iter() {
+            action.hash(&mut hasher);
+            reward.to_bits().hash(&mut hasher);
+        }
+        (hasher.finish(), self.position)
+    }
+}
+
+// Small game with a known solution: `depth` moves enumerated countdown style
+// by alternating players, the whole line is scored for player 0 at the end
+#[derive(Clone, Debug)]
+pub struct PathGame {
+    moves: Vec<u8>,
+    depth: u8,
+    score: fn(&[u8]) -> Outcome,
+}
+
+impl PathGame {
+    pub fn new(depth: u8, score: fn(&[u8]) -> Outcome) -> Self {
+        Self {
+            moves: vec![],
+            depth,
+            score,
+        }
+    }
+
+    pub fn moves(&self) -> &[u8] {
+        &self.moves
+    }
+}
+
+impl State<u8> for PathGame {
+    fn next_action(&self) -> Option<u8> {
+        let played = self.moves.len() as u8;
+        if played == self.depth {
+            return None;
+        }
+        Some(self.depth - played)
+    }
+
+    fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+        self.moves.push(*action);
+        Ok(0.)
+    }
+
+    fn outcome(&self) -> Option<Outcome> {
+        if self.moves.len() as u8 == self.depth {
+            return Some((self.score)(&self.moves));
+        }
+        None
+    }
+
+    fn current_player(&self) -> usize {
+        self.moves.len() % 2
+    }
+
+    fn num_players(&self) -> usize {
+        2
+    }
+}
+
+// Number of nodes at each depth, starting with the given node
+pub fn tree_shape<T, S>(node: &NodeRef<T, S>) -> Vec<usize>
+where
+    S: State<T>,
+    T: Clone,
+{
+    let mut shape = vec![];
+    let mut level = vec![Rc::clone(node)];
+
+    while !level.is_empty() {
+        shape.push(level.len());
+        level = level
+            .iter()
+            .flat_map(|x| {
+                x.borrow()
+                    .children
+                    .iter()
+                    .map(Rc::clone)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+    }
+
+    shape
+}
+
+pub fn assert_tree_shape<T, S>(node: &NodeRef<T, S>, expected: &[usize])
+where
+    S: State<T> + Debug,
+    T: Clone + Debug,
+{
+    let shape = tree_shape(node);
+    assert_eq!(
+        shape, expected,
+        "unexpected tree shape, nodes per depth: {:?}",
+        shape
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::strategies::ExpansionStrategy;
+    use crate::tree::Tree;
+
+    #[test]
+    fn scripted_rewards() {
+        let mut state = MockState::new(vec![('a', 1.), ('b', 2.), ('c', 3.)]);
+        assert_eq!(state.next_action(), Some('a'));
+        assert_eq!(state.do_action(&'a'), 1.);
+
+        // Actions later in the script keep their reward
+        assert_eq!(state.do_action(&'c'), 3.);
+        assert_eq!(state.next_action(), Some('c'));
+        assert_eq!(state.do_action(&'z'), 0.);
+        assert!(state.is_finished());
+        assert_eq!(state.next_action(), None);
+    }
+
+    #[test]
+    fn scripted_outcome() {
+        let mut state = MockState::new(vec![(1, 0.)]).with_outcome(Outcome::Draw);
+        assert_eq!(state.outcome(), None);
+        state.do_action(&1);
+        assert_eq!(state.outcome(), Some(Outcome::Draw));
+    }
+
+    #[test]
+    fn countdown() {
+        let state = MockState::countdown(3, 0.5);
+        assert_eq!(state.next_action(), Some(3));
+        assert_eq!(state.clone().do_action(&3), 0.5);
+    }
+
+    #[test]
+    fn script_keys() {
+        let mut state = MockState::countdown(3, 0.5);
+        let key = state.key();
+        assert_eq!(MockState::countdown(3, 0.5).key(), key);
+        assert_ne!(MockState::countdown(3, 1.).key(), key);
+        state.do_action(&3);
+        assert_ne!(state.key(), key);
+    }
+
+    #[test]
+    fn path_game_players() {
+        let mut game = PathGame::new(2, |_| Outcome::Win);
+        assert_eq!(game.num_players(), 2);
+        game.do_action(&2);
+        assert_eq!(game.current_player(), 1);
+    }
+
+    #[test]
+    fn shape() {
+        let mut tree = Tree::new(1.0, 0, MockState::countdown(3, 0.5));
+        assert_tree_shape(&tree.root(), &[1]);
+
+        let mut child = tree.expand(&mut tree.root()).unwrap();
+        tree.expand(&mut child);
+        assert_tree_shape(&tree.root(), &[1, 3, 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected tree shape")]
+    fn wrong_shape() {
+        let tree = Tree::new(1.0, 0, MockState::countdown(3, 0.5));
+        assert_tree_shape(&tree.root(), &[1, 3]);
+    }
+}
@@ -9,22 +9,199 @@
     unused_qualifications
 )]
 
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-use crate::node::{Node, NodeRef};
-use crate::state::State;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::budget::SearchBudget;
+use crate::cache::{CacheStats, CoverageStats, KeySlot, ResultCache, StateCache, StateCoverage};
+use crate::config::{
+    PlayerExploration, Preset, RewardMode, SearchConfig, SelectionPolicy, SimulationStart,
+};
+use crate::events::{created, updated, EventSink, TreeEvent};
+use crate::knowledge::KnowledgeStore;
+use crate::node::{combine_stats, stat_to_f64, Node, NodeId, NodeRef, Stat, WeakNodeRef};
+use crate::novelty::Novelty;
+use crate::observer::{Interval, IterationSample, Observer};
+use crate::profile::IterationProfile;
+use crate::result::{
+    BackupPath, BackupStep, ChildStats, Degradation, DegradeCause, Repro, ReproError, SearchResult,
+    Stability, Suboptimality, CONFIDENCE_Z,
+};
+use crate::schedule::Schedule;
+use crate::sketch::QuantileSketch;
+use crate::spill::Spill;
+use crate::state::{key_hash, ActionError, Outcome, State, StateKey};
 use crate::strategies::{
-    BackpropagationStrategy, ExpansionStrategy, SelectionStrategy, SimulationStrategy,
+    BackpropagationStrategy, BackupRule, Evaluation, Evaluator, ExpansionRule, ExpansionStrategy,
+    FullExpansion, GreedyRollout, IntrinsicReward, OpponentModel, Puct, RolloutBandit,
+    RolloutPolicy, SelectionRule, SelectionStrategy, SimulationStrategy, SumBackup, Uct,
 };
 
+// Iterations between the checks of Tree::search_bounded
+const BOUND_CHECK_INTERVAL: u32 = 32;
+
+// Index drawn with probabilities proportional to exp(rationality * value)
+fn sample_softmax(values: &[f32], rationality: f32, rng: &mut StdRng) -> Option<usize> {
+    let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let weights: Vec<f32> = values
+        .iter()
+        .map(|x| (rationality * (x - max)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    if values.is_empty() || !total.is_finite() {
+        return None;
+    }
+
+    let mut target = rng.gen::<f32>() * total;
+    for (i, x) in weights.iter().enumerate() {
+        if target < *x {
+            return Some(i);
+        }
+        target -= x;
+    }
+    Some(values.len() - 1)
+}
+
+// A node on the paths of a batch and the values that went through it
+struct BatchUpdate<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    node: NodeRef<T, S>,
+    parent: Option<usize>,
+    pending: usize,
+    // With the index of their result
+    values: Vec<(usize, f32)>,
+}
+
+// Nodes of a line the application asked to look at, boosted until the given
+// iteration
+#[derive(Clone, Debug)]
+struct Hint<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    nodes: Vec<WeakNodeRef<T, S>>,
+    boost: f32,
+    until: u32,
+}
+
+// Weight of a simulated return from the number of rollout steps and whether
+// the rollout was cut short by a failed step
+#[derive(Clone)]
+struct ReturnWeight(Rc<dyn Fn(u32, bool) -> f32>);
+
+impl fmt::Debug for ReturnWeight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ReturnWeight")
+    }
+}
+
+type Callback<T, S> = Rc<dyn Fn(&Tree<T, S>)>;
+
+// Prior of an action in a parent's policy
+type PolicyPrior<T> = fn(&[(T, f32)], &T) -> f32;
+
+// Value of a rollout, with the outcome and payoffs it ended in
+type CachedRollout = (f32, Option<Outcome>, Option<Vec<f32>>);
+
+#[derive(Clone)]
+struct IntervalCallback<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    interval: Interval,
+    last_iteration: u32,
+    last_time: Instant,
+    callback: Callback<T, S>,
+}
+
+impl<T, S> fmt::Debug for IntervalCallback<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IntervalCallback")
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Tree<T, S>
 where
     S: State<T>,
     T: Clone,
 {
-    root: NodeRef<T, S>,
-    learning_rate: f32,
+    pub(crate) root: NodeRef<T, S>,
+    pub(crate) config: SearchConfig,
+    pub(crate) rng: RefCell<StdRng>,
+    temperature: Option<Rc<dyn Schedule>>,
+    novelty: Option<Novelty<S>>,
+    intrinsic_reward: Option<(Rc<dyn IntrinsicReward<T, S>>, f32)>,
+    opponent: Option<Rc<dyn OpponentModel<T, S>>>,
+    rollout_policies: HashMap<usize, Rc<dyn RolloutPolicy<T, S>>>,
+    player_exploration: HashMap<usize, PlayerExploration>,
+    selection_rule: Rc<dyn SelectionRule<T, S>>,
+    pub(crate) expansion_rule: Rc<dyn ExpansionRule<T, S>>,
+    rollout_policy: Rc<dyn RolloutPolicy<T, S>>,
+    backup_rule: Rc<dyn BackupRule<T, S>>,
+    return_weight: Option<ReturnWeight>,
+    pub(crate) spill: Option<Spill<T, S>>,
+    observers: Vec<Rc<dyn Observer>>,
+    pub(crate) event_sink: Option<Rc<dyn EventSink<T>>>,
+    callbacks: Vec<IntervalCallback<T, S>>,
+    rollout_cache: Option<StateCache<S, CachedRollout>>,
+    pub(crate) evaluator: Option<Rc<dyn Evaluator<T, S>>>,
+    pub(crate) evaluation_cache: Option<Rc<StateCache<S, Evaluation<T>>>>,
+    // Evaluation of a leaf done ahead in a batch, taken by its rollout
+    pub(crate) prefetched: RefCell<Option<(NodeId, Evaluation<T>)>>,
+    policy_prior: Option<PolicyPrior<T>>,
+    coverage: Option<StateCoverage<S>>,
+    rollout_bandit: Option<RolloutBandit<S>>,
+    pub(crate) knowledge: Option<KnowledgeStore<S>>,
+    pub(crate) nodes: HashMap<NodeId, WeakNodeRef<T, S>>,
+    next_id: u64,
+    pub(crate) progress: f32,
+    pub(crate) reward_bounds: Option<(f32, f32)>,
+    recent_values: VecDeque<f32>,
+    root_values: Vec<(u32, f32)>,
+    leader: Option<(WeakNodeRef<T, S>, u32, Instant)>,
+    hint: Option<Hint<T, S>>,
+    // Any of the strategies was replaced, worker trees built from the config
+    // alone would search differently
+    pub(crate) custom_strategies: bool,
+    // Payoffs and terminal outcome of the last rollout, handed from simulate
+    // to backpropagation
+    payoffs: RefCell<Option<Vec<f32>>>,
+    outcome: Cell<Option<Outcome>>,
+    lead_changes: u32,
+    fingerprint: Option<u64>,
+    failures: Cell<u32>,
+    steps: Cell<u64>,
+    cost: Cell<f64>,
+    attributed_cost: f64,
+    pub(crate) deadline: Option<Instant>,
+    pub(crate) elapsed: Duration,
+    // Consecutive checks each root child has been dominated for
+    dominated: HashMap<NodeId, u32>,
+    degraded: Option<Degradation>,
+    profile: Option<IterationProfile>,
+    backup_paths: VecDeque<BackupPath>,
+    // Selection and expansion times of the last leaf and when it was handed
+    // to the simulation
+    leaf_times: Option<(Duration, Duration, Instant)>,
+    pub iterations: u32,
     pub size: u32,
 }
 
@@ -34,235 +211,5409 @@ where
     T: Clone,
 {
     pub fn new(learning_rate: f32, action: T, state: S) -> Self {
-        Self {
+        Self::with_config(SearchConfig::new(learning_rate), action, state)
+    }
+
+    pub fn with_config(config: SearchConfig, action: T, state: S) -> Self {
+        let mut tree = Self {
             root: Node::new(action, state),
-            learning_rate,
+            config,
+            rng: RefCell::new(StdRng::seed_from_u64(config.seed)),
+            temperature: None,
+            novelty: None,
+            intrinsic_reward: None,
+            opponent: None,
+            rollout_policies: HashMap::new(),
+            player_exploration: HashMap::new(),
+            selection_rule: Rc::new(Uct),
+            expansion_rule: Rc::new(FullExpansion),
+            rollout_policy: Rc::new(GreedyRollout),
+            backup_rule: Rc::new(SumBackup),
+            return_weight: None,
+            spill: None,
+            observers: vec![],
+            event_sink: None,
+            callbacks: vec![],
+            rollout_cache: None,
+            evaluator: None,
+            policy_prior: None,
+            coverage: None,
+            rollout_bandit: None,
+            evaluation_cache: None,
+            prefetched: RefCell::new(None),
+            knowledge: None,
+            nodes: HashMap::new(),
+            next_id: 0,
+            progress: 0.,
+            reward_bounds: None,
+            recent_values: VecDeque::new(),
+            root_values: vec![],
+            leader: None,
+            hint: None,
+            custom_strategies: false,
+            payoffs: RefCell::new(None),
+            outcome: Cell::new(None),
+            lead_changes: 0,
+            fingerprint: None,
+            failures: Cell::new(0),
+            steps: Cell::new(0),
+            cost: Cell::new(0.),
+            attributed_cost: 0.,
+            deadline: None,
+            elapsed: Duration::ZERO,
+            dominated: HashMap::new(),
+            degraded: None,
+            profile: None,
+            backup_paths: VecDeque::new(),
+            leaf_times: None,
+            iterations: 0,
             size: 1,
+        };
+        tree.register(&tree.root());
+        tree
+    }
+
+    pub fn with_preset(preset: Preset, action: T, state: S) -> Self {
+        let tree = Self::with_config(preset.config(), action, state);
+        match preset {
+            Preset::NeuralPuct => tree.with_selection_rule(Puct),
+            _ => tree,
         }
     }
 
-    pub fn root(&self) -> NodeRef<T, S> {
-        Rc::clone(&self.root)
+    pub fn with_temperature<Sc>(mut self, schedule: Sc) -> Self
+    where
+        Sc: Schedule + 'static,
+    {
+        self.temperature = Some(Rc::new(schedule));
+        self
     }
 
-    pub fn search(&mut self, iterations: u32) -> Option<NodeRef<T, S>> {
-        for _i in 0..iterations {
-            let mut leaf_node = match self.select() {
-                Some(x) => x,
-                None => break,
-            };
+    pub fn with_intrinsic_reward<R>(mut self, reward: R, weight: f32) -> Self
+    where
+        R: IntrinsicReward<T, S> + 'static,
+    {
+        self.intrinsic_reward = Some((Rc::new(reward), weight));
+        self
+    }
 
-            if leaf_node.borrow().visits > 0 {
-                leaf_node = match self.expand(&mut leaf_node) {
-                    Some(x) => x,
-                    None => leaf_node,
-                };
-            }
+    pub fn with_opponent_model<M>(mut self, model: M) -> Self
+    where
+        M: OpponentModel<T, S> + 'static,
+    {
+        self.opponent = Some(Rc::new(model));
+        self.custom_strategies = true;
+        self
+    }
 
-            let reward = self.simulate(&leaf_node);
-            self.backpropagate(&mut leaf_node, reward);
-        }
+    pub fn with_rollout_policy<P>(mut self, player: usize, policy: P) -> Self
+    where
+        P: RolloutPolicy<T, S> + 'static,
+    {
+        self.rollout_policies.insert(player, Rc::new(policy));
+        self.custom_strategies = true;
+        self
+    }
 
-        self.root.borrow().best_child()
+    // Depth decay still applies to the constant of the player
+    pub fn with_player_exploration(
+        mut self,
+        player: usize,
+        exploration: PlayerExploration,
+    ) -> Self {
+        self.player_exploration.insert(player, exploration);
+        self
     }
 
-    pub fn add_node(&mut self, node: NodeRef<T, S>, parent: &mut NodeRef<T, S>) -> NodeRef<T, S> {
-        self.size += 1;
-        node.borrow_mut().set_parent(parent);
-        parent.borrow_mut().add_child(node)
+    pub fn with_selection_rule<R>(mut self, rule: R) -> Self
+    where
+        R: SelectionRule<T, S> + 'static,
+    {
+        self.selection_rule = Rc::new(rule);
+        self.custom_strategies = true;
+        self
     }
-}
 
-impl<T, S> SelectionStrategy<T, S> for Tree<T, S>
-where
-    S: State<T>,
-    T: Clone,
-{
-    fn select(&self) -> Option<NodeRef<T, S>> {
-        let mut child = Rc::clone(&self.root);
+    pub fn with_expansion_rule<R>(mut self, rule: R) -> Self
+    where
+        R: ExpansionRule<T, S> + 'static,
+    {
+        self.expansion_rule = Rc::new(rule);
+        self.custom_strategies = true;
+        self
+    }
 
-        while !child.borrow().children.is_empty() {
-            let next = match child.borrow().children.iter().max_by(|a, b| {
-                if a.borrow().visits == 0 {
-                    return std::cmp::Ordering::Greater;
-                }
+    // Rollout policy of the players without one of their own
+    pub fn with_default_rollout_policy<P>(mut self, policy: P) -> Self
+    where
+        P: RolloutPolicy<T, S> + 'static,
+    {
+        self.rollout_policy = Rc::new(policy);
+        self.custom_strategies = true;
+        self
+    }
 
-                a.borrow()
-                    .score(self.learning_rate)
-                    .partial_cmp(&b.borrow().score(self.learning_rate))
-                    .unwrap_or(std::cmp::Ordering::Less)
-            }) {
-                Some(x) => Rc::clone(x),
-                None => break,
-            };
+    pub(crate) fn with_boxed_rollout_policy(
+        mut self,
+        policy: Box<dyn RolloutPolicy<T, S>>,
+    ) -> Self {
+        self.rollout_policy = Rc::from(policy);
+        self.custom_strategies = true;
+        self
+    }
 
-            child = next;
+    pub fn with_backup_rule<R>(mut self, rule: R) -> Self
+    where
+        R: BackupRule<T, S> + 'static,
+    {
+        self.backup_rule = Rc::new(rule);
+        self.custom_strategies = true;
+        self
+    }
+
+    pub fn with_return_weight<F>(mut self, weight: F) -> Self
+    where
+        F: Fn(u32, bool) -> f32 + 'static,
+    {
+        self.return_weight = Some(ReturnWeight(Rc::new(weight)));
+        self
+    }
+
+    pub(crate) fn emit<F>(&self, event: F)
+    where
+        F: FnOnce() -> TreeEvent<T>,
+    {
+        if let Some(x) = &self.event_sink {
+            x.record(&event());
         }
+    }
 
-        Some(child)
+    pub fn with_observer<O>(mut self, observer: O) -> Self
+    where
+        O: Observer + 'static,
+    {
+        self.observers.push(Rc::new(observer));
+        self
     }
-}
 
-impl<T, S> ExpansionStrategy<T, S> for Tree<T, S>
-where
-    S: State<T>,
-    T: Clone,
-{
-    fn expand(&mut self, node: &mut NodeRef<T, S>) -> Option<NodeRef<T, S>> {
-        let mut curr_state = node.borrow().state.clone();
+    // Called with the tree while the search runs, e.g. to persist partial progress
+    pub fn with_interval_callback<F>(mut self, interval: Interval, callback: F) -> Self
+    where
+        F: Fn(&Tree<T, S>) + 'static,
+    {
+        self.callbacks.push(IntervalCallback {
+            interval,
+            last_iteration: self.iterations,
+            last_time: Instant::now(),
+            callback: Rc::new(callback),
+        });
+        self
+    }
 
-        while let Some(action) = curr_state.next_action() {
-            let mut state = node.borrow().state.clone();
-            state.do_action(&action);
-            curr_state.do_action(&action);
-            let new_node = Node::new(action, state);
-            self.add_node(new_node, node);
+    pub fn with_evaluator<E>(mut self, evaluator: E) -> Self
+    where
+        E: Evaluator<T, S> + 'static,
+    {
+        self.evaluator = Some(Rc::new(evaluator));
+        self.custom_strategies = true;
+        self
+    }
+
+    // Runs the evaluator, going through the evaluation cache when there is one
+    pub fn evaluate(&self, state: &S) -> Option<Evaluation<T>> {
+        let evaluator = self.evaluator.as_ref()?;
+        if let Some(x) = self.evaluation_cache.as_ref().and_then(|x| x.get(state)) {
+            return Some(x);
         }
 
-        node.borrow().child_at(0)
+        let evaluation = evaluator.evaluate(state);
+        if let Some(cache) = &self.evaluation_cache {
+            cache.insert(state, evaluation.clone());
+        }
+        Some(evaluation)
     }
-}
 
-impl<T, S> SimulationStrategy<T, S> for Tree<T, S>
-where
-    S: State<T>,
-    T: Clone,
-{
-    fn simulate(&self, node: &NodeRef<T, S>) -> f32 {
-        let mut total_reward = 0.0;
-        let mut current_state = node.borrow().state.clone();
+    pub fn evaluation_cache_stats(&self) -> Option<CacheStats> {
+        self.evaluation_cache.as_ref().map(|x| x.stats())
+    }
 
-        while let Some(action) = current_state.next_action() {
-            total_reward += current_state.do_action(&action);
+    pub fn rollout_cache_stats(&self) -> Option<CacheStats> {
+        self.rollout_cache.as_ref().map(|x| x.stats())
+    }
+
+    pub fn coverage(&self) -> Option<CoverageStats> {
+        self.coverage.as_ref().map(|x| x.stats())
+    }
+
+    pub fn config(&self) -> &SearchConfig {
+        &self.config
+    }
+
+    pub fn temperature(&self) -> f32 {
+        match &self.temperature {
+            Some(x) => x.value(self.progress),
+            None => 0.,
         }
+    }
 
-        total_reward
+    pub fn root(&self) -> NodeRef<T, S> {
+        Rc::clone(&self.root)
     }
-}
 
-impl<T, S> BackpropagationStrategy<T, S> for Tree<T, S>
-where
-    S: State<T>,
-    T: Clone,
-{
-    fn backpropagate(&mut self, node: &mut NodeRef<T, S>, value: f32) {
-        let child = node;
+    // None once the node has been pruned or spilled to disk
+    pub fn node(&self, id: NodeId) -> Option<NodeRef<T, S>> {
+        self.nodes.get(&id)?.upgrade()
+    }
 
-        loop {
-            child.borrow_mut().total_reward += value;
-            child.borrow_mut().visits += 1;
+    // Read-only access that gives None instead of panicking while the node is
+    // borrowed mutably
+    pub fn with_node<R, F>(&self, id: NodeId, f: F) -> Option<R>
+    where
+        F: FnOnce(&Node<T, S>) -> R,
+    {
+        let node = self.node(id)?;
+        let node = node.try_borrow().ok()?;
+        Some(f(&node))
+    }
 
-            let parent = match child.borrow().parent() {
-                Some(x) => x,
-                None => break,
-            };
+    pub fn node_stats(&self, id: NodeId) -> Option<ChildStats<T>> {
+        self.with_node(id, ChildStats::from_node)
+    }
 
-            *child = parent;
+    // None as soon as any of the children is borrowed mutably, a partial list
+    // would pass for a smaller tree
+    pub fn child_ids(&self, id: NodeId) -> Option<Vec<NodeId>> {
+        self.with_node(id, |node| {
+            node.children
+                .iter()
+                .map(|x| x.try_borrow().ok().map(|x| x.id()))
+                .collect::<Option<Vec<_>>>()
+        })?
+    }
+
+    pub(crate) fn register(&mut self, node: &NodeRef<T, S>) {
+        let id = NodeId(self.next_id);
+        self.next_id += 1;
+        self.register_as(node, id);
+    }
+
+    pub(crate) fn register_as(&mut self, node: &NodeRef<T, S>, id: NodeId) {
+        node.borrow_mut().set_id(id);
+        self.nodes.insert(id, Rc::downgrade(node));
+    }
+
+    pub fn search(&mut self, iterations: u32) -> Option<NodeRef<T, S>> {
+        let root = self.root();
+        self.search_from(&root, iterations, true)
+    }
+
+    // Searches the rewards of the state for `iterations`, then breaks the tie
+    // between the root actions within `epsilon` of the best mean with a
+    // secondary objective: `secondary_iterations` rollouts shared among them,
+    // scored by the sum of `objective` over the actions played
+    pub fn search_lexicographic<F>(
+        &mut self,
+        iterations: u32,
+        epsilon: f32,
+        secondary_iterations: u32,
+        objective: F,
+    ) -> Option<T>
+    where
+        F: Fn(&S, &T) -> f32,
+    {
+        self.search(iterations);
+        let root = self.root();
+        let children: Vec<NodeRef<T, S>> = root
+            .borrow()
+            .children
+            .iter()
+            .filter(|x| !x.borrow().illegal && x.borrow().visits > 0)
+            .map(Rc::clone)
+            .collect();
+        let best = children
+            .iter()
+            .map(|x| x.borrow().mean_value())
+            .fold(f32::MIN, f32::max);
+        let candidates: Vec<NodeRef<T, S>> = children
+            .into_iter()
+            .filter(|x| x.borrow().mean_value() >= best - epsilon)
+            .collect();
+        if candidates.len() <= 1 {
+            return candidates
+                .first()
+                .or(self.best_child().as_ref())
+                .map(|x| x.borrow().action.clone());
         }
+
+        let rollouts = (secondary_iterations / candidates.len() as u32).max(1);
+        let state = &root.borrow().state;
+        let scored: Vec<(T, f32)> = candidates
+            .iter()
+            .map(|x| {
+                let x = x.borrow();
+                let total: f32 = (0..rollouts)
+                    .map(|_| self.objective_rollout(&x.state, &objective))
+                    .sum();
+                let value = objective(state, &x.action) + total / rollouts as f32;
+                (x.action.clone(), value)
+            })
+            .collect();
+        scored
+            .into_iter()
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Less))
+            .map(|x| x.0)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    // Bound on the regret of the best root action from the CONFIDENCE_Z
+    // intervals of the root children. Children with fewer than two visits and
+    // the actions not expanded yet or pruned may be worth anything, up to 1 in
+    // game mode and without limit in planning mode. Proven game children are
+    // worth their outcome
+    pub fn suboptimality(&self) -> Option<Suboptimality<T>> {
+        let best = self.best_child()?;
+        let root = self.root.borrow();
+        let game = self.config.mode == RewardMode::Game;
+        let (min, max) = match game {
+            true => (0., 1.),
+            false => (f32::NEG_INFINITY, f32::INFINITY),
+        };
+        let interval = |node: &Node<T, S>| match node.proven {
+            Some(x) if game => (self.config.outcome_value(x), self.config.outcome_value(x)),
+            _ => Self::confidence_bounds(node)
+                .map_or((min, max), |(low, high)| (low.max(min), high.min(max))),
+        };
 
-    #[derive(Debug, Clone)]
-    struct DummyState {
-        action_reward: f32,
-        actions: u8,
+        let low = interval(&best.borrow()).0;
+        let unexplored = root.is_partially_expanded() || !root.pruned_actions.is_empty();
+        let mut high = if unexplored { max } else { f32::NEG_INFINITY };
+        let mut rival = None;
+        for child in root.children.iter() {
+            let child = child.borrow();
+            if child.illegal || child.id() == best.borrow().id() {
+                continue;
+            }
+            let x = interval(&child).1;
+            if x > high {
+                high = x;
+                rival = Some(child.action.clone());
+            }
+        }
+
+        // Without any other action there is nothing to regret
+        let bound = match high {
+            x if x == f32::NEG_INFINITY => 0.,
+            x => (x - low).max(0.),
+        };
+        let action = best.borrow().action.clone();
+        Some(Suboptimality {
+            action,
+            bound,
+            rival,
+        })
     }
 
-    impl DummyState {
-        fn new() -> Self {
-            Self {
-                action_reward: 0.5,
-                actions: 5,
+    // Searches until the best root action is within `epsilon` of every other
+    // one, see suboptimality, or `max_iterations` more iterations ran
+    pub fn search_bounded(
+        &mut self,
+        epsilon: f32,
+        max_iterations: u32,
+    ) -> Option<Suboptimality<T>> {
+        let start = self.iterations;
+        loop {
+            let bound = self.suboptimality();
+            let ran = self.iterations - start;
+            if ran >= max_iterations || bound.as_ref().is_some_and(|x| x.is_within(epsilon)) {
+                return bound;
+            }
+            self.search(BOUND_CHECK_INTERVAL.min(max_iterations - ran));
+            if self.iterations == start + ran {
+                return self.suboptimality();
             }
         }
     }
 
-    impl State<u8> for DummyState {
-        fn next_action(&self) -> Option<u8> {
-            if self.actions == 0 {
-                return None;
+    fn objective_rollout<F>(&self, state: &S, objective: &F) -> f32
+    where
+        F: Fn(&S, &T) -> f32,
+    {
+        let root_player = self.root.borrow().state.current_player();
+        let mut state = state.clone();
+        let mut total = 0.;
+        while let Some(action) = state.next_action() {
+            let action = self.rollout_action(&state, root_player).unwrap_or(action);
+            total += objective(&state, &action);
+            if self.step(&mut state, &action).is_err() {
+                self.record_failure();
+                break;
             }
-            Some(self.actions)
         }
+        total
+    }
 
-        fn do_action(&mut self, _action: &u8) -> f32 {
-            self.actions -= 1;
-            self.action_reward
+    // Dedicated iterations inside root subtrees, by the index of the child in
+    // the fully expanded root, for meta-controllers such as sequential
+    // halving. Indices past the children are left out
+    pub fn allocate_budget(&mut self, allocations: Vec<(usize, u32)>) -> Option<NodeRef<T, S>> {
+        let mut root = self.root();
+        self.expand_fully(&mut root);
+        let children = root.borrow().children.clone();
+
+        for (index, iterations) in allocations {
+            if let Some(child) = children.get(index) {
+                self.search_from(child, iterations, true);
+            }
         }
+        self.best_child()
     }
 
-    #[test]
-    fn expand() {
-        let mut state1 = DummyState::new();
-        let action1 = state1.next_action().unwrap();
-        state1.do_action(&action1);
-        let available_moves = state1.actions as usize;
+    // Every node down to `depth` below the root expanded, breadth-first and
+    // without simulations, stopping at SearchConfig::max_nodes
+    pub fn expand_to_depth(&mut self, depth: u32) {
+        let mut level = vec![self.root()];
+        for _ in 0..depth {
+            let mut next = vec![];
+            for mut node in level {
+                if self.config.max_nodes > 0 && self.size >= self.config.max_nodes {
+                    return;
+                }
+                self.expand_fully(&mut node);
+                next.extend(node.borrow().children.iter().map(Rc::clone));
+            }
+            level = next;
+        }
+    }
 
-        let mut tree = Tree::new(1.0, action1, state1);
-        let node = tree.expand(&mut tree.root());
+    // Random expansion carries on with the untried actions, a partial one
+    // from its cursor, neither adds an action twice
+    pub(crate) fn expand_fully(&mut self, node: &mut NodeRef<T, S>) {
+        while !node.borrow().expanded {
+            let before = node.borrow().children.len();
+            if node.borrow().untried_actions.is_some() {
+                self.expand_random(node);
+            } else {
+                self.expand(node);
+            }
+            if !node.borrow().expanded && node.borrow().children.len() == before {
+                break;
+            }
+        }
+    }
 
-        assert!(node.is_some());
-        assert!(node.unwrap().borrow().parent().is_some());
-        assert!(tree.root().borrow().parent().is_none());
-        assert_eq!(tree.root().borrow().children.len(), available_moves);
+    pub(crate) fn warm_up(&mut self) {
+        if self.iterations > 0 {
+            return;
+        }
+        if self.config.warm_up_depth > 0 {
+            self.expand_to_depth(self.config.warm_up_depth);
+        }
+        if self.config.pre_evaluate_root {
+            self.pre_evaluate_root();
+        }
     }
 
-    #[test]
-    fn select() {
-        let state1 = DummyState::new();
-        let action1 = state1.next_action().unwrap();
+    // Expands the root and evaluates it with its unvisited children in one
+    // Evaluator::evaluate_batch call. The root policy goes to the priors of
+    // the children, with policy priors, and every child value is backed up
+    // as an iteration of its own. Returns the children evaluated
+    pub fn pre_evaluate_root(&mut self) -> usize {
+        let evaluator = match &self.evaluator {
+            Some(x) => Rc::clone(x),
+            None => return 0,
+        };
+        let mut root = self.root();
+        self.expand_fully(&mut root);
+        let children: Vec<NodeRef<T, S>> = root
+            .borrow()
+            .children
+            .iter()
+            .filter(|x| {
+                let x = x.borrow();
+                !x.illegal && x.visits == 0 && x.state.next_action().is_some()
+            })
+            .map(Rc::clone)
+            .collect();
 
-        let mut tree = Tree::new(1.0, action1, state1);
-        let node1 = tree.expand(&mut tree.root()).unwrap();
+        let evaluations = {
+            let root = root.borrow();
+            let borrowed: Vec<_> = children.iter().map(|x| x.borrow()).collect();
+            let states: Vec<&S> = std::iter::once(&root.state)
+                .chain(borrowed.iter().map(|x| &x.state))
+                .collect();
+            let evaluations = evaluator.evaluate_batch(&states);
+            if let Some(cache) = &self.evaluation_cache {
+                for (state, x) in states.iter().zip(evaluations.iter()) {
+                    cache.insert(state, x.clone());
+                }
+            }
+            evaluations
+        };
+        let mut evaluations = evaluations.into_iter();
 
-        node1.borrow_mut().visits = 1;
-        node1.borrow_mut().total_reward = 1.;
-        tree.root.borrow_mut().visits = 1;
+        if let (Some((policy, _)), Some(lookup)) = (evaluations.next(), self.policy_prior) {
+            let total: f32 = policy.iter().map(|x| x.1.max(0.)).sum();
+            let policy: Vec<(T, f32)> = policy
+                .into_iter()
+                .map(|(a, p)| (a, if total > 0. { p.max(0.) / total } else { 0. }))
+                .collect();
+            for child in root.borrow().children.iter() {
+                let mut child = child.borrow_mut();
+                child.prior = Some(lookup(&policy, &child.action));
+            }
+            root.borrow_mut().policy = Some(policy);
+        }
 
-        // Nodes that have not been visited before are favored
-        let selected_node = tree.select().unwrap();
-        assert_eq!(selected_node.borrow().total_reward, 0.0);
-        assert_eq!(selected_node.borrow().visits, 0);
+        let mut evaluated = 0;
+        for (child, (_, value)) in children.iter().zip(evaluations) {
+            self.payoffs.replace(None);
+            self.outcome.set(None);
+            self.finish_iteration(&root, child, value, true);
+            evaluated += 1;
+        }
+        evaluated
     }
 
-    #[test]
-    fn simulate() {
-        let state1 = DummyState::new();
-        let action1 = state1.next_action().unwrap();
+    // Simulation and backup of a leaf from next_leaf, for searches that select
+    // the leaves of several trees before simulating any of them
+    pub(crate) fn finish_leaf(&mut self, leaf: &NodeRef<T, S>) {
+        let root = self.root();
+        let reward = self.simulate(leaf);
+        self.finish_iteration(&root, leaf, reward, true);
+    }
 
-        // Moves left * reward for each move
-        let final_rerward = (state1.actions) as f32 * state1.action_reward;
-        let tree = Tree::new(1.0, action1, state1);
-        assert_eq!(tree.simulate(&mut tree.root()), final_rerward);
+    // Restricts the iterations to the subtree under `node`, the values only go
+    // past it up to the root when `to_root` is set
+    pub fn search_from(
+        &mut self,
+        node: &NodeRef<T, S>,
+        iterations: u32,
+        to_root: bool,
+    ) -> Option<NodeRef<T, S>> {
+        let start = Instant::now();
+        self.warm_up();
+        for i in 0..iterations {
+            if self.out_of_budget() {
+                break;
+            }
+            self.progress = i as f32 / iterations as f32;
+
+            let leaf = match self.next_leaf(node) {
+                Some(x) => x,
+                None => break,
+            };
+            let reward = self.simulate(&leaf);
+            self.finish_iteration(node, &leaf, reward, to_root);
+        }
+
+        self.progress = 1.;
+        self.elapsed += start.elapsed();
+        self.best_child_of(node)
     }
 
-    #[test]
-    fn backpropagate() {
-        let state1 = DummyState::new();
-        let action1 = state1.next_action().unwrap();
+    // Searches from the root until the time budget is spent
+    pub fn search_for(&mut self, budget: Duration) -> Option<NodeRef<T, S>> {
+        self.search_with(SearchBudget::time(budget));
+        self.best_child()
+    }
 
-        let mut tree = Tree::new(1.0, action1, state1);
-        let mut node1 = tree.expand(&mut tree.root()).unwrap();
-        let mut node2 = tree.expand(&mut node1).unwrap();
+    // Mean value of committing to `plan` from the root, each of the rollouts
+    // goes on from where the plan ends with the usual policies. The tree is
+    // left untouched
+    pub fn simulate_plan(&self, plan: &[T], rollouts: u32) -> Result<f32, ActionError> {
+        let mut state = self.root.borrow().state.clone();
+        let mut reward = 0.;
+        for action in plan {
+            reward += self.step(&mut state, action)?;
+        }
 
-        tree.backpropagate(&mut node2, 5.0);
-        assert_eq!(tree.root().borrow().total_reward, 5.0);
+        let action = match plan.last() {
+            Some(x) => x.clone(),
+            None => self.root.borrow().action.clone(),
+        };
+        // Outside the tree, under an id no registered node gets
+        let node = Node::new(action, state);
+        node.borrow_mut().set_id(NodeId::SCRATCH);
+        let total: f32 = (0..rollouts).map(|_| self.rollout(&node, reward)).sum();
+        Ok(total / rollouts.max(1) as f32)
     }
 
-    #[test]
-    fn search() {
-        let state1 = DummyState::new();
-        let action1 = state1.next_action().unwrap();
+    pub(crate) fn past_deadline(&self) -> bool {
+        self.deadline.is_some_and(|x| Instant::now() >= x)
+    }
 
-        let mut tree = Tree::new(1.0, action1, state1);
-        let best_node = tree.search(20).unwrap();
+    // Selection below `node` and expansion of the selected leaf
+    pub(crate) fn next_leaf(&mut self, node: &NodeRef<T, S>) -> Option<NodeRef<T, S>> {
+        let start = self.config.profile_iterations.then(Instant::now);
+        let mut leaf_node = self.select_from(node)?;
 
-        for child in tree.root.borrow().children.iter() {
-            assert!(child.borrow().total_reward <= best_node.borrow().total_reward);
+        while self.is_spilled(&leaf_node) {
+            self.restore_spilled(&leaf_node);
+            leaf_node = self.select_from(node)?;
+        }
+        let selected = Instant::now();
+
+        // Virtual losses alone don't make a leaf worth expanding
+        let visited = {
+            let leaf = leaf_node.borrow();
+            leaf.visits > leaf.virtual_visits
+        };
+        if visited && self.can_expand() {
+            let first_new = leaf_node.borrow().children.len();
+            let child = if self.config.random_expansion {
+                self.expand_random(&mut leaf_node)
+            } else {
+                self.expand(&mut leaf_node)
+            };
+            if let Some(x) = child {
+                leaf_node = self.simulation_start(&leaf_node, first_new).unwrap_or(x);
+            }
         }
+        if let Some(start) = start {
+            self.leaf_times = Some((selected - start, selected.elapsed(), Instant::now()));
+        }
+        Some(leaf_node)
+    }
+
+    // Node to simulate among the children expanded from `first_new` on, None
+    // for the first of them
+    fn simulation_start(&self, leaf: &NodeRef<T, S>, first_new: usize) -> Option<NodeRef<T, S>> {
+        let node = leaf.borrow();
+        let new = node.children.get(first_new..)?;
+        match self.config.simulation_start {
+            SimulationStart::FirstChild => None,
+            SimulationStart::Leaf => Some(Rc::clone(leaf)),
+            SimulationStart::RandomChild if new.is_empty() => None,
+            SimulationStart::RandomChild => {
+                let index = self.rng.borrow_mut().gen_range(0..new.len());
+                Some(Rc::clone(&new[index]))
+            }
+            SimulationStart::BestChild => {
+                let root = self.root.borrow();
+                let flip = root.state.num_players() > 1
+                    && node.state.current_player() != root.state.current_player();
+                let value = |x: &NodeRef<T, S>| {
+                    let h = x.borrow().state.heuristic();
+                    if flip {
+                        -h
+                    } else {
+                        h
+                    }
+                };
+                new.iter()
+                    .max_by(|x, y| {
+                        value(x)
+                            .partial_cmp(&value(y))
+                            .unwrap_or(std::cmp::Ordering::Less)
+                    })
+                    .map(Rc::clone)
+            }
+        }
+    }
+
+    // False once degraded, after a failed allocation for good and at the node
+    // cap until the tree is back under it
+    fn can_expand(&mut self) -> bool {
+        if self
+            .degraded
+            .is_some_and(|x| x.cause == DegradeCause::AllocationFailure)
+        {
+            return false;
+        }
+        let full = self.config.degrade_at_node_cap
+            && self.config.max_nodes > 0
+            && self.size >= self.config.max_nodes;
+        if full && self.degraded.is_none() {
+            self.degrade(DegradeCause::NodeCap);
+        }
+        !full
+    }
+
+    fn degrade(&mut self, cause: DegradeCause) {
+        self.degraded = Some(Degradation {
+            cause,
+            iteration: self.iterations,
+        });
+    }
+
+    pub fn degraded(&self) -> Option<Degradation> {
+        self.degraded
+    }
+
+    // Phase timings of the iterations so far, with
+    // SearchConfig::profile_iterations
+    pub fn profile(&self) -> Option<&IterationProfile> {
+        self.profile.as_ref()
+    }
+
+    // Backpropagation of a simulated leaf and the bookkeeping of an iteration
+    pub(crate) fn finish_iteration(
+        &mut self,
+        node: &NodeRef<T, S>,
+        leaf: &NodeRef<T, S>,
+        reward: f32,
+        to_root: bool,
+    ) {
+        let simulated = self
+            .leaf_times
+            .take()
+            .map(|x| (x, x.2.elapsed(), Instant::now()));
+        self.attribute_cost(leaf);
+        let mut leaf_node = Rc::clone(leaf);
+        if to_root {
+            self.backpropagate(&mut leaf_node, reward);
+            self.record_value(reward);
+        } else {
+            self.backpropagate_until(&mut leaf_node, reward, Some(node));
+        }
+        self.notify_observers(leaf, reward);
+        self.iterations += 1;
+        self.track_leader();
+        self.record_root_value();
+        self.prune_dominated_root_children();
+        self.enforce_node_cap();
+        self.spill_cold_subtrees(leaf);
+        if let Some(((selection, expansion, _), simulation, start)) = simulated {
+            self.profile.get_or_insert_with(Default::default).record(
+                self.iterations - 1,
+                [selection, expansion, simulation, start.elapsed()],
+            );
+        }
+        self.fire_callbacks();
+    }
+
+    pub fn best_child(&self) -> Option<NodeRef<T, S>> {
+        self.best_child_of(&self.root)
+    }
+
+    // Children with fewer than `min_decision_visits` rollouts only count when no
+    // child has enough of them
+    fn best_child_of(&self, node: &NodeRef<T, S>) -> Option<NodeRef<T, S>> {
+        let min_visits = self.config.min_decision_visits;
+        let node = node.borrow();
+        let legal: Vec<&NodeRef<T, S>> = node
+            .children
+            .iter()
+            .filter(|x| !x.borrow().illegal)
+            .collect();
+
+        // Proven wins whatever their visits, proven losses only when nothing
+        // else is left
+        if let Some(x) = legal
+            .iter()
+            .filter(|x| x.borrow().proven == Some(Outcome::Win))
+            .max_by_key(|x| x.borrow().visits)
+        {
+            return Some(Rc::clone(x));
+        }
+        let lost = |x: &&NodeRef<T, S>| x.borrow().proven == Some(Outcome::Loss);
+        let open: Vec<&NodeRef<T, S>> = if legal.iter().all(lost) {
+            legal
+        } else {
+            legal.into_iter().filter(|x| !lost(x)).collect()
+        };
+
+        let sampled = open.iter().any(|x| x.borrow().visits >= min_visits);
+        let candidates = open
+            .into_iter()
+            .filter(|x| !sampled || x.borrow().visits >= min_visits);
+
+        match self.config.mode {
+            RewardMode::Game => candidates.max_by_key(|x| x.borrow().visits),
+            RewardMode::Planning => candidates.max_by(|x, y| {
+                x.borrow()
+                    .total_reward()
+                    .partial_cmp(&y.borrow().total_reward())
+                    .unwrap_or(std::cmp::Ordering::Less)
+            }),
+        }
+        .map(Rc::clone)
+    }
+
+    pub fn result(&self) -> SearchResult<T> {
+        self.try_result()
+            .expect("search result requested while a node is borrowed mutably")
+    }
+
+    // Same as result, None while the root or one of its children is borrowed
+    // mutably
+    pub fn try_result(&self) -> Option<SearchResult<T>> {
+        let root = self.root.try_borrow().ok()?.id();
+        let children = self
+            .child_ids(root)?
+            .into_iter()
+            .map(|x| self.node_stats(x))
+            .collect::<Option<Vec<_>>>()?;
+        Some(SearchResult {
+            best_action: self.best_child().map(|x| x.borrow().action.clone()),
+            iterations: self.iterations,
+            steps: self.steps(),
+            cost: self.cost(),
+            nodes: self.size,
+            max_depth: self.root.borrow().subtree_stats().depth,
+            elapsed: self.elapsed,
+            recent_values: self.recent_values.iter().copied().collect(),
+            children,
+            stability: self.stability(),
+            failures: self.failures(),
+            coverage: self.coverage(),
+            degraded: self.degraded,
+            profile: self.profile.clone(),
+            repro: Repro::new(self.config, self.iterations, self.fingerprint),
+        })
+    }
+
+    // Actions whose State::try_do_action failed during expansion or rollouts
+    pub fn failures(&self) -> u32 {
+        self.failures.get()
+    }
+
+    pub(crate) fn record_failure(&self) {
+        self.failures.set(self.failures.get() + 1);
+    }
+
+    // State transitions executed by the search so far
+    pub fn steps(&self) -> u64 {
+        self.steps.get()
+    }
+
+    // Sum of State::step_cost over those transitions
+    pub fn cost(&self) -> f64 {
+        self.cost.get()
+    }
+
+    pub(crate) fn out_of_budget(&self) -> bool {
+        (self.config.max_steps > 0 && self.steps() >= self.config.max_steps)
+            || (self.config.max_cost > 0. && self.cost() >= self.config.max_cost)
+    }
+
+    pub(crate) fn step(&self, state: &mut S, action: &T) -> Result<f32, ActionError> {
+        self.steps.set(self.steps.get() + 1);
+        self.cost
+            .set(self.cost.get() + f64::from(state.step_cost(action)));
+        state.try_do_action(action)
+    }
+
+    // Charges the cost since the previous iteration to the path of `leaf`
+    fn attribute_cost(&mut self, leaf: &NodeRef<T, S>) {
+        let cost = self.cost() - self.attributed_cost;
+        self.attributed_cost = self.cost();
+
+        let mut node = Some(Rc::clone(leaf));
+        while let Some(x) = node {
+            x.borrow_mut().cost += cost;
+            node = x.borrow().parent();
+        }
+    }
+
+    pub fn stability(&self) -> Stability {
+        match &self.leader {
+            Some((_, iteration, time)) => Stability {
+                lead_changes: self.lead_changes,
+                stable_iterations: self.iterations - iteration,
+                stable_time: time.elapsed(),
+            },
+            None => Stability::default(),
+        }
+    }
+
+    fn fire_callbacks(&mut self) {
+        if self.callbacks.is_empty() {
+            return;
+        }
+
+        let now = Instant::now();
+        let iterations = self.iterations;
+        let due: Vec<Callback<T, S>> = self
+            .callbacks
+            .iter_mut()
+            .filter(|x| match x.interval {
+                Interval::Iterations(n) => n > 0 && iterations - x.last_iteration >= n,
+                Interval::Time(d) => now.duration_since(x.last_time) >= d,
+            })
+            .map(|x| {
+                x.last_iteration = iterations;
+                x.last_time = now;
+                Rc::clone(&x.callback)
+            })
+            .collect();
+
+        for callback in due {
+            callback(self);
+        }
+    }
+
+    fn notify_observers(&self, leaf: &NodeRef<T, S>, value: f32) {
+        if self.observers.is_empty() || !self.config.is_sampled(self.iterations) {
+            return;
+        }
+
+        let mut depth = 0;
+        let mut node = leaf.borrow().parent();
+        while let Some(x) = node {
+            node = x.borrow().parent();
+            depth += 1;
+        }
+
+        let sample = IterationSample {
+            iteration: self.iterations,
+            depth,
+            value,
+            size: self.size,
+        };
+        for observer in &self.observers {
+            observer.on_iteration(&sample);
+        }
+    }
+
+    fn track_leader(&mut self) {
+        let best = match self.best_child() {
+            Some(x) => Rc::downgrade(&x),
+            None => return,
+        };
+
+        match &self.leader {
+            Some((leader, _, _)) if leader.ptr_eq(&best) => (),
+            Some(_) => {
+                self.lead_changes += 1;
+                self.leader = Some((best, self.iterations, Instant::now()));
+            }
+            None => self.leader = Some((best, self.iterations, Instant::now())),
+        }
+    }
+
+    // Visit-weighted mean of the root children
+    pub fn root_value(&self) -> Option<f32> {
+        let root = self.root.borrow();
+        let (visits, total) = root.children.iter().filter(|x| !x.borrow().illegal).fold(
+            (0, 0.),
+            |(visits, total), x| {
+                let x = x.borrow();
+                (visits + x.visits, total + stat_to_f64(x.total_reward()))
+            },
+        );
+
+        if visits == 0 {
+            return None;
+        }
+        Some((total / visits as f64) as f32)
+    }
+
+    // (iteration, root value) pairs sampled every `root_value_interval` iterations
+    pub fn root_value_history(&self) -> &[(u32, f32)] {
+        &self.root_values
+    }
+
+    fn record_root_value(&mut self) {
+        let interval = self.config.root_value_interval;
+        if interval == 0 || !self.iterations.is_multiple_of(interval) {
+            return;
+        }
+        if let Some(x) = self.root_value() {
+            self.root_values.push((self.iterations, x));
+        }
+    }
+
+    pub(crate) fn record_value(&mut self, value: f32) {
+        if self.config.value_window == 0 {
+            return;
+        }
+        if self.recent_values.len() == self.config.value_window {
+            self.recent_values.pop_front();
+        }
+        self.recent_values.push_back(value);
+    }
+
+    pub fn reward_bounds(&self) -> Option<(f32, f32)> {
+        self.reward_bounds
+    }
+
+    pub fn novelty(&self) -> Option<&Novelty<S>> {
+        self.novelty.as_ref()
+    }
+
+    fn node_score(&self, node: &Node<T, S>, depth: u32, player: usize) -> f32 {
+        let exploration = self.player_exploration.get(&player);
+        if let Some(x) = exploration.and_then(|x| x.fpu).filter(|_| node.visits == 0) {
+            return x;
+        }
+        let c = match exploration {
+            Some(x) => SearchConfig {
+                learning_rate: x.exploration,
+                ..self.config
+            }
+            .exploration_at(depth),
+            None => self.config.exploration_at(depth),
+        };
+
+        let (value, bounds) = self.perspective(node, player);
+        let score = self.selection_rule.score(node, value, c, bounds);
+
+        // Progressive bias, fades as the visits take over
+        let score = match node.prior {
+            Some(x) => score + self.config.prior_weight * x / (node.visits + 1) as f32,
+            None => score,
+        };
+
+        match &self.novelty {
+            Some(x) => score + x.bonus(&node.state, self.iterations),
+            None => score,
+        }
+    }
+
+    // Mean and reward bounds of the node seen by `player`, the values are kept
+    // for the root player
+    fn perspective(&self, node: &Node<T, S>, player: usize) -> (f32, Option<(f32, f32)>) {
+        let bounds = match self.config.mode {
+            RewardMode::Planning => self.reward_bounds,
+            RewardMode::Game => None,
+        };
+        let root = self.root.borrow();
+        let players = root.state.num_players();
+        if players <= 1 || player == root.state.current_player() {
+            return (node.mean_value(), bounds);
+        }
+        if players > 2 {
+            if let Some(x) = node.payoff(player) {
+                return (x, None);
+            }
+        }
+
+        match self.config.mode {
+            RewardMode::Game => (1. - node.mean_value(), None),
+            RewardMode::Planning => (-node.mean_value(), bounds.map(|(low, high)| (-high, -low))),
+        }
+    }
+
+    pub fn clear_hint(&mut self) {
+        self.hint = None;
+    }
+
+    fn hint_bonus(&self, node: &NodeRef<T, S>) -> f32 {
+        match &self.hint {
+            Some(x)
+                if self.iterations < x.until
+                    && x.nodes.iter().any(|y| y.ptr_eq(&Rc::downgrade(node))) =>
+            {
+                x.boost
+            }
+            _ => 0.,
+        }
+    }
+
+    fn confidence_bounds(node: &Node<T, S>) -> Option<(f32, f32)> {
+        if node.visits < 2 {
+            return None;
+        }
+        let visits = node.visits as f32;
+        let variance = node.value_variance() * visits / (visits - 1.);
+        let margin = CONFIDENCE_Z * (variance / visits).sqrt();
+        Some((node.mean_value() - margin, node.mean_value() + margin))
+    }
+
+    // Drops the root children that stayed statistically worse than the best
+    // one, their share of the iterations goes to the others
+    fn prune_dominated_root_children(&mut self) {
+        let checks = self.config.lcb_prune_checks;
+        if checks == 0 {
+            return;
+        }
+
+        let children = self.root.borrow().children.clone();
+        let bounds: Vec<Option<(f32, f32)>> = children
+            .iter()
+            .map(|x| Self::confidence_bounds(&x.borrow()))
+            .collect();
+        // Nothing can be told apart before every child has a bound
+        if children.len() < 2 || bounds.iter().any(Option::is_none) {
+            return;
+        }
+        let best_low = bounds
+            .iter()
+            .flatten()
+            .map(|x| x.0)
+            .fold(f32::NEG_INFINITY, f32::max);
+
+        for (child, bounds) in children.iter().zip(bounds) {
+            let id = child.borrow().id();
+            // Pipelined leaves in flight below hold virtual visits, they are
+            // taken back out through the parents
+            if child.borrow().virtual_visits > 0 {
+                continue;
+            }
+            if bounds.is_some_and(|x| x.1 < best_low) {
+                let count = self.dominated.entry(id).or_insert(0);
+                *count += 1;
+                if *count >= checks {
+                    self.dominated.remove(&id);
+                    self.remove_subtree(child);
+                }
+            } else {
+                self.dominated.remove(&id);
+            }
+        }
+    }
+
+    // Soft pruning: keeps the `keep` most visited children and remembers the
+    // other actions so they can be regenerated once the node gets more visits
+    pub fn prune_children(&mut self, node: &NodeRef<T, S>, keep: usize) {
+        let mut children = node.borrow().children.clone();
+        children.sort_by_key(|x| std::cmp::Reverse(x.borrow().visits));
+
+        for child in children.iter().skip(keep) {
+            self.remove_subtree(child);
+        }
+    }
+
+    // Best child at the root, then the most visited child at every level below
+    pub fn principal_variation(&self) -> Vec<NodeRef<T, S>> {
+        let mut line = vec![];
+        let mut next = self.best_child();
+
+        while let Some(x) = next {
+            next = x.borrow().most_visited_child();
+            line.push(x);
+        }
+
+        line
+    }
+
+    // Prunes the least visited subtrees until the tree fits SearchConfig::max_nodes,
+    // leaving the principal variation, nodes below prune_min_visits and the
+    // paths of pipelined leaves in flight alone
+    pub fn enforce_node_cap(&mut self) {
+        if self.config.max_nodes == 0 || self.config.degrade_at_node_cap {
+            return;
+        }
+
+        while self.size > self.config.max_nodes {
+            let line = self.principal_variation();
+            let mut candidate: Option<NodeRef<T, S>> = None;
+            let mut stack = self.root.borrow().children.clone();
+
+            while let Some(x) = stack.pop() {
+                stack.extend(x.borrow().children.iter().map(Rc::clone));
+
+                let visits = x.borrow().visits;
+                if visits < self.config.prune_min_visits
+                    || x.borrow().virtual_visits > 0
+                    || line.iter().any(|y| Rc::ptr_eq(y, &x))
+                {
+                    continue;
+                }
+
+                let better = match &candidate {
+                    Some(y) => visits < y.borrow().visits,
+                    None => true,
+                };
+                if better {
+                    candidate = Some(x);
+                }
+            }
+
+            match candidate {
+                Some(x) => self.remove_subtree(&x),
+                None => break,
+            }
+        }
+    }
+
+    // Drops a subtree that no longer matches the environment and takes its
+    // values back out of the ancestors, the action is regenerated from the
+    // parent's state only through unpruning
+    pub fn invalidate(&mut self, node: &NodeRef<T, S>) -> bool {
+        let (visits, mean, m2) = {
+            let node = node.borrow();
+            (node.visits, node.mean, node.m2)
+        };
+        if node.borrow().parent().is_none() {
+            return false;
+        }
+
+        let mut ancestor = node.borrow().parent();
+        while let Some(x) = ancestor {
+            x.borrow_mut().remove_stats(visits, mean, m2);
+            self.emit(|| updated(&x.borrow()));
+            ancestor = x.borrow().parent();
+        }
+
+        self.remove_subtree(node);
+        true
+    }
+
+    fn remove_subtree(&mut self, node: &NodeRef<T, S>) {
+        let parent = match node.borrow().parent() {
+            Some(x) => x,
+            None => return,
+        };
+
+        self.emit(|| TreeEvent::Removed {
+            id: node.borrow().id(),
+        });
+        self.size -= Self::subtree_size(node);
+        for x in Self::descendants(node) {
+            self.nodes.remove(&x.borrow().id());
+        }
+        self.nodes.remove(&node.borrow().id());
+
+        parent.borrow().invalidate_subtree_stats();
+        let mut parent = parent.borrow_mut();
+        parent.children.retain(|x| !Rc::ptr_eq(x, node));
+        parent.pruned_actions.push(node.borrow().action.clone());
+        parent.pruned_at = parent.visits;
+    }
+
+    fn unprune_children(&mut self, node: &mut NodeRef<T, S>) {
+        let actions = std::mem::take(&mut node.borrow_mut().pruned_actions);
+
+        for action in actions {
+            let mut state = node.borrow().state.clone();
+            match self.step(&mut state, &action) {
+                Ok(_) => {
+                    let new_node = Node::new(action, state);
+                    self.apply_prior(&new_node);
+                    self.add_node(new_node, node);
+                }
+                Err(_) => self.record_failure(),
+            }
+        }
+    }
+
+    // Root children below the visit quota are tried before UCT takes over
+    // Descends from `start`, the root quota only applies when it is the root
+    fn select_from(&self, start: &NodeRef<T, S>) -> Option<NodeRef<T, S>> {
+        let temperature = self.temperature();
+        let unvisited_first = self.selection_rule.unvisited_first();
+        let mut rng = self.rng.borrow_mut();
+        let root_player = self.root.borrow().state.current_player();
+        let mut child = match self.under_visited_root_child() {
+            Some(x) if Rc::ptr_eq(start, &self.root) => x,
+            _ => Rc::clone(start),
+        };
+
+        while !child.borrow().children.is_empty() && !child.borrow().is_partially_expanded() {
+            if let Some(next) = self.opponent_child(&child, root_player) {
+                child = next;
+                continue;
+            }
+            if let Some(next) = self.imperfect_opponent_child(&child, root_player, &mut rng) {
+                child = next;
+                continue;
+            }
+
+            if self.config.selection == SelectionPolicy::Greedy {
+                child = match Self::greedy_child(&child, Some(&mut rng)) {
+                    Some(x) => x,
+                    None => break,
+                };
+                continue;
+            }
+
+            let depth = match self.config.depth_exploration {
+                Some(_) => Self::depth(&child) + 1,
+                None => 0,
+            };
+            let player = child.borrow().state.current_player();
+            let unvisited_first = unvisited_first
+                && self
+                    .player_exploration
+                    .get(&player)
+                    .is_none_or(|x| x.fpu.is_none());
+            // Tie-breaking noise, cooled down by the temperature schedule
+            // Unvisited nodes without a first play value of their own go first
+            let scored: Vec<(NodeRef<T, S>, f32, bool)> = self
+                .reduced_children(&child, player)
+                .iter()
+                .map(|x| {
+                    let noise = if temperature > 0. {
+                        temperature * rng.gen::<f32>()
+                    } else {
+                        0.
+                    };
+                    let node = x.borrow();
+                    let first_play = match node.visits {
+                        0 => node.state.first_play_value(),
+                        _ => None,
+                    };
+                    let score = match first_play {
+                        Some(x) => x,
+                        None => self.node_score(&node, depth, player),
+                    };
+                    let first = unvisited_first && node.visits == 0 && first_play.is_none();
+                    (Rc::clone(x), score + self.hint_bonus(x) + noise, first)
+                })
+                .collect();
+
+            let next = match scored.iter().max_by(|a, b| {
+                if a.2 {
+                    return std::cmp::Ordering::Greater;
+                }
+
+                a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Less)
+            }) {
+                Some(x) => Rc::clone(&x.0),
+                None => break,
+            };
+
+            child = next;
+        }
+
+        Some(child)
+    }
+
+    // Legal children worth scoring, see SearchConfig::late_move_reduction
+    fn reduced_children(&self, node: &NodeRef<T, S>, player: usize) -> Vec<NodeRef<T, S>> {
+        let node = node.borrow();
+        let legal: Vec<&NodeRef<T, S>> = node
+            .children
+            .iter()
+            .filter(|x| !x.borrow().illegal)
+            .collect();
+        let (reduction, top) = match self.config.late_move_reduction {
+            Some(x) if node.visits >= x.min_visits && legal.len() > x.top.max(1) => {
+                (x, x.top.max(1))
+            }
+            _ => return legal.into_iter().map(Rc::clone).collect(),
+        };
+
+        let values: Vec<f32> = legal
+            .iter()
+            .map(|x| self.perspective(&x.borrow(), player).0)
+            .collect();
+        let mut order: Vec<usize> = (0..legal.len()).collect();
+        order.select_nth_unstable_by(top, |a, b| {
+            values[*b]
+                .partial_cmp(&values[*a])
+                .unwrap_or(std::cmp::Ordering::Less)
+        });
+        let mut rest = order.split_off(top);
+        if reduction.interval > 0 && node.visits.is_multiple_of(reduction.interval) {
+            rest.sort_unstable();
+            order.push(rest[(node.visits / reduction.interval) as usize % rest.len()]);
+        }
+        order.into_iter().map(|i| Rc::clone(legal[i])).collect()
+    }
+
+    // Stops after updating `stop` when given instead of going up to the root
+    fn backpropagate_until(
+        &mut self,
+        node: &mut NodeRef<T, S>,
+        value: f32,
+        stop: Option<&NodeRef<T, S>>,
+    ) {
+        self.reward_bounds = match self.reward_bounds {
+            Some((low, high)) => Some((low.min(value), high.max(value))),
+            None => Some((value, value)),
+        };
+
+        let child = node;
+        let payoffs = self.payoffs.take();
+        let outcome = self.outcome.take();
+        let capture = self.config.capture_paths > 0;
+        let mut steps = vec![];
+
+        loop {
+            self.update_node(child, &[value], payoffs.as_deref(), outcome);
+            if capture {
+                let node = child.borrow();
+                steps.push(BackupStep {
+                    node: node.id(),
+                    visits: node.visits,
+                    mean: node.mean_value(),
+                });
+            }
+
+            if stop.is_some_and(|x| Rc::ptr_eq(x, child)) {
+                break;
+            }
+
+            let parent = match child.borrow().parent() {
+                Some(x) => x,
+                None => break,
+            };
+
+            *child = parent;
+        }
+
+        if capture {
+            self.capture_path(BackupPath {
+                iteration: self.iterations,
+                value,
+                steps,
+            });
+        }
+    }
+
+    fn capture_path(&mut self, path: BackupPath) {
+        while self.backup_paths.len() >= self.config.capture_paths {
+            self.backup_paths.pop_front();
+        }
+        self.backup_paths.push_back(path);
+    }
+
+    // One backup step of every value through `node`, payoffs and outcome are
+    // the ones of the simulation
+    fn update_node(
+        &mut self,
+        node: &mut NodeRef<T, S>,
+        values: &[f32],
+        payoffs: Option<&[f32]>,
+        outcome: Option<Outcome>,
+    ) {
+        for &value in values {
+            self.backup_rule.update(&mut node.borrow_mut(), value);
+        }
+        if let Some(x) = payoffs {
+            node.borrow_mut().push_payoffs(x);
+        }
+        if let Some(x) = outcome {
+            node.borrow_mut().outcomes.add(x);
+        }
+        self.visited(node, values.len() as u32, values);
+        self.emit(|| updated(&node.borrow()));
+    }
+
+    // Oldest first, with SearchConfig::capture_paths. The steps of batched
+    // backups have the statistics after the whole batch
+    pub fn backup_paths(&self) -> &VecDeque<BackupPath> {
+        &self.backup_paths
+    }
+
+    // Applies several results at once, as many backups in their order would.
+    // Nodes shared by their paths are walked a single time, with every value
+    // that goes through them. The payoffs and outcome of the last simulation
+    // go with the first result
+    pub fn backpropagate_batch(&mut self, results: Vec<(NodeRef<T, S>, f32)>) {
+        let mut index: HashMap<NodeId, usize> = HashMap::new();
+        let mut updates: Vec<BatchUpdate<T, S>> = vec![];
+        let payoffs = self.payoffs.take();
+        let outcome = self.outcome.take();
+        let capture = self.config.capture_paths > 0;
+        let mut paths = vec![];
+
+        // Union of the paths, each node walked once
+        for (result, (leaf, value)) in results.into_iter().enumerate() {
+            if capture {
+                paths.push((Rc::clone(&leaf), value));
+            }
+            self.reward_bounds = match self.reward_bounds {
+                Some((low, high)) => Some((low.min(value), high.max(value))),
+                None => Some((value, value)),
+            };
+
+            let mut child: Option<usize> = None;
+            let mut node = Some(leaf);
+            while let Some(x) = node {
+                let id = x.borrow().id();
+                let (i, known) = match index.get(&id) {
+                    Some(&i) => (i, true),
+                    None => {
+                        index.insert(id, updates.len());
+                        updates.push(BatchUpdate {
+                            node: Rc::clone(&x),
+                            parent: None,
+                            pending: 0,
+                            values: vec![],
+                        });
+                        (updates.len() - 1, false)
+                    }
+                };
+
+                match child {
+                    Some(c) => {
+                        updates[c].parent = Some(i);
+                        updates[i].pending += 1;
+                    }
+                    None => updates[i].values.push((result, value)),
+                }
+                if known {
+                    break;
+                }
+
+                child = Some(i);
+                node = x.borrow().parent();
+            }
+        }
+
+        // Children are complete before their parent
+        let mut ready: Vec<usize> = (0..updates.len())
+            .filter(|&i| updates[i].pending == 0)
+            .collect();
+        while let Some(i) = ready.pop() {
+            let mut node = Rc::clone(&updates[i].node);
+            let mut values = std::mem::take(&mut updates[i].values);
+            values.sort_by_key(|x| x.0);
+            let first = values.first().is_some_and(|x| x.0 == 0);
+            let ordered: Vec<f32> = values.iter().map(|x| x.1).collect();
+            let (payoffs, outcome) = match first {
+                true => (payoffs.as_deref(), outcome),
+                false => (None, None),
+            };
+            self.update_node(&mut node, &ordered, payoffs, outcome);
+
+            if let Some(p) = updates[i].parent {
+                updates[p].values.extend(values);
+                updates[p].pending -= 1;
+                if updates[p].pending == 0 {
+                    ready.push(p);
+                }
+            }
+        }
+
+        for (leaf, value) in paths {
+            let mut steps = vec![];
+            let mut node = Some(leaf);
+            while let Some(x) = node {
+                steps.push(BackupStep {
+                    node: x.borrow().id(),
+                    visits: x.borrow().visits,
+                    mean: x.borrow().mean_value(),
+                });
+                node = x.borrow().parent();
+            }
+            self.capture_path(BackupPath {
+                iteration: self.iterations,
+                value,
+                steps,
+            });
+        }
+    }
+
+    // Bookkeeping after the statistics of a node took the given values
+    fn visited(&mut self, node: &mut NodeRef<T, S>, visits: u32, values: &[f32]) {
+        if self.config.quantile_sketch_size > 0 {
+            let size = self.config.quantile_sketch_size;
+            let mut node = node.borrow_mut();
+            let sketch = node.sketch.get_or_insert_with(|| QuantileSketch::new(size));
+            for &x in values {
+                sketch.add(x);
+            }
+        }
+
+        if let Some(novelty) = &mut self.novelty {
+            for _i in 0..visits {
+                novelty.visit(&node.borrow().state);
+            }
+        }
+
+        if self.config.solver {
+            self.update_proof(node);
+        }
+
+        let unprune = {
+            let node = node.borrow();
+            self.config.unprune_visits > 0
+                && !node.pruned_actions.is_empty()
+                && node.visits >= node.pruned_at + self.config.unprune_visits
+        };
+        if unprune {
+            self.unprune_children(node);
+        }
+    }
+
+    // MCTS-Solver: terminal states are proven by their outcome, other nodes once
+    // the player to move has a winning child or every child is proven
+    fn update_proof(&self, node: &NodeRef<T, S>) {
+        if node.borrow().proven.is_some() {
+            return;
+        }
+        let root_player = self.root.borrow().state.current_player();
+
+        let proven = {
+            let node = node.borrow();
+            if node.children.is_empty() {
+                match node.state.next_action() {
+                    Some(_) => None,
+                    None => self.root_outcome(&node.state, root_player),
+                }
+            } else {
+                let rank = |x: &Outcome| match x {
+                    Outcome::Loss => 0,
+                    Outcome::Draw => 1,
+                    Outcome::Win => 2,
+                };
+                let (best, worst) = if node.state.current_player() == root_player {
+                    (Outcome::Win, Outcome::Loss)
+                } else {
+                    (Outcome::Loss, Outcome::Win)
+                };
+                let children: Vec<Option<Outcome>> = node
+                    .children
+                    .iter()
+                    .filter(|x| !x.borrow().illegal)
+                    .map(|x| x.borrow().proven)
+                    .collect();
+                let complete = node.expanded
+                    && node.pruned_actions.is_empty()
+                    && children.iter().all(|x| x.is_some());
+
+                if children.contains(&Some(best)) {
+                    Some(best)
+                } else if complete {
+                    // The mover picks its best outcome among its children
+                    let outcomes = children.into_iter().flatten();
+                    if best == Outcome::Win {
+                        outcomes.max_by_key(rank)
+                    } else {
+                        outcomes.min_by_key(rank)
+                    }
+                    .or(Some(worst))
+                } else {
+                    None
+                }
+            }
+        };
+        node.borrow_mut().proven = proven;
+    }
+
+    // Values and proofs are kept for the root player
+    fn root_outcome(&self, state: &S, root_player: usize) -> Option<Outcome> {
+        let outcome = state.outcome()?;
+        Some(outcome.for_player(root_player, state.num_players()))
+    }
+
+    // Child with the best mean, or a random unvisited one when given a source of
+    // randomness, without one unvisited children are never picked
+    fn greedy_child(node: &NodeRef<T, S>, rng: Option<&mut StdRng>) -> Option<NodeRef<T, S>> {
+        let node = node.borrow();
+        let children: Vec<&NodeRef<T, S>> = node
+            .children
+            .iter()
+            .filter(|x| !x.borrow().illegal)
+            .collect();
+
+        let unvisited: Vec<&NodeRef<T, S>> = children
+            .iter()
+            .filter(|x| x.borrow().visits == 0)
+            .copied()
+            .collect();
+        if let (Some(rng), false) = (rng, unvisited.is_empty()) {
+            return Some(Rc::clone(unvisited[rng.gen_range(0..unvisited.len())]));
+        }
+
+        children
+            .into_iter()
+            .filter(|x| x.borrow().visits > 0)
+            .max_by(|x, y| {
+                x.borrow()
+                    .mean_value()
+                    .partial_cmp(&y.borrow().mean_value())
+                    .unwrap_or(std::cmp::Ordering::Less)
+            })
+            .map(Rc::clone)
+    }
+
+    // Deterministic plan following the best mean at every step
+    pub fn greedy_plan(&self) -> Vec<T> {
+        let mut plan = vec![];
+        let mut next = Self::greedy_child(&self.root, None);
+
+        while let Some(x) = next {
+            plan.push(x.borrow().action.clone());
+            next = Self::greedy_child(&x, None);
+        }
+
+        plan
+    }
+
+    fn under_visited_root_child(&self) -> Option<NodeRef<T, S>> {
+        let root = self.root.borrow();
+        if self.config.root_min_visits == 0 || root.is_partially_expanded() {
+            return None;
+        }
+
+        // The quota becomes the cost of that many average iterations
+        if self.config.root_quota_by_cost {
+            let quota = self.config.root_min_visits as f64 * root.cost / root.visits.max(1) as f64;
+            return root
+                .children
+                .iter()
+                .filter(|x| x.borrow().cost < quota)
+                .min_by(|x, y| {
+                    x.borrow()
+                        .cost
+                        .partial_cmp(&y.borrow().cost)
+                        .unwrap_or(std::cmp::Ordering::Less)
+                })
+                .map(Rc::clone);
+        }
+
+        root.children
+            .iter()
+            .filter(|x| x.borrow().visits < self.config.root_min_visits)
+            .min_by_key(|x| x.borrow().visits)
+            .map(Rc::clone)
+    }
+
+    fn opponent_child(&self, node: &NodeRef<T, S>, root_player: usize) -> Option<NodeRef<T, S>> {
+        let model = match &self.opponent {
+            Some(x) if self.config.opponent_selection => x,
+            _ => return None,
+        };
+
+        let node = node.borrow();
+        if node.state.current_player() == root_player {
+            return None;
+        }
+
+        let actions: Vec<T> = node
+            .children
+            .iter()
+            .map(|x| x.borrow().action.clone())
+            .collect();
+        let index = model.select_child(&node.state, &actions)?;
+        node.child_at(index)
+    }
+
+    // Opponent nodes where every child was visited, the opponent prefers the
+    // children with a low value for the root player
+    fn imperfect_opponent_child(
+        &self,
+        node: &NodeRef<T, S>,
+        root_player: usize,
+        rng: &mut StdRng,
+    ) -> Option<NodeRef<T, S>> {
+        let rationality = self.config.opponent_rationality;
+        let node = node.borrow();
+        if rationality <= 0. || node.state.current_player() == root_player {
+            return None;
+        }
+
+        let children: Vec<&NodeRef<T, S>> = node
+            .children
+            .iter()
+            .filter(|x| !x.borrow().illegal)
+            .collect();
+        if children.iter().any(|x| x.borrow().visits == 0) {
+            return None;
+        }
+
+        let values: Vec<f32> = children.iter().map(|x| -x.borrow().mean_value()).collect();
+        let index = sample_softmax(&values, rationality, rng)?;
+        Some(Rc::clone(children[index]))
+    }
+
+    // A player's own policy comes first, then the opponent model, then the
+    // softmax over immediate rewards of an imperfect opponent
+    fn rollout_action(&self, state: &S, root_player: usize) -> Option<T> {
+        let player = state.current_player();
+
+        if let Some(policy) = self.rollout_policies.get(&player) {
+            return policy.choose(state);
+        }
+
+        if let Some(model) = &self.opponent {
+            if player != root_player {
+                return model.rollout_action(state);
+            }
+        }
+
+        // The trial actions are not charged as steps, only the one played is
+        if self.config.opponent_rationality > 0. && player != root_player {
+            let mut actions = vec![];
+            let mut values = vec![];
+            for action in state.legal_actions() {
+                if let Ok(x) = state.clone().try_do_action(&action) {
+                    values.push(-x);
+                    actions.push(action);
+                }
+            }
+
+            let mut rng = self.rng.borrow_mut();
+            let index = sample_softmax(&values, self.config.opponent_rationality, &mut rng)?;
+            return Some(actions.swap_remove(index));
+        }
+
+        self.rollout_policy.choose(state)
+    }
+
+    // Adds one child for a random untried action, the actions are listed when
+    // the node is first expanded
+    fn expand_random(&mut self, node: &mut NodeRef<T, S>) -> Option<NodeRef<T, S>> {
+        if node.borrow().untried_actions.is_none() {
+            // Children from a regular expansion can't be told apart from the
+            // untried actions, that expansion carries on instead
+            if !node.borrow().children.is_empty() {
+                return self.expand(node);
+            }
+            let reduce = node.borrow().parent().is_some();
+            let actions = {
+                let node = node.borrow();
+                let mut actions = node.state.legal_actions();
+                actions.retain(|x| {
+                    !(reduce && node.state.commutes(&node.action, x))
+                        && self.expansion_rule.expand(&node.state, x)
+                });
+                actions
+            };
+            node.borrow_mut().untried_actions = Some(actions);
+        }
+
+        loop {
+            let action = {
+                let mut node = node.borrow_mut();
+                let untried = node.untried_actions.as_mut().unwrap();
+                if untried.is_empty() {
+                    node.expanded = true;
+                    return None;
+                }
+                let index = self.rng.borrow_mut().gen_range(0..untried.len());
+                let action = untried.swap_remove(index);
+                if untried.is_empty() {
+                    node.expanded = true;
+                }
+                action
+            };
+
+            let mut state = node.borrow().state.clone();
+            match self.step(&mut state, &action) {
+                Ok(_) => {
+                    let new_node = Node::new(action, state);
+                    self.apply_prior(&new_node);
+                    return Some(self.add_node(new_node, node));
+                }
+                Err(_) => self.record_failure(),
+            }
+        }
+    }
+
+    // Makes a root child the new root, e.g. once its action has been played.
+    // Its subtree and statistics are kept for the next search, the rest of
+    // the tree is dropped. Returns false for nodes that are not root children
+    pub fn promote_child(&mut self, child: &NodeRef<T, S>) -> bool {
+        let is_child = child
+            .borrow()
+            .parent()
+            .is_some_and(|x| Rc::ptr_eq(&x, &self.root));
+        if !is_child {
+            return false;
+        }
+
+        child.borrow_mut().clear_parent();
+        self.set_root(Rc::clone(child));
+        true
+    }
+
+    fn set_root(&mut self, root: NodeRef<T, S>) {
+        let flip = self.flips_to(&root.borrow().state);
+        self.root = root;
+        self.drop_spilled_outside(&self.root());
+
+        let mut kept = Self::descendants(&self.root);
+        kept.push(self.root());
+        let ids: HashSet<NodeId> = kept.iter().map(|x| x.borrow().id()).collect();
+        self.nodes.retain(|x, _| ids.contains(x));
+        // Values and proofs are kept for the root player
+        if let Some(pivot) = flip {
+            for x in kept.iter() {
+                x.borrow_mut().reflect_stats(pivot);
+            }
+            if let Some(x) = &mut self.rollout_cache {
+                x.clear();
+            }
+        }
+        self.size = kept.len() as u32 + self.spill.as_ref().map_or(0, |x| x.spilled_nodes());
+
+        // Everything tracked about the old root's children goes with them
+        self.recent_values.clear();
+        self.root_values.clear();
+        self.leader = None;
+        self.lead_changes = 0;
+        self.hint = None;
+        self.dominated.clear();
+        // The fingerprint was taken from the old root state
+        self.fingerprint = None;
+    }
+
+    // Pivot of Node::reflect_stats when the root player of `state` is the
+    // opponent of the current one in a two-player game
+    fn flips_to(&self, state: &S) -> Option<Stat> {
+        let root = self.root.borrow();
+        if state.num_players() != 2 || state.current_player() == root.state.current_player() {
+            return None;
+        }
+        match self.config.mode {
+            RewardMode::Game => Some(1.),
+            RewardMode::Planning => Some(0.),
+        }
+    }
+
+    // Number of edges up to the root
+    pub(crate) fn depth(node: &NodeRef<T, S>) -> u32 {
+        let mut depth = 0;
+        let mut node = node.borrow().parent();
+        while let Some(x) = node {
+            depth += 1;
+            node = x.borrow().parent();
+        }
+        depth
+    }
+
+    pub(crate) fn subtree_size(node: &NodeRef<T, S>) -> u32 {
+        let mut size = 0;
+        let mut stack = vec![Rc::clone(node)];
+
+        while let Some(x) = stack.pop() {
+            size += 1;
+            stack.extend(x.borrow().children.iter().map(Rc::clone));
+        }
+
+        size
+    }
+
+    // All nodes below `node`, in pre-order
+    pub(crate) fn descendants(node: &NodeRef<T, S>) -> Vec<NodeRef<T, S>> {
+        let mut nodes = vec![];
+        let mut stack: Vec<NodeRef<T, S>> =
+            node.borrow().children.iter().rev().map(Rc::clone).collect();
+
+        while let Some(x) = stack.pop() {
+            stack.extend(x.borrow().children.iter().rev().map(Rc::clone));
+            nodes.push(x);
+        }
+
+        nodes
+    }
+
+    pub fn add_node(&mut self, node: NodeRef<T, S>, parent: &mut NodeRef<T, S>) -> NodeRef<T, S> {
+        self.size += 1;
+        self.register(&node);
+        node.borrow_mut().set_parent(parent);
+        self.emit(|| created(&node));
+        if let Some(x) = &self.coverage {
+            x.record(&node.borrow().state);
+        }
+        if let (Some(lookup), Some(policy)) = (self.policy_prior, &parent.borrow().policy) {
+            let mut node = node.borrow_mut();
+            node.prior = Some(lookup(policy, &node.action));
+        }
+        // Seeded from the knowledge store
+        if node.borrow().visits > 0 {
+            self.emit(|| updated(&node.borrow()));
+        }
+        parent.borrow_mut().add_child(node)
+    }
+}
+
+impl<T, S> Tree<T, S>
+where
+    S: State<T>,
+    T: Clone + PartialEq,
+{
+    // New children take their priors from the evaluator policy of their parent
+    pub fn with_policy_priors(mut self) -> Self {
+        self.policy_prior =
+            Some(|policy, action| policy.iter().find(|x| x.0 == *action).map_or(0., |x| x.1));
+        self
+    }
+
+    // Priors of the root children from a policy computed elsewhere, normalized
+    // over the policy, actions it leaves out get none
+    pub fn seed_root_policy(&mut self, policy: Vec<(T, f32)>) {
+        let mut root = self.root();
+        self.expand_fully(&mut root);
+
+        let total: f32 = policy.iter().map(|x| x.1.max(0.)).sum();
+        for child in root.borrow().children.iter() {
+            let mut child = child.borrow_mut();
+            let p = policy
+                .iter()
+                .find(|x| x.0 == child.action)
+                .map_or(0., |x| x.1.max(0.));
+            child.prior = Some(if total > 0. { p / total } else { 0. });
+        }
+    }
+
+    // Boosts the selection score along `line` for the next `iterations`
+    // iterations, replacing any previous hint. The line is expanded as far as
+    // its actions are legal, returns how many of them were
+    pub fn hint_line(&mut self, line: &[T], boost: f32, iterations: u32) -> usize {
+        let mut nodes = vec![];
+        let mut node = self.root();
+        for action in line {
+            self.expand_fully(&mut node);
+            let child = node
+                .borrow()
+                .children
+                .iter()
+                .find(|x| x.borrow().action == *action && !x.borrow().illegal)
+                .map(Rc::clone);
+            node = match child {
+                Some(x) => x,
+                None => break,
+            };
+            nodes.push(Rc::downgrade(&node));
+        }
+
+        let hinted = nodes.len();
+        self.hint = Some(Hint {
+            nodes,
+            boost,
+            until: self.iterations + iterations,
+        });
+        hinted
+    }
+
+    // Moves the root along a played action, reusing its subtree when it is in
+    // the tree and starting over from the resulting state otherwise. Returns
+    // whether statistics were kept
+    pub fn advance_root(&mut self, action: &T) -> Result<bool, ActionError> {
+        let child = self
+            .root
+            .borrow()
+            .children
+            .iter()
+            .find(|x| x.borrow().action == *action)
+            .map(Rc::clone);
+        if let Some(child) = child {
+            let transfer = self.config.sibling_transfer > 0.
+                && child.borrow().visits < self.config.sibling_transfer_below;
+            let stats = if transfer {
+                self.sibling_stats(&child)
+            } else {
+                vec![]
+            };
+
+            self.promote_child(&child);
+            if self.config.reuse_decay < 1. {
+                let root = self.root();
+                root.borrow_mut().decay_stats(self.config.reuse_decay);
+                for x in Self::descendants(&root) {
+                    x.borrow_mut().decay_stats(self.config.reuse_decay);
+                }
+            }
+            if transfer {
+                self.transfer_stats(stats);
+            }
+            self.emit(|| TreeEvent::RootAdvanced {
+                root: child.borrow().id(),
+            });
+            if self.event_sink.is_some() && (transfer || self.config.reuse_decay < 1.) {
+                for x in Self::descendants(&child) {
+                    self.emit(|| updated(&x.borrow()));
+                }
+                self.emit(|| updated(&child.borrow()));
+            }
+            return Ok(true);
+        }
+
+        let mut state = self.root.borrow().state.clone();
+        self.step(&mut state, action)?;
+        let root = Node::new(action.clone(), state);
+        self.register(&root);
+        self.set_root(root);
+        self.emit(|| created(&self.root));
+        self.emit(|| TreeEvent::RootAdvanced {
+            root: self.root.borrow().id(),
+        });
+        Ok(false)
+    }
+
+    // Statistics of the grandchildren of the root that are not below `child`,
+    // combined by action
+    fn sibling_stats(&self, child: &NodeRef<T, S>) -> Vec<(T, (u32, Stat, Stat))> {
+        let mut stats: Vec<(T, (u32, Stat, Stat))> = vec![];
+        // For the root player once the child is the root
+        let flip = self.flips_to(&child.borrow().state);
+        for sibling in self.root.borrow().children.iter() {
+            if Rc::ptr_eq(sibling, child) {
+                continue;
+            }
+            for x in sibling.borrow().children.iter() {
+                let x = x.borrow();
+                let mean = flip.map_or(x.mean, |pivot| pivot - x.mean);
+                let value = (x.visits, mean, x.m2);
+                match stats.iter_mut().find(|y| y.0 == x.action) {
+                    Some(y) => y.1 = combine_stats(y.1, value),
+                    None => stats.push((x.action.clone(), value)),
+                }
+            }
+        }
+        stats
+    }
+
+    // Virtual visits for the root children from the sibling statistics
+    fn transfer_stats(&mut self, stats: Vec<(T, (u32, Stat, Stat))>) {
+        let mut root = self.root();
+        self.expand_fully(&mut root);
+
+        let children = root.borrow().children.clone();
+        for child in children.iter() {
+            let (visits, mean, m2) = match stats.iter().find(|x| x.0 == child.borrow().action) {
+                Some(x) => x.1,
+                None => continue,
+            };
+            let transferred = (visits as f32 * self.config.sibling_transfer).round() as u32;
+            if transferred == 0 {
+                continue;
+            }
+            let m2 = m2 * transferred as Stat / visits as Stat;
+            child.borrow_mut().merge_stats(transferred, mean, m2);
+            root.borrow_mut().merge_stats(transferred, mean, 0.);
+        }
+    }
+
+    // Reconciles the root children with the actions legal in the root state,
+    // returns the actions added and the ones now flagged illegal
+    pub fn refresh_root_actions(&mut self) -> (Vec<T>, Vec<T>) {
+        let mut root = self.root();
+        let mut legal = vec![];
+        let mut state = root.borrow().state.clone();
+        while let Some(action) = state.next_action() {
+            if self.step(&mut state, &action).is_err() {
+                self.record_failure();
+                break;
+            }
+            legal.push(action);
+        }
+
+        let mut illegal = vec![];
+        for child in root.borrow().children.iter() {
+            let mut child = child.borrow_mut();
+            child.illegal = !legal.contains(&child.action);
+            if child.illegal {
+                illegal.push(child.action.clone());
+            }
+        }
+
+        let mut added = vec![];
+        for action in legal {
+            let known = {
+                let root = root.borrow();
+                root.pruned_actions.contains(&action)
+                    || root.children.iter().any(|x| x.borrow().action == action)
+            };
+            if known {
+                continue;
+            }
+
+            let mut state = root.borrow().state.clone();
+            if self.step(&mut state, &action).is_err() {
+                self.record_failure();
+                continue;
+            }
+            let node = Node::new(action.clone(), state);
+            self.apply_prior(&node);
+            self.add_node(node, &mut root);
+            added.push(action);
+        }
+
+        // Every legal action is a child now
+        let mut root = root.borrow_mut();
+        root.expansion_state = None;
+        root.expanded = true;
+
+        (added, illegal)
+    }
+}
+
+impl<T, S> Tree<T, S>
+where
+    S: State<T> + StateKey,
+    T: Clone,
+{
+    pub fn with_novelty(mut self, scale: f32, decay: f32) -> Self {
+        self.novelty = Some(Novelty::new(scale, decay));
+        self
+    }
+
+    // Only sound for deterministic domains, the first rollout from a state
+    // stands for all later ones
+    pub fn with_rollout_cache(mut self, capacity: usize) -> Self {
+        self.rollout_cache = Some(StateCache::new(capacity));
+        self
+    }
+
+    pub fn with_evaluation_cache(mut self, capacity: usize) -> Self {
+        self.evaluation_cache = Some(Rc::new(StateCache::new(capacity)));
+        self
+    }
+
+    // The first `steps` of every rollout pick their actions by UCB1 over a
+    // table of up to `capacity` states instead of the rollout policies
+    pub fn with_rollout_bandit(mut self, steps: u32, capacity: usize) -> Self {
+        self.rollout_bandit = Some(RolloutBandit::new(
+            steps,
+            self.config.learning_rate,
+            capacity,
+        ));
+        self
+    }
+
+    pub fn rollout_bandit(&self) -> Option<&RolloutBandit<S>> {
+        self.rollout_bandit.as_ref()
+    }
+
+    // Counts the distinct states met, exactly for the first `cap`
+    pub fn with_coverage(mut self, cap: usize) -> Self {
+        let coverage = StateCoverage::new(cap);
+        coverage.record(&self.root.borrow().state);
+        self.coverage = Some(coverage);
+        self
+    }
+
+    pub fn with_fingerprint(mut self) -> Self {
+        self.fingerprint = Some(Self::fingerprint(&self.root.borrow().state));
+        self
+    }
+
+    pub fn search_from_repro(repro: &Repro, action: T, state: S) -> Result<Self, ReproError> {
+        if repro.version != env!("CARGO_PKG_VERSION") {
+            return Err(ReproError::Version);
+        }
+
+        let fingerprint = Self::fingerprint(&state);
+        if repro.fingerprint.is_some() && repro.fingerprint != Some(fingerprint) {
+            return Err(ReproError::Fingerprint);
+        }
+
+        let mut tree = Self::with_config(repro.config, action, state);
+        tree.fingerprint = Some(fingerprint);
+        tree.search(repro.iterations);
+        Ok(tree)
+    }
+
+    fn fingerprint(state: &S) -> u64 {
+        key_hash(state)
+    }
+}
+
+impl<T, S> Tree<T, S>
+where
+    S: State<T> + StateKey,
+    T: Clone + PartialEq,
+{
+    // The cached result of the root state when it had `iterations` or more.
+    // Otherwise searches, a fresh tree taking the visit shares of a cached
+    // shallower search as root priors, and caches the result
+    pub fn search_cached(&mut self, cache: &ResultCache<S, T>, iterations: u32) -> SearchResult<T> {
+        let state = self.root.borrow().state.clone();
+        let cached = cache.get(&state);
+        if let Some(x) = cached.as_ref().filter(|x| x.iterations >= iterations) {
+            return x.clone();
+        }
+
+        if let Some(x) = cached.filter(|_| self.iterations == 0) {
+            let policy = x
+                .children
+                .into_iter()
+                .map(|x| (x.action, x.visits as f32))
+                .collect();
+            self.seed_root_policy(policy);
+        }
+        self.search(iterations.saturating_sub(self.iterations));
+        let result = self.result();
+        cache.insert(&state, result.clone());
+        result
+    }
+}
+
+impl<T, S> SelectionStrategy<T, S> for Tree<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn select(&self) -> Option<NodeRef<T, S>> {
+        self.select_from(&self.root)
+    }
+}
+
+impl<T, S> ExpansionStrategy<T, S> for Tree<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn expand(&mut self, node: &mut NodeRef<T, S>) -> Option<NodeRef<T, S>> {
+        let count = match &node.borrow().expansion_state {
+            Some(_) => None,
+            None => node.borrow().state.action_count(),
+        };
+        let reserved = node
+            .borrow_mut()
+            .children
+            .try_reserve_exact(count.unwrap_or(1));
+        if reserved.is_err() {
+            self.degrade(DegradeCause::AllocationFailure);
+            return None;
+        }
+
+        let expansion_state = node.borrow_mut().expansion_state.take();
+        let mut curr_state = match expansion_state {
+            Some(x) => x,
+            None => node.borrow().state.clone(),
+        };
+
+        let first_new = node.borrow().children.len();
+        let batch_size = self.config.expand_batch_size;
+        let reduce = node.borrow().parent().is_some();
+
+        while batch_size == 0 || node.borrow().children.len() - first_new < batch_size {
+            let action = match curr_state.next_action() {
+                Some(x) => x,
+                None => {
+                    node.borrow_mut().expanded = true;
+                    break;
+                }
+            };
+
+            if self.step(&mut curr_state, &action).is_err() {
+                self.record_failure();
+                node.borrow_mut().expanded = true;
+                break;
+            }
+
+            // Partial-order reduction, the other order has its own branch
+            if reduce && node.borrow().state.commutes(&node.borrow().action, &action) {
+                continue;
+            }
+            if !self.expansion_rule.expand(&node.borrow().state, &action) {
+                continue;
+            }
+
+            let mut state = node.borrow().state.clone();
+            match self.step(&mut state, &action) {
+                Ok(_) => {
+                    let new_node = Node::new(action, state);
+                    self.apply_prior(&new_node);
+                    self.add_node(new_node, node);
+                }
+                Err(_) => self.record_failure(),
+            }
+        }
+
+        // Keep the enumeration cursor around for the next batch
+        if !node.borrow().expanded {
+            node.borrow_mut().expansion_state = Some(curr_state);
+        }
+
+        node.borrow().child_at(first_new)
+    }
+}
+
+impl<T, S> SimulationStrategy<T, S> for Tree<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    // The mean of several rollouts in noisy regions, the last one gives the
+    // outcome and payoffs to back up
+    fn simulate(&self, node: &NodeRef<T, S>) -> f32 {
+        let count = self.rollout_count(node);
+        if count == 1 {
+            return self.rollout(node, 0.);
+        }
+        (0..count).map(|_| self.rollout(node, 0.)).sum::<f32>() / count as f32
+    }
+}
+
+impl<T, S> Tree<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    pub(crate) fn rollout_count(&self, node: &NodeRef<T, S>) -> u32 {
+        let x = match self.config.adaptive_rollouts {
+            Some(x) => x,
+            None => return 1,
+        };
+
+        let mut next = Some(Rc::clone(node));
+        while let Some(node) = next {
+            let node = node.borrow();
+            if node.visits >= 2 {
+                let count = 1. + x.scale * node.value_variance().sqrt();
+                return (count.round() as u32).clamp(1, x.max.max(1));
+            }
+            next = node.parent();
+        }
+        1
+    }
+
+    // Simulation from the node with `prefix` already gathered on the way there,
+    // only prefix-free values go through the rollout cache
+    fn rollout(&self, node: &NodeRef<T, S>, prefix: f32) -> f32 {
+        self.rollout_with(node, prefix, prefix == 0.)
+    }
+
+    pub(crate) fn rollout_with(&self, node: &NodeRef<T, S>, prefix: f32, cached: bool) -> f32 {
+        self.payoffs.replace(None);
+        self.outcome.set(None);
+        if let Some(x) = self
+            .rollout_cache
+            .as_ref()
+            .filter(|_| cached)
+            .and_then(|x| x.get(&node.borrow().state))
+        {
+            let (value, outcome, payoffs) = x;
+            self.outcome.set(outcome);
+            self.payoffs.replace(payoffs);
+            return value;
+        }
+
+        let mut total_reward = prefix;
+        let mut steps = 0;
+        let mut truncated = false;
+        let mut current_state = node.borrow().state.clone();
+        let root_player = self.root.borrow().state.current_player();
+
+        // Terminal states keep their own reward, the others are evaluated
+        if current_state.next_action().is_some() {
+            let prefetched = {
+                let mut prefetched = self.prefetched.borrow_mut();
+                match &*prefetched {
+                    Some((id, _)) if *id == node.borrow().id() => prefetched.take().map(|x| x.1),
+                    _ => None,
+                }
+            };
+            if let Some((policy, value)) = prefetched.or_else(|| self.evaluate(&current_state)) {
+                if self.policy_prior.is_some() {
+                    let total: f32 = policy.iter().map(|x| x.1.max(0.)).sum();
+                    node.borrow_mut().policy = Some(
+                        policy
+                            .into_iter()
+                            .map(|(a, p)| (a, if total > 0. { p.max(0.) / total } else { 0. }))
+                            .collect(),
+                    );
+                }
+                return prefix + value;
+            }
+        }
+
+        if let Some(x) = self.config.shallow_search {
+            if Self::depth(node) <= x.within {
+                let value = self.alpha_beta(
+                    &current_state,
+                    x.depth,
+                    self.config.quiescence_steps,
+                    f32::MIN,
+                    f32::MAX,
+                    root_player,
+                );
+                return prefix + value;
+            }
+        }
+
+        let mut bootstrap = 0.;
+        let mut bandit_path = vec![];
+        let mut extended = 0;
+        while let Some(action) = current_state.next_action() {
+            let limit = self.config.rollout_depth;
+            let cutoff = (limit > 0 && steps >= limit)
+                || (self.config.truncate_at_deadline && self.past_deadline());
+            if cutoff {
+                if current_state.is_quiet() || extended >= self.config.quiescence_steps {
+                    bootstrap = current_state.heuristic();
+                    truncated = true;
+                    break;
+                }
+                extended += 1;
+            }
+            let action =
+                match self.bandit_action(&current_state, steps, root_player, &mut bandit_path) {
+                    Some(x) => x,
+                    None => self
+                        .rollout_action(&current_state, root_player)
+                        .unwrap_or(action),
+                };
+
+            if let Some((reward, weight)) = &self.intrinsic_reward {
+                total_reward += weight * reward.bonus(&current_state, &action);
+            }
+            // A failed step ends the rollout with what has been gathered so far
+            match self.step(&mut current_state, &action) {
+                Ok(x) => total_reward += x,
+                Err(_) => {
+                    self.record_failure();
+                    truncated = true;
+                    break;
+                }
+            }
+            if let Some(x) = &self.coverage {
+                x.record(&current_state);
+            }
+            steps += 1;
+        }
+
+        let payoffs = match current_state.num_players() {
+            0..=2 => None,
+            _ => current_state.payoffs(),
+        };
+        let outcome = self.root_outcome(&current_state, root_player);
+        self.outcome.set(outcome);
+        // Outcomes and payoffs replace the rewards of the rollout, not the
+        // ones gathered on the way to the node
+        let value = match (outcome, &payoffs) {
+            (Some(x), _) => prefix + self.config.outcome_value(x),
+            (None, Some(x)) => prefix + x.get(root_player).copied().unwrap_or(0.),
+            (None, None) => total_reward + bootstrap,
+        };
+        // Only terminal scores count the handicap
+        let offset = match (outcome, &payoffs) {
+            (None, None) if !truncated => self.config.reward_offset(root_player),
+            _ => 0.,
+        };
+        if payoffs.is_some() {
+            self.payoffs.replace(payoffs);
+        }
+        let value = match &self.return_weight {
+            Some(x) => value * (x.0)(steps, truncated) + offset,
+            None => value + offset,
+        };
+        if let Some(cache) = self.rollout_cache.as_ref().filter(|_| cached) {
+            let payoffs = self.payoffs.borrow().clone();
+            cache.insert(&node.borrow().state, (value, outcome, payoffs));
+        }
+        if let Some(bandit) = &self.rollout_bandit {
+            bandit.update(&bandit_path, value);
+        }
+        value
+    }
+
+    // Rewards to come under best play by every player, the root player
+    // maximizing and with more than one player the others minimizing. Past
+    // `depth` states that are not quiet are searched up to `extend` more plies
+    fn alpha_beta(
+        &self,
+        state: &S,
+        depth: u32,
+        extend: u32,
+        mut alpha: f32,
+        mut beta: f32,
+        root_player: usize,
+    ) -> f32 {
+        if let Some(x) = self.root_outcome(state, root_player) {
+            return self.config.outcome_value(x);
+        }
+        let actions = state.legal_actions();
+        if actions.is_empty() {
+            return 0.;
+        }
+        if depth == 0 && (extend == 0 || state.is_quiet()) {
+            return state.heuristic();
+        }
+        let (depth, extend) = match depth {
+            0 => (0, extend - 1),
+            x => (x - 1, extend),
+        };
+
+        let maximize = state.num_players() <= 1 || state.current_player() == root_player;
+        let mut best = if maximize { f32::MIN } else { f32::MAX };
+        for action in actions {
+            let mut next = state.clone();
+            let reward = match self.step(&mut next, &action) {
+                Ok(x) => x,
+                Err(_) => {
+                    self.record_failure();
+                    continue;
+                }
+            };
+            // Outcomes replace the rewards, as at the end of a rollout
+            let value = match self.root_outcome(&next, root_player) {
+                Some(x) => self.config.outcome_value(x),
+                // The window of the child leaves out the step reward
+                None => {
+                    reward
+                        + self.alpha_beta(
+                            &next,
+                            depth,
+                            extend,
+                            alpha - reward,
+                            beta - reward,
+                            root_player,
+                        )
+                }
+            };
+
+            if maximize {
+                best = best.max(value);
+                alpha = alpha.max(best);
+            } else {
+                best = best.min(value);
+                beta = beta.min(best);
+            }
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        if best == f32::MIN || best == f32::MAX {
+            return 0.;
+        }
+        best
+    }
+
+    // Action of the rollout bandit for the first steps, noted on `path`
+    fn bandit_action(
+        &self,
+        state: &S,
+        step: u32,
+        root_player: usize,
+        path: &mut Vec<(KeySlot, usize)>,
+    ) -> Option<T> {
+        let bandit = self.rollout_bandit.as_ref().filter(|x| step < x.steps)?;
+        let mut actions = state.legal_actions();
+        if actions.is_empty() {
+            return None;
+        }
+
+        let maximize = state.num_players() <= 1 || state.current_player() == root_player;
+        let (key, index) = bandit.choose(state, actions.len(), maximize);
+        path.push((key, index));
+        Some(actions.swap_remove(index))
+    }
+}
+
+impl<T, S> crate::strategies::RandomExpansionStrategy<T, S> for Tree<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn expand(&mut self, node: &mut NodeRef<T, S>) -> Option<NodeRef<T, S>> {
+        self.expand_random(node)
+    }
+}
+
+impl<T, S> BackpropagationStrategy<T, S> for Tree<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    fn backpropagate(&mut self, node: &mut NodeRef<T, S>, value: f32) {
+        self.backpropagate_until(node, value, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::{
+        AdaptiveRollouts, DepthExploration, Handicap, LateMoveReduction, ShallowSearch,
+    };
+    use crate::node::stat_to_f32;
+    use crate::profile::Phase;
+    use crate::schedule::LinearSchedule;
+    use crate::state::Outcome;
+    use crate::strategies::RandomRollout;
+    use crate::testing::{tree_shape, MockState, PathGame};
+
+    #[derive(Debug, Clone)]
+    struct DummyState {
+        action_reward: f32,
+        actions: u8,
+    }
+
+    impl DummyState {
+        fn new() -> Self {
+            Self {
+                action_reward: 0.5,
+                actions: 5,
+            }
+        }
+    }
+
+    impl State<u8> for DummyState {
+        fn next_action(&self) -> Option<u8> {
+            if self.actions == 0 {
+                return None;
+            }
+            Some(self.actions)
+        }
+
+        fn try_do_action(&mut self, _action: &u8) -> Result<f32, ActionError> {
+            self.actions -= 1;
+            Ok(self.action_reward)
+        }
+    }
+
+    #[test]
+    fn expand() {
+        let mut state1 = DummyState::new();
+        let action1 = state1.next_action().unwrap();
+        state1.do_action(&action1);
+        let available_moves = state1.actions as usize;
+
+        let mut tree = Tree::new(1.0, action1, state1);
+        let node = tree.expand(&mut tree.root());
+
+        assert!(node.is_some());
+        assert!(node.unwrap().borrow().parent().is_some());
+        assert!(tree.root().borrow().parent().is_none());
+        assert_eq!(tree.root().borrow().children.len(), available_moves);
+    }
+
+    #[test]
+    fn expand_preallocates() {
+        let mut tree = Tree::new(1.0, 0, MockState::countdown(7, 0.5));
+        tree.expand(&mut tree.root());
+
+        let root = tree.root();
+        assert_eq!(root.borrow().children.len(), 7);
+        assert_eq!(root.borrow().children.capacity(), 7);
+    }
+
+    #[test]
+    fn expand_batch() {
+        let mut config = SearchConfig::new(1.0);
+        config.expand_batch_size = 2;
+        let mut tree = Tree::with_config(config, 0, MockState::countdown(5, 0.5));
+
+        let node = tree.expand(&mut tree.root()).unwrap();
+        assert_eq!(node.borrow().action, 5);
+        assert_eq!(tree.root().borrow().children.len(), 2);
+        assert!(tree.root().borrow().is_partially_expanded());
+
+        // Selection stops at the root until all of its actions are expanded
+        assert!(Rc::ptr_eq(&tree.select().unwrap(), &tree.root()));
+
+        let node = tree.expand(&mut tree.root()).unwrap();
+        assert_eq!(node.borrow().action, 3);
+        tree.expand(&mut tree.root());
+        assert_eq!(tree.root().borrow().children.len(), 5);
+        assert!(tree.root().borrow().expanded);
+        assert!(!tree.root().borrow().is_partially_expanded());
+
+        let mut tree = Tree::with_config(config, 0, MockState::countdown(5, 0.5));
+        assert!(tree.search(30).is_some());
+        assert_eq!(tree.root().borrow().children.len(), 5);
+    }
+
+    #[test]
+    fn prune_children() {
+        let mut tree = Tree::new(1.0, 0, MockState::countdown(4, 0.5));
+        let mut node = tree.expand(&mut tree.root()).unwrap();
+        tree.expand(&mut node);
+        assert_eq!(tree.size, 8);
+
+        tree.root()
+            .borrow()
+            .child_at(2)
+            .unwrap()
+            .borrow_mut()
+            .visits = 3;
+        tree.prune_children(&tree.root(), 1);
+
+        let root = tree.root();
+        assert_eq!(root.borrow().children.len(), 1);
+        assert_eq!(root.borrow().child_at(0).unwrap().borrow().action, 2);
+        assert_eq!(root.borrow().pruned_actions, vec![4, 3, 1]);
+        assert_eq!(tree.size, 2);
+    }
+
+    #[test]
+    fn enforce_node_cap() {
+        let mut config = SearchConfig::new(1.0);
+        config.max_nodes = 6;
+        config.prune_min_visits = 1;
+        let mut tree = Tree::with_config(config, 0, MockState::countdown(4, 0.5));
+        tree.search(40);
+        assert!(tree.size <= 6);
+
+        // The principal variation is never pruned
+        let line = tree.principal_variation();
+        assert!(!line.is_empty());
+        assert!(Rc::ptr_eq(&line[0], &tree.best_child().unwrap()));
+        assert!(line[0].borrow().parent().is_some());
+    }
+
+    #[test]
+    fn degrade_at_node_cap() {
+        let mut config = SearchConfig::new(1.0);
+        config.max_nodes = 6;
+        config.degrade_at_node_cap = true;
+        let mut tree = Tree::with_config(config, 0, MockState::countdown(4, 0.5));
+        let result = tree.search_with(SearchBudget::iterations(40));
+
+        // One last expansion may go past the cap, nothing is pruned after it
+        assert_eq!(tree.size, 8);
+        assert_eq!(result.iterations, 40);
+        assert_eq!(tree.root().borrow().visits, 40);
+        let degraded = result.degraded.unwrap();
+        assert_eq!(degraded.cause, DegradeCause::NodeCap);
+        assert!(degraded.iteration > 0 && degraded.iteration < 40);
+
+        let mut tree = Tree::new(1.0, 0, MockState::countdown(4, 0.5));
+        tree.search(40);
+        assert_eq!(tree.degraded(), None);
+    }
+
+    #[test]
+    fn profile_iterations() {
+        let mut config = SearchConfig::new(1.0);
+        config.profile_iterations = true;
+        let mut tree = Tree::with_config(config, 0, MockState::countdown(4, 0.5));
+        let result = tree.search_with(SearchBudget::iterations(40));
+
+        let profile = result.profile.unwrap();
+        assert_eq!(profile.iterations, 40);
+        assert_eq!(profile.histogram().iter().map(|x| x.1).sum::<u32>(), 40);
+        let dominated: u32 = Phase::ALL.iter().map(|x| profile.dominated_by(*x)).sum();
+        assert_eq!(dominated, 40);
+        assert!(profile.slowest()[0].time >= profile.mean());
+        assert_eq!(tree.profile(), Some(&profile));
+
+        let mut tree = Tree::new(1.0, 0, MockState::countdown(4, 0.5));
+        tree.search(40);
+        assert_eq!(tree.profile(), None);
+    }
+
+    #[test]
+    fn prune_min_visits() {
+        let mut config = SearchConfig::new(1.0);
+        config.max_nodes = 2;
+        config.prune_min_visits = 1;
+        let mut tree = Tree::with_config(config, 0, MockState::countdown(4, 0.5));
+        tree.expand(&mut tree.root());
+
+        // Unvisited nodes are too young to be judged
+        tree.enforce_node_cap();
+        assert_eq!(tree.size, 5);
+
+        let children = tree.root().borrow().children.clone();
+        for mut child in children {
+            tree.backpropagate(&mut child, 0.);
+        }
+        tree.enforce_node_cap();
+        assert_eq!(tree.size, 2);
+    }
+
+    #[test]
+    fn unprune_children() {
+        let mut config = SearchConfig::new(1.0);
+        config.unprune_visits = 2;
+        let mut tree = Tree::with_config(config, 0, MockState::countdown(4, 0.5));
+        let mut node = tree.expand(&mut tree.root()).unwrap();
+        tree.prune_children(&tree.root(), 1);
+
+        tree.backpropagate(&mut node, 1.0);
+        assert_eq!(tree.root().borrow().children.len(), 1);
+
+        // Enough visits since pruning bring the other actions back
+        let mut node = tree.root().borrow().child_at(0).unwrap();
+        tree.backpropagate(&mut node, 1.0);
+        assert_eq!(tree.root().borrow().children.len(), 4);
+        assert!(tree.root().borrow().pruned_actions.is_empty());
+        assert_eq!(tree.size, 5);
+    }
+
+    #[test]
+    fn select() {
+        let state1 = DummyState::new();
+        let action1 = state1.next_action().unwrap();
+
+        let mut tree = Tree::new(1.0, action1, state1);
+        let node1 = tree.expand(&mut tree.root()).unwrap();
+
+        node1.borrow_mut().visits = 1;
+        node1.borrow_mut().mean = 1.;
+        tree.root.borrow_mut().visits = 1;
+
+        // Nodes that have not been visited before are favored
+        let selected_node = tree.select().unwrap();
+        assert_eq!(selected_node.borrow().mean_value(), 0.0);
+        assert_eq!(selected_node.borrow().visits, 0);
+    }
+
+    #[test]
+    fn root_min_visits() {
+        let mut config = SearchConfig::new(1.0);
+        config.root_min_visits = 3;
+        let mut tree = Tree::with_config(config, 0, MockState::countdown(4, 0.5));
+        tree.search(14);
+
+        for child in tree.root().borrow().children.iter() {
+            assert!(child.borrow().visits >= 3);
+        }
+    }
+
+    #[test]
+    fn simulate() {
+        let state1 = DummyState::new();
+        let action1 = state1.next_action().unwrap();
+
+        // Moves left * reward for each move
+        let final_rerward = (state1.actions) as f32 * state1.action_reward;
+        let tree = Tree::new(1.0, action1, state1);
+        assert_eq!(tree.simulate(&tree.root()), final_rerward);
+    }
+
+    #[test]
+    fn adaptive_rollouts() {
+        let mut config = SearchConfig::new(1.0);
+        config.adaptive_rollouts = Some(AdaptiveRollouts::new(3., 3));
+        let mut tree = Tree::with_config(config, 0, MockState::countdown(3, 0.5));
+        let leaf = tree.expand(&mut tree.root()).unwrap();
+        assert_eq!(tree.rollout_count(&leaf), 1);
+
+        // Values with a standard deviation of 0.5 above the leaf
+        tree.root().borrow_mut().merge_stats(4, 0., 1.);
+        assert_eq!(tree.rollout_count(&leaf), 3);
+        let steps = tree.steps();
+        assert_eq!(tree.simulate(&leaf), 1.);
+        assert_eq!(tree.steps() - steps, 6);
+
+        // Settled values below the root
+        leaf.borrow_mut().merge_stats(2, 1., 0.);
+        assert_eq!(tree.rollout_count(&leaf), 1);
+    }
+
+    #[test]
+    fn simulate_intrinsic_reward() {
+        #[derive(Debug)]
+        struct ActionBonus {}
+
+        impl IntrinsicReward<u8, DummyState> for ActionBonus {
+            fn bonus(&self, _state: &DummyState, action: &u8) -> f32 {
+                *action as f32
+            }
+        }
+
+        let state1 = DummyState::new();
+        let action1 = state1.next_action().unwrap();
+        let final_reward = (state1.actions) as f32 * state1.action_reward;
+
+        // Actions 5, 4, 3, 2 and 1 are played during the rollout
+        let tree = Tree::new(1.0, action1, state1).with_intrinsic_reward(ActionBonus {}, 0.5);
+        assert_eq!(tree.simulate(&tree.root()), final_reward + 7.5);
+    }
+
+    #[derive(Debug, Clone)]
+    struct FlakyState {
+        moves: u8,
+        broken: u8,
+    }
+
+    impl State<u8> for FlakyState {
+        fn next_action(&self) -> Option<u8> {
+            if self.moves == 0 {
+                return None;
+            }
+            Some(self.moves)
+        }
+
+        fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+            if *action == self.broken {
+                return Err(ActionError("simulator crashed".to_string()));
+            }
+            self.moves -= 1;
+            Ok(1.0)
+        }
+    }
+
+    #[test]
+    fn expand_failure() {
+        let mut tree = Tree::new(
+            1.0,
+            0,
+            FlakyState {
+                moves: 4,
+                broken: 2,
+            },
+        );
+        tree.expand(&mut tree.root());
+
+        // Actions 4 and 3 are expanded, enumeration stops at 2
+        assert_eq!(tree.root().borrow().children.len(), 2);
+        assert_eq!(tree.failures(), 1);
+    }
+
+    #[test]
+    fn simulate_failure() {
+        let tree = Tree::new(
+            1.0,
+            0,
+            FlakyState {
+                moves: 4,
+                broken: 2,
+            },
+        );
+        assert_eq!(tree.simulate(&tree.root()), 2.0);
+        assert_eq!(tree.failures(), 1);
+
+        // The search carries on
+        let mut tree = Tree::new(
+            1.0,
+            0,
+            FlakyState {
+                moves: 4,
+                broken: 2,
+            },
+        );
+        assert!(tree.search(10).is_some());
+        assert!(tree.result().failures > 0);
+    }
+
+    #[test]
+    fn return_weight() {
+        let state = DummyState::new();
+        let reward = state.action_reward;
+        let tree = Tree::new(1.0, 0, state).with_return_weight(|steps, _| 1. / steps as f32);
+        assert_eq!(tree.simulate(&tree.root()), reward);
+
+        // Actions 4 and 3 are played before the failure
+        let flaky = FlakyState {
+            moves: 4,
+            broken: 2,
+        };
+        let tree = Tree::new(1.0, 0, flaky).with_return_weight(|steps, truncated| {
+            assert_eq!(steps, 2);
+            if truncated {
+                0.5
+            } else {
+                1.
+            }
+        });
+        assert_eq!(tree.simulate(&tree.root()), 1.0);
+    }
+
+    #[derive(Debug, Clone)]
+    struct TurnState {
+        moves: u8,
+        player: usize,
+    }
+
+    impl State<u8> for TurnState {
+        fn next_action(&self) -> Option<u8> {
+            if self.moves == 0 {
+                return None;
+            }
+            Some(self.moves)
+        }
+
+        fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+            self.moves -= 1;
+            self.player = 1 - self.player;
+            Ok(*action as f32)
+        }
+
+        fn current_player(&self) -> usize {
+            self.player
+        }
+    }
+
+    #[derive(Debug)]
+    struct ScriptedOpponent {}
+
+    impl OpponentModel<u8, TurnState> for ScriptedOpponent {
+        fn rollout_action(&self, _state: &TurnState) -> Option<u8> {
+            Some(10)
+        }
+
+        fn select_child(&self, _state: &TurnState, actions: &[u8]) -> Option<usize> {
+            actions.iter().position(|x| *x == 2)
+        }
+    }
+
+    #[test]
+    fn simulate_opponent_model() {
+        let state = TurnState {
+            moves: 4,
+            player: 0,
+        };
+        let tree = Tree::new(1.0, 0, state.clone());
+        assert_eq!(tree.simulate(&tree.root()), 10.);
+
+        // The opponent answers every move with 10
+        let tree = Tree::new(1.0, 0, state).with_opponent_model(ScriptedOpponent {});
+        assert_eq!(tree.simulate(&tree.root()), 26.);
+    }
+
+    #[test]
+    fn simulate_rollout_policies() {
+        #[derive(Debug)]
+        struct ConstantPolicy(u8);
+
+        impl RolloutPolicy<u8, TurnState> for ConstantPolicy {
+            fn choose(&self, _state: &TurnState) -> Option<u8> {
+                Some(self.0)
+            }
+        }
+
+        let state = TurnState {
+            moves: 4,
+            player: 0,
+        };
+        let tree = Tree::new(1.0, 0, state.clone())
+            .with_rollout_policy(0, ConstantPolicy(5))
+            .with_rollout_policy(1, ConstantPolicy(20));
+        assert_eq!(tree.simulate(&tree.root()), 50.);
+
+        // A player's policy takes precedence over the opponent model
+        let tree = Tree::new(1.0, 0, state)
+            .with_opponent_model(ScriptedOpponent {})
+            .with_rollout_policy(1, ConstantPolicy(20));
+        assert_eq!(tree.simulate(&tree.root()), 46.);
+    }
+
+    #[test]
+    fn rollout_action_rationality_steps() {
+        let state = TurnState {
+            moves: 4,
+            player: 1,
+        };
+        let mut config = SearchConfig::game();
+        config.opponent_rationality = 1.;
+        let tree = Tree::with_config(config, 0, state.clone());
+        assert!(tree.rollout_action(&state, 0).is_some());
+        assert_eq!(tree.steps(), 0);
+        assert_eq!(tree.cost(), 0.);
+    }
+
+    #[test]
+    fn select_opponent_model() {
+        let state = TurnState {
+            moves: 4,
+            player: 0,
+        };
+        let mut config = SearchConfig::new(1.0);
+        config.opponent_selection = true;
+        let mut tree = Tree::with_config(config, 0, state).with_opponent_model(ScriptedOpponent {});
+
+        let mut node = tree.expand(&mut tree.root()).unwrap();
+        tree.expand(&mut node);
+        let children = tree.root().borrow().children.clone();
+        for mut child in children {
+            tree.backpropagate(&mut child, 0.);
+        }
+        node.borrow_mut().mean = 10.;
+
+        // Node 4 is picked by UCT, the opponent then answers with 2
+        let selected = tree.select().unwrap();
+        assert_eq!(selected.borrow().action, 2);
+        assert!(Rc::ptr_eq(&selected.borrow().parent().unwrap(), &node));
+    }
+
+    #[test]
+    fn simulate_outcome() {
+        #[derive(Debug, Clone)]
+        struct DrawState {
+            moves: u8,
+        }
+
+        impl State<u8> for DrawState {
+            fn next_action(&self) -> Option<u8> {
+                if self.moves == 0 {
+                    return None;
+                }
+                Some(self.moves)
+            }
+
+            fn try_do_action(&mut self, _action: &u8) -> Result<f32, ActionError> {
+                self.moves -= 1;
+                Ok(1.0)
+            }
+
+            fn outcome(&self) -> Option<Outcome> {
+                if self.moves == 0 {
+                    return Some(Outcome::Draw);
+                }
+                None
+            }
+        }
+
+        let mut config = SearchConfig::game();
+        config.draw_value = 0.45;
+        let tree = Tree::with_config(config, 0, DrawState { moves: 3 });
+        assert_eq!(tree.simulate(&tree.root()), 0.45);
+    }
+
+    #[test]
+    fn simulate_handicap() {
+        let state1 = DummyState::new();
+        let action1 = state1.next_action().unwrap();
+        let final_reward = (state1.actions) as f32 * state1.action_reward;
+
+        let mut config = SearchConfig::new(1.0);
+        config.handicap = Some(Handicap::new(0, 1.5));
+        let tree = Tree::with_config(config, action1, state1.clone());
+        assert_eq!(tree.simulate(&tree.root()), final_reward + 1.5);
+
+        config.handicap = Some(Handicap::new(1, 1.5));
+        let tree = Tree::with_config(config, action1, state1.clone());
+        assert_eq!(tree.simulate(&tree.root()), final_reward - 1.5);
+
+        // Neither a rollout cut short nor a Game value counts it
+        config.rollout_depth = 1;
+        let tree = Tree::with_config(config, action1, state1.clone());
+        assert_eq!(tree.simulate(&tree.root()), state1.action_reward);
+        let mut config = SearchConfig::game();
+        config.handicap = Some(Handicap::new(0, 1.5));
+        let tree = Tree::with_config(config, action1, state1);
+        assert_eq!(tree.simulate(&tree.root()), final_reward);
+    }
+
+    #[test]
+    fn backpropagate() {
+        let state1 = DummyState::new();
+        let action1 = state1.next_action().unwrap();
+
+        let mut tree = Tree::new(1.0, action1, state1);
+        let mut node1 = tree.expand(&mut tree.root()).unwrap();
+        let mut node2 = tree.expand(&mut node1).unwrap();
+
+        tree.backpropagate(&mut node2, 5.0);
+        assert_eq!(tree.root().borrow().mean_value(), 5.0);
+        assert_eq!(tree.root().borrow().value_variance(), 0.0);
+    }
+
+    #[test]
+    fn late_move_reduction() {
+        // Six jobs in any order, the first one decides the reward
+        #[derive(Debug, Clone)]
+        struct Ranked {
+            done: Vec<u8>,
+        }
+
+        impl State<u8> for Ranked {
+            fn next_action(&self) -> Option<u8> {
+                (1..=6).find(|x| !self.done.contains(x))
+            }
+
+            fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+                self.done.push(*action);
+                Ok(match self.done.len() {
+                    6 => self.done[0] as f32 / 6.,
+                    _ => 0.,
+                })
+            }
+        }
+
+        let added = |reduction| {
+            let mut config = SearchConfig::new(1.0);
+            config.late_move_reduction = Some(reduction);
+            let mut tree = Tree::with_config(config, 0, Ranked { done: vec![] });
+            let visits = |tree: &Tree<u8, Ranked>| -> Vec<u32> {
+                let root = tree.root();
+                let mut children: Vec<(u8, u32)> = root
+                    .borrow()
+                    .children
+                    .iter()
+                    .map(|x| (x.borrow().action, x.borrow().visits))
+                    .collect();
+                children.sort_unstable();
+                children.into_iter().map(|x| x.1).collect()
+            };
+            tree.search(30);
+            let before = visits(&tree);
+            tree.search(100);
+            let after = visits(&tree);
+            let added: Vec<u32> = after.iter().zip(before).map(|(x, y)| x - y).collect();
+            added
+        };
+
+        // Only the two best jobs are searched past 30 visits
+        let frozen = added(LateMoveReduction::new(30, 2, 0));
+        assert_eq!(&frozen[..4], &[0, 0, 0, 0]);
+        assert_eq!(frozen[4] + frozen[5], 100);
+
+        // The others get every tenth visit in turn
+        let revisited = added(LateMoveReduction::new(30, 2, 10));
+        assert!(revisited[..4].iter().all(|x| *x == 2 || *x == 3));
+        assert_eq!(revisited.iter().sum::<u32>(), 100);
+    }
+
+    #[test]
+    fn simulation_start() {
+        #[derive(Debug, Clone)]
+        struct Hinted {
+            done: Vec<u8>,
+        }
+
+        impl State<u8> for Hinted {
+            fn next_action(&self) -> Option<u8> {
+                (1..=3).find(|x| !self.done.contains(x))
+            }
+
+            fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+                self.done.push(*action);
+                Ok(0.)
+            }
+
+            fn heuristic(&self) -> f32 {
+                match self.done.first() {
+                    Some(2) => 1.,
+                    _ => 0.,
+                }
+            }
+        }
+
+        let visits = |start| {
+            let mut config = SearchConfig::new(1.0);
+            config.simulation_start = start;
+            let mut tree = Tree::with_config(config, 0, Hinted { done: vec![] });
+            tree.search(2);
+            let root = tree.root();
+            let visits: Vec<u32> = root
+                .borrow()
+                .children
+                .iter()
+                .map(|x| x.borrow().visits)
+                .collect();
+            visits
+        };
+
+        assert_eq!(visits(SimulationStart::FirstChild), vec![1, 0, 0]);
+        assert_eq!(visits(SimulationStart::BestChild), vec![0, 1, 0]);
+        assert_eq!(visits(SimulationStart::Leaf), vec![0, 0, 0]);
+        assert_eq!(visits(SimulationStart::RandomChild).iter().sum::<u32>(), 1);
+    }
+
+    #[test]
+    fn capture_paths() {
+        let mut config = SearchConfig::new(1.0);
+        config.capture_paths = 2;
+        let mut tree = Tree::with_config(config, 0, DummyState::new());
+        let mut node1 = tree.expand(&mut tree.root()).unwrap();
+        let mut node2 = tree.expand(&mut node1).unwrap();
+        let ids = vec![node2.borrow().id(), node1.borrow().id(), NodeId(0)];
+
+        tree.backpropagate(&mut node2, 5.0);
+        let path = &tree.backup_paths()[0];
+        assert_eq!(path.value, 5.0);
+        let nodes: Vec<NodeId> = path.steps.iter().map(|x| x.node).collect();
+        assert_eq!(nodes, ids);
+        assert!(path.steps.iter().all(|x| x.visits == 1 && x.mean == 5.0));
+
+        // Only the last ones are kept
+        tree.search(5);
+        let paths = tree.backup_paths();
+        assert_eq!(paths.len(), 2);
+        assert_eq!((paths[0].iteration, paths[1].iteration), (3, 4));
+        let root = paths[1].steps.last().unwrap();
+        assert_eq!((root.node, root.visits), (NodeId(0), 6));
+
+        let mut tree = Tree::new(1.0, 0, DummyState::new());
+        tree.search(5);
+        assert!(tree.backup_paths().is_empty());
+    }
+
+    #[test]
+    fn search() {
+        let state1 = DummyState::new();
+        let action1 = state1.next_action().unwrap();
+
+        let mut tree = Tree::new(1.0, action1, state1);
+        let best_node = tree.search(20).unwrap();
+
+        for child in tree.root.borrow().children.iter() {
+            assert!(child.borrow().total_reward() <= best_node.borrow().total_reward());
+        }
+    }
+
+    #[test]
+    fn reward_mode() {
+        let state1 = DummyState::new();
+        let action1 = state1.next_action().unwrap();
+
+        let mut tree = Tree::with_config(SearchConfig::game(), action1, state1);
+        tree.expand(&mut tree.root());
+
+        let node1 = tree.root().borrow().child_at(1).unwrap();
+        let node2 = tree.root().borrow().child_at(2).unwrap();
+        node1.borrow_mut().visits = 1;
+        node1.borrow_mut().mean = 1.;
+        node2.borrow_mut().visits = 3;
+        node2.borrow_mut().mean = 0.3;
+
+        // Games prefer the most visited move, planning the highest total reward
+        assert!(Rc::ptr_eq(&tree.best_child().unwrap(), &node2));
+        tree.config.mode = RewardMode::Planning;
+        assert!(Rc::ptr_eq(&tree.best_child().unwrap(), &node1));
+    }
+
+    #[test]
+    fn reward_bounds() {
+        let state1 = DummyState::new();
+        let action1 = state1.next_action().unwrap();
+
+        let mut tree = Tree::new(1.0, action1, state1);
+        assert!(tree.reward_bounds().is_none());
+
+        let mut node1 = tree.expand(&mut tree.root()).unwrap();
+        tree.backpropagate(&mut node1, 2.0);
+        let mut node2 = tree.root().borrow().child_at(1).unwrap();
+        tree.backpropagate(&mut node2, -1.0);
+        assert_eq!(tree.reward_bounds(), Some((-1.0, 2.0)));
+    }
+
+    #[test]
+    fn result() {
+        let state1 = DummyState::new();
+        let action1 = state1.next_action().unwrap();
+
+        let mut config = SearchConfig::new(1.0);
+        config.value_window = 5;
+        let mut tree = Tree::with_config(config, action1, state1);
+        let best_node = tree.search(20).unwrap();
+
+        let result = tree.result();
+        assert_eq!(result.iterations, 20);
+        assert_eq!(result.recent_values.len(), 5);
+        assert_eq!(result.best_action, Some(best_node.borrow().action));
+        assert_eq!(result.children.len(), tree.root().borrow().children.len());
+        assert!(result.children.iter().any(|x| !x.line.is_empty()));
+        assert!(result.children.iter().all(|x| x.proven.is_none()));
+    }
+
+    #[test]
+    fn novelty() {
+        #[derive(Debug, Clone)]
+        struct CountdownState {
+            moves: u8,
+        }
+
+        impl StateKey for CountdownState {
+            type Key = u8;
+
+            fn key(&self) -> u8 {
+                self.moves
+            }
+        }
+
+        impl State<u8> for CountdownState {
+            fn next_action(&self) -> Option<u8> {
+                if self.moves == 0 {
+                    return None;
+                }
+                Some(self.moves)
+            }
+
+            fn try_do_action(&mut self, _action: &u8) -> Result<f32, ActionError> {
+                self.moves -= 1;
+                Ok(0.0)
+            }
+        }
+
+        let state = CountdownState { moves: 4 };
+        let tree = Tree::new(1.0, 0, state.clone());
+        assert!(tree.novelty().is_none());
+
+        let mut tree = tree.with_novelty(1.0, 0.99);
+        tree.search(10);
+
+        let novelty = tree.novelty().unwrap();
+        assert_eq!(novelty.count(&state), 10);
+        assert!(novelty.distinct_states() > 1);
+    }
+
+    #[test]
+    fn stability() {
+        let state1 = DummyState::new();
+        let action1 = state1.next_action().unwrap();
+
+        let mut tree = Tree::new(1.0, action1, state1);
+        assert_eq!(tree.stability(), Stability::default());
+
+        tree.search(30);
+        let stability = tree.stability();
+        assert!(stability.stable_iterations > 0);
+        assert!(stability.stable_iterations < tree.iterations);
+        assert!(stability.lead_changes > 0);
+        assert_eq!(tree.result().stability.lead_changes, stability.lead_changes);
+    }
+
+    #[test]
+    fn search_from_repro() {
+        #[derive(Debug, Clone)]
+        struct CountdownState {
+            moves: u8,
+        }
+
+        impl StateKey for CountdownState {
+            type Key = u8;
+
+            fn key(&self) -> u8 {
+                self.moves
+            }
+        }
+
+        impl State<u8> for CountdownState {
+            fn next_action(&self) -> Option<u8> {
+                if self.moves == 0 {
+                    return None;
+                }
+                Some(self.moves)
+            }
+
+            fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+                self.moves -= 1;
+                Ok(*action as f32)
+            }
+        }
+
+        let mut config = SearchConfig::new(1.0);
+        config.seed = 7;
+        let mut tree = Tree::with_config(config, 0, CountdownState { moves: 4 }).with_fingerprint();
+        tree.search(15);
+        let result = tree.result();
+
+        let repro = result.repro();
+        assert_eq!(repro.seed(), 7);
+        assert_eq!(repro.iterations, 15);
+
+        let replay = Tree::search_from_repro(&repro, 0, CountdownState { moves: 4 }).unwrap();
+        assert_eq!(replay.result().children, result.children);
+
+        let mismatch = Tree::search_from_repro(&repro, 0, CountdownState { moves: 3 });
+        assert_eq!(mismatch.err(), Some(ReproError::Fingerprint));
+    }
+
+    #[test]
+    fn temperature() {
+        let state1 = DummyState::new();
+        let action1 = state1.next_action().unwrap();
+
+        let tree = Tree::new(1.0, action1, state1.clone());
+        assert_eq!(tree.temperature(), 0.);
+
+        let mut tree =
+            Tree::new(1.0, action1, state1).with_temperature(LinearSchedule::new(1., 0.));
+        assert_eq!(tree.temperature(), 1.);
+
+        // The schedule is fully cooled down once the search is over
+        assert!(tree.search(20).is_some());
+        assert_eq!(tree.temperature(), 0.);
+    }
+
+    #[test]
+    fn observer() {
+        #[derive(Debug, Default)]
+        struct Recorder {
+            samples: RefCell<Vec<(u32, usize)>>,
+        }
+
+        impl Observer for Recorder {
+            fn on_iteration(&self, sample: &IterationSample) {
+                let mut samples = self.samples.borrow_mut();
+                samples.push((sample.iteration, sample.depth));
+            }
+        }
+
+        let recorder = Rc::new(Recorder::default());
+        let mut config = SearchConfig::new(1.0);
+        config.telemetry_interval = 10;
+        let mut tree =
+            Tree::with_config(config, 0, DummyState::new()).with_observer(Rc::clone(&recorder));
+        tree.search(35);
+
+        // The first iteration simulates from the root itself
+        let samples = recorder.samples.borrow();
+        let iterations: Vec<u32> = samples.iter().map(|x| x.0).collect();
+        assert_eq!(iterations, vec![0, 10, 20, 30]);
+        assert_eq!(samples[0].1, 0);
+        assert!(samples[1..].iter().all(|x| x.1 > 0));
+    }
+
+    #[test]
+    fn node_id() {
+        let mut tree = Tree::new(1.0, 0, DummyState::new());
+        assert!(Rc::ptr_eq(
+            &tree.node(tree.root().borrow().id()).unwrap(),
+            &tree.root()
+        ));
+
+        tree.search(20);
+        let node = tree.root().borrow().child_at(1).unwrap();
+        let id = node.borrow().id();
+        assert_ne!(id, tree.root().borrow().id());
+
+        // Ids survive further searches without keeping the node alive
+        tree.search(20);
+        assert!(Rc::ptr_eq(&tree.node(id).unwrap(), &node));
+
+        tree.prune_children(&tree.root(), 0);
+        drop(node);
+        assert!(tree.node(id).is_none());
+    }
+
+    #[test]
+    fn with_node() {
+        let mut tree = Tree::new(1.0, 0, DummyState::new());
+        tree.search(20);
+        let root = tree.root().borrow().id();
+        let children = tree.child_ids(root).unwrap();
+        assert_eq!(children.len(), 5);
+        assert_eq!(tree.with_node(root, |x| x.visits), Some(20));
+        assert_eq!(tree.node_stats(root).unwrap().visits, 20);
+
+        // A node borrowed mutably elsewhere is reported as unavailable
+        let node = tree.node(children[0]).unwrap();
+        let _guard = node.borrow_mut();
+        assert_eq!(tree.with_node(children[0], |x| x.visits), None);
+        assert_eq!(tree.child_ids(root), None);
+        assert!(tree.try_result().is_none());
+    }
+
+    #[test]
+    fn search_from() {
+        let mut tree = Tree::new(1.0, 0, DummyState::new());
+        tree.search(10);
+        let node = tree.root().borrow().child_at(2).unwrap();
+        let visits = node.borrow().visits;
+
+        assert!(tree.search_from(&node, 30, true).is_some());
+        assert_eq!(node.borrow().visits, visits + 30);
+        assert_eq!(tree.root().borrow().visits, 40);
+
+        // The rest of the tree is left untouched
+        tree.search_from(&node, 30, false);
+        assert_eq!(node.borrow().visits, visits + 60);
+        assert_eq!(tree.root().borrow().visits, 40);
+        assert_eq!(tree.iterations, 70);
+    }
+
+    #[test]
+    fn quantile_sketch() {
+        let mut tree = Tree::new(1.0, 0, DummyState::new());
+        tree.search(10);
+        assert!(tree.root().borrow().quantile(0.5).is_none());
+
+        let mut config = SearchConfig::new(1.0);
+        config.quantile_sketch_size = 8;
+        let mut tree = Tree::with_config(config, 0, DummyState::new());
+        tree.search(50);
+        let root = tree.root();
+        assert_eq!(root.borrow().sketch.as_ref().unwrap().count(), 50);
+        assert!(root.borrow().quantile(0.5).unwrap() > 0.);
+    }
+
+    #[test]
+    fn rollout_cache() {
+        #[derive(Debug, Clone)]
+        struct CountdownState {
+            moves: u8,
+            decided: bool,
+        }
+
+        impl StateKey for CountdownState {
+            type Key = u8;
+
+            fn key(&self) -> u8 {
+                self.moves
+            }
+        }
+
+        impl State<u8> for CountdownState {
+            fn next_action(&self) -> Option<u8> {
+                if self.moves == 0 {
+                    return None;
+                }
+                Some(self.moves)
+            }
+
+            fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+                self.moves -= 1;
+                Ok(*action as f32)
+            }
+
+            fn outcome(&self) -> Option<Outcome> {
+                (self.decided && self.moves == 0).then_some(Outcome::Win)
+            }
+        }
+
+        let state = CountdownState {
+            moves: 4,
+            decided: false,
+        };
+        let tree = Tree::new(1.0, 0, state.clone());
+        assert!(tree.rollout_cache_stats().is_none());
+
+        // Every root child reaches the same state, only the first one is rolled out
+        let mut tree = tree.with_rollout_cache(16);
+        tree.search(5);
+        let stats = tree.rollout_cache_stats().unwrap();
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.hits, 3);
+        assert_eq!(
+            tree.root()
+                .borrow()
+                .child_at(3)
+                .unwrap()
+                .borrow()
+                .total_reward(),
+            6.
+        );
+
+        // Cached rollouts still end in their outcome
+        let state = CountdownState {
+            decided: true,
+            ..state
+        };
+        let mut tree = Tree::new(1.0, 0, state).with_rollout_cache(16);
+        tree.search(5);
+        assert_eq!(tree.rollout_cache_stats().unwrap().hits, 3);
+        assert_eq!(tree.root().borrow().outcomes.wins, 5);
+    }
+
+    #[test]
+    fn rollout_bandit() {
+        // Only the last action of each state pays, the state's own order
+        // plays the first one
+        #[derive(Debug, Clone)]
+        struct LastPays {
+            left: u8,
+        }
+
+        impl StateKey for LastPays {
+            type Key = u8;
+
+            fn key(&self) -> u8 {
+                self.left
+            }
+        }
+
+        impl State<u8> for LastPays {
+            fn next_action(&self) -> Option<u8> {
+                if self.left == 0 {
+                    return None;
+                }
+                Some(self.left)
+            }
+
+            fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+                self.left -= 1;
+                Ok(if *action == 1 { 1. } else { 0. })
+            }
+        }
+
+        let mean = |bandit: bool| {
+            let mut tree = Tree::new(1.0, 0, LastPays { left: 6 });
+            if bandit {
+                tree = tree.with_rollout_bandit(6, 64);
+            }
+            tree.search(300);
+            assert_eq!(tree.rollout_bandit().is_some_and(|x| !x.is_empty()), bandit);
+            tree.root().borrow().mean_value()
+        };
+        assert!(mean(true) > mean(false) + 0.5);
+    }
+
+    #[test]
+    fn outcome_histogram() {
+        let game = PathGame::new(3, |x| {
+            if x[1] == 2 {
+                Outcome::Win
+            } else {
+                Outcome::Loss
+            }
+        });
+        // Large enough to keep every value of a child apart
+        let mut config = SearchConfig::game();
+        config.quantile_sketch_size = 64;
+        let mut tree = Tree::with_config(config, 0, game);
+        let result = tree.search_with(SearchBudget::iterations(60));
+
+        // Each move either wins or loses outright, never anything in between
+        for child in result.children.iter() {
+            assert_eq!(child.outcomes.total(), child.visits);
+            assert!(child.outcomes.wins > 0 && child.outcomes.losses > 0);
+            assert_eq!(child.outcomes.draws, 0);
+            let counts: Vec<u32> = child.histogram.iter().map(|x| x.1).collect();
+            assert_eq!(counts.iter().sum::<u32>(), child.visits);
+            assert_eq!(counts[1..7], [0; 6]);
+        }
+        assert_eq!(tree.root().borrow().outcomes.total(), 60);
+    }
+
+    #[test]
+    fn coverage() {
+        #[derive(Debug, Clone)]
+        struct Counter {
+            moves: u8,
+            total: u8,
+        }
+
+        impl StateKey for Counter {
+            type Key = (u8, u8);
+
+            fn key(&self) -> (u8, u8) {
+                (self.moves, self.total)
+            }
+        }
+
+        impl State<u8> for Counter {
+            fn next_action(&self) -> Option<u8> {
+                if self.moves == 0 {
+                    return None;
+                }
+                Some(self.moves % 2 + 1)
+            }
+
+            fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+                self.moves -= 1;
+                self.total += action;
+                Ok(0.)
+            }
+        }
+
+        let mut tree = Tree::new(1.0, 0, Counter { moves: 3, total: 0 }).with_coverage(100);
+        assert_eq!(tree.coverage().unwrap().distinct, 1);
+        let result = tree.search_with(SearchBudget::iterations(30));
+
+        // Transpositions reach a state once, whatever the path
+        let coverage = result.coverage.unwrap();
+        assert!(coverage.exact);
+        assert!(coverage.distinct < tree.size as u64);
+        assert!(Tree::new(1.0, 0, MockState::countdown(2, 0.))
+            .coverage()
+            .is_none());
+    }
+
+    #[test]
+    fn evaluation_cache() {
+        #[derive(Debug, Clone)]
+        struct CountdownState {
+            moves: u8,
+        }
+
+        impl StateKey for CountdownState {
+            type Key = u8;
+
+            fn key(&self) -> u8 {
+                self.moves
+            }
+        }
+
+        impl State<u8> for CountdownState {
+            fn next_action(&self) -> Option<u8> {
+                if self.moves == 0 {
+                    return None;
+                }
+                Some(self.moves)
+            }
+
+            fn try_do_action(&mut self, _action: &u8) -> Result<f32, ActionError> {
+                self.moves -= 1;
+                Ok(0.)
+            }
+        }
+
+        #[derive(Debug)]
+        struct Heuristic {
+            calls: Rc<Cell<u32>>,
+        }
+
+        impl Evaluator<u8, CountdownState> for Heuristic {
+            fn evaluate(&self, state: &CountdownState) -> Evaluation<u8> {
+                self.calls.set(self.calls.get() + 1);
+                (vec![], state.moves as f32)
+            }
+        }
+
+        let calls = Rc::new(Cell::new(0));
+        let evaluator = Heuristic {
+            calls: Rc::clone(&calls),
+        };
+        let mut tree = Tree::new(1.0, 0, CountdownState { moves: 4 })
+            .with_evaluator(evaluator)
+            .with_evaluation_cache(16);
+        tree.search(5);
+
+        // The root children all share one state
+        assert_eq!(calls.get(), 2);
+        assert_eq!(tree.root().borrow().total_reward(), 16.);
+        let stats = tree.evaluation_cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses), (3, 2));
+    }
+
+    #[test]
+    fn pre_evaluate_root() {
+        #[derive(Debug)]
+        struct FirstJob {
+            batches: Rc<RefCell<Vec<usize>>>,
+        }
+
+        impl Evaluator<u8, DuelState> for FirstJob {
+            fn evaluate(&self, state: &DuelState) -> Evaluation<u8> {
+                let value = match state.done.first() {
+                    Some(0) => 0.9,
+                    Some(1) => 0.1,
+                    _ => 0.5,
+                };
+                (vec![(0, 1.), (2, 3.)], value)
+            }
+
+            fn evaluate_batch(&self, states: &[&DuelState]) -> Vec<Evaluation<u8>> {
+                self.batches.borrow_mut().push(states.len());
+                states.iter().map(|x| self.evaluate(x)).collect()
+            }
+        }
+
+        let batches = Rc::new(RefCell::new(vec![]));
+        let mut config = SearchConfig::new(1.0);
+        config.pre_evaluate_root = true;
+        let state = DuelState {
+            players: 1,
+            done: vec![],
+        };
+        let mut tree = Tree::with_config(config, 0, state.clone())
+            .with_evaluator(FirstJob {
+                batches: Rc::clone(&batches),
+            })
+            .with_policy_priors();
+        tree.search(0);
+
+        // One batch for the root and its children, each backed up once
+        assert_eq!(*batches.borrow(), vec![4]);
+        assert_eq!(tree.iterations, 3);
+        let root = tree.root();
+        let children: Vec<(Option<f32>, u32, f32)> = root
+            .borrow()
+            .children
+            .iter()
+            .map(|x| {
+                let x = x.borrow();
+                (x.prior, x.visits, x.mean_value())
+            })
+            .collect();
+        assert_eq!(
+            children,
+            vec![
+                (Some(0.25), 1, 0.9),
+                (Some(0.), 1, 0.1),
+                (Some(0.75), 1, 0.5)
+            ]
+        );
+        assert_eq!(tree.best_child().unwrap().borrow().action, 0);
+
+        // Only the first search starts with it
+        tree.search(5);
+        assert_eq!(batches.borrow().len(), 1);
+
+        let mut tree = Tree::with_config(config, 0, state);
+        assert_eq!(tree.pre_evaluate_root(), 0);
+    }
+
+    #[test]
+    fn refresh_root_actions() {
+        let mut tree = Tree::new(1.0, 0, MockState::countdown(3, 0.5));
+        tree.search(10);
+
+        // The domain now offers a new action and no longer allows 2
+        let root = tree.root();
+        root.borrow_mut().state = MockState::new(vec![(3, 0.5), (1, 0.5), (7, 0.5)]);
+        let (added, illegal) = tree.refresh_root_actions();
+        assert_eq!(added, vec![7]);
+        assert_eq!(illegal, vec![2]);
+        assert_eq!(tree.size, Tree::subtree_size(&root));
+
+        tree.search(30);
+        let flagged = root.borrow().child_at(1).unwrap();
+        assert!(flagged.borrow().illegal);
+        assert_eq!(flagged.borrow().action, 2);
+        let visits = flagged.borrow().visits;
+        tree.search(30);
+        assert_eq!(flagged.borrow().visits, visits);
+        assert_ne!(tree.best_child().unwrap().borrow().action, 2);
+    }
+
+    #[test]
+    fn invalidate() {
+        let mut tree = Tree::new(1.0, 0, DummyState::new());
+        assert!(!tree.invalidate(&tree.root()));
+
+        tree.search(40);
+        let root = tree.root();
+        let node = root.borrow().child_at(0).unwrap();
+        let id = node.borrow().id();
+        let visits = node.borrow().visits;
+        let size = tree.size;
+
+        assert!(tree.invalidate(&node));
+        assert_eq!(root.borrow().visits, 40 - visits);
+        assert_eq!(root.borrow().children.len(), 4);
+        assert_eq!(tree.size, size - Tree::subtree_size(&node));
+        drop(node);
+        assert!(tree.node(id).is_none());
+
+        tree.search(20);
+        assert_eq!(root.borrow().children.len(), 4);
+    }
+
+    #[test]
+    fn greedy_selection() {
+        let mut config = SearchConfig::new(1.0);
+        config.selection = SelectionPolicy::Greedy;
+        let mut tree = Tree::with_config(config, 0, DummyState::new());
+
+        // Every root child is tried once first
+        tree.search(6);
+        let root = tree.root();
+        assert!(root
+            .borrow()
+            .children
+            .iter()
+            .all(|x| x.borrow().visits == 1));
+
+        // Then the best mean gets every iteration
+        let best = root.borrow().child_at(2).unwrap();
+        best.borrow_mut().mean = 100.;
+        tree.search(10);
+        assert_eq!(best.borrow().visits, 11);
+        assert_eq!(tree.greedy_plan()[0], best.borrow().action);
+    }
+
+    #[test]
+    fn root_value() {
+        let mut tree = Tree::new(1.0, 0, DummyState::new());
+        assert!(tree.root_value().is_none());
+        tree.search(20);
+        assert!(tree.root_value_history().is_empty());
+
+        let root = tree.root();
+        let (visits, total) = root.borrow().children.iter().fold((0, 0.), |x, y| {
+            (x.0 + y.borrow().visits, x.1 + y.borrow().total_reward())
+        });
+        assert!((tree.root_value().unwrap() - stat_to_f32(total / visits as Stat)).abs() < 1e-6);
+
+        let mut config = SearchConfig::new(1.0);
+        config.root_value_interval = 5;
+        let mut tree = Tree::with_config(config, 0, DummyState::new());
+        tree.search(20);
+        let iterations: Vec<u32> = tree.root_value_history().iter().map(|x| x.0).collect();
+        assert_eq!(iterations, vec![5, 10, 15, 20]);
+    }
+
+    #[test]
+    fn solver() {
+        let mut config = SearchConfig::game();
+        config.solver = true;
+        let game = PathGame::new(3, |x| match x[0] {
+            1 => Outcome::Win,
+            _ => Outcome::Loss,
+        });
+        let mut tree = Tree::with_config(config, 0, game);
+        tree.search(60);
+
+        let root = tree.root();
+        let proven: Vec<Option<Outcome>> = root
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().proven)
+            .collect();
+        assert_eq!(
+            proven,
+            vec![Some(Outcome::Loss), Some(Outcome::Loss), Some(Outcome::Win)]
+        );
+        assert_eq!(root.borrow().proven, Some(Outcome::Win));
+
+        // Proofs beat visits
+        let winner = root.borrow().child_at(2).unwrap();
+        let loser = root.borrow().child_at(0).unwrap();
+        loser.borrow_mut().visits = 10_000;
+        assert!(Rc::ptr_eq(&tree.best_child().unwrap(), &winner));
+
+        // Proven losses are avoided while another move is open
+        winner.borrow_mut().proven = None;
+        winner.borrow_mut().visits = 1;
+        assert!(Rc::ptr_eq(&tree.best_child().unwrap(), &winner));
+        winner.borrow_mut().proven = Some(Outcome::Loss);
+        assert!(Rc::ptr_eq(&tree.best_child().unwrap(), &loser));
+    }
+
+    #[test]
+    fn opponent_refutation() {
+        // The opponent answers 1 with a refutation, 2 and 3 lose for it
+        let mut config = SearchConfig::game();
+        config.solver = true;
+        let game = PathGame::new(3, |x| match (x[0], x[1]) {
+            (1, 1) => Outcome::Loss,
+            (1, _) => Outcome::Win,
+            (_, _) => Outcome::Win,
+        });
+        let mut tree = Tree::with_config(config, 0, game);
+        tree.search(60);
+
+        let root = tree.root();
+        let refuted = root.borrow().child_at(2).unwrap();
+        assert_eq!(refuted.borrow().proven, Some(Outcome::Loss));
+        assert_eq!(root.borrow().proven, Some(Outcome::Win));
+        assert_ne!(tree.best_child().unwrap().borrow().action, 1);
+    }
+
+    #[test]
+    fn min_decision_visits() {
+        let mut config = SearchConfig::new(1.0);
+        config.min_decision_visits = 3;
+        let mut tree = Tree::with_config(config, 0, DummyState::new());
+        tree.search(30);
+
+        // A lucky child with a single rollout cannot win the decision
+        let root = tree.root();
+        let lucky = root.borrow().child_at(0).unwrap();
+        lucky.borrow_mut().visits = 1;
+        lucky.borrow_mut().mean = 1000.;
+        assert!(!Rc::ptr_eq(&tree.best_child().unwrap(), &lucky));
+
+        config.min_decision_visits = 0;
+        tree.config = config;
+        assert!(Rc::ptr_eq(&tree.best_child().unwrap(), &lucky));
+    }
+
+    #[test]
+    fn backpropagate_batch() {
+        let mut batched = Tree::new(1.0, 0, MockState::countdown(4, 0.5));
+        let mut sequential = Tree::new(1.0, 0, MockState::countdown(4, 0.5));
+        batched.search(20);
+        sequential.search(20);
+
+        let leaves = |tree: &Tree<u8, MockState<u8>>| -> Vec<(NodeRef<u8, MockState<u8>>, f32)> {
+            let root = tree.root();
+            let a = root.borrow().child_at(0).unwrap();
+            let b = a.borrow().child_at(0).unwrap();
+            let c = root.borrow().child_at(1).unwrap();
+            vec![(b, 1.), (Rc::clone(&a), 0.25), (c, 2.), (a, 0.5)]
+        };
+
+        for (mut leaf, value) in leaves(&sequential) {
+            sequential.backpropagate(&mut leaf, value);
+        }
+        batched.backpropagate_batch(leaves(&batched));
+
+        let stats = |tree: &Tree<u8, MockState<u8>>| -> Vec<(u32, f32, f32)> {
+            std::iter::once(tree.root())
+                .chain(Tree::descendants(&tree.root()))
+                .map(|x| {
+                    let x = x.borrow();
+                    (x.visits, x.mean_value(), x.value_variance())
+                })
+                .collect()
+        };
+        for (x, y) in stats(&batched).into_iter().zip(stats(&sequential)) {
+            assert_eq!(x.0, y.0);
+            assert!((x.1 - y.1).abs() < 1e-5);
+            assert!((x.2 - y.2).abs() < 1e-5);
+        }
+        assert_eq!(batched.reward_bounds(), sequential.reward_bounds());
+
+        // The backup rule takes the values one at a time, in the order of
+        // the results
+        #[derive(Debug)]
+        struct Smoothed;
+
+        impl BackupRule<u8, MockState<u8>> for Smoothed {
+            fn update(&self, node: &mut Node<u8, MockState<u8>>, value: f32) {
+                node.visits += 1;
+                node.mean += (Stat::from(value) - node.mean) / 2.;
+            }
+        }
+
+        let mut config = SearchConfig::game();
+        config.capture_paths = 8;
+        let build = || {
+            let mut tree = Tree::with_config(config, 0, MockState::countdown(4, 0.5))
+                .with_backup_rule(Smoothed);
+            tree.search(20);
+            tree
+        };
+        let (mut batched, mut sequential) = (build(), build());
+        // The first backup takes the outcome of the last simulation
+        sequential.outcome.set(Some(Outcome::Win));
+        for (mut leaf, value) in leaves(&sequential) {
+            sequential.backpropagate(&mut leaf, value);
+        }
+        batched.outcome.set(Some(Outcome::Win));
+        batched.backpropagate_batch(leaves(&batched));
+
+        let stats = |tree: &Tree<u8, MockState<u8>>| -> Vec<(u32, f32, u32)> {
+            std::iter::once(tree.root())
+                .chain(Tree::descendants(&tree.root()))
+                .map(|x| {
+                    let x = x.borrow();
+                    (x.visits, x.mean_value(), x.outcomes.wins)
+                })
+                .collect()
+        };
+        for (x, y) in stats(&batched).into_iter().zip(stats(&sequential)) {
+            assert_eq!((x.0, x.2), (y.0, y.2));
+            assert!((x.1 - y.1).abs() < 1e-5);
+        }
+        assert_eq!(batched.root().borrow().outcomes.wins, 1);
+        assert_eq!(
+            batched.backup_paths().len(),
+            sequential.backup_paths().len()
+        );
+    }
+
+    #[test]
+    fn seed_root_policy() {
+        let mut config = SearchConfig::new(1.0);
+        config.prior_weight = 10.;
+        let visits = |tree: &Tree<u8, MockState<u8>>| {
+            tree.root()
+                .borrow()
+                .children
+                .iter()
+                .find(|x| x.borrow().action == 1)
+                .map(|x| x.borrow().visits)
+                .unwrap()
+        };
+
+        let mut plain = Tree::with_config(config, 0, MockState::countdown(4, 0.5));
+        plain.search(40);
+
+        let mut seeded = Tree::with_config(config, 0, MockState::countdown(4, 0.5));
+        seeded.seed_root_policy(vec![(1, 3.), (2, 1.)]);
+        let priors: Vec<Option<f32>> = seeded
+            .root()
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().prior)
+            .collect();
+        assert_eq!(priors, vec![Some(0.), Some(0.), Some(0.25), Some(0.75)]);
+
+        seeded.search(40);
+        assert!(visits(&seeded) > visits(&plain));
+
+        // Children from random expansion are kept, the rest are added once
+        config.random_expansion = true;
+        let mut tree = Tree::with_config(config, 0, MockState::countdown(4, 0.5));
+        tree.search(3);
+        assert!(tree.root().borrow().children.len() < 4);
+        tree.seed_root_policy(vec![(1, 1.)]);
+        let mut actions: Vec<u8> = tree
+            .root()
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().action)
+            .collect();
+        actions.sort_unstable();
+        assert_eq!(actions, vec![1, 2, 3, 4]);
+        assert_eq!(tree.size, 5);
+    }
+
+    #[test]
+    fn interval_callback() {
+        let calls = Rc::new(RefCell::new(vec![]));
+        let recorded = Rc::clone(&calls);
+        let mut tree = Tree::new(1.0, 0, DummyState::new())
+            .with_interval_callback(Interval::Iterations(4), move |x| {
+                recorded.borrow_mut().push(x.iterations)
+            });
+        tree.search(10);
+        assert_eq!(*calls.borrow(), vec![4, 8]);
+
+        let calls = Rc::new(Cell::new(0));
+        let counted = Rc::clone(&calls);
+        let mut tree = Tree::new(1.0, 0, DummyState::new())
+            .with_interval_callback(Interval::Time(Duration::ZERO), move |_| {
+                counted.set(counted.get() + 1)
+            });
+        tree.search(5);
+        assert_eq!(calls.get(), 5);
+    }
+
+    #[test]
+    fn max_steps() {
+        // Expanding the root takes two steps per child, every rollout five
+        let mut tree = Tree::new(1.0, 0, DummyState::new());
+        tree.search(1);
+        assert_eq!(tree.steps(), 5);
+        tree.search(1);
+        assert_eq!(tree.steps(), 5 + 10 + 4);
+
+        let mut config = SearchConfig::new(1.0);
+        config.max_steps = 100;
+        let mut tree = Tree::with_config(config, 0, DummyState::new());
+        tree.search(1000);
+        assert!(tree.steps() >= 100);
+        assert!(tree.iterations < 1000);
+        assert_eq!(tree.result().steps, tree.steps());
+    }
+
+    #[derive(Debug, Clone)]
+    struct CostlyState {
+        first: Option<u8>,
+        actions: u8,
+    }
+
+    impl State<u8> for CostlyState {
+        fn next_action(&self) -> Option<u8> {
+            if self.actions == 0 {
+                return None;
+            }
+            Some(self.actions)
+        }
+
+        fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+            self.first.get_or_insert(*action);
+            self.actions -= 1;
+            Ok(0.5)
+        }
+
+        // Everything below action 2 is ten times slower
+        fn step_cost(&self, action: &u8) -> f32 {
+            match self.first.unwrap_or(*action) {
+                2 => 10.,
+                _ => 1.,
+            }
+        }
+    }
+
+    #[test]
+    fn step_cost() {
+        let state = CostlyState {
+            first: None,
+            actions: 2,
+        };
+        let mut tree = Tree::new(1.0, 0, state.clone());
+        // The rollout plays 2 and then 1, below 2
+        tree.search(1);
+        assert_eq!(tree.cost(), 20.);
+
+        let mut config = SearchConfig::new(1.0);
+        config.max_cost = 50.;
+        let mut tree = Tree::with_config(config, 0, state.clone());
+        tree.search(100);
+        assert!(tree.cost() >= 50.);
+        assert!(tree.iterations < 100);
+        assert_eq!(tree.root().borrow().cost, tree.result().cost);
+
+        // The quota gives the cheap move more iterations for the same cost
+        let mut config = SearchConfig::new(1.0);
+        config.root_min_visits = 1000;
+        config.root_quota_by_cost = true;
+        let mut tree = Tree::with_config(config, 0, state);
+        tree.search(100);
+        let root = tree.root();
+        let slow = root.borrow().child_at(0).unwrap();
+        let fast = root.borrow().child_at(1).unwrap();
+        assert!(fast.borrow().visits > 3 * slow.borrow().visits);
+    }
+
+    #[test]
+    fn sample_softmax() {
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(super::sample_softmax(&[], 1., &mut rng), None);
+
+        let counts = |rationality: f32, rng: &mut StdRng| {
+            let mut counts = [0; 3];
+            for _i in 0..1000 {
+                counts[super::sample_softmax(&[0., 1., 0.5], rationality, rng).unwrap()] += 1;
+            }
+            counts
+        };
+        assert_eq!(counts(1000., &mut rng), [0, 1000, 0]);
+        let counts = counts(1., &mut rng);
+        assert!(counts[1] > counts[2] && counts[2] > counts[0] && counts[0] > 100);
+    }
+
+    #[test]
+    fn opponent_rationality() {
+        // The opponent refutes either move by answering 1
+        let game = PathGame::new(3, |x| match x[1] {
+            1 => Outcome::Loss,
+            _ => Outcome::Win,
+        });
+        let refutations = |rationality: f32| {
+            let mut config = SearchConfig::game();
+            config.opponent_rationality = rationality;
+            let mut tree = Tree::with_config(config, 0, game.clone());
+            tree.search(200);
+
+            let root = tree.root();
+            let child = root.borrow().child_at(0).unwrap();
+            let answers: Vec<u32> = child
+                .borrow()
+                .children
+                .iter()
+                .map(|x| x.borrow().visits)
+                .collect();
+            (answers[1], answers[0])
+        };
+
+        // A perfect opponent and a rational one find the refutation, a nearly
+        // random one answers both ways
+        let (refuted, missed) = refutations(0.);
+        assert!(refuted > missed * 4);
+        let (refuted, missed) = refutations(20.);
+        assert!(refuted > missed * 4);
+        let (refuted, missed) = refutations(0.01);
+        assert!(missed * 2 > refuted);
+    }
+
+    #[test]
+    fn subtree_stats() {
+        let mut tree = Tree::new(1.0, 0, MockState::countdown(3, 0.5));
+        tree.search(40);
+
+        let root = tree.root();
+        let stats = root.borrow().subtree_stats();
+        assert_eq!(stats.nodes, tree.size);
+        assert_eq!(stats.depth, 3);
+        assert_eq!(stats.best_terminal, Some(0.));
+
+        let child = root.borrow().child_at(0).unwrap();
+        tree.prune_children(&child, 0);
+        assert_eq!(root.borrow().subtree_stats().nodes, tree.size);
+
+        // Changes below the root reach its statistics too
+        let mut tree = Tree::new(1.0, 0, MockState::countdown(5, 0.5));
+        tree.search(10);
+        let root = tree.root();
+        let stats = root.borrow().subtree_stats();
+        let child = root.borrow().child_at(0).unwrap();
+        tree.search_from(&child, 20, false);
+        tree.expand_to_depth(3);
+        tree.allocate_budget(vec![(1, 5)]);
+        let fresh = root.borrow().subtree_stats();
+        assert_eq!(fresh.nodes, tree.size);
+        assert!(fresh.nodes > stats.nodes && fresh.visits > stats.visits);
+        assert_eq!(tree.result().max_depth, fresh.depth);
+    }
+
+    #[test]
+    fn hint_line() {
+        let mut tree = Tree::new(1.0, 0, MockState::countdown(3, 0.5));
+        assert_eq!(tree.hint_line(&[2, 1, 7], 10., 30), 2);
+        tree.search(30);
+
+        let root = tree.root();
+        let hinted = root.borrow().child_at(1).unwrap();
+        assert_eq!(hinted.borrow().action, 2);
+        assert!(hinted.borrow().visits > 20);
+        let visits = hinted.borrow().visits;
+
+        // Past its iterations the hint no longer steers the search
+        tree.search(60);
+        assert!(hinted.borrow().visits < visits + 50);
+        assert_eq!(tree.hint_line(&[], 1., 10), 0);
+        tree.clear_hint();
+        assert!(tree.hint.is_none());
+    }
+
+    #[derive(Debug, Clone)]
+    struct JobState {
+        jobs: u8,
+        done: Vec<u8>,
+    }
+
+    impl State<u8> for JobState {
+        fn next_action(&self) -> Option<u8> {
+            (0..self.jobs).find(|x| !self.done.contains(x))
+        }
+
+        fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+            self.done.push(*action);
+            Ok(0.)
+        }
+
+        // Jobs are independent, only increasing orders are kept
+        fn commutes(&self, previous: &u8, action: &u8) -> bool {
+            action < previous
+        }
+    }
+
+    #[test]
+    fn commuting_actions() {
+        let mut tree = Tree::new(
+            1.0,
+            0,
+            JobState {
+                jobs: 3,
+                done: vec![],
+            },
+        );
+        tree.search(100);
+
+        // One node per subset of the jobs instead of one per permutation prefix
+        assert_eq!(tree.size, 8);
+        assert_eq!(tree_shape(&tree.root()), vec![1, 3, 3, 1]);
+    }
+
+    // The opponent replies with job 2 to win, unless the root player took it.
+    // The last job is forced
+    #[derive(Debug, Clone)]
+    struct DuelState {
+        players: usize,
+        done: Vec<u8>,
+    }
+
+    impl State<u8> for DuelState {
+        fn next_action(&self) -> Option<u8> {
+            (0..3).find(|x| !self.done.contains(x))
+        }
+
+        fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+            self.done.push(*action);
+            Ok(0.)
+        }
+
+        fn outcome(&self) -> Option<Outcome> {
+            match self.done[..] {
+                _ if self.players > 2 => None,
+                [2, _, _] => Some(Outcome::Draw),
+                [_, 2, _] => Some(Outcome::Loss),
+                [_, _, _] => Some(Outcome::Win),
+                _ => None,
+            }
+        }
+
+        fn current_player(&self) -> usize {
+            self.done.len() % self.players
+        }
+
+        fn num_players(&self) -> usize {
+            self.players
+        }
+
+        fn payoffs(&self) -> Option<Vec<f32>> {
+            match self.done[..] {
+                [2, _, _] => Some(vec![0.5, 0.5, 0.]),
+                [_, 2, _] => Some(vec![0., 1., 0.]),
+                [_, _, _] => Some(vec![1., 0., 0.]),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn player_perspective() {
+        let best = |players| {
+            let state = DuelState {
+                players,
+                done: vec![],
+            };
+            let mut tree = Tree::with_config(SearchConfig::game(), 0, state);
+            tree.search(300);
+            let action = tree.best_child().unwrap().borrow().action;
+            action
+        };
+
+        // Maximizing everywhere counts on the opponent to miss job 2
+        assert_ne!(best(1), 2);
+        assert_eq!(best(2), 2);
+        assert_eq!(best(3), 2);
+    }
+
+    // Player 1 moves first and wins with job 0, outcomes are seen by player 0
+    #[derive(Debug, Clone)]
+    struct SecondMover {
+        done: Vec<u8>,
+    }
+
+    impl State<u8> for SecondMover {
+        fn next_action(&self) -> Option<u8> {
+            match self.done.len() {
+                0 | 1 => (0..2).find(|x| !self.done.contains(x)),
+                _ => None,
+            }
+        }
+
+        fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+            self.done.push(*action);
+            Ok(0.)
+        }
+
+        fn outcome(&self) -> Option<Outcome> {
+            match self.done[..] {
+                [0, _] => Some(Outcome::Loss),
+                [_, _] => Some(Outcome::Win),
+                _ => None,
+            }
+        }
+
+        fn current_player(&self) -> usize {
+            (self.done.len() + 1) % 2
+        }
+
+        fn num_players(&self) -> usize {
+            2
+        }
+    }
+
+    #[test]
+    fn second_player_at_root() {
+        let mut config = SearchConfig::game();
+        config.solver = true;
+        let mut tree = Tree::with_config(config, 0, SecondMover { done: vec![] });
+        tree.search(100);
+
+        // Values and proofs are for player 1, not for player 0
+        let best = tree.best_child().unwrap();
+        assert_eq!(best.borrow().action, 0);
+        assert_eq!(best.borrow().proven, Some(Outcome::Win));
+        assert_eq!(best.borrow().mean_value(), 1.);
+        let result = tree.result();
+        let stats = result.children.iter().find(|x| x.action == 0).unwrap();
+        assert_eq!(stats.proven, Some(Outcome::Win));
+    }
+
+    #[test]
+    fn shallow_search() {
+        let state = DuelState {
+            players: 2,
+            done: vec![],
+        };
+        let mut config = SearchConfig::game();
+        config.shallow_search = Some(ShallowSearch::new(2, 1));
+        let mut tree = Tree::with_config(config, 0, state.clone());
+        tree.search(4);
+
+        // Exact values of the root moves, where rollouts expect job 2 from
+        // the opponent to be missed
+        let means: Vec<f32> = tree
+            .root()
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().mean_value())
+            .collect();
+        assert_eq!(means, vec![0., 0., 0.5]);
+
+        let mut tree = Tree::with_config(SearchConfig::game(), 0, state);
+        tree.search(8);
+        let first = tree.root().borrow().child_at(0).unwrap();
+        assert_eq!(first.borrow().mean_value(), 1.);
+    }
+
+    // The root player takes 10 with job 1, then the opponent answers with a
+    // heuristic of 3 or -100. Job 0 leads to 5
+    #[derive(Debug, Clone)]
+    struct RewardedReply {
+        done: Vec<u8>,
+    }
+
+    impl State<u8> for RewardedReply {
+        fn next_action(&self) -> Option<u8> {
+            self.legal_actions().first().copied()
+        }
+
+        fn legal_actions(&self) -> Vec<u8> {
+            match self.done[..] {
+                [] | [1] => vec![0, 1],
+                _ => vec![0],
+            }
+        }
+
+        fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+            self.done.push(*action);
+            Ok(if self.done == [1] { 10. } else { 0. })
+        }
+
+        fn current_player(&self) -> usize {
+            self.done.len() % 2
+        }
+
+        fn num_players(&self) -> usize {
+            2
+        }
+
+        fn heuristic(&self) -> f32 {
+            match self.done[..] {
+                [1, 0] => 3.,
+                [1, 1] => -100.,
+                _ => 5.,
+            }
+        }
+    }
+
+    #[test]
+    fn alpha_beta_rewards() {
+        let state = RewardedReply { done: vec![] };
+        let tree = Tree::with_config(SearchConfig::new(1.0), 0, state.clone());
+
+        // Job 1 is worth 10 - 100, the cutoff after the reply of 3 compares it
+        // to 5 without the reward of 10
+        let value = tree.alpha_beta(&state, 2, 0, f32::MIN, f32::MAX, 0);
+        assert_eq!(value, 5.);
+    }
+
+    #[derive(Debug, Clone)]
+    struct ExchangeState {
+        done: u8,
+    }
+
+    impl State<u8> for ExchangeState {
+        fn next_action(&self) -> Option<u8> {
+            if self.done == 4 {
+                return None;
+            }
+            Some(self.done)
+        }
+
+        fn try_do_action(&mut self, _action: &u8) -> Result<f32, ActionError> {
+            self.done += 1;
+            Ok(match self.done {
+                2 => 1.,
+                3 => -1.,
+                _ => 0.,
+            })
+        }
+
+        // The capture of the second move is taken back by the third
+        fn is_quiet(&self) -> bool {
+            self.done != 2
+        }
+    }
+
+    #[test]
+    fn quiescence() {
+        let value = |quiescence_steps| {
+            let mut config = SearchConfig::new(1.0);
+            config.rollout_depth = 2;
+            config.quiescence_steps = quiescence_steps;
+            let tree = Tree::with_config(config, 0, ExchangeState { done: 0 });
+            tree.simulate_plan(&[], 1).unwrap()
+        };
+
+        assert_eq!(value(0), 1.);
+        assert_eq!(value(1), 0.);
+
+        let mut config = SearchConfig::new(1.0);
+        config.shallow_search = Some(ShallowSearch::new(2, 0));
+        let tree = Tree::with_config(config, 0, ExchangeState { done: 0 });
+        assert_eq!(tree.simulate_plan(&[], 1).unwrap(), 1.);
+        config.quiescence_steps = 2;
+        let tree = Tree::with_config(config, 0, ExchangeState { done: 0 });
+        assert_eq!(tree.simulate_plan(&[], 1).unwrap(), 0.);
+    }
+
+    #[test]
+    fn push_payoffs() {
+        let node = Node::new(
+            0u8,
+            DuelState {
+                players: 3,
+                done: vec![],
+            },
+        );
+        node.borrow_mut().push_payoffs(&[1., 0., 0.]);
+        node.borrow_mut().push_payoffs(&[0., 1., 0.]);
+        assert_eq!(node.borrow().payoff(0), Some(0.5));
+        assert_eq!(node.borrow().payoff(2), Some(0.));
+        assert_eq!(node.borrow().payoff(3), None);
+    }
+
+    #[derive(Debug, Clone)]
+    struct SlowState {
+        moves: u32,
+    }
+
+    impl State<u8> for SlowState {
+        fn next_action(&self) -> Option<u8> {
+            if self.moves == 100_000 {
+                return None;
+            }
+            Some(1)
+        }
+
+        fn try_do_action(&mut self, _action: &u8) -> Result<f32, ActionError> {
+            std::thread::sleep(Duration::from_micros(100));
+            self.moves += 1;
+            Ok(0.)
+        }
+
+        fn heuristic(&self) -> f32 {
+            1.
+        }
+    }
+
+    #[test]
+    fn truncate_at_deadline() {
+        let mut config = SearchConfig::new(1.0);
+        config.truncate_at_deadline = true;
+        let mut tree = Tree::with_config(config, 0, SlowState { moves: 0 });
+        let start = Instant::now();
+        tree.search_for(Duration::from_millis(20));
+
+        // The rollout in flight at the deadline still counts
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(tree.iterations >= 1);
+        assert_eq!(tree.root().borrow().visits, tree.iterations);
+        assert_eq!(tree.root().borrow().mean_value(), 1.);
+    }
+
+    #[test]
+    fn lcb_pruning() {
+        let game = PathGame::new(2, |x| {
+            if x[0] == 2 {
+                Outcome::Win
+            } else {
+                Outcome::Loss
+            }
+        });
+        let mut tree = Tree::new(1.0, 0, game.clone());
+        tree.search(50);
+        assert_eq!(tree.root().borrow().children.len(), 2);
+
+        let mut config = SearchConfig::new(1.0);
+        config.lcb_prune_checks = 5;
+        let mut tree = Tree::with_config(config, 0, game);
+        tree.search(50);
+        let root = tree.root();
+        assert_eq!(root.borrow().children.len(), 1);
+        assert_eq!(root.borrow().child_at(0).unwrap().borrow().action, 2);
+        assert!(root.borrow().child_at(0).unwrap().borrow().visits > 40);
+    }
+
+    #[test]
+    fn custom_rules() {
+        #[derive(Debug)]
+        struct LeastVisited;
+
+        impl SelectionRule<u8, DummyState> for LeastVisited {
+            fn score(
+                &self,
+                node: &Node<u8, DummyState>,
+                _value: f32,
+                _c: f32,
+                _bounds: Option<(f32, f32)>,
+            ) -> f32 {
+                -(node.visits as f32)
+            }
+        }
+
+        #[derive(Debug)]
+        struct OddActions;
+
+        impl ExpansionRule<u8, DummyState> for OddActions {
+            fn expand(&self, _state: &DummyState, action: &u8) -> bool {
+                action % 2 == 1
+            }
+        }
+
+        #[derive(Debug)]
+        struct Doubled;
+
+        impl BackupRule<u8, DummyState> for Doubled {
+            fn update(&self, node: &mut Node<u8, DummyState>, value: f32) {
+                node.push_value(2. * value);
+            }
+        }
+
+        let mut tree = Tree::new(1.0, 0, DummyState::new())
+            .with_selection_rule(LeastVisited)
+            .with_expansion_rule(OddActions)
+            .with_backup_rule(Doubled);
+        tree.search(31);
+
+        let root = tree.root();
+        let actions: Vec<u8> = root
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().action)
+            .collect();
+        assert_eq!(actions, vec![5, 3, 1]);
+        let visits: Vec<u32> = root
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().visits)
+            .collect();
+        assert!(visits.iter().all(|x| *x == 10 || *x == 11));
+        // Every rollout takes the remaining 0.5 rewards
+        assert!(root.borrow().mean_value() > 1.);
+    }
+
+    #[test]
+    fn with_preset() {
+        let game = PathGame::new(2, |x| {
+            if x[0] == 1 {
+                Outcome::Win
+            } else {
+                Outcome::Loss
+            }
+        });
+        let mut tree = Tree::with_preset(Preset::TwoPlayerGame, 0, game);
+        let best = tree.search(20).unwrap();
+        assert_eq!(best.borrow().action, 1);
+        assert_eq!(best.borrow().proven, Some(Outcome::Win));
+    }
+
+    #[test]
+    fn simulate_plan() {
+        let tree = Tree::new(1.0, 0, MockState::new(vec![(3, 0.5), (2, 0.2), (1, 0.1)]));
+        let value = |plan: &[u8]| tree.simulate_plan(plan, 3).unwrap();
+
+        assert!((value(&[]) - 0.8).abs() < 1e-6);
+        assert!((value(&[3, 2, 1]) - 0.8).abs() < 1e-6);
+        // Action 2 is left out, the rollout carries on from the third step
+        assert!((value(&[3, 1]) - 0.7).abs() < 1e-6);
+        assert_eq!(tree.size, 1);
+        assert_eq!(tree.root().borrow().visits, 0);
+
+        // The outcome replaces the rewards of the rollout, not those of the plan
+        let state = MockState::new(vec![(3, 0.5), (2, 0.2), (1, 0.1)]).with_outcome(Outcome::Win);
+        let tree = Tree::new(1.0, 0, state);
+        assert_eq!(tree.simulate_plan(&[3], 1).unwrap(), 1.5);
+
+        // The evaluation prefetched for the root stays with it
+        *tree.prefetched.borrow_mut() = Some((tree.root().borrow().id(), (vec![], 10.)));
+        assert_eq!(tree.simulate_plan(&[], 1).unwrap(), 1.);
+        assert!(tree.prefetched.borrow().is_some());
+    }
+
+    #[test]
+    fn policy_priors() {
+        #[derive(Debug)]
+        struct Policy;
+
+        impl Evaluator<u8, MockState<u8>> for Policy {
+            fn evaluate(&self, _state: &MockState<u8>) -> Evaluation<u8> {
+                (vec![(1, 3.), (4, 1.)], 0.5)
+            }
+        }
+
+        let mut tree = Tree::with_preset(Preset::NeuralPuct, 0, MockState::countdown(4, 0.5))
+            .with_evaluator(Policy)
+            .with_policy_priors();
+        tree.search(40);
+
+        let root = tree.root();
+        let priors: Vec<Option<f32>> = root
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().prior)
+            .collect();
+        assert_eq!(priors, vec![Some(0.25), Some(0.), Some(0.), Some(0.75)]);
+
+        // Equal values, the visits follow the priors and the unlikely moves wait
+        let visits: Vec<u32> = root
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().visits)
+            .collect();
+        assert!(visits[3] > visits[0] && visits[0] > visits[1]);
+        assert_eq!(tree.best_child().unwrap().borrow().action, 1);
+    }
+
+    #[test]
+    fn depth_exploration() {
+        let mut config = SearchConfig::game();
+        config.depth_exploration = Some(DepthExploration::new(0.01, 0.));
+        let mut tree = Tree::with_config(config, 0, MockState::countdown(4, 0.5));
+        tree.search(60);
+
+        let root = tree.root();
+        let child = root.borrow().child_at(0).unwrap();
+        let grandchild = child.borrow().child_at(0).unwrap();
+        assert_eq!(Tree::depth(&grandchild), 2);
+        // Same node, almost no exploration term two levels down
+        let shallow = tree.node_score(&grandchild.borrow(), 1, 0);
+        let deep = tree.node_score(&grandchild.borrow(), 2, 0);
+        assert!(deep < shallow);
+        assert!(deep - grandchild.borrow().mean_value() < 0.1);
+    }
+
+    #[derive(Debug, Clone)]
+    struct GuidedState {
+        left: u8,
+        last: Option<u8>,
+    }
+
+    impl State<u8> for GuidedState {
+        fn next_action(&self) -> Option<u8> {
+            Some(self.left).filter(|x| *x > 0)
+        }
+
+        fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+            self.left -= 1;
+            self.last = Some(*action);
+            Ok(0.)
+        }
+
+        // Action 1 looks promising before it is tried
+        fn first_play_value(&self) -> Option<f32> {
+            Some(if self.last == Some(1) { 10. } else { -10. })
+        }
+    }
+
+    #[test]
+    fn search_cached() {
+        #[derive(Debug, Clone)]
+        struct CountdownState {
+            moves: u8,
+        }
+
+        impl StateKey for CountdownState {
+            type Key = u8;
+
+            fn key(&self) -> u8 {
+                self.moves
+            }
+        }
+
+        impl State<u8> for CountdownState {
+            fn next_action(&self) -> Option<u8> {
+                if self.moves == 0 {
+                    return None;
+                }
+                Some(self.moves)
+            }
+
+            fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+                self.moves -= 1;
+                Ok(*action as f32 / 10.)
+            }
+        }
+
+        let cache = ResultCache::new(4);
+        let mut tree = Tree::new(1.0, 0, CountdownState { moves: 4 });
+        let result = tree.search_cached(&cache, 50);
+        assert_eq!(result.iterations, 50);
+
+        // Same position, smaller budget, the tree is not touched
+        let mut again = Tree::new(1.0, 0, CountdownState { moves: 4 });
+        assert_eq!(again.search_cached(&cache, 30), result);
+        assert_eq!(again.iterations, 0);
+
+        // Deeper searches start from the shares of the cached one
+        let mut deeper = Tree::new(1.0, 0, CountdownState { moves: 4 });
+        let result = deeper.search_cached(&cache, 80);
+        assert_eq!(result.iterations, 80);
+        assert!(deeper
+            .root()
+            .borrow()
+            .children
+            .iter()
+            .all(|x| x.borrow().prior.is_some()));
+        assert_eq!(
+            cache.get(&CountdownState { moves: 4 }).unwrap().iterations,
+            80
+        );
+        assert_eq!(cache.stats().hits, 3);
+    }
+
+    #[test]
+    fn first_play_value() {
+        let state = GuidedState {
+            left: 3,
+            last: None,
+        };
+        let mut tree = Tree::new(1.0, 0, state);
+        tree.search(20);
+
+        // Past the expanded first child, action 1 went next and action 2 never
+        // scored above the others
+        let visits: Vec<u32> = tree
+            .root()
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().visits)
+            .collect();
+        assert_eq!(visits[1], 0);
+        assert_eq!(visits[0] + visits[2], 19);
+    }
+
+    #[test]
+    fn player_exploration() {
+        let mut tree = Tree::new(1.0, 0, MockState::countdown(4, 0.5))
+            .with_player_exploration(0, PlayerExploration::new(0.1, Some(-1.)));
+        tree.search(40);
+
+        // Past the first child the others never look better than the fpu
+        let root = tree.root();
+        let visits: Vec<u32> = root
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().visits)
+            .collect();
+        assert_eq!(visits, vec![39, 0, 0, 0]);
+
+        let unvisited = root.borrow().child_at(1).unwrap();
+        assert_eq!(tree.node_score(&unvisited.borrow(), 0, 0), -1.);
+        // Other players keep learning_rate
+        let child = root.borrow().child_at(0).unwrap();
+        let child = child.borrow();
+        assert!(tree.node_score(&child, 0, 1) > tree.node_score(&child, 0, 0));
+    }
+
+    #[test]
+    fn random_expansion() {
+        let mut config = SearchConfig::new(1.0);
+        config.random_expansion = true;
+        config.seed = 3;
+        let search = |iterations| {
+            let mut tree = Tree::with_config(config, 0, DummyState::new())
+                .with_default_rollout_policy(RandomRollout::new(5));
+            tree.search(iterations);
+            tree
+        };
+
+        // One new child per visit of the root
+        let tree = search(3);
+        let root = tree.root();
+        assert_eq!(root.borrow().children.len(), 2);
+        assert!(root.borrow().is_partially_expanded());
+
+        let tree = search(40);
+        let root = tree.root();
+        assert_eq!(root.borrow().children.len(), 5);
+        assert!(root.borrow().expanded && !root.borrow().is_partially_expanded());
+
+        // Same seeds, same tree
+        let shape = tree_shape(&root);
+        let order: Vec<u8> = root
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().action)
+            .collect();
+        let other = search(40);
+        assert_eq!(tree_shape(&other.root()), shape);
+        let other_order: Vec<u8> = other
+            .root()
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().action)
+            .collect();
+        assert_eq!(other_order, order);
+    }
+
+    #[test]
+    fn random_action() {
+        let state = DummyState::new();
+        assert_eq!(state.legal_actions(), vec![5, 4, 3, 2, 1]);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let actions: Vec<u8> = (0..20)
+            .filter_map(|_| state.random_action(&mut rng))
+            .collect();
+        assert_eq!(actions.len(), 20);
+        assert!(actions.iter().any(|x| *x != actions[0]));
+
+        let policy = RandomRollout::new(1);
+        assert!(RolloutPolicy::<u8, DummyState>::choose(&policy, &state).is_some());
+    }
+
+    #[test]
+    fn advance_root() {
+        let mut tree = Tree::new(1.0, 0, MockState::countdown(4, 0.5));
+        tree.search(60);
+        let child = tree.root().borrow().child_at(1).unwrap();
+        let (id, visits) = (child.borrow().id(), child.borrow().visits);
+        let action = child.borrow().action;
+        let size = Tree::subtree_size(&child);
+        drop(child);
+
+        assert_eq!(tree.advance_root(&action), Ok(true));
+        let root = tree.root();
+        assert_eq!(root.borrow().id(), id);
+        assert_eq!(root.borrow().visits, visits);
+        assert!(root.borrow().parent().is_none());
+        assert_eq!(tree.size, size);
+        assert_eq!(tree.nodes.len() as u32, size);
+
+        // The next search carries on from the kept statistics
+        tree.search(20);
+        assert_eq!(tree.root().borrow().visits, visits + 20);
+
+        // Actions outside the tree start from scratch
+        let mut tree = Tree::new(1.0, 0, MockState::countdown(4, 0.5));
+        assert_eq!(tree.advance_root(&3), Ok(false));
+        assert_eq!(tree.size, 1);
+        assert_eq!(tree.root().borrow().action, 3);
+        assert!(!tree.promote_child(&tree.root()));
+    }
+
+    #[test]
+    fn advance_root_to_opponent() {
+        let state = DuelState {
+            players: 2,
+            done: vec![],
+        };
+        let mut config = SearchConfig::game();
+        config.solver = true;
+        let mut tree = Tree::with_config(config, 0, state);
+        tree.search(300);
+        assert_eq!(tree.advance_root(&0), Ok(true));
+
+        // The opponent moves at the new root, job 2 wins for it
+        let reply = tree.root().borrow().child_at(1).unwrap();
+        assert_eq!(reply.borrow().action, 2);
+        assert_eq!(reply.borrow().proven, Some(Outcome::Win));
+        assert_eq!(reply.borrow().mean_value(), 1.);
+        assert!(tree.root().borrow().mean_value() > 0.5);
+        tree.search(50);
+        assert_eq!(tree.best_child().unwrap().borrow().action, 2);
+    }
+
+    #[test]
+    fn search_lexicographic() {
+        // Three jobs in any order, the first one decides the reward
+        #[derive(Debug, Clone)]
+        struct Jobs {
+            done: Vec<u8>,
+        }
+
+        impl State<u8> for Jobs {
+            fn next_action(&self) -> Option<u8> {
+                (1..=3).find(|x| !self.done.contains(x))
+            }
+
+            fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+                self.done.push(*action);
+                Ok(match (self.done.len(), self.done[0]) {
+                    (3, 1) => 1.,
+                    (3, 3) => 0.9,
+                    _ => 0.,
+                })
+            }
+        }
+
+        let state = Jobs { done: vec![] };
+        let larger = |_: &Jobs, x: &u8| *x as f32;
+
+        // 3 is the largest action but 1 is the only one close to the best
+        let mut tree = Tree::new(1.0, 0, state.clone());
+        assert_eq!(tree.search_lexicographic(30, 0.01, 30, larger), Some(1));
+        let mut tree = Tree::new(1.0, 0, state);
+        assert_eq!(tree.search_lexicographic(30, 0.1, 30, larger), Some(3));
+    }
+
+    #[test]
+    fn suboptimality() {
+        let mut tree = Tree::new(1.0, 0, MockState::countdown(3, 0.5));
+        assert_eq!(tree.suboptimality(), None);
+
+        // Planning values of the unexplored actions are unbounded
+        tree.search(2);
+        assert_eq!(tree.suboptimality().unwrap().bound, f32::INFINITY);
+
+        let bound = tree.search_bounded(0.01, 5000).unwrap();
+        assert!(bound.is_within(0.01));
+        assert!(tree.iterations < 5000);
+        assert_eq!(
+            Some(bound.action),
+            tree.best_child().map(|x| x.borrow().action)
+        );
+
+        // Nothing to regret with a single action
+        let mut tree = Tree::new(1.0, 0, MockState::countdown(1, 0.5));
+        tree.search(4);
+        let bound = tree.suboptimality().unwrap();
+        assert_eq!((bound.bound, bound.rival), (0., None));
+    }
+
+    #[test]
+    fn search_bounded() {
+        let game = PathGame::new(3, |x| match x[0] {
+            3 => Outcome::Win,
+            _ => Outcome::Loss,
+        });
+
+        // Unexplored actions may be wins
+        let mut tree = Tree::with_config(SearchConfig::game(), 0, game.clone());
+        let bound = tree.search_bounded(0.05, 2).unwrap();
+        assert_eq!(bound.bound, 1.);
+        assert_eq!(tree.iterations, 2);
+
+        let mut tree = Tree::with_config(SearchConfig::game(), 0, game);
+        let bound = tree.search_bounded(0.05, 1000).unwrap();
+        assert_eq!(bound.action, 3);
+        assert_eq!(bound.bound, 0.);
+        assert!(bound.rival.is_some());
+        assert!(tree.iterations < 1000);
+    }
+
+    #[test]
+    fn allocate_budget() {
+        let mut tree = Tree::new(1.0, 0, MockState::countdown(4, 0.5));
+        let best = tree.allocate_budget(vec![(0, 10), (2, 5), (9, 5)]);
+
+        let visits: Vec<u32> = tree
+            .root()
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().visits)
+            .collect();
+        assert_eq!(visits, vec![10, 0, 5, 0]);
+        assert_eq!(tree.root().borrow().visits, 15);
+        assert_eq!(best.unwrap().borrow().action, 4);
+        assert_eq!(tree.iterations, 15);
+    }
+
+    #[test]
+    fn warm_up() {
+        let mut config = SearchConfig::new(1.0);
+        config.warm_up_depth = 2;
+        let mut tree = Tree::with_config(config, 0, MockState::countdown(3, 0.5));
+        tree.search(1);
+        assert_eq!(tree_shape(&tree.root()), vec![1, 3, 6]);
+        assert_eq!(tree.root().borrow().visits, 1);
+
+        // The iterations go through the unvisited plies first
+        tree.search(8);
+        let root = tree.root();
+        assert!(root
+            .borrow()
+            .children
+            .iter()
+            .all(|x| x.borrow().visits >= 2));
+
+        config.max_nodes = 3;
+        let mut tree = Tree::with_config(config, 0, MockState::countdown(3, 0.5));
+        tree.expand_to_depth(2);
+        assert_eq!(tree.size, 4);
+    }
+
+    #[test]
+    fn reuse_decay() {
+        let mut config = SearchConfig::new(1.0);
+        config.reuse_decay = 0.25;
+        let mut tree = Tree::with_config(config, 0, MockState::countdown(4, 0.5));
+        tree.search(60);
+        let child = tree.root().borrow().child_at(0).unwrap();
+        let (visits, mean) = (child.borrow().visits, child.borrow().mean_value());
+        let action = child.borrow().action;
+        drop(child);
+
+        assert_eq!(tree.advance_root(&action), Ok(true));
+        let root = tree.root();
+        assert_eq!(root.borrow().visits, (visits as f32 * 0.25).round() as u32);
+        assert_eq!(root.borrow().mean_value(), mean);
+        for x in Tree::descendants(&root) {
+            assert!(x.borrow().visits >= 1);
+            assert!(x.borrow().visits <= root.borrow().visits);
+        }
+    }
+
+    #[test]
+    fn sibling_transfer() {
+        let mut config = SearchConfig::new(1.0);
+        config.sibling_transfer = 0.5;
+        let mut tree = Tree::with_config(config, 0, MockState::countdown(4, 0.5));
+        tree.search(60);
+
+        let child = tree
+            .root()
+            .borrow()
+            .children
+            .iter()
+            .min_by_key(|x| x.borrow().visits)
+            .map(Rc::clone)
+            .unwrap();
+        let (action, visits) = (child.borrow().action, child.borrow().visits);
+        let own: u32 = child
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().visits)
+            .sum();
+        drop(child);
+        tree.config.sibling_transfer_below = visits + 1;
+
+        // The siblings' replies start the new root children warm
+        assert_eq!(tree.advance_root(&action), Ok(true));
+        let root = tree.root();
+        assert!(root.borrow().expanded);
+        let children: u32 = root
+            .borrow()
+            .children
+            .iter()
+            .map(|x| x.borrow().visits)
+            .sum();
+        assert!(children > own);
+        assert_eq!(root.borrow().visits, visits + children - own);
     }
 }
@@ -0,0 +1,232 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::cell::{Cell, RefCell};
+use std::fmt::Debug;
+use std::io::{self, Stdout};
+use std::rc::Rc;
+use std::time::Instant;
+
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::widgets::{BarChart, Block, Borders, Paragraph};
+use ratatui::Terminal;
+
+use crate::export::ActionFormatter;
+use crate::inspect::TreeInspector;
+use crate::observer::{Interval, IterationSample, Observer};
+use crate::state::State;
+use crate::tree::Tree;
+
+const TOP_MOVES: usize = 5;
+const MAX_DEPTH: usize = 24;
+
+// Live view of a running search: speed, top root moves, principal line and
+// the depths of the sampled leaves. It only goes through the Observer and
+// TreeInspector interfaces, see Tree::with_monitor
+#[derive(Debug)]
+pub struct Monitor<B>
+where
+    B: Backend,
+{
+    terminal: RefCell<Terminal<B>>,
+    depths: RefCell<Vec<u64>>,
+    last: Cell<(Instant, u32)>,
+    nps: Cell<f32>,
+}
+
+impl Monitor<CrosstermBackend<Stdout>> {
+    pub fn stdout() -> io::Result<Self> {
+        let monitor = Self::new(CrosstermBackend::new(io::stdout()))?;
+        monitor.terminal.borrow_mut().clear()?;
+        Ok(monitor)
+    }
+}
+
+impl<B> Monitor<B>
+where
+    B: Backend,
+{
+    pub fn new(backend: B) -> io::Result<Self> {
+        Ok(Self {
+            terminal: RefCell::new(Terminal::new(backend)?),
+            depths: RefCell::new(vec![]),
+            last: Cell::new((Instant::now(), 0)),
+            nps: Cell::new(0.),
+        })
+    }
+
+    pub fn terminal(&self) -> std::cell::Ref<'_, Terminal<B>> {
+        self.terminal.borrow()
+    }
+
+    pub fn draw<T, I, F>(&self, inspector: &I, formatter: &F) -> io::Result<()>
+    where
+        I: TreeInspector<T>,
+        F: ActionFormatter<T>,
+    {
+        let (time, iterations) = self.last.get();
+        let elapsed = time.elapsed().as_secs_f32();
+        if elapsed > 0. && inspector.iterations() > iterations {
+            self.nps
+                .set((inspector.iterations() - iterations) as f32 / elapsed);
+        }
+        self.last.set((Instant::now(), inspector.iterations()));
+
+        let header = format!(
+            "iterations {}  nodes {}  {:.0} it/s",
+            inspector.iterations(),
+            inspector.size(),
+            self.nps.get()
+        );
+
+        let mut moves: Vec<_> = inspector
+            .children_of(inspector.root_id())
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|x| inspector.stats_of(x))
+            .collect();
+        moves.sort_by_key(|x| std::cmp::Reverse(x.visits));
+        let moves: Vec<String> = moves
+            .iter()
+            .take(TOP_MOVES)
+            .map(|x| {
+                format!(
+                    "{:<12} {:>8} {:>8.3}",
+                    formatter.format(&x.action),
+                    x.visits,
+                    x.mean
+                )
+            })
+            .collect();
+
+        let line: Vec<String> = inspector
+            .principal_line()
+            .into_iter()
+            .filter_map(|x| inspector.stats_of(x))
+            .map(|x| formatter.format(&x.action))
+            .collect();
+
+        let labels: Vec<String> = (0..self.depths.borrow().len())
+            .map(|x| x.to_string())
+            .collect();
+        let depths: Vec<(&str, u64)> = labels
+            .iter()
+            .map(String::as_str)
+            .zip(self.depths.borrow().iter().copied())
+            .collect();
+
+        self.terminal.borrow_mut().draw(|frame| {
+            let areas = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([
+                    Constraint::Length(3),
+                    Constraint::Length(TOP_MOVES as u16 + 2),
+                    Constraint::Length(3),
+                    Constraint::Min(3),
+                ])
+                .split(frame.area());
+
+            let block = |title| Block::default().borders(Borders::ALL).title(title);
+            frame.render_widget(Paragraph::new(header).block(block("search")), areas[0]);
+            frame.render_widget(
+                Paragraph::new(moves.join("\n")).block(block("top moves")),
+                areas[1],
+            );
+            frame.render_widget(
+                Paragraph::new(line.join(" ")).block(block("principal line")),
+                areas[2],
+            );
+            frame.render_widget(
+                BarChart::default()
+                    .block(block("leaf depths"))
+                    .data(&depths)
+                    .bar_width(3),
+                areas[3],
+            );
+        })?;
+        Ok(())
+    }
+}
+
+impl<B> Observer for Monitor<B>
+where
+    B: Backend + Debug,
+{
+    fn on_iteration(&self, sample: &IterationSample) {
+        let mut depths = self.depths.borrow_mut();
+        let depth = sample.depth.min(MAX_DEPTH);
+        if depths.len() <= depth {
+            depths.resize(depth + 1, 0);
+        }
+        depths[depth] += 1;
+    }
+}
+
+impl<T, S> Tree<T, S>
+where
+    S: State<T>,
+    T: Clone,
+{
+    // Redraws the monitor at every interval, the leaf depths come from the
+    // samples of SearchConfig::telemetry_interval
+    pub fn with_monitor<B, F>(
+        self,
+        monitor: Rc<Monitor<B>>,
+        interval: Interval,
+        formatter: F,
+    ) -> Self
+    where
+        B: Backend + Debug + 'static,
+        F: ActionFormatter<T> + 'static,
+    {
+        let observer = Rc::clone(&monitor);
+        self.with_observer(observer)
+            .with_interval_callback(interval, move |tree| {
+                let _ = monitor.draw(tree, &formatter);
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SearchConfig;
+    use crate::export::DebugFormatter;
+    use crate::testing::MockState;
+    use ratatui::backend::TestBackend;
+
+    #[test]
+    fn monitor() {
+        let monitor = Rc::new(Monitor::new(TestBackend::new(60, 24)).unwrap());
+        let mut config = SearchConfig::new(1.0);
+        config.telemetry_interval = 1;
+        let mut tree = Tree::with_config(config, 0u8, MockState::countdown(4, 0.5)).with_monitor(
+            Rc::clone(&monitor),
+            Interval::Iterations(10),
+            DebugFormatter,
+        );
+        tree.search(40);
+
+        let screen: String = monitor
+            .terminal()
+            .backend()
+            .buffer()
+            .content
+            .iter()
+            .map(|x| x.symbol())
+            .collect();
+        assert!(screen.contains("iterations 40"));
+        assert!(screen.contains("top moves"));
+        assert!(screen.contains("principal line"));
+        assert_eq!(monitor.depths.borrow().iter().sum::<u64>(), 40);
+    }
+}
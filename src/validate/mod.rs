@@ -0,0 +1,324 @@
+#![deny(
+    missing_debug_implementations,
+    missing_copy_implementations,
+    trivial_casts,
+    trivial_numeric_casts,
+    unsafe_code,
+    unstable_features,
+    unused_import_braces,
+    unused_qualifications
+)]
+
+use std::cell::RefCell;
+use std::fmt::Debug;
+
+use rand::Rng;
+
+use crate::state::{ActionError, Outcome, State};
+
+// Wraps a State and panics as soon as it breaks the contract the search relies on
+#[derive(Clone, Debug)]
+pub struct ValidatedState<T, S> {
+    state: S,
+    last_action: RefCell<Option<Option<T>>>,
+}
+
+impl<T, S> ValidatedState<T, S>
+where
+    S: State<T> + Debug,
+    T: Clone + PartialEq + Debug,
+{
+    pub fn new(state: S) -> Self {
+        Self {
+            state,
+            last_action: RefCell::new(None),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.state
+    }
+
+    pub fn into_inner(self) -> S {
+        self.state
+    }
+}
+
+impl<T, S> State<T> for ValidatedState<T, S>
+where
+    S: State<T> + Debug,
+    T: Clone + PartialEq + Debug,
+{
+    fn next_action(&self) -> Option<T> {
+        let action = self.state.next_action();
+
+        if let Some(previous) = &*self.last_action.borrow() {
+            if *previous != action {
+                panic!(
+                    "State contract violated: next_action returned {:?} and then {:?} \
+                     without an action being done in between, state: {:?}",
+                    previous, action, self.state
+                );
+            }
+        }
+
+        if let (Some(action), Some(outcome)) = (&action, self.state.outcome()) {
+            panic!(
+                "State contract violated: terminal state with outcome {:?} yielded \
+                 action {:?}, state: {:?}",
+                outcome, action, self.state
+            );
+        }
+
+        *self.last_action.borrow_mut() = Some(action.clone());
+        action
+    }
+
+    fn try_do_action(&mut self, action: &T) -> Result<f32, ActionError> {
+        let reward = self.state.try_do_action(action);
+
+        if let Ok(x) = reward {
+            if !x.is_finite() {
+                panic!(
+                    "State contract violated: action {:?} returned non-finite reward {}, \
+                     state: {:?}",
+                    action, x, self.state
+                );
+            }
+        }
+
+        *self.last_action.get_mut() = None;
+        reward
+    }
+
+    fn outcome(&self) -> Option<Outcome> {
+        self.state.outcome()
+    }
+
+    fn current_player(&self) -> usize {
+        self.state.current_player()
+    }
+
+    fn step_cost(&self, action: &T) -> f32 {
+        self.state.step_cost(action)
+    }
+
+    fn num_players(&self) -> usize {
+        self.state.num_players()
+    }
+
+    fn payoffs(&self) -> Option<Vec<f32>> {
+        self.state.payoffs()
+    }
+
+    fn heuristic(&self) -> f32 {
+        self.state.heuristic()
+    }
+
+    fn first_play_value(&self) -> Option<f32> {
+        self.state.first_play_value()
+    }
+
+    fn is_quiet(&self) -> bool {
+        self.state.is_quiet()
+    }
+
+    fn commutes(&self, previous: &T, action: &T) -> bool {
+        self.state.commutes(previous, action)
+    }
+
+    fn legal_actions(&self) -> Vec<T> {
+        self.state.legal_actions()
+    }
+
+    fn random_action<R>(&self, rng: &mut R) -> Option<T>
+    where
+        R: Rng + ?Sized,
+    {
+        self.state.random_action(rng)
+    }
+
+    fn action_count(&self) -> Option<usize> {
+        self.state.action_count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SearchConfig;
+    use crate::export::DebugFormatter;
+    use crate::testing::PathGame;
+    use crate::tree::Tree;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::cell::Cell;
+
+    #[derive(Debug, Clone)]
+    struct DummyState {
+        actions: u8,
+        reward: f32,
+        terminal: bool,
+        calls: Cell<u8>,
+        unstable: bool,
+    }
+
+    impl DummyState {
+        fn new() -> Self {
+            Self {
+                actions: 5,
+                reward: 0.5,
+                terminal: false,
+                calls: Cell::new(0),
+                unstable: false,
+            }
+        }
+    }
+
+    impl State<u8> for DummyState {
+        fn next_action(&self) -> Option<u8> {
+            if self.actions == 0 {
+                return None;
+            }
+            if self.unstable {
+                self.calls.set(self.calls.get() + 1);
+                return Some(self.calls.get());
+            }
+            Some(self.actions)
+        }
+
+        fn try_do_action(&mut self, _action: &u8) -> Result<f32, ActionError> {
+            self.actions -= 1;
+            Ok(self.reward)
+        }
+
+        fn outcome(&self) -> Option<Outcome> {
+            if self.terminal {
+                return Some(Outcome::Win);
+            }
+            None
+        }
+    }
+
+    #[test]
+    fn valid_state() {
+        let state = ValidatedState::new(DummyState::new());
+        let mut tree = Tree::new(1.0, 0, state);
+        assert!(tree.search(20).is_some());
+    }
+
+    #[test]
+    fn forwards_state() {
+        // Two players, the second one wins every line
+        #[derive(Debug, Clone)]
+        struct Duel {
+            moves: PathGame,
+        }
+
+        impl State<u8> for Duel {
+            fn next_action(&self) -> Option<u8> {
+                self.moves.next_action()
+            }
+
+            fn try_do_action(&mut self, action: &u8) -> Result<f32, ActionError> {
+                Ok(self.moves.do_action(action))
+            }
+
+            fn outcome(&self) -> Option<Outcome> {
+                self.moves.outcome()
+            }
+
+            fn current_player(&self) -> usize {
+                self.moves.current_player()
+            }
+
+            fn num_players(&self) -> usize {
+                2
+            }
+
+            fn payoffs(&self) -> Option<Vec<f32>> {
+                Some(vec![0., 1.])
+            }
+
+            fn step_cost(&self, action: &u8) -> f32 {
+                f32::from(*action)
+            }
+
+            fn heuristic(&self) -> f32 {
+                0.25
+            }
+
+            fn first_play_value(&self) -> Option<f32> {
+                Some(0.75)
+            }
+
+            fn is_quiet(&self) -> bool {
+                false
+            }
+
+            fn commutes(&self, previous: &u8, action: &u8) -> bool {
+                previous < action
+            }
+
+            fn action_count(&self) -> Option<usize> {
+                Some(7)
+            }
+        }
+
+        let state = Duel {
+            moves: PathGame::new(3, |_| Outcome::Loss),
+        };
+        let wrapped = ValidatedState::new(state.clone());
+        assert_eq!(wrapped.num_players(), 2);
+        assert_eq!(wrapped.payoffs(), Some(vec![0., 1.]));
+        assert_eq!(wrapped.step_cost(&3), 3.);
+        assert_eq!(wrapped.heuristic(), 0.25);
+        assert_eq!(wrapped.first_play_value(), Some(0.75));
+        assert!(!wrapped.is_quiet());
+        assert!(wrapped.commutes(&1, &2));
+        assert_eq!(wrapped.action_count(), Some(7));
+        assert_eq!(wrapped.legal_actions(), state.legal_actions());
+        let mut rng = StdRng::seed_from_u64(1);
+        assert_eq!(
+            wrapped.random_action(&mut rng),
+            state.random_action(&mut StdRng::seed_from_u64(1))
+        );
+
+        // Searched as the two-player game it wraps
+        let mut tree = Tree::with_config(SearchConfig::game(), 0, wrapped);
+        tree.search(30);
+        let mut other = Tree::with_config(SearchConfig::game(), 0, state);
+        other.search(30);
+        assert_eq!(
+            tree.to_json(&DebugFormatter, 3),
+            other.to_json(&DebugFormatter, 3)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "without an action being done")]
+    fn unstable_next_action() {
+        let mut state = DummyState::new();
+        state.unstable = true;
+
+        let state = ValidatedState::new(state);
+        state.next_action();
+        state.next_action();
+    }
+
+    #[test]
+    #[should_panic(expected = "terminal state with outcome Win")]
+    fn terminal_with_actions() {
+        let mut state = DummyState::new();
+        state.terminal = true;
+        ValidatedState::new(state).next_action();
+    }
+
+    #[test]
+    #[should_panic(expected = "non-finite reward")]
+    fn non_finite_reward() {
+        let mut state = DummyState::new();
+        state.reward = f32::NAN;
+        ValidatedState::new(state).do_action(&1);
+    }
+}